@@ -0,0 +1,95 @@
+use std::fmt;
+
+use crate::cpu::{Cpu, BP, IP, NUM_REGISTERS, SP};
+
+// The calling convention:
+//
+// - arguments are pushed right to left as 4 byte slots, and the caller removes
+//   them after the call returns.
+// - the callee sets up its frame with `enter <locals>`, which leaves the saved `bp`
+//   at `bp`, the return address at `bp + 4` and the first argument at `bp + 8`.
+//   `leave` tears the frame down again before `ret`.
+// - the return value is left in `rax`.
+// - `rax`, `rbx`, `rcx`, `rdx` and `flags` are scratch. every other general purpose
+//   register, `bp` and `sp` must hold the same value after `ret` as before `call`.
+
+/// Offset from `bp` of the first argument, once the callee has run `enter`.
+pub const FIRST_ARG_OFFSET: i32 = 8;
+
+/// Registers a callee may clobber without restoring them.
+pub const SCRATCH: [usize; 4] = [0, 1, 2, 3];
+
+/// Registers that must survive a call, including `ip` so returning
+/// somewhere other than after the `call` is caught too.
+pub fn preserved() -> impl Iterator<Item = usize> {
+    (SCRATCH.len()..=16).chain([BP, SP, IP])
+}
+
+/// A callee that returned with a register it should have preserved changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// address of the `call` instruction.
+    pub call: u32,
+    pub target: u32,
+    pub register: usize,
+    pub expected: u32,
+    pub found: u32,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "call at 0x{:08X} to 0x{:08X} returned with {} = 0x{:08X}, expected 0x{:08X}",
+            self.call,
+            self.target,
+            Cpu::reg_index_to_str(&self.register),
+            self.found,
+            self.expected
+        )
+    }
+}
+
+struct Frame {
+    target: u32,
+    /// the registers as they should be after `ret`.
+    registers: [u32; NUM_REGISTERS],
+}
+
+/// Checks the calling convention at runtime: `call` snapshots the registers and
+/// the matching `ret` compares them. Enabled through `Cpu::abi_checker`.
+#[derive(Default)]
+pub struct AbiChecker {
+    frames: Vec<Frame>,
+    pub violations: Vec<Violation>,
+}
+
+impl AbiChecker {
+    /// Called by `call` before it pushes the return address, with `ip` already
+    /// past the instruction.
+    pub fn call(&mut self, registers: &[u32; NUM_REGISTERS], target: u32) {
+        self.frames.push(Frame {
+            target,
+            registers: *registers,
+        });
+    }
+
+    /// Called by `ret` after it has popped the return address.
+    pub fn ret(&mut self, registers: &[u32; NUM_REGISTERS]) {
+        // a `ret` without a `call`, like returning out of the entry point.
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        for register in preserved() {
+            if registers[register] != frame.registers[register] {
+                self.violations.push(Violation {
+                    call: frame.registers[IP].wrapping_sub(5),
+                    target: frame.target,
+                    register,
+                    expected: frame.registers[register],
+                    found: registers[register],
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::object::{invalid, Object, Reader, Writer};
+
+/// An object file stored in an archive, under the file name it was added as.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub object: Object,
+}
+
+/// A static library: objects the linker only pulls in when they define a
+/// symbol something else needs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Archive {
+    /// every global symbol and the index of the member defining it. when two
+    /// members define the same symbol the first one wins.
+    pub index: Vec<(String, u32)>,
+    pub members: Vec<Member>,
+}
+
+impl Archive {
+    pub const MAGIC: &'static [u8; 4] = b"B32A";
+    pub const VERSION: u16 = 1;
+
+    pub fn new(members: Vec<Member>) -> Archive {
+        let mut index: Vec<(String, u32)> = Vec::new();
+        for (i, member) in members.iter().enumerate() {
+            for symbol in member.object.symbols.iter() {
+                let defined = symbol.global && symbol.section.is_some();
+                if defined && !index.iter().any(|(name, _)| *name == symbol.name) {
+                    index.push((symbol.name.clone(), i as u32));
+                }
+            }
+        }
+        Archive { index, members }
+    }
+
+    /// The member that defines `symbol`, according to the index.
+    pub fn find(&self, symbol: &str) -> Option<usize> {
+        self.index
+            .iter()
+            .find(|(name, _)| name == symbol)
+            .map(|(_, member)| *member as usize)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer::default();
+        out.bytes(Archive::MAGIC);
+        out.short(Archive::VERSION);
+        out.long(self.index.len() as u32);
+        for (name, member) in self.index.iter() {
+            out.string(name);
+            out.long(*member);
+        }
+        out.long(self.members.len() as u32);
+        for member in self.members.iter() {
+            let bytes = member.object.to_bytes();
+            out.string(&member.name);
+            out.long(bytes.len() as u32);
+            out.bytes(&bytes);
+        }
+        out.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Archive> {
+        let mut input = Reader { bytes, pos: 0 };
+        if input.take(4)? != Archive::MAGIC {
+            return Err(invalid("not a bit32 archive"));
+        }
+        let version = input.short()?;
+        if version != Archive::VERSION {
+            return Err(invalid(&format!("unsupported archive version {}", version)));
+        }
+
+        let mut archive = Archive::default();
+        for _ in 0..input.long()? {
+            archive.index.push((input.string()?, input.long()?));
+        }
+        for _ in 0..input.long()? {
+            let name = input.string()?;
+            // `ar extract` writes members out by name, so they stay in its directory.
+            if matches!(name.as_str(), "" | "." | "..") || name.contains(['/', '\\']) {
+                return Err(invalid(&format!(
+                    "member name {:?} is not a file name",
+                    name
+                )));
+            }
+            let len = input.long()? as usize;
+            let object = Object::from_bytes(input.take(len)?)
+                .map_err(|err| invalid(&format!("member {}: {}", name, err)))?;
+            archive.members.push(Member { name, object });
+        }
+        if archive
+            .index
+            .iter()
+            .any(|(_, member)| *member as usize >= archive.members.len())
+        {
+            return Err(invalid("symbol index refers to a missing member"));
+        }
+        Ok(archive)
+    }
+
+    /// Whether `bytes` look like an archive rather than an object.
+    pub fn is_archive(bytes: &[u8]) -> bool {
+        bytes.starts_with(Archive::MAGIC)
+    }
+
+    pub fn write_to_file<T: AsRef<Path> + ?Sized>(&self, path: &T) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn read_from_file<T: AsRef<Path> + ?Sized>(path: &T) -> io::Result<Archive> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        Archive::from_bytes(&buffer)
+    }
+}
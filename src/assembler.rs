@@ -0,0 +1,1282 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use crate::cpu::Cpu;
+use crate::linker::{self, Input};
+use crate::listing::ListingEntry;
+use crate::object::{LineEntry, Object, Relocation, SectionKind, Symbol, Target};
+use crate::opcodes::{Opcode, OperandKind, Width};
+use crate::preprocessor::{self, Preprocessor};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    pub fn new(line: &SourceLine, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            file: line.file.clone(),
+            line: line.line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: error: {}", self.file, self.message);
+        }
+        write!(
+            f,
+            "{}:{}:{}: error: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// A single line of assembly along with where it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+}
+
+impl SourceLine {
+    pub fn split(file: &str, source: &str) -> Vec<SourceLine> {
+        source
+            .lines()
+            .enumerate()
+            .map(|(i, text)| SourceLine {
+                file: file.to_string(),
+                line: i + 1,
+                text: text.to_string(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Number(value) => write!(f, "`{}`", value),
+            Token::Str(_) => write!(f, "string literal"),
+            Token::Symbol(symbol) => write!(f, "`{}`", symbol),
+        }
+    }
+}
+
+/// A token and the 1-based column it starts at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned {
+    pub token: Token,
+    pub column: usize,
+}
+
+const SYMBOLS: [&str; 18] = [
+    "<<", ">>", ",", ":", "[", "]", "(", ")", "+", "-", "*", "/", "%", "&", "|", "^", "~", "$",
+];
+
+pub fn tokenize(line: &SourceLine) -> Result<Vec<Spanned>, AsmError> {
+    let chars = line.text.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        if c == ';' {
+            break;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+            {
+                i += 1;
+            }
+            let ident = chars[start..i].iter().collect::<String>();
+            tokens.push(Spanned {
+                token: Token::Ident(ident),
+                column,
+            });
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let text = chars[start..i]
+                .iter()
+                .filter(|c| **c != '_')
+                .collect::<String>();
+            let lower = text.to_ascii_lowercase();
+            let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+                i64::from_str_radix(hex, 16)
+            } else if let Some(bin) = lower.strip_prefix("0b") {
+                i64::from_str_radix(bin, 2)
+            } else if let Some(oct) = lower.strip_prefix("0o") {
+                i64::from_str_radix(oct, 8)
+            } else {
+                lower.parse::<i64>()
+            };
+            match parsed {
+                Ok(value) => tokens.push(Spanned {
+                    token: Token::Number(value),
+                    column,
+                }),
+                Err(_) => {
+                    return Err(AsmError::new(
+                        line,
+                        column,
+                        format!("invalid number `{}`", text),
+                    ))
+                }
+            }
+            continue;
+        }
+
+        if c == '\'' {
+            i += 1;
+            let (value, next) = char_literal(&chars, i)
+                .ok_or_else(|| AsmError::new(line, column, "invalid character literal"))?;
+            i = next;
+            if chars.get(i) != Some(&'\'') {
+                return Err(AsmError::new(
+                    line,
+                    column,
+                    "unterminated character literal",
+                ));
+            }
+            i += 1;
+            tokens.push(Spanned {
+                token: Token::Number(value as i64),
+                column,
+            });
+            continue;
+        }
+
+        if c == '"' {
+            i += 1;
+            let mut bytes = Vec::new();
+            while i < chars.len() && chars[i] != '"' {
+                let (value, next) = char_literal(&chars, i)
+                    .ok_or_else(|| AsmError::new(line, i + 1, "invalid escape sequence"))?;
+                let mut buffer = [0; 4];
+                let c = char::from_u32(value).unwrap();
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                i = next;
+            }
+            if i >= chars.len() {
+                return Err(AsmError::new(line, column, "unterminated string literal"));
+            }
+            i += 1;
+            tokens.push(Spanned {
+                token: Token::Str(bytes),
+                column,
+            });
+            continue;
+        }
+
+        let rest = chars[i..].iter().take(2).collect::<String>();
+        match SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            Some(symbol) => {
+                i += symbol.len();
+                tokens.push(Spanned {
+                    token: Token::Symbol(symbol),
+                    column,
+                });
+            }
+            None => {
+                return Err(AsmError::new(
+                    line,
+                    column,
+                    format!("unexpected character `{}`", c),
+                ));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Reads one possibly escaped character starting at `i`, returning it and the index after it.
+fn char_literal(chars: &[char], i: usize) -> Option<(u32, usize)> {
+    match chars.get(i)? {
+        '\\' => {
+            let value = match chars.get(i + 1)? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                '\\' => '\\',
+                '\'' => '\'',
+                '"' => '"',
+                _ => return None,
+            };
+            Some((value as u32, i + 2))
+        }
+        c => Some((*c as u32, i + 1)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String, usize),
+    /// `$`, the address of the current instruction.
+    Here,
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Imm(Expr),
+    Reg(usize),
+    Abs(Expr),
+    Mem(Expr),
+    Indirect(usize),
+}
+
+impl Operand {
+    pub fn kind(&self) -> OperandKind {
+        match self {
+            Operand::Imm(_) => OperandKind::Imm,
+            Operand::Reg(_) => OperandKind::Reg,
+            Operand::Abs(_) => OperandKind::Abs,
+            Operand::Mem(_) => OperandKind::Mem,
+            Operand::Indirect(_) => OperandKind::Indirect,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    /// operands and the column each one starts at.
+    pub operands: Vec<(Operand, usize)>,
+}
+
+impl Instruction {
+    pub fn size(&self) -> usize {
+        let (first, second) = self.opcode.operand_sizes();
+        1 + first + second
+    }
+}
+
+struct Parser<'a> {
+    line: &'a SourceLine,
+    tokens: &'a [Spanned],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+    fn peek_at(&self, offset: usize) -> Option<&'a Token> {
+        self.tokens.get(self.pos + offset).map(|t| &t.token)
+    }
+    fn column(&self) -> usize {
+        match self.tokens.get(self.pos) {
+            Some(t) => t.column,
+            None => self.line.text.len() + 1,
+        }
+    }
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+    }
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError::new(self.line, self.column(), message)
+    }
+    fn unexpected(&self) -> AsmError {
+        match self.peek() {
+            Some(token) => self.error(format!("unexpected {}", token)),
+            None => self.error("unexpected end of line"),
+        }
+    }
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), AsmError> {
+        if self.is_symbol(symbol) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`", symbol)))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, AsmError> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, AsmError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let (op, precedence) = match self.peek() {
+                Some(Token::Symbol("|")) => (BinaryOp::Or, 1),
+                Some(Token::Symbol("^")) => (BinaryOp::Xor, 2),
+                Some(Token::Symbol("&")) => (BinaryOp::And, 3),
+                Some(Token::Symbol("<<")) => (BinaryOp::Shl, 4),
+                Some(Token::Symbol(">>")) => (BinaryOp::Shr, 4),
+                Some(Token::Symbol("+")) => (BinaryOp::Add, 5),
+                Some(Token::Symbol("-")) => (BinaryOp::Sub, 5),
+                Some(Token::Symbol("*")) => (BinaryOp::Mul, 6),
+                Some(Token::Symbol("/")) => (BinaryOp::Div, 6),
+                Some(Token::Symbol("%")) => (BinaryOp::Rem, 6),
+                _ => break,
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.binary(precedence + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, AsmError> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(*value)),
+            Some(Token::Ident(name)) => Ok(Expr::Symbol(name.clone(), column)),
+            Some(Token::Symbol("$")) => Ok(Expr::Here),
+            Some(Token::Symbol("-")) => Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?))),
+            Some(Token::Symbol("~")) => Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?))),
+            Some(Token::Symbol("+")) => self.unary(),
+            Some(Token::Symbol("(")) => {
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected an expression"))
+            }
+        }
+    }
+
+    fn register(&self, offset: usize) -> Option<usize> {
+        match self.peek_at(offset) {
+            Some(Token::Ident(name)) => Cpu::reg_str_to_index(&name.to_ascii_lowercase()),
+            _ => None,
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, AsmError> {
+        if let Some(reg) = self.register(0) {
+            self.pos += 1;
+            return Ok(Operand::Reg(reg));
+        }
+        if !self.is_symbol("[") {
+            return Ok(Operand::Imm(self.expr()?));
+        }
+        self.pos += 1;
+
+        let operand = if let (Some(reg), Some(Token::Symbol("]"))) =
+            (self.register(0), self.peek_at(1))
+        {
+            self.pos += 1;
+            Operand::Indirect(reg)
+        } else if matches!(self.peek(), Some(Token::Ident(name)) if name.eq_ignore_ascii_case("rel"))
+        {
+            self.pos += 1;
+            Operand::Mem(self.expr()?)
+        } else {
+            Operand::Abs(self.expr()?)
+        };
+        self.expect_symbol("]")?;
+        Ok(operand)
+    }
+}
+
+/// Splits `mov.b` into `mov` and its width suffix.
+fn split_mnemonic(name: &str) -> (String, Option<Width>, bool) {
+    let lower = name.to_ascii_lowercase();
+    if let Some((base, suffix)) = lower.rsplit_once('.') {
+        let width = match suffix {
+            "b" => Some(Width::Byte),
+            "s" => Some(Width::Short),
+            "l" => Some(Width::Long),
+            _ => return (lower.clone(), None, true),
+        };
+        return (base.to_string(), width, false);
+    }
+    (lower, None, false)
+}
+
+fn select_opcode(
+    line: &SourceLine,
+    column: usize,
+    name: &str,
+    operands: &[(Operand, usize)],
+) -> Result<Opcode, AsmError> {
+    let (mnemonic, suffix, bad_suffix) = split_mnemonic(name);
+    if bad_suffix {
+        return Err(AsmError::new(
+            line,
+            column,
+            format!("invalid size suffix on `{}`", name),
+        ));
+    }
+
+    let candidates = Opcode::all()
+        .filter(|op| op.mnemonic() == mnemonic)
+        .collect::<Vec<Opcode>>();
+    if candidates.is_empty() {
+        return Err(AsmError::new(
+            line,
+            column,
+            format!("unknown instruction `{}`", name),
+        ));
+    }
+
+    let sized = candidates.iter().any(|op| op.width().is_some());
+    let width = match (sized, suffix) {
+        (true, None) => Some(Width::Long),
+        (true, Some(width)) => Some(width),
+        (false, None) => None,
+        (false, Some(_)) => {
+            return Err(AsmError::new(
+                line,
+                column,
+                format!("`{}` does not take a size suffix", mnemonic),
+            ))
+        }
+    };
+
+    let kinds = (
+        operands.first().map(|(operand, _)| operand.kind()),
+        operands.get(1).map(|(operand, _)| operand.kind()),
+    );
+    if operands.len() > 2 {
+        return Err(AsmError::new(line, operands[2].1, "too many operands"));
+    }
+    // two operand `cmp` only comes with a register on the left, see `Opcode`
+    if mnemonic == "cmp" && operands.len() == 2 && kinds.0 != Some(OperandKind::Reg) {
+        return Err(AsmError::new(
+            line,
+            operands[0].1,
+            "the left operand of `cmp` must be a register",
+        ));
+    }
+
+    candidates
+        .into_iter()
+        .find(|op| op.width() == width && op.operand_kinds() == kinds)
+        .ok_or_else(|| {
+            let found = operands
+                .iter()
+                .map(|(operand, _)| format!("{:?}", operand.kind()).to_ascii_lowercase())
+                .collect::<Vec<String>>();
+            AsmError::new(
+                line,
+                column,
+                format!("invalid operands for `{}`: ({})", name, found.join(", ")),
+            )
+        })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Directive {
+    Section(SectionKind),
+    Global(Vec<(String, usize)>),
+    Extern(Vec<(String, usize)>),
+    /// `db`, `dw` or `dd`: items of the given size, and strings with one item per byte.
+    Data(usize, Vec<DataItem>),
+    /// `align n`: pads to a multiple of `n` bytes from the start of the section.
+    Align(Expr, usize),
+    /// `resb`, `resw` or `resd`: space for that many items of the given size.
+    Reserve(usize, Expr, usize),
+    /// `incbin "path"`, relative to the file it appears in.
+    Incbin(String, usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataItem {
+    Expr(Expr, usize),
+    Str(Vec<u8>),
+}
+
+impl Directive {
+    /// Bytes a data directive takes up, or 0 for anything else.
+    fn data_size(&self) -> usize {
+        let Directive::Data(size, items) = self else {
+            return 0;
+        };
+        let count = items
+            .iter()
+            .map(|item| match item {
+                DataItem::Expr(..) => 1,
+                DataItem::Str(bytes) => bytes.len(),
+            })
+            .sum::<usize>();
+        count * size
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Instruction(Instruction),
+    Directive(Directive),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub labels: Vec<(String, usize)>,
+    pub item: Option<Item>,
+}
+
+fn parse_names(parser: &mut Parser) -> Result<Vec<(String, usize)>, AsmError> {
+    let mut names = Vec::new();
+    loop {
+        let column = parser.column();
+        match parser.next() {
+            Some(Token::Ident(name)) => names.push((name.clone(), column)),
+            _ => {
+                parser.pos -= 1;
+                return Err(parser.error("expected a symbol name"));
+            }
+        }
+        if parser.at_end() {
+            return Ok(names);
+        }
+        parser.expect_symbol(",")?;
+    }
+}
+
+fn parse_data(parser: &mut Parser) -> Result<Vec<DataItem>, AsmError> {
+    let mut items = Vec::new();
+    loop {
+        let column = parser.column();
+        if let Some(Token::Str(bytes)) = parser.peek() {
+            parser.pos += 1;
+            items.push(DataItem::Str(bytes.clone()));
+        } else {
+            items.push(DataItem::Expr(parser.expr()?, column));
+        }
+        if parser.at_end() {
+            return Ok(items);
+        }
+        parser.expect_symbol(",")?;
+    }
+}
+
+fn parse_string(parser: &mut Parser) -> Result<Vec<u8>, AsmError> {
+    match parser.next() {
+        Some(Token::Str(bytes)) => Ok(bytes.clone()),
+        _ => {
+            parser.pos -= 1;
+            Err(parser.error("expected a string"))
+        }
+    }
+}
+
+fn parse_directive(parser: &mut Parser, name: &str) -> Result<Option<Directive>, AsmError> {
+    let directive = match name.to_ascii_lowercase().as_str() {
+        "section" => {
+            let column = parser.column();
+            let kind = match parser.next() {
+                Some(Token::Ident(name)) => SectionKind::from_name(&name.to_ascii_lowercase()),
+                _ => None,
+            };
+            match kind {
+                Some(kind) => Directive::Section(kind),
+                None => {
+                    return Err(AsmError::new(
+                        parser.line,
+                        column,
+                        "expected `.text`, `.data` or `.bss`",
+                    ))
+                }
+            }
+        }
+        "global" => Directive::Global(parse_names(parser)?),
+        "extern" => Directive::Extern(parse_names(parser)?),
+        "db" => Directive::Data(1, parse_data(parser)?),
+        "dw" => Directive::Data(2, parse_data(parser)?),
+        "dd" => Directive::Data(4, parse_data(parser)?),
+        "ascii" => Directive::Data(1, vec![DataItem::Str(parse_string(parser)?)]),
+        "asciz" => {
+            let mut bytes = parse_string(parser)?;
+            bytes.push(0);
+            Directive::Data(1, vec![DataItem::Str(bytes)])
+        }
+        "align" => {
+            let column = parser.column();
+            Directive::Align(parser.expr()?, column)
+        }
+        "resb" | "resw" | "resd" => {
+            let size = match name.to_ascii_lowercase().as_str() {
+                "resb" => 1,
+                "resw" => 2,
+                _ => 4,
+            };
+            let column = parser.column();
+            Directive::Reserve(size, parser.expr()?, column)
+        }
+        "incbin" => {
+            let column = parser.column();
+            let path = parse_string(parser)?;
+            Directive::Incbin(String::from_utf8_lossy(&path).to_string(), column)
+        }
+        _ => return Ok(None),
+    };
+    if !parser.at_end() {
+        return Err(parser.unexpected());
+    }
+    Ok(Some(directive))
+}
+
+pub fn parse_line(line: &SourceLine) -> Result<Statement, AsmError> {
+    let tokens = tokenize(line)?;
+    let mut parser = Parser {
+        line,
+        tokens: &tokens,
+        pos: 0,
+    };
+    let mut statement = Statement {
+        labels: Vec::new(),
+        item: None,
+    };
+
+    while let (Some(Token::Ident(name)), Some(Token::Symbol(":"))) =
+        (parser.peek(), parser.peek_at(1))
+    {
+        statement.labels.push((name.clone(), parser.column()));
+        parser.pos += 2;
+    }
+
+    if parser.at_end() {
+        return Ok(statement);
+    }
+
+    let column = parser.column();
+    let name = match parser.next() {
+        Some(Token::Ident(name)) => name.clone(),
+        _ => {
+            parser.pos -= 1;
+            return Err(parser.error("expected an instruction"));
+        }
+    };
+
+    if let Some(directive) = parse_directive(&mut parser, &name)? {
+        statement.item = Some(Item::Directive(directive));
+        return Ok(statement);
+    }
+
+    let mut operands = Vec::new();
+    if !parser.at_end() {
+        loop {
+            let operand_column = parser.column();
+            operands.push((parser.operand()?, operand_column));
+            if parser.at_end() {
+                break;
+            }
+            if !parser.is_symbol(",") {
+                return Err(parser.unexpected());
+            }
+            parser.pos += 1;
+        }
+    }
+
+    let opcode = select_opcode(line, column, &name, &operands)?;
+    statement.item = Some(Item::Instruction(Instruction { opcode, operands }));
+    Ok(statement)
+}
+
+/// Evaluates `line` as a single expression that must not depend on any label.
+pub fn eval_constant(line: &SourceLine) -> Result<i64, AsmError> {
+    let tokens = tokenize(line)?;
+    let mut parser = Parser {
+        line,
+        tokens: &tokens,
+        pos: 0,
+    };
+    let expr = parser.expr()?;
+    if !parser.at_end() {
+        return Err(parser.unexpected());
+    }
+    match Assembler::new().eval(line, &expr, 0)? {
+        Value {
+            target: Target::Absolute,
+            constant,
+        } => Ok(constant),
+        _ => Err(AsmError::new(line, 1, "expression is not constant")),
+    }
+}
+
+/// The result of evaluating an expression: `constant` bytes past `target`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value {
+    pub target: Target,
+    pub constant: i64,
+}
+
+impl Value {
+    fn absolute(constant: i64) -> Self {
+        Self {
+            target: Target::Absolute,
+            constant,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Assembler {
+    pub object: Object,
+    /// every label, and the section offset it points at.
+    pub labels: HashMap<String, (SectionKind, u32)>,
+    pub externs: HashSet<String>,
+    /// where every line ended up, for `listing::listing`.
+    pub listing: Vec<ListingEntry>,
+    section: SectionKind,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn eval(&self, line: &SourceLine, expr: &Expr, here: u32) -> Result<Value, AsmError> {
+        let not_relocatable = || {
+            let column = match expr {
+                Expr::Symbol(_, column) => *column,
+                _ => 1,
+            };
+            AsmError::new(line, column, "expression is not relocatable")
+        };
+
+        match expr {
+            Expr::Number(value) => Ok(Value::absolute(*value)),
+            Expr::Here => Ok(Value {
+                target: Target::Section(self.section),
+                constant: here as i64,
+            }),
+            Expr::Symbol(name, column) => {
+                if let Some((section, offset)) = self.labels.get(name) {
+                    return Ok(Value {
+                        target: Target::Section(*section),
+                        constant: *offset as i64,
+                    });
+                }
+                if self.externs.contains(name) {
+                    return Ok(Value {
+                        target: Target::Symbol(name.clone()),
+                        constant: 0,
+                    });
+                }
+                Err(AsmError::new(
+                    line,
+                    *column,
+                    format!("undefined symbol `{}`", name),
+                ))
+            }
+            Expr::Unary(op, expr) => {
+                let value = self.eval(line, expr, here)?;
+                if value.target != Target::Absolute {
+                    return Err(not_relocatable());
+                }
+                Ok(Value::absolute(match op {
+                    UnaryOp::Neg => value.constant.wrapping_neg(),
+                    UnaryOp::Not => !value.constant,
+                }))
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(line, lhs, here)?;
+                let rhs = self.eval(line, rhs, here)?;
+                match (op, &lhs.target, &rhs.target) {
+                    (BinaryOp::Add, _, Target::Absolute) => {
+                        return Ok(Value {
+                            target: lhs.target,
+                            constant: lhs.constant.wrapping_add(rhs.constant),
+                        })
+                    }
+                    (BinaryOp::Add, Target::Absolute, _) => {
+                        return Ok(Value {
+                            target: rhs.target,
+                            constant: lhs.constant.wrapping_add(rhs.constant),
+                        })
+                    }
+                    (BinaryOp::Sub, _, Target::Absolute) => {
+                        return Ok(Value {
+                            target: lhs.target,
+                            constant: lhs.constant.wrapping_sub(rhs.constant),
+                        })
+                    }
+                    // the distance between two labels in the same section is known
+                    (BinaryOp::Sub, Target::Section(a), Target::Section(b)) if a == b => {
+                        return Ok(Value::absolute(lhs.constant.wrapping_sub(rhs.constant)))
+                    }
+                    (_, Target::Absolute, Target::Absolute) => {}
+                    _ => return Err(not_relocatable()),
+                }
+
+                let (lhs, rhs) = (lhs.constant, rhs.constant);
+                Ok(Value::absolute(match op {
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div | BinaryOp::Rem if rhs == 0 => {
+                        return Err(AsmError::new(line, 1, "division by zero in expression"))
+                    }
+                    BinaryOp::Div => lhs.wrapping_div(rhs),
+                    BinaryOp::Rem => lhs.wrapping_rem(rhs),
+                    BinaryOp::And => lhs & rhs,
+                    BinaryOp::Or => lhs | rhs,
+                    BinaryOp::Xor => lhs ^ rhs,
+                    BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+                    BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+                }))
+            }
+        }
+    }
+
+    /// Writes `value` into a `size` byte field, or records a relocation for it
+    /// when its address is only known after linking.
+    fn emit_value(
+        &mut self,
+        line: &SourceLine,
+        column: usize,
+        value: Value,
+        size: usize,
+        pc_relative: bool,
+    ) -> Result<(), AsmError> {
+        let section = self.section;
+        let offset = self.object.section(section).size;
+        let constant = if value.target == Target::Absolute && !pc_relative {
+            value.constant
+        } else {
+            if value.constant < i32::MIN as i64 || value.constant > u32::MAX as i64 {
+                return Err(AsmError::new(
+                    line,
+                    column,
+                    format!("value {} does not fit in 4 byte(s)", value.constant),
+                ));
+            }
+            self.object.relocations.push(Relocation {
+                section,
+                offset,
+                size: size as u8,
+                pc_relative,
+                target: value.target,
+                addend: value.constant as i32,
+            });
+            0
+        };
+
+        let bits = size as u32 * 8;
+        let min = -(1i64 << (bits - 1));
+        let max = (1i64 << bits) - 1;
+        if constant < min || constant > max {
+            return Err(AsmError::new(
+                line,
+                column,
+                format!("value {} does not fit in {} byte(s)", constant, size),
+            ));
+        }
+        self.emit(&constant.to_le_bytes()[..size]);
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        let section = self.object.section_mut(self.section);
+        section.bytes.extend_from_slice(bytes);
+        section.size += bytes.len() as u32;
+    }
+
+    /// `len` bytes of `fill`. `.bss` only grows, it has no contents.
+    fn pad(&mut self, len: u32, fill: u8) {
+        if self.section == SectionKind::Bss {
+            self.object.section_mut(SectionKind::Bss).size += len;
+            return;
+        }
+        self.emit(&vec![fill; len as usize]);
+    }
+
+    /// Evaluates an expression that has to be known in the first pass.
+    fn constant(
+        &self,
+        line: &SourceLine,
+        expr: &Expr,
+        column: usize,
+        here: u32,
+    ) -> Result<i64, AsmError> {
+        match self.eval(line, expr, here)? {
+            Value {
+                target: Target::Absolute,
+                constant,
+            } => Ok(constant),
+            _ => Err(AsmError::new(line, column, "expression must be a constant")),
+        }
+    }
+
+    fn alignment(
+        &self,
+        line: &SourceLine,
+        expr: &Expr,
+        column: usize,
+        here: u32,
+    ) -> Result<u32, AsmError> {
+        let align = self.constant(line, expr, column, here)?;
+        if align <= 0 || align > u32::MAX as i64 || (align as u32).count_ones() != 1 {
+            return Err(AsmError::new(
+                line,
+                column,
+                format!("alignment {} is not a power of two", align),
+            ));
+        }
+        Ok(align as u32)
+    }
+
+    fn reserved(
+        &self,
+        line: &SourceLine,
+        size: usize,
+        expr: &Expr,
+        column: usize,
+        here: u32,
+    ) -> Result<u32, AsmError> {
+        let count = self.constant(line, expr, column, here)?;
+        let len = count.checked_mul(size as i64).map(u32::try_from);
+        match len {
+            Some(Ok(len)) => Ok(len),
+            _ => Err(AsmError::new(
+                line,
+                column,
+                format!("cannot reserve {} item(s)", count),
+            )),
+        }
+    }
+
+    fn encode(&mut self, line: &SourceLine, instruction: &Instruction) -> Result<(), AsmError> {
+        let address = self.object.section(self.section).size;
+        let end = address.wrapping_add(instruction.size() as u32);
+        let (first, second) = instruction.opcode.operand_sizes();
+        self.object.lines.push(LineEntry {
+            section: self.section,
+            offset: address,
+            file: line.file.clone(),
+            line: line.line as u32,
+        });
+        self.emit(&[instruction.opcode as u8]);
+
+        for ((operand, column), size) in instruction.operands.iter().zip([first, second]) {
+            match operand {
+                Operand::Reg(reg) | Operand::Indirect(reg) => self.emit(&[*reg as u8]),
+                Operand::Imm(expr) | Operand::Abs(expr) => {
+                    let value = self.eval(line, expr, address)?;
+                    self.emit_value(line, *column, value, size, false)?;
+                }
+                Operand::Mem(expr) => {
+                    let mut value = self.eval(line, expr, address)?;
+                    if value.target == Target::Section(self.section) {
+                        value = Value::absolute(value.constant - end as i64);
+                        self.emit_value(line, *column, value, size, false)?;
+                    } else {
+                        // relocations are relative to the field, the cpu is relative to the end
+                        let field = self.object.section(self.section).size;
+                        value.constant -= (end - field) as i64;
+                        self.emit_value(line, *column, value, size, true)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn define_label(
+        &mut self,
+        line: &SourceLine,
+        label: &str,
+        column: usize,
+        offset: u32,
+    ) -> Result<(), AsmError> {
+        if Cpu::reg_str_to_index(&label.to_ascii_lowercase()).is_some() {
+            return Err(AsmError::new(
+                line,
+                column,
+                format!("`{}` is a register name", label),
+            ));
+        }
+        if self
+            .labels
+            .insert(label.to_string(), (self.section, offset))
+            .is_some()
+        {
+            return Err(AsmError::new(
+                line,
+                column,
+                format!("duplicate label `{}`", label),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves the current section's offset `len` bytes on in the first pass.
+    fn advance(
+        &self,
+        line: &SourceLine,
+        offsets: &mut [u32; 3],
+        len: usize,
+    ) -> Result<(), AsmError> {
+        let offset = &mut offsets[self.section.index()];
+        *offset = u32::try_from(len)
+            .ok()
+            .and_then(|len| offset.checked_add(len))
+            .ok_or_else(|| {
+                let message = format!("section `{}` exceeds 4 GiB", self.section.name());
+                AsmError::new(line, 1, message)
+            })?;
+        Ok(())
+    }
+
+    /// Assembles `lines` into a relocatable object.
+    pub fn assemble(&mut self, lines: &[SourceLine]) -> Result<Object, AsmError> {
+        // first pass: parse everything and lay out each section.
+        let mut statements = Vec::new();
+        let mut offsets = [0u32; 3];
+        let mut globals = Vec::new();
+        self.section = SectionKind::Text;
+        for line in lines {
+            let mut statement = parse_line(line)?;
+            for (label, column) in statement.labels.iter() {
+                self.define_label(line, label, *column, offsets[self.section.index()])?;
+            }
+            match &statement.item {
+                Some(Item::Instruction(instruction)) => {
+                    if self.section == SectionKind::Bss {
+                        return Err(AsmError::new(
+                            line,
+                            1,
+                            "instructions are not allowed in `.bss`",
+                        ));
+                    }
+                    self.advance(line, &mut offsets, instruction.size())?;
+                }
+                Some(Item::Directive(Directive::Incbin(path, column))) => {
+                    let dir = Path::new(&line.file).parent().unwrap_or(Path::new(""));
+                    let bytes = std::fs::read(dir.join(path)).map_err(|err| {
+                        AsmError::new(line, *column, format!("cannot include `{}`: {}", path, err))
+                    })?;
+                    if self.section == SectionKind::Bss {
+                        return Err(AsmError::new(
+                            line,
+                            1,
+                            "initialized data is not allowed in `.bss`",
+                        ));
+                    }
+                    self.advance(line, &mut offsets, bytes.len())?;
+                    let data = Directive::Data(1, vec![DataItem::Str(bytes)]);
+                    statement.item = Some(Item::Directive(data));
+                }
+                Some(Item::Directive(directive @ Directive::Data(..))) => {
+                    if self.section == SectionKind::Bss {
+                        return Err(AsmError::new(
+                            line,
+                            1,
+                            "initialized data is not allowed in `.bss`",
+                        ));
+                    }
+                    self.advance(line, &mut offsets, directive.data_size())?;
+                }
+                Some(Item::Directive(Directive::Align(expr, column))) => {
+                    let offset = offsets[self.section.index()];
+                    let align = self.alignment(line, expr, *column, offset)?;
+                    let padding = offset.wrapping_neg() % align;
+                    self.advance(line, &mut offsets, padding as usize)?;
+                }
+                Some(Item::Directive(Directive::Reserve(size, expr, column))) => {
+                    let offset = offsets[self.section.index()];
+                    let len = self.reserved(line, *size, expr, *column, offset)?;
+                    self.advance(line, &mut offsets, len as usize)?;
+                }
+                Some(Item::Directive(Directive::Section(kind))) => self.section = *kind,
+                Some(Item::Directive(Directive::Global(names))) => {
+                    globals.extend(names.iter().map(|name| (line, name.clone())));
+                }
+                Some(Item::Directive(Directive::Extern(names))) => {
+                    self.externs
+                        .extend(names.iter().map(|(name, _)| name.clone()));
+                }
+                None => {}
+            }
+            statements.push((line, statement));
+        }
+
+        // second pass: every label is known, encode.
+        self.section = SectionKind::Text;
+        for (line, statement) in statements.iter() {
+            let (section, start) = (self.section, self.object.section(self.section).size);
+            match &statement.item {
+                Some(Item::Instruction(instruction)) => self.encode(line, instruction)?,
+                Some(Item::Directive(Directive::Data(size, items))) => {
+                    for item in items.iter() {
+                        match item {
+                            DataItem::Expr(expr, column) => {
+                                let here = self.object.section(self.section).size;
+                                let value = self.eval(line, expr, here)?;
+                                self.emit_value(line, *column, value, *size, false)?;
+                            }
+                            DataItem::Str(bytes) => {
+                                for byte in bytes.iter() {
+                                    self.emit(&(*byte as u32).to_le_bytes()[..*size]);
+                                }
+                            }
+                        }
+                    }
+                }
+                Some(Item::Directive(Directive::Align(expr, column))) => {
+                    let here = self.object.section(self.section).size;
+                    let align = self.alignment(line, expr, *column, here)?;
+                    let section = self.object.section_mut(self.section);
+                    section.align = section.align.max(align);
+                    // padding in code is safe to run through.
+                    let fill = match self.section {
+                        SectionKind::Text => Opcode::Nop as u8,
+                        _ => 0,
+                    };
+                    self.pad(here.wrapping_neg() % align, fill);
+                }
+                Some(Item::Directive(Directive::Reserve(size, expr, column))) => {
+                    let here = self.object.section(self.section).size;
+                    let len = self.reserved(line, *size, expr, *column, here)?;
+                    self.pad(len, 0);
+                }
+                Some(Item::Directive(Directive::Section(kind))) => self.section = *kind,
+                _ => {}
+            }
+            self.listing.push(ListingEntry {
+                line: (*line).clone(),
+                section,
+                offset: start,
+                size: self.object.section(section).size - start,
+            });
+        }
+
+        for (line, (name, column)) in globals.iter() {
+            if !self.labels.contains_key(name) {
+                return Err(AsmError::new(
+                    line,
+                    *column,
+                    format!("global symbol `{}` is never defined", name),
+                ));
+            }
+        }
+
+        let mut labels = self.labels.iter().collect::<Vec<_>>();
+        labels.sort_by_key(|(name, (section, offset))| (section.index(), *offset, name.as_str()));
+        for (name, (section, offset)) in labels {
+            self.object.symbols.push(Symbol {
+                name: name.clone(),
+                section: Some(*section),
+                value: *offset,
+                global: globals.iter().any(|(_, (global, _))| global == name),
+            });
+        }
+        let mut externs = self.externs.iter().collect::<Vec<&String>>();
+        externs.sort();
+        for name in externs {
+            self.object.symbols.push(Symbol {
+                name: name.clone(),
+                section: None,
+                value: 0,
+                global: true,
+            });
+        }
+
+        Ok(std::mem::take(&mut self.object))
+    }
+}
+
+/// Assembles and links a single source into a flat binary loaded at address 0.
+pub fn assemble(file: &str, source: &str) -> Result<Vec<u8>, AsmError> {
+    let lines = Preprocessor::new().process(&SourceLine::split(file, source))?;
+    let object = Assembler::new().assemble(&lines)?;
+    link_flat(file, object)
+}
+
+pub fn link_flat(file: &str, object: Object) -> Result<Vec<u8>, AsmError> {
+    let input = Input {
+        name: file.to_string(),
+        object,
+    };
+    match linker::link(&[input], 0) {
+        Ok(linked) => Ok(linked.bytes),
+        Err(errors) => Err(AsmError {
+            file: file.to_string(),
+            line: 0,
+            column: 0,
+            message: errors[0].to_string(),
+        }),
+    }
+}
+
+pub fn read_source<T: AsRef<Path> + ?Sized>(path: &T) -> Result<Vec<SourceLine>, AsmError> {
+    let file = path.as_ref().display().to_string();
+    let source = std::fs::read_to_string(path).map_err(|err| AsmError {
+        file: file.clone(),
+        line: 0,
+        column: 0,
+        message: err.to_string(),
+    })?;
+    Ok(SourceLine::split(&file, &source))
+}
+
+pub fn assemble_file<T: AsRef<Path> + ?Sized>(path: &T) -> Result<Object, AsmError> {
+    Assembler::new().assemble(&preprocessor::preprocess_file(path)?)
+}
@@ -0,0 +1,145 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{hardware::Hardware, ram::Ram};
+
+/// Everything guest memory accesses reach: ram, with address ranges mapped
+/// over it to rom or devices.
+#[derive(Debug)]
+pub struct Bus {
+    pub ram: Ram,
+    /// never overlapping, and checked before ram.
+    mappings: Vec<Mapping>,
+}
+
+/// What a mapped address range is backed by.
+pub enum Region {
+    /// read only memory holding these bytes, zeroes past their end. writes fault.
+    Rom(Vec<u8>),
+    /// a device's registers or memory, accessed through `Hardware::read_mapped`
+    /// and `Hardware::write_mapped` with the offset into the range.
+    Device(Rc<RefCell<dyn Hardware>>),
+}
+
+impl fmt::Debug for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Rom(bytes) => write!(f, "Rom({} bytes)", bytes.len()),
+            Region::Device(_) => write!(f, "Device"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Mapping {
+    start: usize,
+    len: usize,
+    region: Region,
+}
+
+impl Mapping {
+    fn contains(&self, address: usize) -> bool {
+        address.wrapping_sub(self.start) < self.len
+    }
+}
+
+/// Why an access went nowhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusError {
+    /// past the end of ram and in no mapping.
+    Unmapped(usize),
+    /// a write to rom.
+    ReadOnly(usize),
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusError::Unmapped(address) => write!(f, "nothing at 0x{:08X}", address),
+            BusError::ReadOnly(address) => write!(f, "0x{:08X} is read only", address),
+        }
+    }
+}
+
+/// A range that can't be mapped because it's empty or overlaps another mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overlap {
+    pub start: usize,
+    pub len: usize,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "can't map {} bytes at 0x{:08X}", self.len, self.start)
+    }
+}
+
+impl std::error::Error for Overlap {}
+
+impl Bus {
+    pub fn new(ram: Ram) -> Bus {
+        Bus {
+            ram,
+            mappings: Vec::new(),
+        }
+    }
+
+    /// Maps `len` bytes at `start` to `region`, hiding any ram there.
+    pub fn map(&mut self, start: usize, len: usize, region: Region) -> Result<(), Overlap> {
+        let overlap = Overlap { start, len };
+        let end = start.checked_add(len).ok_or(overlap)?;
+        let overlaps =
+            |mapping: &Mapping| start < mapping.start + mapping.len && mapping.start < end;
+        if len == 0 || self.mappings.iter().any(overlaps) {
+            return Err(overlap);
+        }
+        self.mappings.push(Mapping { start, len, region });
+        Ok(())
+    }
+
+    fn mapping(&self, address: usize) -> Option<&Mapping> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.contains(address))
+    }
+
+    #[inline(always)]
+    pub fn get(&self, address: usize) -> Result<u8, BusError> {
+        let Some(mapping) = self.mapping(address) else {
+            return self.ram.get(address).ok_or(BusError::Unmapped(address));
+        };
+        let offset = address - mapping.start;
+        Ok(match &mapping.region {
+            Region::Rom(bytes) => bytes.get(offset).copied().unwrap_or(0),
+            Region::Device(device) => device.borrow_mut().read_mapped(offset),
+        })
+    }
+
+    #[inline(always)]
+    pub fn set(&mut self, address: usize, value: u8) -> Result<(), BusError> {
+        let Some(mapping) = self.mapping(address) else {
+            return self
+                .ram
+                .set(address, value)
+                .map_err(|_| BusError::Unmapped(address));
+        };
+        match &mapping.region {
+            Region::Rom(_) => Err(BusError::ReadOnly(address)),
+            Region::Device(device) => {
+                device
+                    .borrow_mut()
+                    .write_mapped(address - mapping.start, value);
+                Ok(())
+            }
+        }
+    }
+
+    /// `len` bytes at `address` as the guest would read them.
+    pub fn read(&self, address: usize, len: usize) -> Result<Vec<u8>, BusError> {
+        (0..len)
+            .map(|i| match address.checked_add(i) {
+                Some(address) => self.get(address),
+                None => Err(BusError::Unmapped(address)),
+            })
+            .collect()
+    }
+}
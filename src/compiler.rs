@@ -0,0 +1,1676 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::abi::FIRST_ARG_OFFSET;
+use crate::assembler::SourceLine;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: error: {}", self.file, self.message);
+        }
+        write!(
+            f,
+            "{}:{}:{}: error: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// A 1-based line and column in the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Number(value) => write!(f, "`{}`", value),
+            Token::Str(_) => write!(f, "string literal"),
+            Token::Punct(punct) => write!(f, "`{}`", punct),
+        }
+    }
+}
+
+// longest first, so `<<` wins over `<`.
+const PUNCTUATION: [&str; 34] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "->", "+", "-", "*", "/", "%", "&", "|", "^",
+    "~", "!", "<", ">", "=", "(", ")", "{", "}", "[", "]", ";", ",", ".", "?", ":", "#",
+];
+
+fn escape(c: char) -> Option<u8> {
+    Some(match c {
+        'n' => b'\n',
+        'r' => b'\r',
+        't' => b'\t',
+        '0' => 0,
+        '\\' => b'\\',
+        '\'' => b'\'',
+        '"' => b'"',
+        _ => return None,
+    })
+}
+
+pub fn tokenize(file: &str, source: &str) -> Result<Vec<(Token, Pos)>, CompileError> {
+    let chars = source.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut line_start) = (0, 1, 0);
+    let error = |pos: Pos, message: String| CompileError {
+        file: file.to_string(),
+        line: pos.line,
+        column: pos.column,
+        message,
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let pos = Pos {
+            line,
+            column: i - line_start + 1,
+        };
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            line_start = i;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if chars[i..].starts_with(&['/', '/']) {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if chars[i..].starts_with(&['/', '*']) {
+            i += 2;
+            while i < chars.len() && !chars[i..].starts_with(&['*', '/']) {
+                if chars[i] == '\n' {
+                    line += 1;
+                    line_start = i + 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(error(pos, "unterminated comment".to_string()));
+            }
+            i += 2;
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), pos));
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let text = chars[start..i]
+                .iter()
+                .filter(|c| **c != '_')
+                .collect::<String>()
+                .to_ascii_lowercase();
+            let parsed = if let Some(hex) = text.strip_prefix("0x") {
+                i64::from_str_radix(hex, 16)
+            } else if let Some(bin) = text.strip_prefix("0b") {
+                i64::from_str_radix(bin, 2)
+            } else {
+                text.parse::<i64>()
+            };
+            match parsed {
+                Ok(value) => tokens.push((Token::Number(value), pos)),
+                Err(_) => return Err(error(pos, format!("invalid number `{}`", text))),
+            }
+            continue;
+        }
+
+        if c == '\'' || c == '"' {
+            let mut bytes = Vec::new();
+            i += 1;
+            while i < chars.len() && chars[i] != c && chars[i] != '\n' {
+                if chars[i] == '\\' {
+                    let escaped = chars.get(i + 1).and_then(|c| escape(*c));
+                    let Some(byte) = escaped else {
+                        return Err(error(pos, "invalid escape sequence".to_string()));
+                    };
+                    bytes.push(byte);
+                    i += 2;
+                } else {
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(chars[i].encode_utf8(&mut buffer).as_bytes());
+                    i += 1;
+                }
+            }
+            if chars.get(i) != Some(&c) {
+                return Err(error(pos, "unterminated literal".to_string()));
+            }
+            i += 1;
+            if c == '"' {
+                tokens.push((Token::Str(bytes), pos));
+            } else if bytes.len() == 1 {
+                tokens.push((Token::Number(bytes[0] as i64), pos));
+            } else {
+                return Err(error(pos, "invalid character literal".to_string()));
+            }
+            continue;
+        }
+
+        let rest = chars[i..].iter().take(2).collect::<String>();
+        match PUNCTUATION.iter().find(|p| rest.starts_with(**p)) {
+            Some(punct) => {
+                i += punct.len();
+                tokens.push((Token::Punct(punct), pos));
+            }
+            None => return Err(error(pos, format!("unexpected character `{}`", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Void,
+    Int { size: u32, signed: bool },
+    Pointer(Box<Type>),
+    Array(Box<Type>, u32),
+    Struct(String),
+}
+
+impl Type {
+    pub const INT32: Type = Type::Int {
+        size: 4,
+        signed: true,
+    };
+
+    fn pointer_to(self) -> Type {
+        Type::Pointer(Box::new(self))
+    }
+
+    /// What the type becomes when used as a value: arrays turn into pointers.
+    fn decay(self) -> Type {
+        match self {
+            Type::Array(element, _) => Type::Pointer(element),
+            ty => ty,
+        }
+    }
+
+    fn is_scalar(&self) -> bool {
+        matches!(self, Type::Int { .. } | Type::Pointer(_))
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Void => write!(f, "void"),
+            Type::Int { size, signed } => {
+                write!(f, "{}int{}", if *signed { "" } else { "u" }, size * 8)
+            }
+            Type::Pointer(ty) => write!(f, "{}*", ty),
+            Type::Array(ty, len) => write!(f, "{}[{}]", ty, len),
+            Type::Struct(name) => write!(f, "struct {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    LogicalNot,
+    Deref,
+    AddressOf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Xor,
+    Or,
+    LogicalAnd,
+    LogicalOr,
+}
+
+const BINARY_OPS: [(&str, BinaryOp, u8); 18] = [
+    ("||", BinaryOp::LogicalOr, 1),
+    ("&&", BinaryOp::LogicalAnd, 2),
+    ("|", BinaryOp::Or, 3),
+    ("^", BinaryOp::Xor, 4),
+    ("&", BinaryOp::And, 5),
+    ("==", BinaryOp::Equal, 6),
+    ("!=", BinaryOp::NotEqual, 6),
+    ("<", BinaryOp::Less, 7),
+    ("<=", BinaryOp::LessEqual, 7),
+    (">", BinaryOp::Greater, 7),
+    (">=", BinaryOp::GreaterEqual, 7),
+    ("<<", BinaryOp::Shl, 8),
+    (">>", BinaryOp::Shr, 8),
+    ("+", BinaryOp::Add, 9),
+    ("-", BinaryOp::Sub, 9),
+    ("*", BinaryOp::Mul, 10),
+    ("/", BinaryOp::Div, 10),
+    ("%", BinaryOp::Rem, 10),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(i64),
+    Str(Vec<u8>),
+    Name(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    /// `.field`, or `->field` when the flag is set.
+    Member(Box<Expr>, String, bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Decl(String, Type, Option<Expr>, Pos),
+    Expr(Expr),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    For(
+        Option<Box<Stmt>>,
+        Option<Expr>,
+        Option<Expr>,
+        Box<Stmt>,
+        Pos,
+    ),
+    Return(Option<Expr>, Pos),
+    Break(Pos),
+    Continue(Pos),
+    Block(Vec<Stmt>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub ret: Type,
+    pub params: Vec<(String, Type)>,
+    /// `None` for a declaration of a function defined elsewhere.
+    pub body: Option<Vec<Stmt>>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub name: String,
+    pub ty: Type,
+    pub init: Option<Expr>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDef {
+    /// name, type and byte offset of each field. fields are packed.
+    pub fields: Vec<(String, Type, u32)>,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Program {
+    pub structs: HashMap<String, StructDef>,
+    pub globals: Vec<Global>,
+    pub functions: Vec<Function>,
+}
+
+struct Parser<'a> {
+    file: &'a str,
+    tokens: &'a [(Token, Pos)],
+    pos: usize,
+    structs: HashMap<String, StructDef>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+    fn peek_at(&self, offset: usize) -> Option<&'a Token> {
+        self.tokens.get(self.pos + offset).map(|(token, _)| token)
+    }
+    fn position(&self) -> Pos {
+        match self.tokens.get(self.pos).or(self.tokens.last()) {
+            Some((_, pos)) => *pos,
+            None => Pos { line: 1, column: 1 },
+        }
+    }
+    fn error_at(&self, pos: Pos, message: impl Into<String>) -> CompileError {
+        CompileError {
+            file: self.file.to_string(),
+            line: pos.line,
+            column: pos.column,
+            message: message.into(),
+        }
+    }
+    fn error(&self, message: impl Into<String>) -> CompileError {
+        self.error_at(self.position(), message)
+    }
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+    fn eat(&mut self, punct: &str) -> bool {
+        if self.is_punct(punct) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+    fn expect(&mut self, punct: &str) -> Result<(), CompileError> {
+        if self.eat(punct) {
+            return Ok(());
+        }
+        Err(match self.peek() {
+            Some(token) => self.error(format!("expected `{}`, found {}", punct, token)),
+            None => self.error(format!("expected `{}` at end of file", punct)),
+        })
+    }
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(name)) if name == keyword)
+    }
+    fn ident(&mut self) -> Result<String, CompileError> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                Ok(name.clone())
+            }
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    fn starts_type(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(Token::Ident(name)) if matches!(
+                name.as_str(),
+                "void" | "int8" | "int16" | "int32" | "uint8" | "uint16" | "uint32" | "struct"
+            )
+        )
+    }
+
+    /// A base type followed by any number of `*`.
+    fn base_type(&mut self) -> Result<Type, CompileError> {
+        let pos = self.position();
+        let int = |size, signed| Type::Int { size, signed };
+        let mut ty = match self.ident()?.as_str() {
+            "void" => Type::Void,
+            "int8" => int(1, true),
+            "int16" => int(2, true),
+            "int32" => int(4, true),
+            "uint8" => int(1, false),
+            "uint16" => int(2, false),
+            "uint32" => int(4, false),
+            "struct" => {
+                let name = self.ident()?;
+                if !self.structs.contains_key(&name) && !self.is_punct("{") {
+                    return Err(self.error_at(pos, format!("unknown struct `{}`", name)));
+                }
+                Type::Struct(name)
+            }
+            name => return Err(self.error_at(pos, format!("unknown type `{}`", name))),
+        };
+        while self.eat("*") {
+            ty = ty.pointer_to();
+        }
+        Ok(ty)
+    }
+
+    /// `name` or `name[N]...` after a type.
+    fn declarator(&mut self, ty: Type) -> Result<(String, Type), CompileError> {
+        let pos = self.position();
+        let name = self.ident()?;
+        let mut dims = Vec::new();
+        while self.eat("[") {
+            dims.push(self.array_length()?);
+        }
+        let ty = dims
+            .into_iter()
+            .rev()
+            .fold(ty, |ty, len| Type::Array(Box::new(ty), len));
+        self.check_size(&ty, pos)?;
+        Ok((name, ty))
+    }
+
+    /// The `N]` of an array type, after its `[`.
+    fn array_length(&mut self) -> Result<u32, CompileError> {
+        let len = match self.peek() {
+            Some(Token::Number(n)) if *n > 0 => u32::try_from(*n).ok(),
+            _ => return Err(self.error("expected a positive array length")),
+        };
+        let len = len.ok_or_else(|| self.error("array length is too large"))?;
+        self.pos += 1;
+        self.expect("]")?;
+        Ok(len)
+    }
+
+    /// Rejects arrays of sized elements that are bigger than the address space.
+    fn check_size(&self, ty: &Type, pos: Pos) -> Result<(), CompileError> {
+        let mut element = ty;
+        while let Type::Array(inner, _) = element {
+            element = inner;
+        }
+        if size_of(&self.structs, element).is_some() && size_of(&self.structs, ty).is_none() {
+            return Err(self.error_at(pos, format!("`{}` is too large", ty)));
+        }
+        Ok(())
+    }
+
+    fn program(&mut self) -> Result<Program, CompileError> {
+        let mut program = Program::default();
+        while self.peek().is_some() {
+            let pos = self.position();
+            if self.is_keyword("struct") && matches!(self.peek_at(2), Some(Token::Punct("{"))) {
+                self.struct_def()?;
+                continue;
+            }
+            let ty = self.base_type()?;
+            let (name, ty) = self.declarator(ty)?;
+            if self.eat("(") {
+                program.functions.push(self.function(name, ty, pos)?);
+                continue;
+            }
+            let init = if self.eat("=") {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            self.expect(";")?;
+            program.globals.push(Global {
+                name,
+                ty,
+                init,
+                pos,
+            });
+        }
+        program.structs = std::mem::take(&mut self.structs);
+        Ok(program)
+    }
+
+    fn struct_def(&mut self) -> Result<(), CompileError> {
+        self.pos += 1;
+        let pos = self.position();
+        let name = self.ident()?;
+        if self.structs.contains_key(&name) {
+            return Err(self.error_at(pos, format!("struct `{}` is already defined", name)));
+        }
+        self.expect("{")?;
+        let mut def = StructDef {
+            fields: Vec::new(),
+            size: 0,
+        };
+        while !self.eat("}") {
+            let field_pos = self.position();
+            let ty = self.base_type()?;
+            let (field, ty) = self.declarator(ty)?;
+            self.expect(";")?;
+            let size = size_of(&self.structs, &ty).ok_or_else(|| {
+                self.error_at(field_pos, format!("field `{}` has incomplete type", field))
+            })?;
+            def.fields.push((field, ty, def.size));
+            def.size = def.size.checked_add(size).ok_or_else(|| {
+                self.error_at(field_pos, format!("struct `{}` is too large", name))
+            })?;
+        }
+        self.expect(";")?;
+        self.structs.insert(name, def);
+        Ok(())
+    }
+
+    fn function(&mut self, name: String, ret: Type, pos: Pos) -> Result<Function, CompileError> {
+        let mut params = Vec::new();
+        if !self.eat(")") {
+            if self.is_keyword("void") && matches!(self.peek_at(1), Some(Token::Punct(")"))) {
+                self.pos += 1;
+            } else {
+                loop {
+                    let ty = self.base_type()?;
+                    params.push(self.declarator(ty)?);
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+            }
+            self.expect(")")?;
+        }
+        let body = if self.eat(";") {
+            None
+        } else {
+            self.expect("{")?;
+            Some(self.block()?)
+        };
+        Ok(Function {
+            name,
+            ret,
+            params,
+            body,
+            pos,
+        })
+    }
+
+    /// Statements up to the closing `}`, which has already been opened.
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return Err(self.error("expected `}` at end of file"));
+            }
+            stmts.push(self.statement()?);
+        }
+        Ok(stmts)
+    }
+
+    fn declaration(&mut self) -> Result<Stmt, CompileError> {
+        let pos = self.position();
+        let ty = self.base_type()?;
+        let (name, ty) = self.declarator(ty)?;
+        let init = if self.eat("=") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        self.expect(";")?;
+        Ok(Stmt::Decl(name, ty, init, pos))
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let pos = self.position();
+        if self.starts_type() {
+            return self.declaration();
+        }
+        if self.eat("{") {
+            return Ok(Stmt::Block(self.block()?));
+        }
+
+        let keyword = match self.peek() {
+            Some(Token::Ident(name)) => name.as_str(),
+            _ => "",
+        };
+        match keyword {
+            "if" => {
+                self.pos += 1;
+                self.expect("(")?;
+                let cond = self.expr()?;
+                self.expect(")")?;
+                let then = Box::new(self.statement()?);
+                let otherwise = if self.is_keyword("else") {
+                    self.pos += 1;
+                    Some(Box::new(self.statement()?))
+                } else {
+                    None
+                };
+                Ok(Stmt::If(cond, then, otherwise))
+            }
+            "while" => {
+                self.pos += 1;
+                self.expect("(")?;
+                let cond = self.expr()?;
+                self.expect(")")?;
+                Ok(Stmt::While(cond, Box::new(self.statement()?)))
+            }
+            "for" => {
+                self.pos += 1;
+                self.expect("(")?;
+                let init = if self.eat(";") {
+                    None
+                } else if self.starts_type() {
+                    Some(Box::new(self.declaration()?))
+                } else {
+                    let expr = self.expr()?;
+                    self.expect(";")?;
+                    Some(Box::new(Stmt::Expr(expr)))
+                };
+                let cond = if self.is_punct(";") {
+                    None
+                } else {
+                    Some(self.expr()?)
+                };
+                self.expect(";")?;
+                let step = if self.is_punct(")") {
+                    None
+                } else {
+                    Some(self.expr()?)
+                };
+                self.expect(")")?;
+                let body = Box::new(self.statement()?);
+                Ok(Stmt::For(init, cond, step, body, pos))
+            }
+            "return" => {
+                self.pos += 1;
+                let value = if self.is_punct(";") {
+                    None
+                } else {
+                    Some(self.expr()?)
+                };
+                self.expect(";")?;
+                Ok(Stmt::Return(value, pos))
+            }
+            "break" | "continue" => {
+                self.pos += 1;
+                self.expect(";")?;
+                Ok(match keyword {
+                    "break" => Stmt::Break(pos),
+                    _ => Stmt::Continue(pos),
+                })
+            }
+            _ => {
+                let expr = self.expr()?;
+                self.expect(";")?;
+                Ok(Stmt::Expr(expr))
+            }
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        let lhs = self.binary(1)?;
+        let pos = self.position();
+        if self.eat("=") {
+            let rhs = self.expr()?;
+            return Ok(Expr {
+                kind: ExprKind::Assign(Box::new(lhs), Box::new(rhs)),
+                pos,
+            });
+        }
+        Ok(lhs)
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, CompileError> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Punct(punct)) = self.peek() {
+            let Some((_, op, precedence)) = BINARY_OPS
+                .iter()
+                .find(|(p, _, precedence)| p == punct && *precedence >= min_precedence)
+            else {
+                break;
+            };
+            let pos = self.position();
+            self.pos += 1;
+            let rhs = self.binary(precedence + 1)?;
+            lhs = Expr {
+                kind: ExprKind::Binary(*op, Box::new(lhs), Box::new(rhs)),
+                pos,
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        let pos = self.position();
+        let op = match self.peek() {
+            Some(Token::Punct("-")) => Some(UnaryOp::Neg),
+            Some(Token::Punct("~")) => Some(UnaryOp::Not),
+            Some(Token::Punct("!")) => Some(UnaryOp::LogicalNot),
+            Some(Token::Punct("*")) => Some(UnaryOp::Deref),
+            Some(Token::Punct("&")) => Some(UnaryOp::AddressOf),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.pos += 1;
+            let operand = self.unary()?;
+            return Ok(Expr {
+                kind: ExprKind::Unary(op, Box::new(operand)),
+                pos,
+            });
+        }
+        if self.is_keyword("sizeof") {
+            self.pos += 1;
+            self.expect("(")?;
+            let ty = self.base_type()?;
+            let mut ty = ty;
+            while self.eat("[") {
+                ty = Type::Array(Box::new(ty), self.array_length()?);
+            }
+            self.check_size(&ty, pos)?;
+            self.expect(")")?;
+            let size = size_of(&self.structs, &ty)
+                .ok_or_else(|| self.error_at(pos, format!("`{}` has no size", ty)))?;
+            return Ok(Expr {
+                kind: ExprKind::Number(size as i64),
+                pos,
+            });
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr, CompileError> {
+        let mut expr = self.primary()?;
+        loop {
+            let pos = self.position();
+            if self.eat("[") {
+                let index = self.expr()?;
+                self.expect("]")?;
+                expr = Expr {
+                    kind: ExprKind::Index(Box::new(expr), Box::new(index)),
+                    pos,
+                };
+            } else if self.is_punct(".") || self.is_punct("->") {
+                let arrow = self.is_punct("->");
+                self.pos += 1;
+                let field = self.ident()?;
+                expr = Expr {
+                    kind: ExprKind::Member(Box::new(expr), field, arrow),
+                    pos,
+                };
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let pos = self.position();
+        let kind = match self.peek() {
+            Some(Token::Number(value)) => ExprKind::Number(*value),
+            Some(Token::Str(bytes)) => ExprKind::Str(bytes.clone()),
+            Some(Token::Ident(name)) => {
+                if matches!(self.peek_at(1), Some(Token::Punct("("))) {
+                    self.pos += 2;
+                    let mut args = Vec::new();
+                    if !self.eat(")") {
+                        loop {
+                            args.push(self.expr()?);
+                            if !self.eat(",") {
+                                break;
+                            }
+                        }
+                        self.expect(")")?;
+                    }
+                    return Ok(Expr {
+                        kind: ExprKind::Call(name.clone(), args),
+                        pos,
+                    });
+                }
+                ExprKind::Name(name.clone())
+            }
+            Some(Token::Punct("(")) => {
+                self.pos += 1;
+                let expr = self.expr()?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            Some(token) => {
+                return Err(self.error(format!("expected an expression, found {}", token)))
+            }
+            None => return Err(self.error("expected an expression at end of file")),
+        };
+        self.pos += 1;
+        Ok(Expr { kind, pos })
+    }
+}
+
+/// Size in bytes, or `None` for `void` and for arrays too big to address.
+fn size_of(structs: &HashMap<String, StructDef>, ty: &Type) -> Option<u32> {
+    match ty {
+        Type::Void => None,
+        Type::Int { size, .. } => Some(*size),
+        Type::Pointer(_) => Some(4),
+        Type::Array(element, len) => size_of(structs, element)?.checked_mul(*len),
+        Type::Struct(name) => structs.get(name).map(|def| def.size),
+    }
+}
+
+pub fn parse(file: &str, source: &str) -> Result<Program, CompileError> {
+    let tokens = tokenize(file, source)?;
+    Parser {
+        file,
+        tokens: &tokens,
+        pos: 0,
+        structs: HashMap::new(),
+    }
+    .program()
+}
+
+/// A local variable or parameter, stored at `bp + offset`.
+#[derive(Debug, Clone)]
+struct Local {
+    ty: Type,
+    offset: i32,
+}
+
+/// Generates assembly where every line remembers the source line it came from,
+/// so debug info points back at the program rather than the assembly. Functions
+/// follow the calling convention in `abi`, and only ever use its scratch registers.
+struct Codegen<'a> {
+    file: &'a str,
+    program: &'a Program,
+    functions: HashMap<String, (Type, Vec<Type>)>,
+    globals: HashMap<String, Type>,
+    scopes: Vec<HashMap<String, Local>>,
+    frame_size: u32,
+    /// (continue, break) labels of the enclosing loops.
+    loops: Vec<(String, String)>,
+    function: String,
+    ret: Type,
+    text: Vec<SourceLine>,
+    data: Vec<SourceLine>,
+    line: usize,
+    labels: usize,
+    strings: usize,
+}
+
+impl<'a> Codegen<'a> {
+    fn error(&self, pos: Pos, message: impl Into<String>) -> CompileError {
+        CompileError {
+            file: self.file.to_string(),
+            line: pos.line,
+            column: pos.column,
+            message: message.into(),
+        }
+    }
+
+    fn emit(&mut self, text: impl Into<String>) {
+        self.text.push(SourceLine {
+            file: self.file.to_string(),
+            line: self.line,
+            text: format!("    {}", text.into()),
+        });
+    }
+
+    fn label(&mut self, label: &str) {
+        self.text.push(SourceLine {
+            file: self.file.to_string(),
+            line: self.line,
+            text: format!("{}:", label),
+        });
+    }
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("{}.L{}", self.function, self.labels)
+    }
+
+    fn size(&self, ty: &Type, pos: Pos) -> Result<u32, CompileError> {
+        size_of(&self.program.structs, ty)
+            .ok_or_else(|| self.error(pos, format!("`{}` has no size", ty)))
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Local> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// Leaves the address of an lvalue in `rax`, returning the type stored there.
+    fn gen_addr(&mut self, expr: &Expr) -> Result<Type, CompileError> {
+        self.line = expr.pos.line;
+        match &expr.kind {
+            ExprKind::Name(name) => {
+                if let Some(local) = self.lookup(name).cloned() {
+                    self.emit("mov rax, bp");
+                    self.emit(format!("add {}", local.offset));
+                    return Ok(local.ty);
+                }
+                match self.globals.get(name).cloned() {
+                    Some(ty) => {
+                        self.emit(format!("mov rax, {}", name));
+                        Ok(ty)
+                    }
+                    None => Err(self.error(expr.pos, format!("undefined variable `{}`", name))),
+                }
+            }
+            ExprKind::Unary(UnaryOp::Deref, operand) => match self.gen_expr(operand)? {
+                Type::Pointer(ty) if *ty == Type::Void => {
+                    Err(self.error(expr.pos, "cannot dereference a `void*`"))
+                }
+                Type::Pointer(ty) => Ok(*ty),
+                ty => Err(self.error(expr.pos, format!("cannot dereference `{}`", ty))),
+            },
+            ExprKind::Index(base, index) => {
+                let element = match self.gen_expr(base)? {
+                    Type::Pointer(ty) if *ty != Type::Void => *ty,
+                    ty => return Err(self.error(expr.pos, format!("cannot index `{}`", ty))),
+                };
+                self.emit("push rax");
+                let index_ty = self.gen_expr(index)?;
+                if !matches!(index_ty, Type::Int { .. }) {
+                    return Err(self.error(index.pos, "array index must be an integer"));
+                }
+                self.line = expr.pos.line;
+                let size = self.size(&element, expr.pos)?;
+                if size != 1 {
+                    self.emit(format!("mul {}", size));
+                }
+                self.emit("mov rbx, rax");
+                self.emit("pop rax");
+                self.emit("add rbx");
+                Ok(element)
+            }
+            ExprKind::Member(base, field, arrow) => {
+                let ty = if *arrow {
+                    match self.gen_expr(base)? {
+                        Type::Pointer(ty) => *ty,
+                        ty => {
+                            return Err(
+                                self.error(expr.pos, format!("`->` on non-pointer `{}`", ty))
+                            )
+                        }
+                    }
+                } else {
+                    self.gen_addr(base)?
+                };
+                let Type::Struct(name) = &ty else {
+                    return Err(self.error(expr.pos, format!("`{}` is not a struct", ty)));
+                };
+                let def = &self.program.structs[name];
+                let Some((_, field_ty, offset)) = def.fields.iter().find(|(f, _, _)| f == field)
+                else {
+                    return Err(self.error(
+                        expr.pos,
+                        format!("struct `{}` has no field `{}`", name, field),
+                    ));
+                };
+                let (field_ty, offset) = (field_ty.clone(), *offset);
+                self.line = expr.pos.line;
+                if offset != 0 {
+                    self.emit(format!("add {}", offset));
+                }
+                Ok(field_ty)
+            }
+            _ => Err(self.error(expr.pos, "expression is not assignable")),
+        }
+    }
+
+    /// Loads a `ty` from the address in `rax` into `rax`.
+    fn load(&mut self, ty: &Type, pos: Pos) -> Result<Type, CompileError> {
+        match ty {
+            Type::Int { size: 1, signed } => {
+                self.emit("mov.b rax, [rax]");
+                if *signed {
+                    self.emit("shl 24");
+                    self.emit("sar 24");
+                }
+            }
+            Type::Int { size: 2, signed } => {
+                self.emit("mov.s rax, [rax]");
+                if *signed {
+                    self.emit("shl 16");
+                    self.emit("sar 16");
+                }
+            }
+            Type::Int { .. } | Type::Pointer(_) => self.emit("mov rax, [rax]"),
+            // the address of an array is its value.
+            Type::Array(..) => {}
+            Type::Struct(_) => {
+                return Err(self.error(pos, "structs can only be used through fields or pointers"))
+            }
+            Type::Void => return Err(self.error(pos, "void value used")),
+        }
+        Ok(ty.clone().decay())
+    }
+
+    /// Stores `rax` as a `ty` to the address in `rbx`.
+    fn store(&mut self, ty: &Type, pos: Pos) -> Result<(), CompileError> {
+        match ty {
+            Type::Int { size: 1, .. } => self.emit("mov.b [rbx], rax"),
+            Type::Int { size: 2, .. } => self.emit("mov.s [rbx], rax"),
+            Type::Int { .. } | Type::Pointer(_) => self.emit("mov [rbx], rax"),
+            _ => return Err(self.error(pos, format!("cannot assign to `{}`", ty))),
+        }
+        Ok(())
+    }
+
+    fn scalar(&self, ty: Type, pos: Pos) -> Result<Type, CompileError> {
+        if ty.is_scalar() {
+            Ok(ty)
+        } else {
+            Err(self.error(pos, format!("expected a number or pointer, found `{}`", ty)))
+        }
+    }
+
+    /// Evaluates `expr` into `rax`, returning its type.
+    fn gen_expr(&mut self, expr: &Expr) -> Result<Type, CompileError> {
+        self.line = expr.pos.line;
+        match &expr.kind {
+            ExprKind::Number(value) => {
+                if *value < i32::MIN as i64 || *value > u32::MAX as i64 {
+                    return Err(self.error(expr.pos, format!("{} does not fit in 32 bits", value)));
+                }
+                self.emit(format!("mov rax, {}", value));
+                Ok(Type::INT32)
+            }
+            ExprKind::Str(bytes) => {
+                self.strings += 1;
+                let label = format!("str.{}", self.strings);
+                let mut bytes = bytes.clone();
+                bytes.push(0);
+                self.data(&label, &bytes);
+                self.emit(format!("mov rax, {}", label));
+                Ok(Type::Int {
+                    size: 1,
+                    signed: false,
+                }
+                .pointer_to())
+            }
+            ExprKind::Name(_) | ExprKind::Index(..) | ExprKind::Member(..) => {
+                let ty = self.gen_addr(expr)?;
+                self.load(&ty, expr.pos)
+            }
+            ExprKind::Unary(UnaryOp::Deref, _) => {
+                let ty = self.gen_addr(expr)?;
+                self.load(&ty, expr.pos)
+            }
+            ExprKind::Unary(UnaryOp::AddressOf, operand) => {
+                Ok(self.gen_addr(operand)?.pointer_to())
+            }
+            ExprKind::Unary(op, operand) => {
+                let ty = self.gen_expr(operand)?;
+                let ty = self.scalar(ty, operand.pos)?;
+                self.line = expr.pos.line;
+                match op {
+                    UnaryOp::Neg => self.emit("neg rax"),
+                    UnaryOp::Not => self.emit("not rax"),
+                    _ => {
+                        self.emit("mov rbx, 0");
+                        self.emit("cmp rbx");
+                        return Ok(Type::INT32);
+                    }
+                }
+                Ok(arithmetic(&ty, &ty))
+            }
+            ExprKind::Assign(lhs, rhs) => {
+                let ty = self.gen_addr(lhs)?;
+                if !ty.is_scalar() {
+                    return Err(self.error(expr.pos, format!("cannot assign to `{}`", ty)));
+                }
+                self.emit("push rax");
+                let value = self.gen_expr(rhs)?;
+                self.scalar(value, rhs.pos)?;
+                self.line = expr.pos.line;
+                self.emit("pop rbx");
+                self.store(&ty, expr.pos)?;
+                Ok(ty)
+            }
+            ExprKind::Binary(op @ (BinaryOp::LogicalAnd | BinaryOp::LogicalOr), lhs, rhs) => {
+                self.logical(expr, lhs, rhs, *op == BinaryOp::LogicalAnd)
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs_ty = self.gen_expr(lhs)?;
+                let lhs_ty = self.scalar(lhs_ty, lhs.pos)?;
+                self.emit("push rax");
+                let rhs_ty = self.gen_expr(rhs)?;
+                let rhs_ty = self.scalar(rhs_ty, rhs.pos)?;
+                self.line = expr.pos.line;
+                self.emit("mov rbx, rax");
+                self.emit("pop rax");
+                self.binary(*op, lhs_ty, rhs_ty, expr.pos)
+            }
+            ExprKind::Call(name, args) => self.call(name, args, expr.pos),
+        }
+    }
+
+    /// `rax op rbx` into `rax`.
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        lhs: Type,
+        rhs: Type,
+        pos: Pos,
+    ) -> Result<Type, CompileError> {
+        let element = |ty: &Type| match ty {
+            Type::Pointer(element) => Some((**element).clone()),
+            _ => None,
+        };
+        match (op, element(&lhs), element(&rhs)) {
+            // pointer arithmetic counts in elements.
+            (BinaryOp::Add | BinaryOp::Sub, Some(element), None) => {
+                let size = self.size(&element, pos)?;
+                if size != 1 {
+                    self.emit("push rax");
+                    self.emit("mov rax, rbx");
+                    self.emit(format!("mul {}", size));
+                    self.emit("mov rbx, rax");
+                    self.emit("pop rax");
+                }
+                self.emit(if op == BinaryOp::Add {
+                    "add rbx"
+                } else {
+                    "sub rbx"
+                });
+                return Ok(lhs);
+            }
+            (BinaryOp::Add, None, Some(element)) => {
+                let size = self.size(&element, pos)?;
+                if size != 1 {
+                    self.emit(format!("mul {}", size));
+                }
+                self.emit("add rbx");
+                return Ok(rhs);
+            }
+            (BinaryOp::Sub, Some(element), Some(_)) => {
+                let size = self.size(&element, pos)?;
+                self.emit("sub rbx");
+                if size != 1 {
+                    self.emit(format!("idiv {}", size));
+                }
+                return Ok(Type::INT32);
+            }
+            _ => {}
+        }
+
+        let signed = match (&lhs, &rhs) {
+            (Type::Int { .. }, Type::Int { .. }) => arithmetic(&lhs, &rhs) == Type::INT32,
+            _ => false,
+        };
+        let jump = |signed_jump: &'static str, unsigned_jump: &'static str| {
+            if signed {
+                signed_jump
+            } else {
+                unsigned_jump
+            }
+        };
+        let compare = match op {
+            BinaryOp::Less => Some(jump("jsl", "jl")),
+            BinaryOp::LessEqual => Some(jump("jsle", "jle")),
+            BinaryOp::Greater => Some(jump("jsg", "jg")),
+            BinaryOp::GreaterEqual => Some(jump("jsge", "jge")),
+            _ => None,
+        };
+        if let Some(jump) = compare {
+            let done = self.new_label();
+            self.emit("mov rcx, 1");
+            self.emit(format!("{} {}", jump, done));
+            self.emit("mov rcx, 0");
+            self.label(&done);
+            self.emit("mov rax, rcx");
+            return Ok(Type::INT32);
+        }
+
+        match op {
+            BinaryOp::Equal => self.emit("cmp rbx"),
+            BinaryOp::NotEqual => {
+                self.emit("cmp rbx");
+                self.emit("xor 1");
+            }
+            BinaryOp::Add => self.emit("add rbx"),
+            BinaryOp::Sub => self.emit("sub rbx"),
+            BinaryOp::Mul => self.emit("mul rbx"),
+            BinaryOp::Div => self.emit(if signed { "idiv rbx" } else { "div rbx" }),
+            BinaryOp::Rem => {
+                self.emit(if signed { "idiv rbx" } else { "div rbx" });
+                // the remainder is left in `rbx`.
+                self.emit("mov rax, rbx");
+            }
+            BinaryOp::And => self.emit("and rbx"),
+            BinaryOp::Or => self.emit("or rbx"),
+            BinaryOp::Xor => self.emit("xor rbx"),
+            BinaryOp::Shl => self.emit("shl rbx"),
+            BinaryOp::Shr => self.emit(if signed { "sar rbx" } else { "shr rbx" }),
+            _ => unreachable!(),
+        }
+        match op {
+            BinaryOp::Equal | BinaryOp::NotEqual => Ok(Type::INT32),
+            _ if element(&lhs).is_some() || element(&rhs).is_some() => {
+                Err(self.error(pos, format!("invalid operands `{}` and `{}`", lhs, rhs)))
+            }
+            _ => Ok(arithmetic(&lhs, &rhs)),
+        }
+    }
+
+    /// `&&` and `||`, which skip the right side once the answer is known.
+    fn logical(
+        &mut self,
+        expr: &Expr,
+        lhs: &Expr,
+        rhs: &Expr,
+        and: bool,
+    ) -> Result<Type, CompileError> {
+        let done = self.new_label();
+        let ty = self.gen_expr(lhs)?;
+        self.scalar(ty, lhs.pos)?;
+        self.line = expr.pos.line;
+        self.emit("mov rbx, 0");
+        self.emit("cmp rbx");
+        // `rax` is now 1 when the left side was false, which decides `&&` but not `||`.
+        self.emit(format!("mov rdx, {}", !and as u32));
+        self.emit(format!("mov rbx, {}", and as u32));
+        self.emit(format!("je {}", done));
+        let ty = self.gen_expr(rhs)?;
+        self.scalar(ty, rhs.pos)?;
+        self.line = expr.pos.line;
+        self.emit("mov rbx, 0");
+        self.emit("cmp rbx");
+        self.emit("xor 1");
+        self.emit("mov rdx, rax");
+        self.label(&done);
+        self.emit("mov rax, rdx");
+        Ok(Type::INT32)
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr], pos: Pos) -> Result<Type, CompileError> {
+        if !self.functions.contains_key(name) {
+            // output goes through the host functions behind `syscall`.
+            match (name, args) {
+                ("print", [arg]) => {
+                    self.gen_expr(arg)?;
+                    self.line = pos.line;
+                    self.emit("syscall 2");
+                    return Ok(Type::Void);
+                }
+                ("print_int", [arg]) => {
+                    self.gen_expr(arg)?;
+                    self.line = pos.line;
+                    self.emit("mov rbx, rax");
+                    self.emit("mov rax, 1");
+                    self.emit("syscall 3");
+                    return Ok(Type::Void);
+                }
+                _ => return Err(self.error(pos, format!("undefined function `{}`", name))),
+            }
+        }
+
+        let (ret, params) = self.functions[name].clone();
+        if params.len() != args.len() {
+            return Err(self.error(
+                pos,
+                format!(
+                    "`{}` takes {} argument(s), found {}",
+                    name,
+                    params.len(),
+                    args.len()
+                ),
+            ));
+        }
+        for arg in args.iter().rev() {
+            let ty = self.gen_expr(arg)?;
+            self.scalar(ty, arg.pos)?;
+            self.emit("push rax");
+        }
+        self.line = pos.line;
+        self.emit(format!("call {}", name));
+        if !args.is_empty() {
+            self.emit("mov rcx, rax");
+            self.emit("mov rax, sp");
+            self.emit(format!("add {}", args.len() * 4));
+            self.emit("mov sp, rax");
+            self.emit("mov rax, rcx");
+        }
+        Ok(ret)
+    }
+
+    /// `je` to `target` when `cond` is false.
+    fn branch_if_false(&mut self, cond: &Expr, target: &str) -> Result<(), CompileError> {
+        let ty = self.gen_expr(cond)?;
+        self.scalar(ty, cond.pos)?;
+        self.emit("mov rbx, 0");
+        self.emit(format!("je {}", target));
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        match stmt {
+            Stmt::Decl(name, ty, init, pos) => {
+                self.line = pos.line;
+                let size = self.size(ty, *pos)?;
+                // locals are addressed as negative offsets from bp.
+                self.frame_size = self
+                    .frame_size
+                    .checked_add(size)
+                    .filter(|size| *size <= i32::MAX as u32)
+                    .ok_or_else(|| {
+                        self.error(*pos, format!("`{}` doesn't fit in the stack frame", name))
+                    })?;
+                let local = Local {
+                    ty: ty.clone(),
+                    offset: -(self.frame_size as i32),
+                };
+                self.scopes.last_mut().unwrap().insert(name.clone(), local);
+                if let Some(init) = init {
+                    let target = Expr {
+                        kind: ExprKind::Name(name.clone()),
+                        pos: *pos,
+                    };
+                    let assign = Expr {
+                        kind: ExprKind::Assign(Box::new(target), Box::new(init.clone())),
+                        pos: *pos,
+                    };
+                    self.gen_expr(&assign)?;
+                }
+            }
+            Stmt::Expr(expr) => {
+                self.gen_expr(expr)?;
+            }
+            Stmt::If(cond, then, otherwise) => {
+                let (else_label, end) = (self.new_label(), self.new_label());
+                self.branch_if_false(cond, &else_label)?;
+                self.statement(then)?;
+                if otherwise.is_some() {
+                    self.emit(format!("jmp {}", end));
+                }
+                self.label(&else_label);
+                if let Some(otherwise) = otherwise {
+                    self.statement(otherwise)?;
+                    self.label(&end);
+                }
+            }
+            Stmt::While(cond, body) => {
+                let (top, end) = (self.new_label(), self.new_label());
+                self.line = cond.pos.line;
+                self.label(&top);
+                self.branch_if_false(cond, &end)?;
+                self.loops.push((top.clone(), end.clone()));
+                self.statement(body)?;
+                self.loops.pop();
+                self.emit(format!("jmp {}", top));
+                self.label(&end);
+            }
+            Stmt::For(init, cond, step, body, pos) => {
+                self.scopes.push(HashMap::new());
+                if let Some(init) = init {
+                    self.statement(init)?;
+                }
+                let (top, next, end) = (self.new_label(), self.new_label(), self.new_label());
+                self.line = pos.line;
+                self.label(&top);
+                if let Some(cond) = cond {
+                    self.branch_if_false(cond, &end)?;
+                }
+                self.loops.push((next.clone(), end.clone()));
+                self.statement(body)?;
+                self.loops.pop();
+                self.line = pos.line;
+                self.label(&next);
+                if let Some(step) = step {
+                    self.gen_expr(step)?;
+                }
+                self.emit(format!("jmp {}", top));
+                self.label(&end);
+                self.scopes.pop();
+            }
+            Stmt::Return(value, pos) => {
+                self.line = pos.line;
+                match (value, &self.ret) {
+                    (Some(value), Type::Void) => {
+                        return Err(self.error(value.pos, "void function returns a value"))
+                    }
+                    (Some(value), _) => {
+                        let ty = self.gen_expr(value)?;
+                        self.scalar(ty, value.pos)?;
+                    }
+                    (None, Type::Void) => {}
+                    (None, _) => return Err(self.error(*pos, "missing return value")),
+                }
+                self.emit(format!("jmp {}.return", self.function));
+            }
+            Stmt::Break(pos) | Stmt::Continue(pos) => {
+                self.line = pos.line;
+                let Some((next, end)) = self.loops.last().cloned() else {
+                    return Err(self.error(*pos, "`break` or `continue` outside of a loop"));
+                };
+                let target = if matches!(stmt, Stmt::Break(_)) {
+                    end
+                } else {
+                    next
+                };
+                self.emit(format!("jmp {}", target));
+            }
+            Stmt::Block(stmts) => {
+                self.scopes.push(HashMap::new());
+                for stmt in stmts {
+                    self.statement(stmt)?;
+                }
+                self.scopes.pop();
+            }
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, function: &Function, body: &[Stmt]) -> Result<(), CompileError> {
+        self.function = function.name.clone();
+        self.ret = function.ret.clone();
+        self.frame_size = 0;
+        let mut params = HashMap::new();
+        for (i, (name, ty)) in function.params.iter().enumerate() {
+            if !ty.is_scalar() {
+                return Err(self.error(
+                    function.pos,
+                    format!("parameter `{}` must be a number or pointer", name),
+                ));
+            }
+            params.insert(
+                name.clone(),
+                Local {
+                    ty: ty.clone(),
+                    offset: FIRST_ARG_OFFSET + 4 * i as i32,
+                },
+            );
+        }
+        self.scopes = vec![params, HashMap::new()];
+
+        // the body goes first so the frame size is known for the prologue.
+        let outer = std::mem::take(&mut self.text);
+        for stmt in body {
+            self.statement(stmt)?;
+        }
+        let body = std::mem::replace(&mut self.text, outer);
+
+        self.line = function.pos.line;
+        self.text.push(SourceLine {
+            file: self.file.to_string(),
+            line: self.line,
+            text: format!("global {}", function.name),
+        });
+        self.label(&function.name);
+        self.emit(format!("enter {}", self.frame_size));
+        self.text.extend(body);
+        self.label(&format!("{}.return", function.name));
+        self.emit("leave");
+        self.emit("ret");
+        Ok(())
+    }
+
+    /// Appends `label: db ...` to the data section.
+    fn data(&mut self, label: &str, bytes: &[u8]) {
+        let line = self.line;
+        let mut push = |text: String| {
+            self.data.push(SourceLine {
+                file: self.file.to_string(),
+                line,
+                text,
+            })
+        };
+        push(format!("{}:", label));
+        for chunk in bytes.chunks(16) {
+            let values = chunk.iter().map(|b| b.to_string()).collect::<Vec<String>>();
+            push(format!("    db {}", values.join(", ")));
+        }
+    }
+
+    fn global(&mut self, global: &Global) -> Result<(), CompileError> {
+        self.line = global.pos.line;
+        let size = self.size(&global.ty, global.pos)?;
+        let mut bytes = vec![0u8; size as usize];
+        if let Some(init) = &global.init {
+            let value = match &init.kind {
+                ExprKind::Number(value) => *value,
+                ExprKind::Unary(UnaryOp::Neg, operand) => match operand.kind {
+                    ExprKind::Number(value) => -value,
+                    _ => return Err(self.error(init.pos, "global initializers must be constant")),
+                },
+                _ => return Err(self.error(init.pos, "global initializers must be constant")),
+            };
+            if !matches!(global.ty, Type::Int { .. }) {
+                return Err(self.error(init.pos, format!("cannot initialize `{}`", global.ty)));
+            }
+            bytes.copy_from_slice(&value.to_le_bytes()[..size as usize]);
+        }
+        self.data.push(SourceLine {
+            file: self.file.to_string(),
+            line: self.line,
+            text: format!("global {}", global.name),
+        });
+        self.data(&global.name, &bytes);
+        Ok(())
+    }
+}
+
+/// The type of arithmetic between two integers: unsigned only when a 32 bit
+/// unsigned is involved, like C's usual conversions.
+fn arithmetic(lhs: &Type, rhs: &Type) -> Type {
+    let unsigned = |ty: &Type| {
+        matches!(
+            ty,
+            Type::Int {
+                size: 4,
+                signed: false
+            } | Type::Pointer(_)
+        )
+    };
+    if unsigned(lhs) || unsigned(rhs) {
+        Type::Int {
+            size: 4,
+            signed: false,
+        }
+    } else {
+        Type::INT32
+    }
+}
+
+/// Compiles a program to assembly. When it defines `main`, a `start` entry point
+/// that calls it and halts is added.
+pub fn compile(file: &str, source: &str) -> Result<Vec<SourceLine>, CompileError> {
+    let program = parse(file, source)?;
+    let mut gen = Codegen {
+        file,
+        program: &program,
+        functions: HashMap::new(),
+        globals: HashMap::new(),
+        scopes: Vec::new(),
+        frame_size: 0,
+        loops: Vec::new(),
+        function: String::new(),
+        ret: Type::Void,
+        text: Vec::new(),
+        data: Vec::new(),
+        line: 1,
+        labels: 0,
+        strings: 0,
+    };
+
+    for function in program.functions.iter() {
+        let signature = (
+            function.ret.clone(),
+            function.params.iter().map(|(_, ty)| ty.clone()).collect(),
+        );
+        match gen.functions.get(&function.name) {
+            Some(existing) if *existing != signature => {
+                return Err(gen.error(
+                    function.pos,
+                    format!("conflicting declarations of `{}`", function.name),
+                ))
+            }
+            _ => gen.functions.insert(function.name.clone(), signature),
+        };
+    }
+    for global in program.globals.iter() {
+        if gen.globals.contains_key(&global.name) || gen.functions.contains_key(&global.name) {
+            return Err(gen.error(global.pos, format!("`{}` is already defined", global.name)));
+        }
+        gen.globals.insert(global.name.clone(), global.ty.clone());
+        gen.global(global)?;
+    }
+
+    let mut defined = Vec::new();
+    for function in program.functions.iter() {
+        let Some(body) = &function.body else {
+            continue;
+        };
+        if defined.contains(&function.name) {
+            return Err(gen.error(
+                function.pos,
+                format!("function `{}` is defined twice", function.name),
+            ));
+        }
+        defined.push(function.name.clone());
+        gen.function(function, body)?;
+    }
+
+    let mut lines = Vec::new();
+    let mut line = |text: String| {
+        lines.push(SourceLine {
+            file: file.to_string(),
+            line: 1,
+            text,
+        })
+    };
+    for name in gen.functions.keys() {
+        if !defined.contains(name) {
+            line(format!("extern {}", name));
+        }
+    }
+    if defined.iter().any(|name| name == "main") {
+        line("global start".to_string());
+        line("start:".to_string());
+        line("    call main".to_string());
+        line("    hlt".to_string());
+    }
+    lines.extend(gen.text);
+    if !gen.data.is_empty() {
+        lines.push(SourceLine {
+            file: file.to_string(),
+            line: 1,
+            text: "section .data".to_string(),
+        });
+        lines.extend(gen.data);
+    }
+    Ok(lines)
+}
+
+pub fn compile_file<T: AsRef<Path> + ?Sized>(path: &T) -> Result<Vec<SourceLine>, CompileError> {
+    let file = path.as_ref().display().to_string();
+    let source = std::fs::read_to_string(path).map_err(|err| CompileError {
+        file: file.clone(),
+        line: 0,
+        column: 0,
+        message: err.to_string(),
+    })?;
+    compile(&file, &source)
+}
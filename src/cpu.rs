@@ -1,6 +1,14 @@
+use crate::abi::AbiChecker;
+use crate::bus::Bus;
+use crate::exception::{Exception, Fault};
 use crate::handlers::*;
-use crate::hardware::Hardware;
-use crate::opcodes::Opcode;
+use crate::hardware::{Hardware, Interrupts};
+use crate::image::Image;
+use crate::library::Loader;
+use crate::mmu::{Access, Mmu, PageFault};
+use crate::opcodes::{Opcode, OperandKind};
+use crate::pic::Pic;
+use crate::ram::{OutOfRange, Ram};
 use core::fmt;
 use std::cell::RefCell;
 use std::fmt::Debug;
@@ -12,7 +20,15 @@ use std::str::Utf8Error;
 
 pub type OpcodeHandler = fn(&mut Cpu);
 
-pub const NUM_REGISTERS: usize = 22;
+pub const NUM_REGISTERS: usize = 25;
+
+/// supervisor stack pointer, what `sp` becomes when user code is interrupted.
+pub const KSP: usize = 24;
+
+/// page fault address, where the last page fault happened.
+pub const PFA: usize = 23;
+/// page table base, the page directory paging goes through. 0 turns paging off.
+pub const PTB: usize = 22;
 
 #[allow(dead_code)]
 pub const IDT: usize = 21;
@@ -25,24 +41,88 @@ pub const BP: usize = 18;
 #[allow(dead_code)]
 pub const FLAGS: usize = 17;
 
-// 100 MiB
-const MEMORY_SIZE: usize = 100 * 1024 * 1024;
+/// registers only the supervisor can name, user code faults on any instruction
+/// that does.
+const CONTROL_REGISTERS: [usize; 5] = [FLAGS, IDT, PTB, PFA, KSP];
 
 #[derive(Debug)]
 pub struct Memory {
-    pub buffer: Vec<u8>,
+    pub bus: Bus,
+    /// the first address the bus couldn't access, reported as a memory fault
+    /// once the instruction finishes. reads there give 0, writes are dropped.
+    pub fault: Option<usize>,
+    /// translates every guest access once paging is on.
+    pub mmu: Mmu,
+    /// the first translation that failed, reported as a page fault like `fault`.
+    pub page_fault: Option<PageFault>,
 }
 
 impl Memory {
-    pub fn new() -> Self {
+    // 100 MiB
+    pub const DEFAULT_SIZE: usize = 100 * 1024 * 1024;
+
+    pub fn new(size: usize) -> Self {
         return Self {
-            buffer: vec![0; MEMORY_SIZE],
+            bus: Bus::new(Ram::new(size)),
+            fault: None,
+            mmu: Mmu::default(),
+            page_fault: None,
+        };
+    }
+    /// The physical address behind `addr`, which is `addr` itself unless paging
+    /// is on.
+    #[inline(always)]
+    fn physical(&mut self, addr: usize, access: Access) -> Option<usize> {
+        if !self.mmu.enabled() {
+            return Some(addr);
+        }
+        match self.mmu.translate(&self.bus.ram, addr as u32, access) {
+            Ok(physical) => Some(physical as usize),
+            Err(fault) => {
+                self.page_fault.get_or_insert(fault);
+                None
+            }
+        }
+    }
+    #[inline(always)]
+    fn read(&mut self, addr: usize, access: Access) -> u8 {
+        let Some(addr) = self.physical(addr, access) else {
+            return 0;
         };
+        match self.bus.get(addr) {
+            Ok(b) => b,
+            Err(_) => {
+                self.fault.get_or_insert(addr);
+                0
+            }
+        }
     }
     #[inline(always)]
     pub fn byte(&mut self, addr: usize) -> u8 {
-        let b = self.buffer[addr];
-        return b;
+        self.read(addr, Access::Read)
+    }
+    /// Reads an instruction byte, which has to be in an executable page.
+    #[inline(always)]
+    pub fn fetch(&mut self, addr: usize) -> u8 {
+        self.read(addr, Access::Execute)
+    }
+    /// Reads an instruction byte without faulting, `None` where `fetch` would.
+    pub fn peek(&mut self, addr: usize) -> Option<u8> {
+        let addr = match self.mmu.enabled() {
+            true => self.mmu.translate(&self.bus.ram, addr as u32, Access::Execute).ok()? as usize,
+            false => addr,
+        };
+        self.bus.get(addr).ok()
+    }
+    pub fn faulted(&self) -> bool {
+        self.fault.is_some() || self.page_fault.is_some()
+    }
+    /// Forgets any fault, returning whether there was one.
+    pub fn clear_faults(&mut self) -> bool {
+        let faulted = self.faulted();
+        self.fault = None;
+        self.page_fault = None;
+        faulted
     }
     #[inline(always)]
     pub fn short(&mut self, addr: usize) -> u16 {
@@ -58,6 +138,12 @@ impl Memory {
     }
     
     pub fn utf8(&mut self, addr: usize) -> Result<String, Utf8Error> {
+        let bytes = self.c_string(addr);
+        std::str::from_utf8(&bytes).map(|s| s.to_string())
+    }
+
+    /// The bytes from `addr` up to, but not including, the first 0.
+    pub fn c_string(&mut self, addr: usize) -> Vec<u8> {
         let mut bytes = Vec::new();
         let mut i = addr;
         loop {
@@ -68,40 +154,120 @@ impl Memory {
             }
             bytes.push(b);
         }
-        std::str::from_utf8(&bytes).map(|s| s.to_string())
+        bytes
     }
 
+    /// Whether all `len` bytes at `addr` are in writable pages, so a write
+    /// straddling two pages happens whole or not at all.
+    #[inline(always)]
+    fn writable(&mut self, addr: usize, len: usize) -> bool {
+        let last = addr.wrapping_add(len - 1);
+        let next_page = last - last % Mmu::PAGE_SIZE as usize;
+        if !self.mmu.enabled() || next_page <= addr {
+            return true;
+        }
+        self.physical(addr, Access::Write).is_some()
+            && self.physical(next_page, Access::Write).is_some()
+    }
     #[inline(always)]
     pub fn set_long(&mut self, addr: usize, value: u32) {
+        if !self.writable(addr, 4) {
+            return;
+        }
         self.set_short(addr, value as u16);
         self.set_short(addr + 2, (value >> 16) as u16);
     }
     #[inline(always)]
     pub fn set_short(&mut self, addr: usize, value: u16) {
+        if !self.writable(addr, 2) {
+            return;
+        }
         self.set_byte(addr, value as u8);
         self.set_byte(addr + 1, (value >> 8) as u8);
     }
     #[inline(always)]
     pub fn set_byte(&mut self, addr: usize, value: u8) {
-        if self.buffer.len() <= addr {
-            panic!("memory access out of bounds {addr}");
+        // an instruction that faulted is undone, so its later writes are too.
+        if self.faulted() {
+            return;
+        }
+        let Some(addr) = self.physical(addr, Access::Write) else {
+            return;
+        };
+        if self.bus.set(addr, value).is_err() {
+            self.fault.get_or_insert(addr);
         }
-        self.buffer[addr] = value;
     }
 }
 
-pub type HardwareInterrupt = Box<dyn Fn(&mut Cpu) + Send + Sync>;
-
 pub struct Cpu {
     pub registers: [u32; NUM_REGISTERS],
     pub memory: Memory,
-    pub hardware: Vec<Rc<RefCell<dyn Hardware>>>
+    pub hardware: Vec<Rc<RefCell<dyn Hardware>>>,
+    /// checks the calling convention on every `call` and `ret` when set.
+    pub abi_checker: Option<AbiChecker>,
+    /// shared libraries loaded through `syscall 4`.
+    pub loader: Loader,
+    /// decides when `int` and device interrupts reach their handlers.
+    pub pic: Pic,
+    /// lines raised by devices, moved to `pic` at the start of every cycle.
+    pub interrupts: Interrupts,
+    /// cycles run so far, every instruction adding its cost from `costs`.
+    pub cycles: u64,
+    /// what each instruction costs by opcode byte, see `default_costs`.
+    pub costs: [u32; 256],
+    /// what to check before running each opcode byte, see `operand_checks`.
+    checks: [OperandChecks; 256],
+    /// collects what syscalls print instead of writing it to stdout when set.
+    pub output: Option<String>,
+    /// raised by the current instruction, delivered once it finishes.
+    pub pending: Option<(Exception, u32)>,
+    /// the fault whose handler is running, until its `iret`.
+    pub handling: Option<Fault>,
+    /// why the cpu halted, when it was a fault it couldn't deliver.
+    pub fault_report: Option<String>,
 }
 
 pub type OpcodeHandlerArray = [OpcodeHandler; 256];
 
+/// `Opcode::cost` for every opcode, and 1 for bytes that aren't one.
+pub fn default_costs() -> [u32; 256] {
+    let mut costs = [1; 256];
+    for opcode in Opcode::all() {
+        costs[opcode as usize] = opcode.cost();
+    }
+    costs
+}
+
+/// What `Cpu::cycle` checks before running an opcode.
+#[derive(Debug, Clone, Copy, Default)]
+struct OperandChecks {
+    /// where operands naming a register start, counted from after the opcode.
+    registers: [Option<u8>; 2],
+    /// only the supervisor may run it, whatever its operands.
+    privileged: bool,
+}
+
+/// `OperandChecks` for every opcode, and nothing to check for bytes that
+/// aren't one, so `cycle` doesn't decode operand kinds on every instruction.
+fn operand_checks() -> [OperandChecks; 256] {
+    let mut checks = [OperandChecks::default(); 256];
+    for opcode in Opcode::all() {
+        let (first, second) = opcode.operand_kinds();
+        let offsets = [(first, 0), (second, opcode.operand_sizes().0)];
+        let register = |(kind, offset)| {
+            matches!(kind, Some(OperandKind::Reg | OperandKind::Indirect)).then_some(offset as u8)
+        };
+        checks[opcode as usize] = OperandChecks {
+            registers: offsets.map(register),
+            privileged: opcode.privileged(),
+        };
+    }
+    checks
+}
+
 pub const fn get_opcode_handlers() -> OpcodeHandlerArray {
-    let mut handlers: OpcodeHandlerArray = [hlt; 256];
+    let mut handlers: OpcodeHandlerArray = [invalid_opcode; 256];
 
     handlers[Opcode::Hlt as usize] = hlt;
     handlers[Opcode::MoveImmRegByte as usize] = move_imm_reg_byte;
@@ -389,6 +555,46 @@ pub const fn get_opcode_handlers() -> OpcodeHandlerArray {
     handlers[Opcode::Syscall as usize] = syscall;
 
     handlers[Opcode::ClearCarry as usize] = clear_carry;
+    handlers[Opcode::DisableInterrupts as usize] = disable_interrupts;
+    handlers[Opcode::EnableInterrupts as usize] = enable_interrupts;
+    handlers[Opcode::InvalidatePage as usize] = invalidate_page;
+    handlers[Opcode::Trap as usize] = trap;
+    handlers[Opcode::ReadCycles as usize] = read_cycles;
+
+    handlers[Opcode::Enter as usize] = enter;
+    handlers[Opcode::Leave as usize] = leave;
+
+    handlers[Opcode::JumpZero as usize] = jump_zero;
+    handlers[Opcode::JumpNotZero as usize] = jump_not_zero;
+    handlers[Opcode::JumpSign as usize] = jump_sign;
+    handlers[Opcode::JumpNotSign as usize] = jump_not_sign;
+    handlers[Opcode::JumpCarry as usize] = jump_carry;
+    handlers[Opcode::JumpNotCarry as usize] = jump_not_carry;
+    handlers[Opcode::JumpOverflow as usize] = jump_overflow;
+    handlers[Opcode::JumpNotOverflow as usize] = jump_not_overflow;
+
+    handlers[Opcode::CompareRegImmByte as usize] = compare_reg_imm_byte;
+    handlers[Opcode::CompareRegImmShort as usize] = compare_reg_imm_short;
+    handlers[Opcode::CompareRegImmLong as usize] = compare_reg_imm_long;
+    handlers[Opcode::CompareRegRegByte as usize] = compare_reg_reg_byte;
+    handlers[Opcode::CompareRegRegShort as usize] = compare_reg_reg_short;
+    handlers[Opcode::CompareRegRegLong as usize] = compare_reg_reg_long;
+    handlers[Opcode::CompareRegAbsByte as usize] = compare_reg_abs_byte;
+    handlers[Opcode::CompareRegAbsShort as usize] = compare_reg_abs_short;
+    handlers[Opcode::CompareRegAbsLong as usize] = compare_reg_abs_long;
+    handlers[Opcode::CompareRegIndirectByte as usize] = compare_reg_indirect_byte;
+    handlers[Opcode::CompareRegIndirectShort as usize] = compare_reg_indirect_short;
+    handlers[Opcode::CompareRegIndirectLong as usize] = compare_reg_indirect_long;
+
+    handlers[Opcode::JumpAbove as usize] = jump_above;
+    handlers[Opcode::JumpAboveEqual as usize] = jump_above_equal;
+    handlers[Opcode::JumpBelow as usize] = jump_below;
+    handlers[Opcode::JumpBelowEqual as usize] = jump_below_equal;
+    handlers[Opcode::JumpGreaterThan as usize] = jump_greater_than;
+    handlers[Opcode::JumpGreaterThanEqual as usize] = jump_greater_than_equal;
+    handlers[Opcode::JumpLessThan as usize] = jump_less_than;
+    handlers[Opcode::JumpLessThanEqual as usize] = jump_less_than_equal;
+
     handlers[Opcode::Nop as usize] = nop;
 
     assert!(handlers.len() == 256);
@@ -462,15 +668,22 @@ impl Cpu {
             19 => "ip",
             20 => "sp",
             21 => "idt",
+            22 => "ptb",
+            23 => "pfa",
+            24 => "ksp",
             _ => {
                 panic!("invalid register {index}");
             }
         }
     }
 
-    pub fn load_program(&mut self, program: &[u8]) {
-        let iter = program.iter().cloned();
-        self.memory.buffer.splice(0..program.len(), iter);
+    pub fn reg_str_to_index(name: &str) -> Option<usize> {
+        (0..NUM_REGISTERS).find(|i| Cpu::reg_index_to_str(i) == name)
+    }
+
+    /// Copies a flat binary to address 0.
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), OutOfRange> {
+        self.memory.bus.ram.write(0, program)
     }
 
     pub fn load_program_from_file<T: AsRef<Path> + ?Sized>(
@@ -480,14 +693,166 @@ impl Cpu {
         let mut file = std::fs::File::open(file_path)?;
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer)?;
-        self.load_program(&buffer);
+        self.load_program(&buffer)?;
+        Ok(())
+    }
+    /// Validates `image`, copies it into memory and points the registers at its
+    /// entry point, stack and interrupt table.
+    pub fn load_image(&mut self, image: &Image) -> std::io::Result<()> {
+        image.validate(self.memory.bus.ram.len())?;
+        for segment in image.segments.iter() {
+            self.memory.bus.ram.write(segment.address as usize, &segment.bytes)?;
+        }
+        let bss = image.bss_address as usize;
+        self.memory.bus.ram.fill(bss, image.bss_size as usize, 0)?;
+
+        let stack_top = image.stack_top(self.memory.bus.ram.len()) as u32;
+        self.registers[IP] = image.entry;
+        self.registers[BP] = stack_top;
+        self.registers[SP] = stack_top;
+        self.registers[IDT] = image.idt_base;
+        let end = image.bss_address.saturating_add(image.bss_size);
+        self.loader.next = self.loader.next.max(end);
         Ok(())
     }
+    pub fn load_image_from_file<T: AsRef<Path> + ?Sized>(
+        &mut self,
+        file_path: &T,
+    ) -> std::io::Result<()> {
+        let image = Image::read_from_file(file_path)?;
+        self.load_image(&image)
+    }
 
     #[inline(always)]
     pub fn cycle(&mut self) {
+        // devices interrupt between instructions, never inside one.
+        for line in self.interrupts.take() {
+            self.pic.raise(line);
+        }
+        if !self.pic.pending.is_empty() {
+            deliver_interrupt(self);
+        }
+
+        if self.registers[PTB] != self.memory.mmu.base {
+            self.memory.mmu.set_base(self.registers[PTB]);
+        }
+        self.memory.mmu.user = self.has_flag(Cpu::USER_FLAG);
+
+        // a faulting instruction leaves the registers as it found them, so its
+        // handler can fix the fault and `iret` to run it again.
+        let registers = self.registers;
+        let ip = self.registers[IP];
         let instruction = self.next_byte();
-        unsafe { (Self::OPCODE_HANDLERS.get_unchecked(instruction as usize))(self) };
+        if !self.memory.faulted() {
+            self.cycles += self.costs[instruction as usize] as u64;
+            match self.check_operands(instruction) {
+                Some((exception, code)) => self.raise(exception, code),
+                None => unsafe { (Self::OPCODE_HANDLERS.get_unchecked(instruction as usize))(self) },
+            }
+        }
+        if let Some(fault) = self.memory.page_fault.take() {
+            self.registers = registers;
+            self.registers[PFA] = fault.address;
+            self.raise(Exception::PageFault, fault.code);
+        }
+        if let Some(address) = self.memory.fault.take() {
+            self.registers = registers;
+            self.raise(Exception::MemoryFault, address as u32);
+        }
+        if let Some((exception, code)) = self.pending.take() {
+            self.deliver(Fault {
+                exception,
+                code,
+                ip,
+            });
+        }
+    }
+
+    /// What running `instruction` would raise before it starts: a bad register
+    /// for any register operand that names none, or a protection fault in user
+    /// mode for a privileged opcode or one naming a control register.
+    fn check_operands(&mut self, instruction: u8) -> Option<(Exception, u32)> {
+        let checks = self.checks[instruction as usize];
+        let user = self.has_flag(Cpu::USER_FLAG);
+        let mut privileged = checks.privileged;
+        for offset in checks.registers.into_iter().flatten() {
+            // an operand that can't be read faults once the instruction reads it.
+            let Some(index) = self.memory.peek(self.ip() + offset as usize) else {
+                break;
+            };
+            if index as usize >= NUM_REGISTERS {
+                return Some((Exception::BadRegister, index as u32));
+            }
+            privileged |= CONTROL_REGISTERS.contains(&(index as usize));
+        }
+        (user && privileged).then_some((Exception::ProtectionFault, instruction as u32))
+    }
+}
+
+// Exceptions
+impl Cpu {
+    /// Raises `exception` for the instruction being run. the first one raised wins.
+    pub fn raise(&mut self, exception: Exception, code: u32) {
+        self.pending.get_or_insert((exception, code));
+    }
+
+    /// Pushes the faulting ip and the code and jumps to the handler in the idt.
+    /// An idt register or entry of 0 means there is no handler. A fault while
+    /// one is being handled, or one with no handler, halts the cpu instead.
+    fn deliver(&mut self, fault: Fault) {
+        if let Some(first) = self.handling {
+            return self.halt_on_fault(format!("double fault: {} while handling {}", fault, first));
+        }
+        // handlers run as the supervisor, which is who reads the idt.
+        self.memory.mmu.user = false;
+        let idt = self.registers[IDT];
+        let entry = idt as usize + fault.exception.vector() as usize * 4;
+        let isr = self.memory.long(entry);
+        if self.memory.clear_faults() || idt == 0 || isr == 0 {
+            return self.halt_on_fault(format!("unhandled {}", fault));
+        }
+
+        // the same frame as an interrupt with the code on top, so `iret` works
+        // once the handler pops it.
+        self.push_frame(fault.ip);
+        self.push_long(fault.code);
+        if self.memory.clear_faults() {
+            return self.halt_on_fault(format!(
+                "double fault: no room on the stack at 0x{:08X} for {}",
+                self.registers[SP], fault
+            ));
+        }
+        self.set_flag(Cpu::INTERRUPT_FLAG, true);
+        self.handling = Some(fault);
+        self.registers[IP] = isr;
+    }
+
+    /// Halts with `report` as the reason, for a fault nothing can handle.
+    pub fn halt_on_fault(&mut self, report: String) {
+        self.fault_report = Some(report);
+        hlt(self);
+    }
+
+    /// Pushes flags and the address `iret` returns to, entering supervisor
+    /// mode. User code is interrupted onto the stack at `ksp`, with its own
+    /// `sp` pushed first for `iret` to restore.
+    pub fn push_frame(&mut self, ip: u32) {
+        let flags = self.flags();
+        if flags & Cpu::USER_FLAG != 0 {
+            let sp = self.registers[SP];
+            self.registers[SP] = self.registers[KSP];
+            self.set_flag(Cpu::USER_FLAG, false);
+            self.memory.mmu.user = false;
+            self.push_long(sp);
+        }
+        self.push_long(flags);
+        self.push_long(ip);
+    }
+
+    fn push_long(&mut self, value: u32) {
+        let sp = self.registers[SP].wrapping_sub(4);
+        self.registers[SP] = sp;
+        self.memory.set_long(sp as usize, value);
     }
 }
 
@@ -495,29 +860,23 @@ impl Cpu {
 impl Cpu {
     #[inline(always)]
     pub fn next_byte(&mut self) -> u8 {
-        let b = unsafe { *self.memory.buffer.get_unchecked(self.ip() as usize) };
+        let b = self.memory.fetch(self.ip());
         self.inc_ip(1);
         b
     }
     
     #[inline(always)]
     pub fn next_short(&mut self) -> u16 {
-        let ip = self.ip() as usize;
-        let low = unsafe { *self.memory.buffer.get_unchecked(ip) as u16 };
-        let high = unsafe { *self.memory.buffer.get_unchecked(ip + 1) as u16 };
-        self.inc_ip(2);
+        let low = self.next_byte() as u16;
+        let high = self.next_byte() as u16;
         (high << 8) | low
     }
     
     #[inline(always)]
     pub fn next_long(&mut self) -> u32 {
-        let ip = self.ip() as usize;
-        let low = unsafe { *self.memory.buffer.get_unchecked(ip) as u32 };
-        let mid = unsafe { *self.memory.buffer.get_unchecked(ip + 1) as u32 };
-        let high = unsafe { *self.memory.buffer.get_unchecked(ip + 2) as u32 };
-        let top = unsafe { *self.memory.buffer.get_unchecked(ip + 3) as u32 };
-        self.inc_ip(4);
-        (top << 24) | (high << 16) | (mid << 8) | low
+        let low = self.next_short() as u32;
+        let high = self.next_short() as u32;
+        (high << 16) | low
     }
     
     #[inline(always)]
@@ -531,30 +890,66 @@ impl Cpu {
 // General, register helpers.
 impl Cpu {
     pub const VGA_BUFFER_LEN: usize = 80 * 25 * 2; // Each character has 2 bytes (char + color)
+    /// where `graphical` maps the gpu's vram on the bus, plain ram otherwise.
     pub const VGA_BUFFER_ADDRESS: usize = 0xA0000;
 
     pub const HALT_FLAG: u32 = 1 << 0;
     pub const INTERRUPT_FLAG: u32 = 1 << 1;
     pub const CARRY_FLAG: u32 = 1 << 2;
+    /// the last alu result was zero, at the width of the instruction.
+    pub const ZERO_FLAG: u32 = 1 << 3;
+    /// the top bit of the last alu result.
+    pub const SIGN_FLAG: u32 = 1 << 4;
+    /// the last alu result doesn't fit when the operands are read as signed.
+    pub const OVERFLOW_FLAG: u32 = 1 << 5;
+    /// interrupts are delivered, set with `sti` and cleared with `cli`. faults
+    /// ignore it. separate from `INTERRUPT_FLAG`, which means a handler is running.
+    pub const INTERRUPT_ENABLE_FLAG: u32 = 1 << 6;
+    /// the cpu runs user code: privileged instructions raise a protection fault
+    /// and only `Mmu::USER` pages can be reached. cleared on entering any
+    /// handler, `iret` restores it.
+    pub const USER_FLAG: u32 = 1 << 7;
+
+    /// the idt entry `trap` enters the supervisor through.
+    pub const TRAP_VECTOR: u8 = 0x80;
 
     pub fn new() -> Self {
+        Cpu::with_memory(Memory::DEFAULT_SIZE)
+    }
+
+    /// A cpu with `size` bytes of memory, which are only allocated once used.
+    pub fn with_memory(size: usize) -> Self {
         let mut cpu = Cpu {
             registers: [0; NUM_REGISTERS],
-            memory: Memory::new(),
+            memory: Memory::new(size),
             hardware: Vec::new(),
+            abi_checker: None,
+            loader: Loader::default(),
+            pic: Pic::default(),
+            interrupts: Interrupts::default(),
+            cycles: 0,
+            costs: default_costs(),
+            checks: operand_checks(),
+            output: None,
+            pending: None,
+            handling: None,
+            fault_report: None,
         };
 
-        // TODO: remove this after testing.
-        // just a default stack so we don't have to set it up constantly.
-        let bp = cpu.memory.buffer.len() - 20;
+        cpu.set_flag(Cpu::INTERRUPT_ENABLE_FLAG, true);
+
+        // a default stack for raw programs, executable images declare their own.
+        let bp = size.saturating_sub(20);
         cpu.registers[BP] = bp as u32;
-        let sp = bp - 1000;
+        let sp = bp.saturating_sub(1000);
         cpu.registers[SP] = sp as u32;
 
+        // blank the text buffer, if memory is big enough to have one.
         for i in (Cpu::VGA_BUFFER_ADDRESS..Cpu::VGA_BUFFER_ADDRESS + Cpu::VGA_BUFFER_LEN).step_by(2)
         {
-            cpu.memory.buffer[i] = b' ';
-            cpu.memory.buffer[i + 1] = 0x0;
+            if cpu.memory.bus.ram.set(i, b' ').is_err() {
+                break;
+            }
         }
 
         return cpu;
@@ -592,16 +987,27 @@ impl Cpu {
     pub fn bp(&self) -> usize {
         unsafe { *self.registers.get_unchecked(BP) as usize }
     }
+    // sp and ip wrap around the address space, whatever they land on faults
+    // when it's accessed rather than panicking here.
     #[inline(always)]
     pub fn dec_sp(&mut self, value: u32) {
-        unsafe { *self.registers.get_unchecked_mut(SP) -= value; }
+        unsafe {
+            let register = self.registers.get_unchecked_mut(SP);
+            *register = register.wrapping_sub(value);
+        }
     }
     #[inline(always)]
     pub fn inc_sp(&mut self, value: u32) {
-        unsafe { *self.registers.get_unchecked_mut(SP) += value; } 
+        unsafe {
+            let register = self.registers.get_unchecked_mut(SP);
+            *register = register.wrapping_add(value);
+        }
     }
     #[inline(always)]
     pub fn inc_ip(&mut self, value: u32) {
-        unsafe { *self.registers.get_unchecked_mut(IP) += value; }
+        unsafe {
+            let register = self.registers.get_unchecked_mut(IP);
+            *register = register.wrapping_add(value);
+        }
     }
 }
@@ -6,10 +6,15 @@ use crossterm::{
     terminal::{self, LeaveAlternateScreen},
 };
 use std::{
+    collections::HashMap,
     io::{stdout, Write}, time::Duration
 };
 
-use crate::{cpu::{Cpu, IP}, opcodes::Opcode};
+use crate::{
+    cpu::{Cpu, IP, NUM_REGISTERS},
+    debuginfo::DebugInfo,
+    disassembler,
+};
 use crossterm::event::{Event, KeyCode};
 
 pub enum DebugState {
@@ -23,8 +28,38 @@ pub enum DebugState {
 
 pub struct Debugger {
     pub file: String,
+    /// load `file` as a flat binary instead of an executable image.
+    pub raw: bool,
+    /// bytes of guest memory, see `Cpu::with_memory`.
+    pub memory: usize,
+    /// the sidecar written by the linker, if there is one.
+    pub info: Option<DebugInfo>,
+    sources: HashMap<String, Vec<String>>,
 }
 impl Debugger {
+    pub fn new(file: &str, raw: bool, memory: usize) -> Self {
+        Self {
+            file: file.to_string(),
+            raw,
+            memory,
+            info: DebugInfo::read_from_file(&DebugInfo::path_for(file)).ok(),
+            sources: HashMap::new(),
+        }
+    }
+
+    /// The text of `line` in `file`, read once and cached.
+    fn source_line(&mut self, file: &str, line: u32) -> String {
+        let lines = self.sources.entry(file.to_string()).or_insert_with(|| {
+            std::fs::read_to_string(file)
+                .map(|source| source.lines().map(|line| line.to_string()).collect())
+                .unwrap_or_default()
+        });
+        lines
+            .get((line as usize).saturating_sub(1))
+            .map(|text| text.trim().to_string())
+            .unwrap_or_default()
+    }
+
     pub fn input(&self, state: &mut DebugState) {
         if event::poll(Duration::from_secs(0)).unwrap() {
             let event = event::read().unwrap();
@@ -47,11 +82,19 @@ impl Debugger {
         }
     }
 
+    fn load(&self, cpu: &mut Cpu) {
+        if self.raw {
+            cpu.load_program_from_file(self.file.as_str()).unwrap();
+        } else {
+            cpu.load_image_from_file(self.file.as_str()).unwrap();
+        }
+    }
+
     pub fn run(&mut self, file: &str) {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::with_memory(self.memory);
         self.file = file.to_string();
 
-        cpu.load_program_from_file(file).unwrap();
+        self.load(&mut cpu);
         let mut stdout = stdout();
         let _raw = terminal::enable_raw_mode().unwrap();
         execute!(stdout, cursor::Hide).unwrap();
@@ -73,8 +116,8 @@ impl Debugger {
                 }
                 DebugState::Reset => {
                     execute!(stdout, terminal::Clear(terminal::ClearType::All)).unwrap();
-                    cpu = Cpu::new();
-                    cpu.load_program_from_file(self.file.as_str()).unwrap();
+                    cpu = Cpu::with_memory(self.memory);
+                    self.load(&mut cpu);
                     state = DebugState::Pause;
                     continue;
                 }
@@ -96,37 +139,70 @@ impl Debugger {
         execute!(stdout, LeaveAlternateScreen).unwrap();
     }
 
-    pub fn display_registers(&self, cpu: &Cpu) {
+    pub fn display_registers(&mut self, cpu: &Cpu) {
         let mut stdout = stdout();
         for (i, register) in cpu.registers.iter().enumerate() {
+            // the instruction pointer is shown as `label+offset` when debug info knows it.
+            let symbol = match (&self.info, i) {
+                (Some(info), IP) => info
+                    .symbolize(*register)
+                    .map(|symbol| format!(" <{}>", symbol))
+                    .unwrap_or_default(),
+                _ => String::new(),
+            };
             execute!(stdout, cursor::MoveTo(0, i as u16)).unwrap();
             queue!(
                 stdout,
                 Print(format!(
-                    "\x1b[1;96m{}\x1b[1;97m: {} (0x{:X}){}\r",
+                    "\x1b[1;96m{}\x1b[1;97m: {} (0x{:X}){}{}\r",
                     Cpu::reg_index_to_str(&i),
                     register,
                     register,
+                    symbol,
                     "           "
                 ))
             )
             .unwrap();
         }
-        let next_i = cpu.memory.buffer[cpu.registers[IP] as usize];
-        let next_i_str = if next_i < Opcode::Nop as u8 {
-            format!("{:?}", Opcode::from(next_i))
-        } else {
-            format!("Invalid Opcode: {}", next_i)
+        let ip = cpu.registers[IP] as usize;
+        let bytes = (ip..ip + 16)
+            .map_while(|address| cpu.memory.bus.get(address).ok())
+            .collect::<Vec<u8>>();
+        let next = disassembler::decode(&bytes, ip as u32);
+        let (next_i_str, function, source) = match &self.info {
+            Some(info) => (
+                next.symbolic_text(|address| info.symbolize(address)),
+                info.function(ip as u32).map(|symbol| symbol.name.clone()),
+                info.line(ip as u32).cloned(),
+            ),
+            None => (next.text(), None, None),
         };
-        queue!(
-            stdout,
-            Print(format!(
-                "\x1b[1;96m{}\x1b[1;97m: {}{}\r",
-                "Next Instruction:",
-                next_i_str,
-                "           "
-            ))
-        ).unwrap();
+        let source = match source {
+            Some(line) => format!(
+                "{}:{}: {}",
+                line.file,
+                line.line,
+                self.source_line(&line.file, line.line)
+            ),
+            None => String::from("?"),
+        };
+
+        let rows = [
+            ("Next Instruction", next_i_str),
+            ("Function", function.unwrap_or_else(|| String::from("?"))),
+            ("Source", source),
+        ];
+        for (i, (label, value)) in rows.iter().enumerate() {
+            execute!(stdout, cursor::MoveTo(0, (NUM_REGISTERS + i) as u16)).unwrap();
+            queue!(
+                stdout,
+                Print(format!(
+                    "\x1b[1;96m{}\x1b[1;97m: {}{}\r",
+                    label, value, "           "
+                ))
+            )
+            .unwrap();
+        }
 
         stdout.flush().unwrap();
     }
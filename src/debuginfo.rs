@@ -0,0 +1,171 @@
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::linker::{Input, Linked};
+use crate::object::{invalid, Reader, Writer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineInfo {
+    pub address: u32,
+    pub file: String,
+    pub line: u32,
+}
+
+/// A label and the addresses up to the next label in its section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolInfo {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+}
+
+impl SymbolInfo {
+    /// Labels with a `.` in them are local to the label before them, like `.loop`
+    /// or the ones made by macro expansion.
+    pub fn is_function(&self) -> bool {
+        !self.name.contains('.')
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        address >= self.address && address - self.address < self.size.max(1)
+    }
+}
+
+/// The sidecar the toolchain writes next to an executable, mapping addresses back
+/// to source lines and symbols.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugInfo {
+    /// sorted by address.
+    pub lines: Vec<LineInfo>,
+    /// sorted by address.
+    pub symbols: Vec<SymbolInfo>,
+}
+
+impl DebugInfo {
+    pub const MAGIC: &'static [u8; 4] = b"B32D";
+    pub const VERSION: u16 = 1;
+
+    /// Where the debug info for the executable at `path` lives.
+    pub fn path_for<T: AsRef<Path> + ?Sized>(path: &T) -> PathBuf {
+        path.as_ref().with_extension("dbg")
+    }
+
+    pub fn from_link(inputs: &[Input], linked: &Linked) -> DebugInfo {
+        let mut info = DebugInfo::default();
+        for (i, input) in inputs.iter().enumerate() {
+            for entry in input.object.lines.iter() {
+                info.lines.push(LineInfo {
+                    address: linked.placement(i, entry.section).address + entry.offset,
+                    file: entry.file.clone(),
+                    line: entry.line,
+                });
+            }
+        }
+        info.lines.sort_by_key(|line| line.address);
+
+        for symbol in linked.symbols.iter() {
+            info.symbols.push(SymbolInfo {
+                name: symbol.name.clone(),
+                address: symbol.address,
+                size: linked.symbol_size(symbol),
+            });
+        }
+        info.symbols.sort_by_key(|symbol| symbol.address);
+        info
+    }
+
+    /// The source line of the instruction at `address`.
+    pub fn line(&self, address: u32) -> Option<&LineInfo> {
+        let i = self
+            .lines
+            .binary_search_by_key(&address, |line| line.address)
+            .ok()?;
+        Some(&self.lines[i])
+    }
+
+    fn covering(&self, address: u32) -> Option<usize> {
+        self.symbols
+            .iter()
+            .rposition(|symbol| symbol.contains(address))
+    }
+
+    /// The function `address` is in: the label covering it, or the closest
+    /// non-local label before that.
+    pub fn function(&self, address: u32) -> Option<&SymbolInfo> {
+        let i = self.covering(address)?;
+        self.symbols[..=i]
+            .iter()
+            .rev()
+            .find(|symbol| symbol.is_function())
+    }
+
+    /// `address` as `label` or `label+0x4`, if some label covers it.
+    pub fn symbolize(&self, address: u32) -> Option<String> {
+        let symbol = &self.symbols[self.covering(address)?];
+        match address - symbol.address {
+            0 => Some(symbol.name.clone()),
+            offset => Some(format!("{}+0x{:X}", symbol.name, offset)),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer::default();
+        out.bytes(DebugInfo::MAGIC);
+        out.short(DebugInfo::VERSION);
+        out.long(self.lines.len() as u32);
+        for line in self.lines.iter() {
+            out.long(line.address);
+            out.string(&line.file);
+            out.long(line.line);
+        }
+        out.long(self.symbols.len() as u32);
+        for symbol in self.symbols.iter() {
+            out.string(&symbol.name);
+            out.long(symbol.address);
+            out.long(symbol.size);
+        }
+        out.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<DebugInfo> {
+        let mut input = Reader { bytes, pos: 0 };
+        if input.take(4)? != DebugInfo::MAGIC {
+            return Err(invalid("not a bit32 debug info file"));
+        }
+        let version = input.short()?;
+        if version != DebugInfo::VERSION {
+            return Err(invalid(&format!(
+                "unsupported debug info version {}",
+                version
+            )));
+        }
+
+        let mut info = DebugInfo::default();
+        for _ in 0..input.long()? {
+            info.lines.push(LineInfo {
+                address: input.long()?,
+                file: input.string()?,
+                line: input.long()?,
+            });
+        }
+        for _ in 0..input.long()? {
+            info.symbols.push(SymbolInfo {
+                name: input.string()?,
+                address: input.long()?,
+                size: input.long()?,
+            });
+        }
+        Ok(info)
+    }
+
+    pub fn write_to_file<T: AsRef<Path> + ?Sized>(&self, path: &T) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn read_from_file<T: AsRef<Path> + ?Sized>(path: &T) -> io::Result<DebugInfo> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        DebugInfo::from_bytes(&buffer)
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt;
+
+use crate::cpu::{Cpu, NUM_REGISTERS};
+use crate::opcodes::{Opcode, OperandKind, Width};
+
+/// One decoded instruction, or a byte that could not be decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoded {
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    /// `None` when the byte at `address` is not a valid opcode, or the
+    /// instruction runs past the end of the input.
+    pub opcode: Option<Opcode>,
+    pub operands: Vec<u32>,
+}
+
+pub fn register_name(index: u32) -> String {
+    if (index as usize) < NUM_REGISTERS {
+        Cpu::reg_index_to_str(&(index as usize)).to_string()
+    } else {
+        format!("r?{}", index)
+    }
+}
+
+impl Decoded {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.opcode.is_some()
+    }
+
+    /// The instruction in assembler syntax, e.g. `mov.l rax, [rel 0x40]`.
+    pub fn text(&self) -> String {
+        self.symbolic_text(|_| None)
+    }
+
+    /// Like `text`, but addresses that `symbolize` knows a name for are shown by name.
+    pub fn symbolic_text(&self, symbolize: impl Fn(u32) -> Option<String>) -> String {
+        let Some(opcode) = self.opcode else {
+            return format!("(bad) 0x{:02X}", self.bytes.first().copied().unwrap_or(0));
+        };
+
+        let mut text = opcode.mnemonic().to_string();
+        match opcode.width() {
+            Some(Width::Byte) => text.push_str(".b"),
+            Some(Width::Short) => text.push_str(".s"),
+            Some(Width::Long) => text.push_str(".l"),
+            None => {}
+        }
+
+        let address = |value: u32| symbolize(value).unwrap_or_else(|| format!("0x{:X}", value));
+        let end = self.address.wrapping_add(self.len() as u32);
+        let (first, second) = opcode.operand_kinds();
+        let (first_size, second_size) = opcode.operand_sizes();
+        let operands = [(first, first_size), (second, second_size)]
+            .iter()
+            .zip(self.operands.iter())
+            .filter_map(|((kind, size), value)| kind.map(|kind| (kind, *size, *value)))
+            .map(|(kind, size, value)| match kind {
+                OperandKind::Reg => register_name(value),
+                OperandKind::Indirect => format!("[{}]", register_name(value)),
+                OperandKind::Abs => format!("[{}]", address(value)),
+                OperandKind::Mem => format!("[rel {}]", address(end.wrapping_add(value))),
+                OperandKind::Imm if size == 4 && opcode.width().is_none() => address(value),
+                OperandKind::Imm if value < 10 => value.to_string(),
+                OperandKind::Imm => format!("0x{:X}", value),
+            })
+            .collect::<Vec<String>>();
+
+        if !operands.is_empty() {
+            text.push(' ');
+            text.push_str(&operands.join(", "));
+        }
+        text
+    }
+}
+
+impl Decoded {
+    /// A listing row: address, raw bytes, then `text`.
+    pub fn row(&self, text: &str) -> String {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(" ");
+        format!("{:08X}  {:<30}{}", self.address, bytes, text)
+    }
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.row(&self.text()))
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, which is located at `address`.
+pub fn decode(bytes: &[u8], address: u32) -> Decoded {
+    let invalid = || Decoded {
+        address,
+        bytes: bytes.iter().take(1).cloned().collect(),
+        opcode: None,
+        operands: Vec::new(),
+    };
+
+    let Some(opcode) = bytes.first().and_then(|b| Opcode::from_byte(*b)) else {
+        return invalid();
+    };
+    let (first, second) = opcode.operand_sizes();
+    let len = 1 + first + second;
+    if bytes.len() < len {
+        return invalid();
+    }
+
+    let mut operands = Vec::new();
+    let mut offset = 1;
+    for size in [first, second] {
+        if size == 0 {
+            continue;
+        }
+        let mut value = [0u8; 4];
+        value[..size].copy_from_slice(&bytes[offset..offset + size]);
+        operands.push(u32::from_le_bytes(value));
+        offset += size;
+    }
+
+    Decoded {
+        address,
+        bytes: bytes[..len].to_vec(),
+        opcode: Some(opcode),
+        operands,
+    }
+}
+
+/// Walks `bytes` from the start, decoding every instruction. Undecodable bytes are
+/// skipped one at a time so the rest of the input still lines up.
+pub fn disassemble(bytes: &[u8], base: u32) -> Vec<Decoded> {
+    let mut decoded = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instruction = decode(&bytes[offset..], base.wrapping_add(offset as u32));
+        offset += instruction.len();
+        decoded.push(instruction);
+    }
+    decoded
+}
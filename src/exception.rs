@@ -0,0 +1,73 @@
+use std::fmt;
+
+/// Faults an instruction can raise instead of panicking the host. Each one is
+/// delivered through the idt like `int`, at its own vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    /// `div` or `idiv` by zero, or a signed quotient that doesn't fit.
+    DivideError,
+    /// a byte that isn't an opcode. the code is the byte.
+    InvalidOpcode,
+    /// a register operand past the last register. the code is the index.
+    BadRegister,
+    /// a privileged instruction, or one naming a control register, in user
+    /// mode. the code is the opcode.
+    ProtectionFault,
+    /// an access through a page that isn't mapped or doesn't allow it. the
+    /// code says why, see `PageFault`, and `pfa` holds the address.
+    PageFault,
+    /// a read or write that reached nothing on the bus, or a write to rom. the
+    /// code is the address.
+    MemoryFault,
+}
+
+impl Exception {
+    /// The idt entry the handler is read from, numbered like x86 where there
+    /// is an equivalent.
+    pub fn vector(self) -> u32 {
+        match self {
+            Exception::DivideError => 0,
+            Exception::InvalidOpcode => 6,
+            Exception::ProtectionFault => 12,
+            Exception::BadRegister => 13,
+            Exception::PageFault => 14,
+            Exception::MemoryFault => 15,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Exception::DivideError => "divide error",
+            Exception::InvalidOpcode => "invalid opcode",
+            Exception::BadRegister => "bad register",
+            Exception::ProtectionFault => "protection fault",
+            Exception::PageFault => "page fault",
+            Exception::MemoryFault => "memory fault",
+        }
+    }
+}
+
+/// An exception and the instruction that raised it.
+///
+/// Handlers are entered with the code on top of the stack, then the address of
+/// the faulting instruction, then flags. They pop the code before `iret`, which
+/// runs the instruction again unless the handler moved the return address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub exception: Exception,
+    pub code: u32,
+    /// address of the faulting instruction.
+    pub ip: u32,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (code 0x{:X}) at 0x{:08X}",
+            self.exception.name(),
+            self.code,
+            self.ip
+        )
+    }
+}
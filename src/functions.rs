@@ -1,5 +1,7 @@
-use crate::cpu::Cpu;
+use crate::cpu::{Cpu, NUM_REGISTERS, SP};
+use crate::exception::Exception;
 use crate::opcodes::Opcode;
+use std::fmt::Write as _;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
 
@@ -13,7 +15,8 @@ pub fn get_or_create_log_file() -> File {
 
 pub fn log(cpu: &mut Cpu) {
     let addr = cpu.registers[0];
-    let string = cpu.memory.utf8(addr as usize).unwrap();
+    let bytes = cpu.memory.c_string(addr as usize);
+    let string = String::from_utf8_lossy(&bytes);
     let mut file = get_or_create_log_file();
     writeln!(file, "{}", string).unwrap();
 }
@@ -36,17 +39,76 @@ pub fn log_memory(cpu: &mut Cpu) {
         .unwrap();
         return;
     }
-    let range = &cpu.memory.buffer[start_idx..end_idx];
-    writeln!(file, "memory at {} to {}, {:?}", start_idx, end_idx, range).unwrap();
+    match cpu.memory.bus.read(start_idx, end_idx - start_idx) {
+        Ok(range) => writeln!(file, "memory at {} to {}, {:?}", start_idx, end_idx, range),
+        Err(err) => writeln!(file, "log memory got invalid range: {}", err),
+    }
+    .unwrap();
+}
+
+/// Prints `line` to stdout, or to `cpu.output` when that collects output.
+fn print_line(cpu: &mut Cpu, line: &str) {
+    match cpu.output.as_mut() {
+        Some(output) => writeln!(output, "{}", line).unwrap(),
+        None => println!("{}", line),
+    }
 }
 
 pub fn print_string(cpu: &mut Cpu) {
     let addr = cpu.registers[0];
-    let string = cpu.memory.utf8(addr as usize).unwrap();
-    println!("{}", string);
+    let bytes = cpu.memory.c_string(addr as usize);
+    let string = String::from_utf8_lossy(&bytes);
+    print_line(cpu, &string);
 }
 
 pub fn print_register(cpu: &mut Cpu) {
     let reg = cpu.registers[0] as usize;
-    println!("{}", cpu.registers[reg]);
+    if reg >= NUM_REGISTERS {
+        return cpu.raise(Exception::BadRegister, reg as u32);
+    }
+    print_line(cpu, &cpu.registers[reg].to_string());
+}
+
+/// Loads the shared library at the path in rax, leaving its address in rax or 0
+/// if it can't be loaded. Libraries are only loaded once.
+pub fn load_library(cpu: &mut Cpu) {
+    let addr = cpu.registers[0];
+    let Ok(path) = cpu.memory.utf8(addr as usize) else {
+        cpu.registers[0] = 0;
+        return;
+    };
+    // keep clear of the stack.
+    let limit = cpu.registers[SP];
+    cpu.registers[0] = cpu
+        .loader
+        .load_file(&mut cpu.memory, limit, &path)
+        .unwrap_or(0);
+}
+
+/// Leaves the address of the loaded symbol named by the string at rax in rax,
+/// or 0 if no loaded library exports it.
+pub fn find_symbol(cpu: &mut Cpu) {
+    let addr = cpu.registers[0];
+    cpu.registers[0] = match cpu.memory.utf8(addr as usize) {
+        Ok(name) => cpu.loader.symbol(&name).unwrap_or(0),
+        Err(_) => 0,
+    };
+}
+
+/// Masks the interrupt line in rax when rbx is nonzero, unmasks it otherwise.
+/// A masked line still queues, it is delivered once unmasked.
+pub fn mask_irq(cpu: &mut Cpu) {
+    let line = cpu.registers[0] as u8;
+    cpu.pic.set_masked(line, cpu.registers[1] != 0);
+}
+
+/// Sets the priority of the interrupt line in rax to rbx.
+pub fn set_irq_priority(cpu: &mut Cpu) {
+    let line = cpu.registers[0] as u8;
+    cpu.pic.set_priority(line, cpu.registers[1] as u8);
+}
+
+/// Acknowledges the interrupt being handled, see `Pic::end_of_interrupt`.
+pub fn end_of_interrupt(cpu: &mut Cpu) {
+    cpu.pic.end_of_interrupt();
 }
@@ -26,6 +26,8 @@ pub struct GPU {
     sender: Sender<[u8; 9]>,
     receiver: Receiver<u8>,
     pub cfg: Option<Config>,
+    /// what the guest wrote to vram through the bus, so it can read it back.
+    vram: Box<[u8; GPU::VRAM_SIZE]>,
     instruction_size: usize,
     instruction_buffer_ptr: usize,
     instruction_buffer: [u8; 9],
@@ -33,6 +35,11 @@ pub struct GPU {
 
 impl GPU {
     const VRAM_SIZE: usize = 0x10000;
+    /// mapped right after vram, writing it draws and reading it says whether
+    /// the window should close.
+    const DRAW_REGISTER: usize = GPU::VRAM_SIZE;
+    /// how much address space to map the gpu at, see `Bus::map`.
+    pub const MAPPED_SIZE: usize = GPU::DRAW_REGISTER + 1;
     const HLT: u8 = 0;
     const DRAW_VGA: u8 = GPU::HLT + 1;
     const WRITE_BYTE: u8 = GPU::DRAW_VGA + 1;
@@ -127,6 +134,7 @@ impl GPU {
             sender: interface_sender,
             receiver: interface_receiver,
             cfg: None,
+            vram: Box::new([0; GPU::VRAM_SIZE]),
             instruction_size: 0,
             instruction_buffer_ptr: 0,
             instruction_buffer: [0; 9],
@@ -134,6 +142,16 @@ impl GPU {
     }
 }
 
+impl GPU {
+    fn send(&self, bytes: &[u8]) {
+        let mut message = [0; 9];
+        message[..bytes.len()].copy_from_slice(bytes);
+        self.sender
+            .send(message)
+            .expect("Could not send message to gpu thread");
+    }
+}
+
 impl Hardware for GPU {
     fn init(&mut self, cfg: Config) {
         self.cfg = Some(cfg);
@@ -168,6 +186,24 @@ impl Hardware for GPU {
         }
     }
 
+    // mapped accesses send whole messages, so they can't garble one half
+    // written through the port.
+    fn read_mapped(&mut self, offset: usize) -> u8 {
+        if offset == GPU::DRAW_REGISTER {
+            self.send(&[GPU::WINDOW_SHOULD_CLOSE]);
+            return self.read();
+        }
+        self.vram[offset]
+    }
+
+    fn write_mapped(&mut self, offset: usize, value: u8) {
+        if offset == GPU::DRAW_REGISTER {
+            return self.send(&[GPU::DRAW_VGA]);
+        }
+        self.vram[offset] = value;
+        self.send(&[GPU::WRITE_BYTE, offset as u8, (offset >> 8) as u8, value]);
+    }
+
     fn deinit(&mut self) {
         self.sender
             .send([0; 9])
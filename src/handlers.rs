@@ -1,8 +1,10 @@
-use std::ops::{Neg, Not, Shl, Shr};
+use std::ops::Not;
 
 use crate::{
-    cpu::{Cpu, FLAGS, IDT, IP},
+    cpu::{Cpu, BP, FLAGS, IDT, IP, SP},
+    exception::Exception,
     functions,
+    opcodes::Opcode,
 };
 
 // pub fn hlt(cpu: &mut Cpu);
@@ -405,28 +407,85 @@ pub fn move_indirect_indirect_long(cpu: &mut Cpu) {
     cpu.memory.set_long(dst_adr, src_val);
 }
 
+// flags for alu results. every result is passed in the low `bits` of a u32;
+// zero and sign only look at those, so sign extended results are fine.
+// carry is unsigned overflow, overflow is signed overflow.
+
+fn set_zero_sign(cpu: &mut Cpu, result: u32, bits: u32) {
+    let mask = u32::MAX >> (32 - bits);
+    cpu.set_flag(Cpu::ZERO_FLAG, result & mask == 0);
+    cpu.set_flag(Cpu::SIGN_FLAG, (result >> (bits - 1)) & 1 == 1);
+}
+/// Zero and sign from `result`, carry and overflow cleared.
+fn set_result_flags(cpu: &mut Cpu, result: u32, bits: u32) {
+    set_zero_sign(cpu, result, bits);
+    cpu.set_flag(Cpu::CARRY_FLAG, false);
+    cpu.set_flag(Cpu::OVERFLOW_FLAG, false);
+}
+fn set_add_flags(cpu: &mut Cpu, lhs: u32, rhs: u32, result: u32, carry: bool, bits: u32) {
+    set_zero_sign(cpu, result, bits);
+    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    // both operands have the same sign and the result has the other one.
+    let overflow = (lhs ^ result) & (rhs ^ result);
+    cpu.set_flag(Cpu::OVERFLOW_FLAG, (overflow >> (bits - 1)) & 1 == 1);
+}
+/// Like `set_add_flags`, with `borrow` as the carry.
+fn set_sub_flags(cpu: &mut Cpu, lhs: u32, rhs: u32, result: u32, borrow: bool, bits: u32) {
+    set_zero_sign(cpu, result, bits);
+    cpu.set_flag(Cpu::CARRY_FLAG, borrow);
+    // the operands have different signs and the result doesn't have the sign of lhs.
+    let overflow = (lhs ^ rhs) & (lhs ^ result);
+    cpu.set_flag(Cpu::OVERFLOW_FLAG, (overflow >> (bits - 1)) & 1 == 1);
+}
+/// Carry and overflow both mean the full product didn't fit.
+fn set_mul_flags(cpu: &mut Cpu, result: u32, overflow: bool, bits: u32) {
+    set_zero_sign(cpu, result, bits);
+    cpu.set_flag(Cpu::CARRY_FLAG, overflow);
+    cpu.set_flag(Cpu::OVERFLOW_FLAG, overflow);
+}
+/// Carry is the last bit shifted or rotated out, overflow is set when the
+/// sign bit changed.
+fn set_shift_flags(cpu: &mut Cpu, value: u32, result: u32, carry: bool, bits: u32) {
+    set_zero_sign(cpu, result, bits);
+    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    cpu.set_flag(Cpu::OVERFLOW_FLAG, ((value ^ result) >> (bits - 1)) & 1 == 1);
+}
+/// inc and dec leave carry alone, so they can count loops in multi word math.
+fn set_inc_flags(cpu: &mut Cpu, result: u32, overflow: bool, bits: u32) {
+    set_zero_sign(cpu, result, bits);
+    cpu.set_flag(Cpu::OVERFLOW_FLAG, overflow);
+}
+fn shl_carry(value: u32, count: u32, bits: u32) -> bool {
+    count != 0 && count <= bits && (value >> (bits - count)) & 1 == 1
+}
+fn shr_carry(value: u32, count: u32) -> bool {
+    count != 0 && value.checked_shr(count - 1).is_some_and(|value| value & 1 == 1)
+}
+/// `value` sign extended, so shifting past the width keeps giving the sign.
+fn sar_carry(value: i32, count: u32) -> bool {
+    count != 0 && (value >> (count - 1).min(31)) & 1 == 1
+}
+
 pub fn add_byte_imm(cpu: &mut Cpu) {
-    let lhs = (unsafe { cpu.registers.get_unchecked(0) } & 0xFF) as u8;
+    let lhs = (cpu.registers[0] & 0xFF) as u8;
     let rhs = cpu.next_byte();
     let (result, carry) = lhs.overflowing_add(rhs);
-    unsafe {
-        *cpu.registers.get_unchecked_mut(0) = result as u32;
-    }
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    cpu.registers[0] = result as u32;
+    set_add_flags(cpu, lhs as u32, rhs as u32, result as u32, carry, 8);
 }
 pub fn add_short_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let rhs = cpu.next_short();
     let (result, carry) = lhs.overflowing_add(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_add_flags(cpu, lhs as u32, rhs as u32, result as u32, carry, 16);
 }
 pub fn add_long_imm(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let rhs = cpu.next_long();
     let (result, carry) = lhs.overflowing_add(rhs);
     cpu.registers[0] = result;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_add_flags(cpu, lhs, rhs, result, carry, 32);
 }
 
 pub fn add_byte_reg(cpu: &mut Cpu) {
@@ -435,7 +494,7 @@ pub fn add_byte_reg(cpu: &mut Cpu) {
     let rhs = (cpu.registers[index] & 0xFF) as u8;
     let (result, carry) = lhs.overflowing_add(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_add_flags(cpu, lhs as u32, rhs as u32, result as u32, carry, 8);
 }
 pub fn add_short_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
@@ -443,7 +502,7 @@ pub fn add_short_reg(cpu: &mut Cpu) {
     let rhs = (cpu.registers[index] & 0xFFFF) as u16;
     let (result, carry) = lhs.overflowing_add(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_add_flags(cpu, lhs as u32, rhs as u32, result as u32, carry, 16);
 }
 pub fn add_long_reg(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
@@ -451,7 +510,7 @@ pub fn add_long_reg(cpu: &mut Cpu) {
     let rhs = cpu.registers[index];
     let (result, carry) = lhs.overflowing_add(rhs);
     cpu.registers[0] = result;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_add_flags(cpu, lhs, rhs, result, carry, 32);
 }
 
 pub fn add_carry_byte_imm(cpu: &mut Cpu) {
@@ -461,7 +520,7 @@ pub fn add_carry_byte_imm(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_add(carry);
     let (result, carry1) = result.overflowing_add(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_add_flags(cpu, lhs as u32, rhs as u32, result as u32, carry0 | carry1, 8);
 }
 pub fn add_carry_short_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
@@ -470,7 +529,7 @@ pub fn add_carry_short_imm(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_add(carry);
     let (result, carry1) = result.overflowing_add(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_add_flags(cpu, lhs as u32, rhs as u32, result as u32, carry0 | carry1, 16);
 }
 pub fn add_carry_long_imm(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
@@ -479,7 +538,7 @@ pub fn add_carry_long_imm(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_add(carry);
     let (result, carry1) = result.overflowing_add(rhs);
     cpu.registers[0] = result;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_add_flags(cpu, lhs, rhs, result, carry0 | carry1, 32);
 }
 
 pub fn add_carry_byte_reg(cpu: &mut Cpu) {
@@ -490,7 +549,7 @@ pub fn add_carry_byte_reg(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_add(carry);
     let (result, carry1) = result.overflowing_add(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_add_flags(cpu, lhs as u32, rhs as u32, result as u32, carry0 | carry1, 8);
 }
 pub fn add_carry_short_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
@@ -500,7 +559,7 @@ pub fn add_carry_short_reg(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_add(carry);
     let (result, carry1) = result.overflowing_add(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_add_flags(cpu, lhs as u32, rhs as u32, result as u32, carry0 | carry1, 16);
 }
 pub fn add_carry_long_reg(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
@@ -510,7 +569,7 @@ pub fn add_carry_long_reg(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_add(carry);
     let (result, carry1) = result.overflowing_add(rhs);
     cpu.registers[0] = result;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_add_flags(cpu, lhs, rhs, result, carry0 | carry1, 32);
 }
 
 pub fn sub_byte_imm(cpu: &mut Cpu) {
@@ -518,21 +577,21 @@ pub fn sub_byte_imm(cpu: &mut Cpu) {
     let rhs = cpu.next_byte();
     let (result, carry) = lhs.overflowing_sub(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, carry, 8);
 }
 pub fn sub_short_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let rhs = cpu.next_short();
     let (result, carry) = lhs.overflowing_sub(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, carry, 16);
 }
 pub fn sub_long_imm(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let rhs = cpu.next_long();
     let (result, carry) = lhs.overflowing_sub(rhs);
     cpu.registers[0] = result;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_sub_flags(cpu, lhs, rhs, result, carry, 32);
 }
 
 pub fn sub_byte_reg(cpu: &mut Cpu) {
@@ -541,7 +600,7 @@ pub fn sub_byte_reg(cpu: &mut Cpu) {
     let rhs = (cpu.registers[index] & 0xFF) as u8;
     let (result, carry) = lhs.overflowing_sub(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, carry, 8);
 }
 pub fn sub_short_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
@@ -549,7 +608,7 @@ pub fn sub_short_reg(cpu: &mut Cpu) {
     let rhs = (cpu.registers[index] & 0xFFFF) as u16;
     let (result, carry) = lhs.overflowing_sub(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, carry, 16);
 }
 pub fn sub_long_reg(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
@@ -557,7 +616,7 @@ pub fn sub_long_reg(cpu: &mut Cpu) {
     let rhs = cpu.registers[index];
     let (result, carry) = lhs.overflowing_sub(rhs);
     cpu.registers[0] = result;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry);
+    set_sub_flags(cpu, lhs, rhs, result, carry, 32);
 }
 
 pub fn sub_borrow_byte_imm(cpu: &mut Cpu) {
@@ -567,7 +626,7 @@ pub fn sub_borrow_byte_imm(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_sub(carry);
     let (result, carry1) = result.overflowing_sub(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, carry0 | carry1, 8);
 }
 pub fn sub_borrow_short_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
@@ -576,7 +635,7 @@ pub fn sub_borrow_short_imm(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_sub(carry);
     let (result, carry1) = result.overflowing_sub(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, carry0 | carry1, 16);
 }
 pub fn sub_borrow_long_imm(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
@@ -585,7 +644,7 @@ pub fn sub_borrow_long_imm(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_sub(carry);
     let (result, carry1) = result.overflowing_sub(rhs);
     cpu.registers[0] = result;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_sub_flags(cpu, lhs, rhs, result, carry0 | carry1, 32);
 }
 
 pub fn sub_borrow_byte_reg(cpu: &mut Cpu) {
@@ -596,7 +655,7 @@ pub fn sub_borrow_byte_reg(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_sub(carry);
     let (result, carry1) = result.overflowing_sub(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, carry0 | carry1, 8);
 }
 pub fn sub_borrow_short_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
@@ -606,7 +665,7 @@ pub fn sub_borrow_short_reg(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_sub(carry);
     let (result, carry1) = result.overflowing_sub(rhs);
     cpu.registers[0] = result as u32;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, carry0 | carry1, 16);
 }
 pub fn sub_borrow_long_reg(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
@@ -616,246 +675,303 @@ pub fn sub_borrow_long_reg(cpu: &mut Cpu) {
     let (result, carry0) = lhs.overflowing_sub(carry);
     let (result, carry1) = result.overflowing_sub(rhs);
     cpu.registers[0] = result;
-    cpu.set_flag(Cpu::CARRY_FLAG, carry0 | carry1);
+    set_sub_flags(cpu, lhs, rhs, result, carry0 | carry1, 32);
 }
 
 pub fn mul_byte_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFF) as u8;
     let rhs = cpu.next_byte();
-    let result = lhs.wrapping_mul(rhs);
-    cpu.registers[0] = (result & 0xFF) as u32;
+    let (result, overflow) = lhs.overflowing_mul(rhs);
+    cpu.registers[0] = result as u32;
+    set_mul_flags(cpu, result as u32, overflow, 8);
 }
 pub fn mul_short_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let rhs = cpu.next_short();
-    let result = lhs.wrapping_mul(rhs);
-    cpu.registers[0] = (result & 0xFFFF) as u32;
+    let (result, overflow) = lhs.overflowing_mul(rhs);
+    cpu.registers[0] = result as u32;
+    set_mul_flags(cpu, result as u32, overflow, 16);
 }
 pub fn mul_long_imm(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let rhs = cpu.next_long();
-    let result = lhs.wrapping_mul(rhs);
+    let (result, overflow) = lhs.overflowing_mul(rhs);
     cpu.registers[0] = result;
+    set_mul_flags(cpu, result, overflow, 32);
 }
 
 pub fn mul_byte_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFF) as u8;
     let index = cpu.next_byte() as usize;
     let rhs = (cpu.registers[index] & 0xFF) as u8;
-    let result = lhs.wrapping_mul(rhs);
-    cpu.registers[0] = (result & 0xFF) as u32;
+    let (result, overflow) = lhs.overflowing_mul(rhs);
+    cpu.registers[0] = result as u32;
+    set_mul_flags(cpu, result as u32, overflow, 8);
 }
 pub fn mul_short_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let index = cpu.next_byte() as usize;
     let rhs = (cpu.registers[index] & 0xFFFF) as u16;
-    let result = lhs.wrapping_mul(rhs);
-    cpu.registers[0] = (result & 0xFFFF) as u32;
+    let (result, overflow) = lhs.overflowing_mul(rhs);
+    cpu.registers[0] = result as u32;
+    set_mul_flags(cpu, result as u32, overflow, 16);
 }
 pub fn mul_long_reg(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let index = cpu.next_byte() as usize;
     let rhs = cpu.registers[index];
-    let result = lhs.wrapping_mul(rhs);
+    let (result, overflow) = lhs.overflowing_mul(rhs);
     cpu.registers[0] = result;
+    set_mul_flags(cpu, result, overflow, 32);
 }
 
 pub fn div_byte_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFF) as u8;
     let rhs = cpu.next_byte();
-    let quotient = lhs / rhs;
-    let remainder = lhs % rhs;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient as u32;
     cpu.registers[1] = remainder as u32;
+    set_result_flags(cpu, quotient as u32, 8);
 }
 pub fn div_short_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let rhs = cpu.next_short();
-    let quotient = lhs / rhs;
-    let remainder = lhs % rhs;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient as u32;
     cpu.registers[1] = remainder as u32;
+    set_result_flags(cpu, quotient as u32, 16);
 }
 pub fn div_long_imm(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let rhs = cpu.next_long();
-    let quotient = lhs / rhs;
-    let remainder = lhs % rhs;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient;
     cpu.registers[1] = remainder;
+    set_result_flags(cpu, quotient, 32);
 }
 
 pub fn div_byte_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFF) as u8;
     let index = cpu.next_byte() as usize;
     let rhs = (cpu.registers[index] & 0xFF) as u8;
-    let quotient = lhs / rhs;
-    let remainder = lhs % rhs;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient as u32;
     cpu.registers[1] = remainder as u32;
+    set_result_flags(cpu, quotient as u32, 8);
 }
 pub fn div_short_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let index = cpu.next_byte() as usize;
     let rhs = (cpu.registers[index] & 0xFFFF) as u16;
-    let quotient = lhs / rhs;
-    let remainder = lhs % rhs;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient as u32;
     cpu.registers[1] = remainder as u32;
+    set_result_flags(cpu, quotient as u32, 16);
 }
 pub fn div_long_reg(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let index = cpu.next_byte() as usize;
     let rhs = cpu.registers[index];
-    let quotient = lhs / rhs;
-    let remainder = lhs % rhs;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient;
     cpu.registers[1] = remainder;
+    set_result_flags(cpu, quotient, 32);
 }
 
 pub fn signed_mul_byte_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFF) as u8;
     let rhs = cpu.next_byte() as i8;
-    let result = (lhs as i8).wrapping_mul(rhs);
+    let (result, overflow) = (lhs as i8).overflowing_mul(rhs);
     cpu.registers[0] = result as u32;
+    set_mul_flags(cpu, result as u32, overflow, 8);
 }
 pub fn signed_mul_short_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let rhs = cpu.next_short() as i16;
-    let result = (lhs as i16).wrapping_mul(rhs);
+    let (result, overflow) = (lhs as i16).overflowing_mul(rhs);
     cpu.registers[0] = result as u32;
+    set_mul_flags(cpu, result as u32, overflow, 16);
 }
 pub fn signed_mul_long_imm(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let rhs = cpu.next_long() as i32;
-    let result = (lhs as i32).wrapping_mul(rhs);
+    let (result, overflow) = (lhs as i32).overflowing_mul(rhs);
     cpu.registers[0] = result as u32;
+    set_mul_flags(cpu, result as u32, overflow, 32);
 }
 
 pub fn signed_mul_byte_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFF) as u8;
     let index = cpu.next_byte() as usize;
     let rhs = cpu.registers[index] as i8;
-    let result = (lhs as i8).wrapping_mul(rhs);
+    let (result, overflow) = (lhs as i8).overflowing_mul(rhs);
     cpu.registers[0] = result as u32;
+    set_mul_flags(cpu, result as u32, overflow, 8);
 }
 pub fn signed_mul_short_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let index = cpu.next_byte() as usize;
     let rhs = (cpu.registers[index] & 0xFFFF) as i16;
-    let result = (lhs as i16).wrapping_mul(rhs);
+    let (result, overflow) = (lhs as i16).overflowing_mul(rhs);
     cpu.registers[0] = result as u32;
+    set_mul_flags(cpu, result as u32, overflow, 16);
 }
 pub fn signed_mul_long_reg(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let index = cpu.next_byte() as usize;
     let rhs = cpu.registers[index] as i32;
-    let result = (lhs as i32).wrapping_mul(rhs);
+    let (result, overflow) = (lhs as i32).overflowing_mul(rhs);
     cpu.registers[0] = result as u32;
+    set_mul_flags(cpu, result as u32, overflow, 32);
 }
 
 pub fn signed_div_byte_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFF) as u8;
     let rhs = cpu.next_byte() as i8;
-    let quotient = (lhs as i8) / rhs;
-    let remainder = (lhs as i8) % rhs;
+    let lhs = lhs as i8;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient as u32;
     cpu.registers[1] = remainder as u32;
+    set_result_flags(cpu, quotient as u32, 8);
 }
 pub fn signed_div_short_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let rhs = cpu.next_short() as i16;
-    let quotient = (lhs as i16) / rhs;
-    let remainder = (lhs as i16) % rhs;
+    let lhs = lhs as i16;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient as u32;
     cpu.registers[1] = remainder as u32;
+    set_result_flags(cpu, quotient as u32, 16);
 }
 pub fn signed_div_long_imm(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let rhs = cpu.next_long() as i32;
-    let quotient = lhs as i32 / rhs;
-    let remainder = lhs as i32 % rhs;
+    let lhs = lhs as i32;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient as u32;
     cpu.registers[1] = remainder as u32;
+    set_result_flags(cpu, quotient as u32, 32);
 }
 
 pub fn signed_div_byte_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFF) as u8;
     let index = cpu.next_byte() as usize;
     let rhs = cpu.registers[index] as i8;
-    let quotient = (lhs as i8) / rhs;
-    let remainder = (lhs as i8) % rhs;
+    let lhs = lhs as i8;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient as u32;
     cpu.registers[1] = remainder as u32;
+    set_result_flags(cpu, quotient as u32, 8);
 }
 pub fn signed_div_short_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let index = cpu.next_byte() as usize;
     let rhs = (cpu.registers[index] & 0xFFFF) as i16;
-    let quotient = (lhs as i16) / rhs;
-    let remainder = (lhs as i16) % rhs;
+    let lhs = lhs as i16;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient as u32;
     cpu.registers[1] = remainder as u32;
+    set_result_flags(cpu, quotient as u32, 16);
 }
 pub fn signed_div_long_reg(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let index = cpu.next_byte() as usize;
     let rhs = cpu.registers[index] as i32;
-    let quotient = lhs as i32 / rhs;
-    let remainder = lhs as i32 % rhs;
+    let lhs = lhs as i32;
+    let (Some(quotient), Some(remainder)) = (lhs.checked_div(rhs), lhs.checked_rem(rhs)) else {
+        return cpu.raise(Exception::DivideError, 0);
+    };
     cpu.registers[0] = quotient as u32;
     cpu.registers[1] = remainder as u32;
+    set_result_flags(cpu, quotient as u32, 32);
 }
 
 pub fn and_byte_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFF) as u8;
     let rhs = cpu.next_byte();
-    cpu.registers[0] = (lhs & rhs) as u32;
+    let result = lhs & rhs;
+    cpu.registers[0] = result as u32;
+    set_result_flags(cpu, result as u32, 8);
 }
 pub fn and_short_imm(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let rhs = cpu.next_short();
-    cpu.registers[0] = (lhs & rhs) as u32;
+    let result = lhs & rhs;
+    cpu.registers[0] = result as u32;
+    set_result_flags(cpu, result as u32, 16);
 }
 pub fn and_long_imm(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let rhs = cpu.next_long();
-    cpu.registers[0] = lhs & rhs;
+    let result = lhs & rhs;
+    cpu.registers[0] = result;
+    set_result_flags(cpu, result, 32);
 }
 
 pub fn and_byte_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFF) as u8;
     let index = cpu.next_byte() as usize;
     let rhs = (cpu.registers[index] & 0xFF) as u8;
-    cpu.registers[0] = (lhs & rhs) as u32;
+    let result = lhs & rhs;
+    cpu.registers[0] = result as u32;
+    set_result_flags(cpu, result as u32, 8);
 }
 pub fn and_short_reg(cpu: &mut Cpu) {
     let lhs = (cpu.registers[0] & 0xFFFF) as u16;
     let index = cpu.next_byte() as usize;
     let rhs = (cpu.registers[index] & 0xFFFF) as u16;
-    cpu.registers[0] = (lhs & rhs) as u32;
+    let result = lhs & rhs;
+    cpu.registers[0] = result as u32;
+    set_result_flags(cpu, result as u32, 16);
 }
 pub fn and_long_reg(cpu: &mut Cpu) {
     let lhs = cpu.registers[0];
     let index = cpu.next_byte() as usize;
     let rhs = cpu.registers[index];
-    cpu.registers[0] = lhs & rhs;
+    let result = lhs & rhs;
+    cpu.registers[0] = result;
+    set_result_flags(cpu, result, 32);
 }
 
 pub fn or_byte_imm(cpu: &mut Cpu) {
-    let val = cpu.next_short();
-    let res = cpu.registers[0] as u16 | val;
+    let val = cpu.next_byte();
+    let res = cpu.registers[0] as u8 | val;
     cpu.registers[0] = res as u32;
+    set_result_flags(cpu, res as u32, 8);
 }
 pub fn or_short_imm(cpu: &mut Cpu) {
     let val = cpu.next_short();
     let res = cpu.registers[0] as u16 | val;
     cpu.registers[0] = res as u32;
+    set_result_flags(cpu, res as u32, 16);
 }
 pub fn or_long_imm(cpu: &mut Cpu) {
     let val = cpu.next_long();
-    let res = cpu.registers[0] as u32 | val;
-    cpu.registers[0] = res as u32;
+    let res = cpu.registers[0] | val;
+    cpu.registers[0] = res;
+    set_result_flags(cpu, res, 32);
 }
 
 pub fn or_byte_reg(cpu: &mut Cpu) {
@@ -863,34 +979,40 @@ pub fn or_byte_reg(cpu: &mut Cpu) {
     let val = cpu.registers[reg] as u8;
     let res = cpu.registers[0] as u8 | val;
     cpu.registers[0] = res as u32;
+    set_result_flags(cpu, res as u32, 8);
 }
 pub fn or_short_reg(cpu: &mut Cpu) {
-    let reg = cpu.next_short() as usize;
+    let reg = cpu.next_byte() as usize;
     let val = cpu.registers[reg] as u16;
     let res = cpu.registers[0] as u16 | val;
     cpu.registers[0] = res as u32;
+    set_result_flags(cpu, res as u32, 16);
 }
 pub fn or_long_reg(cpu: &mut Cpu) {
-    let reg = cpu.next_long() as usize;
-    let val = cpu.registers[reg] as u32;
-    let res = cpu.registers[0] as u32 | val;
-    cpu.registers[0] = res as u32;
+    let reg = cpu.next_byte() as usize;
+    let val = cpu.registers[reg];
+    let res = cpu.registers[0] | val;
+    cpu.registers[0] = res;
+    set_result_flags(cpu, res, 32);
 }
 
 pub fn xor_byte_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
     let res = cpu.registers[0] as u8 ^ val;
     cpu.registers[0] = res as u32;
+    set_result_flags(cpu, res as u32, 8);
 }
 pub fn xor_short_imm(cpu: &mut Cpu) {
     let val = cpu.next_short();
     let res = cpu.registers[0] as u16 ^ val;
     cpu.registers[0] = res as u32;
+    set_result_flags(cpu, res as u32, 16);
 }
 pub fn xor_long_imm(cpu: &mut Cpu) {
     let val = cpu.next_long();
-    let res = cpu.registers[0] as u32 ^ val;
-    cpu.registers[0] = res as u32;
+    let res = cpu.registers[0] ^ val;
+    cpu.registers[0] = res;
+    set_result_flags(cpu, res, 32);
 }
 
 pub fn xor_byte_reg(cpu: &mut Cpu) {
@@ -898,18 +1020,21 @@ pub fn xor_byte_reg(cpu: &mut Cpu) {
     let val = cpu.registers[reg] as u8;
     let res = cpu.registers[0] as u8 ^ val;
     cpu.registers[0] = res as u32;
+    set_result_flags(cpu, res as u32, 8);
 }
 pub fn xor_short_reg(cpu: &mut Cpu) {
-    let reg = cpu.next_short() as usize;
+    let reg = cpu.next_byte() as usize;
     let val = cpu.registers[reg] as u16;
     let res = cpu.registers[0] as u16 ^ val;
     cpu.registers[0] = res as u32;
+    set_result_flags(cpu, res as u32, 16);
 }
 pub fn xor_long_reg(cpu: &mut Cpu) {
-    let reg = cpu.next_long() as usize;
-    let val = cpu.registers[reg] as u32;
-    let res = cpu.registers[0] as u32 ^ val;
-    cpu.registers[0] = res as u32;
+    let reg = cpu.next_byte() as usize;
+    let val = cpu.registers[reg];
+    let res = cpu.registers[0] ^ val;
+    cpu.registers[0] = res;
+    set_result_flags(cpu, res, 32);
 }
 
 pub fn push_byte_imm(cpu: &mut Cpu) {
@@ -982,177 +1107,424 @@ pub fn compare_long_reg(cpu: &mut Cpu) {
     cpu.registers[0] = if lhs == rhs { 1 } else { 0 };
 }
 
+// `cmp` with two operands works out lhs - rhs for its flags and throws the
+// result away.
+pub fn compare_reg_imm_byte(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index] as u8;
+    let rhs = cpu.next_byte();
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, borrow, 8);
+}
+pub fn compare_reg_imm_short(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index] as u16;
+    let rhs = cpu.next_short();
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, borrow, 16);
+}
+pub fn compare_reg_imm_long(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index];
+    let rhs = cpu.next_long();
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs, rhs, result, borrow, 32);
+}
+
+pub fn compare_reg_reg_byte(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index] as u8;
+    let rhs_index = cpu.next_byte() as usize;
+    let rhs = cpu.registers[rhs_index] as u8;
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, borrow, 8);
+}
+pub fn compare_reg_reg_short(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index] as u16;
+    let rhs_index = cpu.next_byte() as usize;
+    let rhs = cpu.registers[rhs_index] as u16;
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, borrow, 16);
+}
+pub fn compare_reg_reg_long(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index];
+    let rhs_index = cpu.next_byte() as usize;
+    let rhs = cpu.registers[rhs_index];
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs, rhs, result, borrow, 32);
+}
+
+pub fn compare_reg_abs_byte(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index] as u8;
+    let addr = cpu.next_long() as usize;
+    let rhs = cpu.memory.byte(addr);
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, borrow, 8);
+}
+pub fn compare_reg_abs_short(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index] as u16;
+    let addr = cpu.next_long() as usize;
+    let rhs = cpu.memory.short(addr);
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, borrow, 16);
+}
+pub fn compare_reg_abs_long(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index];
+    let addr = cpu.next_long() as usize;
+    let rhs = cpu.memory.long(addr);
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs, rhs, result, borrow, 32);
+}
+
+pub fn compare_reg_indirect_byte(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index] as u8;
+    let reg = cpu.next_byte() as usize;
+    let addr = cpu.registers[reg] as usize;
+    let rhs = cpu.memory.byte(addr);
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, borrow, 8);
+}
+pub fn compare_reg_indirect_short(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index] as u16;
+    let reg = cpu.next_byte() as usize;
+    let addr = cpu.registers[reg] as usize;
+    let rhs = cpu.memory.short(addr);
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs as u32, rhs as u32, result as u32, borrow, 16);
+}
+pub fn compare_reg_indirect_long(cpu: &mut Cpu) {
+    let index = cpu.next_byte() as usize;
+    let lhs = cpu.registers[index];
+    let reg = cpu.next_byte() as usize;
+    let addr = cpu.registers[reg] as usize;
+    let rhs = cpu.memory.long(addr);
+    let (result, borrow) = lhs.overflowing_sub(rhs);
+    set_sub_flags(cpu, lhs, rhs, result, borrow, 32);
+}
+
 pub fn log_shift_left_byte_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u8).shl(val) as u32;
+    let value = cpu.registers[0] as u8;
+    let result = value.checked_shl(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shl_carry(value as u32, val as u32, 8);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn log_shift_left_short_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u16).shl(val) as u32;
+    let value = cpu.registers[0] as u16;
+    let result = value.checked_shl(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shl_carry(value as u32, val as u32, 16);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn log_shift_left_long_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u32).shl(val) as u32;
+    let value = cpu.registers[0];
+    let result = value.checked_shl(val as u32).unwrap_or(0);
+    cpu.registers[0] = result;
+    let carry = shl_carry(value, val as u32, 32);
+    set_shift_flags(cpu, value, result, carry, 32);
 }
 
 pub fn log_shift_left_byte_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = cpu.registers[reg] as u8;
-    cpu.registers[0] = (cpu.registers[0] as u8).shl(val) as u32;
+    let value = cpu.registers[0] as u8;
+    let result = value.checked_shl(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shl_carry(value as u32, val as u32, 8);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn log_shift_left_short_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = cpu.registers[reg] as u16;
-    cpu.registers[0] = (cpu.registers[0] as u16).shl(val) as u32;
+    let value = cpu.registers[0] as u16;
+    let result = value.checked_shl(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shl_carry(value as u32, val as u32, 16);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn log_shift_left_long_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = cpu.registers[reg] as u32;
-    cpu.registers[0] = (cpu.registers[0] as u32).shl(val) as u32;
+    let val = cpu.registers[reg];
+    let value = cpu.registers[0];
+    let result = value.checked_shl(val).unwrap_or(0);
+    cpu.registers[0] = result;
+    let carry = shl_carry(value, val, 32);
+    set_shift_flags(cpu, value, result, carry, 32);
 }
 
 pub fn log_shift_right_byte_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u8).shr(val) as u32;
+    let value = cpu.registers[0] as u8;
+    let result = value.checked_shr(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shr_carry(value as u32, val as u32);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn log_shift_right_short_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u16).shr(val) as u32;
+    let value = cpu.registers[0] as u16;
+    let result = value.checked_shr(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shr_carry(value as u32, val as u32);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn log_shift_right_long_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u32).shr(val) as u32;
+    let value = cpu.registers[0];
+    let result = value.checked_shr(val as u32).unwrap_or(0);
+    cpu.registers[0] = result;
+    let carry = shr_carry(value, val as u32);
+    set_shift_flags(cpu, value, result, carry, 32);
 }
+
 pub fn log_shift_right_byte_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = cpu.registers[reg] as u8;
-    cpu.registers[0] = (cpu.registers[0] as u8).shr(val) as u32;
+    let value = cpu.registers[0] as u8;
+    let result = value.checked_shr(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shr_carry(value as u32, val as u32);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn log_shift_right_short_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = cpu.registers[reg] as u16;
-    cpu.registers[0] = (cpu.registers[0] as u16).shr(val) as u32;
+    let value = cpu.registers[0] as u16;
+    let result = value.checked_shr(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shr_carry(value as u32, val as u32);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn log_shift_right_long_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = cpu.registers[reg] as u32;
-    cpu.registers[0] = (cpu.registers[0] as u32).shr(val) as u32;
+    let val = cpu.registers[reg];
+    let value = cpu.registers[0];
+    let result = value.checked_shr(val).unwrap_or(0);
+    cpu.registers[0] = result;
+    let carry = shr_carry(value, val);
+    set_shift_flags(cpu, value, result, carry, 32);
 }
 
 pub fn arith_shift_left_byte_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u8).shl(val) as u32;
+    let value = cpu.registers[0] as u8;
+    let result = value.checked_shl(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shl_carry(value as u32, val as u32, 8);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn arith_shift_left_short_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u16).shl(val) as u32;
+    let value = cpu.registers[0] as u16;
+    let result = value.checked_shl(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shl_carry(value as u32, val as u32, 16);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn arith_shift_left_long_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u32).shl(val) as u32;
+    let value = cpu.registers[0];
+    let result = value.checked_shl(val as u32).unwrap_or(0);
+    cpu.registers[0] = result;
+    let carry = shl_carry(value, val as u32, 32);
+    set_shift_flags(cpu, value, result, carry, 32);
 }
 
 pub fn arith_shift_left_byte_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = cpu.registers[reg] as u8;
-    cpu.registers[0] = (cpu.registers[0] as i8).shl(val) as u32;
+    let value = cpu.registers[0] as i8;
+    let result = value.checked_shl(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shl_carry(value as u32, val as u32, 8);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn arith_shift_left_short_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = cpu.registers[reg] as u16;
-    cpu.registers[0] = (cpu.registers[0] as i16).shl(val) as u32;
+    let value = cpu.registers[0] as i16;
+    let result = value.checked_shl(val as u32).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shl_carry(value as u32, val as u32, 16);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn arith_shift_left_long_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = cpu.registers[reg] as u32;
-    cpu.registers[0] = (cpu.registers[0] as i32).shl(val) as u32;
+    let val = cpu.registers[reg];
+    let value = cpu.registers[0] as i32;
+    let result = value.checked_shl(val).unwrap_or(0);
+    cpu.registers[0] = result as u32;
+    let carry = shl_carry(value as u32, val, 32);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 32);
 }
 
 pub fn arith_shift_right_byte_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u8).shr(val) as u32;
+    let value = cpu.registers[0] as i8;
+    let result = value >> (val as u32).min(i8::BITS - 1);
+    cpu.registers[0] = result as u32;
+    let carry = sar_carry(value as i32, val as u32);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn arith_shift_right_short_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u16).shr(val) as u32;
+    let value = cpu.registers[0] as i16;
+    let result = value >> (val as u32).min(i16::BITS - 1);
+    cpu.registers[0] = result as u32;
+    let carry = sar_carry(value as i32, val as u32);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn arith_shift_right_long_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u32).shr(val) as u32;
+    let value = cpu.registers[0] as i32;
+    let result = value >> (val as u32).min(i32::BITS - 1);
+    cpu.registers[0] = result as u32;
+    let carry = sar_carry(value, val as u32);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 32);
 }
 
 pub fn arith_shift_right_byte_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = cpu.registers[reg] as u8;
-    cpu.registers[0] = (cpu.registers[0] as i8).shr(val) as u32;
+    let value = cpu.registers[0] as i8;
+    let result = value >> (val as u32).min(i8::BITS - 1);
+    cpu.registers[0] = result as u32;
+    let carry = sar_carry(value as i32, val as u32);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn arith_shift_right_short_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = cpu.registers[reg] as u16;
-    cpu.registers[0] = (cpu.registers[0] as i16).shr(val) as u32;
+    let value = cpu.registers[0] as i16;
+    let result = value >> (val as u32).min(i16::BITS - 1);
+    cpu.registers[0] = result as u32;
+    let carry = sar_carry(value as i32, val as u32);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn arith_shift_right_long_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = cpu.registers[reg] as u32;
-    cpu.registers[0] = (cpu.registers[0] as i32).shr(val) as u32;
+    let val = cpu.registers[reg];
+    let value = cpu.registers[0] as i32;
+    let result = value >> val.min(i32::BITS - 1);
+    cpu.registers[0] = result as u32;
+    let carry = sar_carry(value, val);
+    set_shift_flags(cpu, value as u32, result as u32, carry, 32);
 }
 
 pub fn rotate_left_byte_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u8).rotate_left(val as u32) as u32;
+    let value = cpu.registers[0] as u8;
+    let result = value.rotate_left(val as u32);
+    cpu.registers[0] = result as u32;
+    let carry = val != 0 && result & 1 == 1;
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn rotate_left_short_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u16).rotate_left(val as u32) as u32;
+    let value = cpu.registers[0] as u16;
+    let result = value.rotate_left(val as u32);
+    cpu.registers[0] = result as u32;
+    let carry = val != 0 && result & 1 == 1;
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn rotate_left_long_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u32).rotate_left(val as u32) as u32;
+    let value = cpu.registers[0];
+    let result = value.rotate_left(val as u32);
+    cpu.registers[0] = result;
+    let carry = val != 0 && result & 1 == 1;
+    set_shift_flags(cpu, value, result, carry, 32);
 }
 
 pub fn rotate_left_byte_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = cpu.registers[reg] as u32;
-    cpu.registers[0] = (cpu.registers[0] as u8).rotate_left(val) as u32;
+    let val = cpu.registers[reg];
+    let value = cpu.registers[0] as u8;
+    let result = value.rotate_left(val);
+    cpu.registers[0] = result as u32;
+    let carry = val != 0 && result & 1 == 1;
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn rotate_left_short_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = cpu.registers[reg] as u32;
-    cpu.registers[0] = (cpu.registers[0] as u16).rotate_left(val) as u32;
+    let val = cpu.registers[reg];
+    let value = cpu.registers[0] as u16;
+    let result = value.rotate_left(val);
+    cpu.registers[0] = result as u32;
+    let carry = val != 0 && result & 1 == 1;
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn rotate_left_long_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = cpu.registers[reg] as u32;
-    cpu.registers[0] = (cpu.registers[0] as u32).rotate_left(val) as u32;
+    let val = cpu.registers[reg];
+    let value = cpu.registers[0];
+    let result = value.rotate_left(val);
+    cpu.registers[0] = result;
+    let carry = val != 0 && result & 1 == 1;
+    set_shift_flags(cpu, value, result, carry, 32);
 }
 
 pub fn rotate_right_byte_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u8).rotate_right(val as u32) as u32;
+    let value = cpu.registers[0] as u8;
+    let result = value.rotate_right(val as u32);
+    cpu.registers[0] = result as u32;
+    let carry = val != 0 && result >> 7 == 1;
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn rotate_right_short_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u16).rotate_right(val as u32) as u32;
+    let value = cpu.registers[0] as u16;
+    let result = value.rotate_right(val as u32);
+    cpu.registers[0] = result as u32;
+    let carry = val != 0 && result >> 15 == 1;
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn rotate_right_long_imm(cpu: &mut Cpu) {
     let val = cpu.next_byte();
-    cpu.registers[0] = (cpu.registers[0] as u32).rotate_right(val as u32) as u32;
+    let value = cpu.registers[0];
+    let result = value.rotate_right(val as u32);
+    cpu.registers[0] = result;
+    let carry = val != 0 && result >> 31 == 1;
+    set_shift_flags(cpu, value, result, carry, 32);
 }
 
 pub fn rotate_right_byte_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = cpu.registers[reg] as u32;
-    cpu.registers[0] = (cpu.registers[0] as u8).rotate_right(val) as u32;
+    let val = cpu.registers[reg];
+    let value = cpu.registers[0] as u8;
+    let result = value.rotate_right(val);
+    cpu.registers[0] = result as u32;
+    let carry = val != 0 && result >> 7 == 1;
+    set_shift_flags(cpu, value as u32, result as u32, carry, 8);
 }
 pub fn rotate_right_short_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = cpu.registers[reg] as u32;
-    cpu.registers[0] = (cpu.registers[0] as u16).rotate_right(val) as u32;
+    let val = cpu.registers[reg];
+    let value = cpu.registers[0] as u16;
+    let result = value.rotate_right(val);
+    cpu.registers[0] = result as u32;
+    let carry = val != 0 && result >> 15 == 1;
+    set_shift_flags(cpu, value as u32, result as u32, carry, 16);
 }
 pub fn rotate_right_long_reg(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = cpu.registers[reg] as u32;
-    cpu.registers[0] = (cpu.registers[0] as u32).rotate_right(val) as u32;
+    let val = cpu.registers[reg];
+    let value = cpu.registers[0];
+    let result = value.rotate_right(val);
+    cpu.registers[0] = result;
+    let carry = val != 0 && result >> 31 == 1;
+    set_shift_flags(cpu, value, result, carry, 32);
 }
 
 pub fn pop_byte(cpu: &mut Cpu) {
@@ -1182,66 +1554,87 @@ pub fn pop_long(cpu: &mut Cpu) {
 
 pub fn negate_byte(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = (cpu.registers[reg] as i8).neg();
+    let value = cpu.registers[reg] as i8;
+    let (val, overflow) = value.overflowing_neg();
     cpu.registers[reg] = val as u32;
+    set_result_flags(cpu, val as u32, 8);
+    cpu.set_flag(Cpu::CARRY_FLAG, value != 0);
+    cpu.set_flag(Cpu::OVERFLOW_FLAG, overflow);
 }
 pub fn negate_short(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = (cpu.registers[reg] as i16).neg();
+    let value = cpu.registers[reg] as i16;
+    let (val, overflow) = value.overflowing_neg();
     cpu.registers[reg] = val as u32;
+    set_result_flags(cpu, val as u32, 16);
+    cpu.set_flag(Cpu::CARRY_FLAG, value != 0);
+    cpu.set_flag(Cpu::OVERFLOW_FLAG, overflow);
 }
 pub fn negate_long(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = (cpu.registers[reg] as i32).neg();
+    let value = cpu.registers[reg] as i32;
+    let (val, overflow) = value.overflowing_neg();
     cpu.registers[reg] = val as u32;
+    set_result_flags(cpu, val as u32, 32);
+    cpu.set_flag(Cpu::CARRY_FLAG, value != 0);
+    cpu.set_flag(Cpu::OVERFLOW_FLAG, overflow);
 }
 
 pub fn not_byte(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = (cpu.registers[reg] as i8).not();
     cpu.registers[reg] = val as u32;
+    set_result_flags(cpu, val as u32, 8);
 }
 pub fn not_short(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = (cpu.registers[reg] as i16).not();
     cpu.registers[reg] = val as u32;
+    set_result_flags(cpu, val as u32, 16);
 }
 pub fn not_long(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = (cpu.registers[reg] as i32).not();
     cpu.registers[reg] = val as u32;
+    set_result_flags(cpu, val as u32, 32);
 }
 
 pub fn increment_byte(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = (cpu.registers[reg] as u8).wrapping_add(1);
     cpu.registers[reg] = val as u32;
+    set_inc_flags(cpu, val as u32, val == 0x80, 8);
 }
 pub fn increment_short(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = (cpu.registers[reg] as u16).wrapping_add(1);
     cpu.registers[reg] = val as u32;
+    set_inc_flags(cpu, val as u32, val == 0x8000, 16);
 }
 pub fn increment_long(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = (cpu.registers[reg] as u32).wrapping_add(1);
-    cpu.registers[reg] = val as u32;
+    let val = cpu.registers[reg].wrapping_add(1);
+    cpu.registers[reg] = val;
+    set_inc_flags(cpu, val, val == 0x8000_0000, 32);
 }
 
 pub fn decrement_byte(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = (cpu.registers[reg] as u8).wrapping_sub(1);
     cpu.registers[reg] = val as u32;
+    set_inc_flags(cpu, val as u32, val == 0x7F, 8);
 }
 pub fn decrement_short(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
     let val = (cpu.registers[reg] as u16).wrapping_sub(1);
     cpu.registers[reg] = val as u32;
+    set_inc_flags(cpu, val as u32, val == 0x7FFF, 16);
 }
 pub fn decrement_long(cpu: &mut Cpu) {
     let reg = cpu.next_byte() as usize;
-    let val = (cpu.registers[reg] as u32).wrapping_sub(1);
-    cpu.registers[reg] = val as u32;
+    let val = cpu.registers[reg].wrapping_sub(1);
+    cpu.registers[reg] = val;
+    set_inc_flags(cpu, val, val == 0x7FFF_FFFF, 32);
 }
 
 pub fn read_byte(cpu: &mut Cpu) {
@@ -1418,6 +1811,108 @@ pub fn jump_signed_less_equal(cpu: &mut Cpu) {
     }
 }
 
+pub fn jump_zero(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if cpu.has_flag(Cpu::ZERO_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_not_zero(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if !cpu.has_flag(Cpu::ZERO_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_sign(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if cpu.has_flag(Cpu::SIGN_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_not_sign(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if !cpu.has_flag(Cpu::SIGN_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_carry(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if cpu.has_flag(Cpu::CARRY_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_not_carry(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if !cpu.has_flag(Cpu::CARRY_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_overflow(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if cpu.has_flag(Cpu::OVERFLOW_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_not_overflow(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if !cpu.has_flag(Cpu::OVERFLOW_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+
+// unsigned jumps read carry and zero, signed ones sign, overflow and zero.
+fn signed_less(cpu: &Cpu) -> bool {
+    cpu.has_flag(Cpu::SIGN_FLAG) != cpu.has_flag(Cpu::OVERFLOW_FLAG)
+}
+pub fn jump_above(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if !cpu.has_flag(Cpu::CARRY_FLAG) && !cpu.has_flag(Cpu::ZERO_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_above_equal(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if !cpu.has_flag(Cpu::CARRY_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_below(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if cpu.has_flag(Cpu::CARRY_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_below_equal(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if cpu.has_flag(Cpu::CARRY_FLAG) || cpu.has_flag(Cpu::ZERO_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_greater_than(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if !cpu.has_flag(Cpu::ZERO_FLAG) && !signed_less(cpu) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_greater_than_equal(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if !signed_less(cpu) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_less_than(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if signed_less(cpu) {
+        cpu.registers[IP] = addr;
+    }
+}
+pub fn jump_less_than_equal(cpu: &mut Cpu) {
+    let addr = cpu.next_long();
+    if signed_less(cpu) || cpu.has_flag(Cpu::ZERO_FLAG) {
+        cpu.registers[IP] = addr;
+    }
+}
+
 pub fn jump_imm(cpu: &mut Cpu) {
     let addr = cpu.next_long();
     cpu.registers[IP] = addr;
@@ -1429,52 +1924,95 @@ pub fn jump_reg(cpu: &mut Cpu) {
 }
 
 pub fn interrupt(cpu: &mut Cpu) {
-    let busy_in_interrupt = (cpu.registers[FLAGS] & Cpu::INTERRUPT_FLAG as u32) != 0;
-
-    // we block interrupts while handling an interrupt.
-    // in a more complicated emulator, you wouldn't have this
-    // loss of data, but it's complicated and we don't do insane
-    // rewinding and reordering of instructions.
-    if busy_in_interrupt {
+    let irq = cpu.next_byte();
+    cpu.pic.raise(irq);
+    deliver_interrupt(cpu);
+}
+
+/// Enters the handler of the next interrupt the pic lets through, if any.
+/// Interrupts wait while they're disabled and while a fault is being handled.
+/// Entering a handler leaves them enabled, the pic keeps equal and lower
+/// priorities out. Without an idt there's nowhere to go, so they stay pending
+/// until one is set.
+pub fn deliver_interrupt(cpu: &mut Cpu) {
+    if !cpu.has_flag(Cpu::INTERRUPT_ENABLE_FLAG) || cpu.handling.is_some() {
         return;
     }
-
-    let irq = cpu.next_byte() as u32;
+    if cpu.registers[IDT] == 0 {
+        return;
+    }
+    let Some(irq) = cpu.pic.take() else {
+        return;
+    };
 
     // get the base of the idt
-    let idt_base = cpu.registers[IDT] as u32;
+    let idt_base = cpu.registers[IDT];
 
     // idt entries are exactly 4 bytes long
-    let isr_addr = idt_base + (irq * 4);
-
-    // push return address
-    let return_address = cpu.ip();
-
-    cpu.dec_sp(4);
-    cpu.memory.set_long(cpu.sp(), return_address as u32);
-
-    // set the interrupt flag
-    unsafe {
-        *cpu.registers.get_unchecked_mut(FLAGS) |= Cpu::INTERRUPT_FLAG as u32;
+    let isr_addr = idt_base.wrapping_add(irq as u32 * 4);
+
+    // push flags and the return address, `iret` restores both.
+    cpu.push_frame(cpu.ip() as u32);
+
+    cpu.set_flag(Cpu::INTERRUPT_FLAG, true);
+    cpu.registers[IP] = cpu.memory.long(isr_addr as usize);
+}
+/// Calls the supervisor through the handler at `Cpu::TRAP_VECTOR`. Unlike `int`
+/// it can be used in user mode and is never held back.
+pub fn trap(cpu: &mut Cpu) {
+    let ip = cpu.registers[IP].wrapping_sub(1);
+    // the supervisor reads the idt, and there may be nothing in it.
+    cpu.memory.mmu.user = false;
+    let idt = cpu.registers[IDT];
+    let isr = cpu
+        .memory
+        .long(idt.wrapping_add(Cpu::TRAP_VECTOR as u32 * 4) as usize);
+    if cpu.memory.clear_faults() || idt == 0 || isr == 0 {
+        return cpu.halt_on_fault(format!("unhandled trap at 0x{:08X}", ip));
     }
-    unsafe {
-        *cpu.registers.get_unchecked_mut(IP) = cpu.memory.long(isr_addr as usize);
+
+    cpu.push_frame(cpu.ip() as u32);
+    if cpu.memory.clear_faults() {
+        return cpu.halt_on_fault(format!(
+            "double fault: no room on the stack at 0x{:08X} for trap at 0x{:08X}",
+            cpu.registers[SP], ip
+        ));
     }
+    cpu.pic.enter(Cpu::TRAP_VECTOR);
+    cpu.set_flag(Cpu::INTERRUPT_FLAG, true);
+    cpu.registers[IP] = isr;
 }
 pub fn interrupt_return(cpu: &mut Cpu) {
-    // clear the interrupt flag
-    cpu.registers[FLAGS] &= !(Cpu::INTERRUPT_FLAG as u32);
-
-    // pop return address
+    // pop return address and flags
     let ret_addr = cpu.memory.long(cpu.sp());
-
     cpu.inc_sp(4);
+    let flags = cpu.memory.long(cpu.sp());
+    cpu.inc_sp(4);
+    // the frame couldn't be read, the fault is taken where the handler was.
+    if cpu.memory.faulted() {
+        return;
+    }
+
     cpu.registers[IP] = ret_addr;
+    cpu.registers[FLAGS] = flags;
+    // back to user code, and its stack.
+    if flags & Cpu::USER_FLAG != 0 {
+        cpu.registers[SP] = cpu.memory.long(cpu.sp());
+    }
+
+    // a fault handler returns before any interrupt it blocked is delivered.
+    if cpu.handling.take().is_none() {
+        cpu.pic.finish();
+    }
+    deliver_interrupt(cpu);
 }
 
 pub fn call(cpu: &mut Cpu) {
-    cpu.dec_sp(4);
     let addr = cpu.next_long();
+    if let Some(checker) = cpu.abi_checker.as_mut() {
+        checker.call(&cpu.registers, addr);
+    }
+    cpu.dec_sp(4);
     cpu.memory.set_long(cpu.sp(), cpu.ip() as u32);
     cpu.registers[IP] = addr;
 }
@@ -1482,21 +2020,73 @@ pub fn ret(cpu: &mut Cpu) {
     let addr = cpu.memory.long(cpu.sp());
     cpu.inc_sp(4);
     cpu.registers[IP] = addr;
+    if let Some(checker) = cpu.abi_checker.as_mut() {
+        checker.ret(&cpu.registers);
+    }
+}
+
+pub fn enter(cpu: &mut Cpu) {
+    let locals = cpu.next_long();
+    cpu.dec_sp(4);
+    cpu.memory.set_long(cpu.sp(), cpu.registers[BP]);
+    cpu.registers[BP] = cpu.registers[SP];
+    // a frame bigger than the stack wraps, and faults once it's used.
+    cpu.registers[SP] = cpu.registers[SP].wrapping_sub(locals);
+}
+pub fn leave(cpu: &mut Cpu) {
+    cpu.registers[SP] = cpu.registers[BP];
+    cpu.registers[BP] = cpu.memory.long(cpu.sp());
+    cpu.inc_sp(4);
 }
 
 pub fn syscall(cpu: &mut Cpu) {
     let idx = cpu.next_byte() as usize;
+    // the loader writes physical memory and the pic belongs to the supervisor.
+    if (4..=8).contains(&idx) && cpu.has_flag(Cpu::USER_FLAG) {
+        return cpu.raise(Exception::ProtectionFault, Opcode::Syscall as u32);
+    }
     match idx {
         0 => functions::log_memory(cpu),
         1 => functions::log(cpu),
         2 => functions::print_string(cpu),
         3 => functions::print_register(cpu),
-        _ => panic!("invalid rust function: {}", idx),
+        4 => functions::load_library(cpu),
+        5 => functions::find_symbol(cpu),
+        6 => functions::mask_irq(cpu),
+        7 => functions::set_irq_priority(cpu),
+        8 => functions::end_of_interrupt(cpu),
+        _ => cpu.raise(Exception::InvalidOpcode, idx as u32),
     }
 }
 pub fn clear_carry(cpu: &mut Cpu) {
     cpu.set_flag(Cpu::CARRY_FLAG, false);
 }
+pub fn disable_interrupts(cpu: &mut Cpu) {
+    cpu.set_flag(Cpu::INTERRUPT_ENABLE_FLAG, false);
+}
+/// Anything pending is delivered before the next instruction.
+pub fn enable_interrupts(cpu: &mut Cpu) {
+    cpu.set_flag(Cpu::INTERRUPT_ENABLE_FLAG, true);
+}
+/// Drops the cached translation of the page holding the address in a register.
+pub fn invalidate_page(cpu: &mut Cpu) {
+    let reg = cpu.next_byte() as usize;
+    cpu.memory.mmu.invalidate(cpu.registers[reg]);
+}
+
+/// Reads the cycle counter, which includes this instruction, into two
+/// registers: the high half into the first.
+pub fn read_cycles(cpu: &mut Cpu) {
+    let high = cpu.next_byte() as usize;
+    let low = cpu.next_byte() as usize;
+    cpu.registers[high] = (cpu.cycles >> 32) as u32;
+    cpu.registers[low] = cpu.cycles as u32;
+}
+
+pub fn invalid_opcode(cpu: &mut Cpu) {
+    let opcode = cpu.memory.byte(cpu.registers[IP].wrapping_sub(1) as usize);
+    cpu.raise(Exception::InvalidOpcode, opcode as u32);
+}
 
 pub fn nop(_: &mut Cpu) {
     // do fricken nothin
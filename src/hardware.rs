@@ -1,4 +1,11 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use crate::cpu::Cpu;
 
@@ -16,6 +23,32 @@ impl_numeric!(u8, u16, u32);
 pub struct Config {
     pub cpu: Rc<RefCell<Cpu>>,
     pub id: u8,
+    /// raises interrupt lines on `cpu`, from the device's own thread if it has one.
+    pub interrupts: Interrupts,
+}
+
+/// Interrupt lines raised by devices, collected by the cpu between instructions
+/// and handed to its pic. Clones share the same lines.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupts {
+    raised: Arc<AtomicBool>,
+    lines: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Interrupts {
+    pub fn raise(&self, line: u8) {
+        self.lines.lock().unwrap().push(line);
+        self.raised.store(true, Ordering::Release);
+    }
+
+    /// Takes every line raised since the last call. Cheap when there are none,
+    /// so the cpu can ask every cycle.
+    pub fn take(&self) -> Vec<u8> {
+        if !self.raised.swap(false, Ordering::Acquire) {
+            return Vec::new();
+        }
+        std::mem::take(&mut *self.lines.lock().unwrap())
+    }
 }
 
 pub trait Hardware {
@@ -23,4 +56,12 @@ pub trait Hardware {
     fn deinit(&mut self);
     fn read(&self) -> u8;
     fn write(&mut self, b: u8);
+
+    /// Reads the byte at `offset` into the range the device is mapped at, see
+    /// `Bus::map`. Only called from inside `Cpu::cycle`, so the device can't
+    /// borrow the cpu here.
+    fn read_mapped(&mut self, _offset: usize) -> u8 {
+        0
+    }
+    fn write_mapped(&mut self, _offset: usize, _value: u8) {}
 }
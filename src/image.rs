@@ -0,0 +1,191 @@
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::linker::Linked;
+use crate::object::{invalid, Reader, SectionKind, Writer};
+
+/// Bytes copied to `address` when the image is loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub address: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Segment {
+    pub fn end(&self) -> u64 {
+        self.address as u64 + self.bytes.len() as u64
+    }
+}
+
+/// An executable: what to load where, and how to set up the cpu before running it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    pub bss_address: u32,
+    pub bss_size: u32,
+    /// highest address of the stack, which grows down. 0 places it at the end of memory.
+    pub stack_top: u32,
+    pub stack_size: u32,
+    pub idt_base: u32,
+}
+
+impl Image {
+    pub const MAGIC: &'static [u8; 4] = b"B32X";
+    pub const VERSION: u16 = 1;
+    pub const DEFAULT_STACK_SIZE: u32 = 64 * 1024;
+    /// one 4 byte handler address for every possible `int` operand.
+    pub const IDT_SIZE: u32 = 256 * 4;
+
+    /// Builds an image from a link: `.text` and `.data` each get a segment.
+    pub fn from_linked(linked: &Linked, entry: u32) -> Image {
+        let text_size = linked
+            .placements
+            .iter()
+            .filter(|p| p.section == SectionKind::Text)
+            .map(|p| (p.address + p.size - linked.base) as usize)
+            .max()
+            .unwrap_or(0);
+        let (text, data) = linked.bytes.split_at(text_size);
+
+        let mut segments = vec![Segment {
+            address: linked.base,
+            bytes: text.to_vec(),
+        }];
+        if !data.is_empty() {
+            segments.push(Segment {
+                address: linked.base.wrapping_add(text_size as u32),
+                bytes: data.to_vec(),
+            });
+        }
+
+        Image {
+            entry,
+            segments,
+            bss_address: linked.base.wrapping_add(linked.bytes.len() as u32),
+            bss_size: linked.bss_size,
+            stack_top: 0,
+            stack_size: Image::DEFAULT_STACK_SIZE,
+            idt_base: 0,
+        }
+    }
+
+    /// The address the stack starts at in `memory_size` bytes of memory.
+    pub fn stack_top(&self, memory_size: usize) -> u64 {
+        match self.stack_top {
+            0 => memory_size as u64,
+            top => top as u64,
+        }
+    }
+
+    /// Checks that the image fits in `memory_size` bytes and makes sense to run.
+    pub fn validate(&self, memory_size: usize) -> io::Result<()> {
+        let memory_size = memory_size as u64;
+        let in_memory = |start: u64, len: u64| start + len <= memory_size;
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            if !in_memory(segment.address as u64, segment.bytes.len() as u64) {
+                return Err(invalid(&format!(
+                    "segment at 0x{:X} does not fit in memory",
+                    segment.address
+                )));
+            }
+            for other in self.segments[..i].iter() {
+                if (segment.address as u64) < other.end() && (other.address as u64) < segment.end()
+                {
+                    return Err(invalid(&format!(
+                        "segments at 0x{:X} and 0x{:X} overlap",
+                        other.address, segment.address
+                    )));
+                }
+            }
+        }
+        if !in_memory(self.bss_address as u64, self.bss_size as u64) {
+            return Err(invalid("bss does not fit in memory"));
+        }
+
+        let entry = self.entry as u64;
+        if !self
+            .segments
+            .iter()
+            .any(|segment| segment.address as u64 <= entry && entry < segment.end())
+        {
+            return Err(invalid(&format!(
+                "entry point 0x{:X} is outside every segment",
+                self.entry
+            )));
+        }
+
+        let stack_top = self.stack_top(memory_size as usize);
+        if stack_top > memory_size || (self.stack_size as u64) > stack_top {
+            return Err(invalid("stack does not fit in memory"));
+        }
+        if !in_memory(self.idt_base as u64, Image::IDT_SIZE as u64) {
+            return Err(invalid("interrupt descriptor table does not fit in memory"));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer::default();
+        out.bytes(Image::MAGIC);
+        out.short(Image::VERSION);
+        out.long(self.entry);
+        out.long(self.bss_address);
+        out.long(self.bss_size);
+        out.long(self.stack_top);
+        out.long(self.stack_size);
+        out.long(self.idt_base);
+        out.long(self.segments.len() as u32);
+        for segment in self.segments.iter() {
+            out.long(segment.address);
+            out.long(segment.bytes.len() as u32);
+            out.bytes(&segment.bytes);
+        }
+        out.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Image> {
+        let mut input = Reader { bytes, pos: 0 };
+        if input.take(4)? != Image::MAGIC {
+            return Err(invalid("not a bit32 executable"));
+        }
+        let version = input.short()?;
+        if version != Image::VERSION {
+            return Err(invalid(&format!(
+                "unsupported executable version {}",
+                version
+            )));
+        }
+
+        let mut image = Image {
+            entry: input.long()?,
+            segments: Vec::new(),
+            bss_address: input.long()?,
+            bss_size: input.long()?,
+            stack_top: input.long()?,
+            stack_size: input.long()?,
+            idt_base: input.long()?,
+        };
+        for _ in 0..input.long()? {
+            let address = input.long()?;
+            let len = input.long()? as usize;
+            image.segments.push(Segment {
+                address,
+                bytes: input.take(len)?.to_vec(),
+            });
+        }
+        Ok(image)
+    }
+
+    pub fn write_to_file<T: AsRef<Path> + ?Sized>(&self, path: &T) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn read_from_file<T: AsRef<Path> + ?Sized>(path: &T) -> io::Result<Image> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        Image::from_bytes(&buffer)
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::cpu::Memory;
+use crate::object::{invalid, Reader, Writer};
+
+/// A field the loader patches once it knows where the library goes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixup {
+    /// offset of the field from the start of the library.
+    pub offset: u32,
+    pub size: u8,
+    pub pc_relative: bool,
+    /// `None` moves the value already in the field by the load address. `Some`
+    /// stores the address of that symbol plus `addend`, looked up when loading.
+    pub import: Option<String>,
+    pub addend: i32,
+}
+
+/// A shared library, linked at 0 so it can be loaded at any address.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Library {
+    /// every `.text` followed by every `.data`.
+    pub bytes: Vec<u8>,
+    /// zero filled memory directly after `bytes`.
+    pub bss_size: u32,
+    /// global symbols and their offsets from the start of the library.
+    pub exports: Vec<(String, u32)>,
+    pub fixups: Vec<Fixup>,
+}
+
+impl Library {
+    pub const MAGIC: &'static [u8; 4] = b"B32L";
+    pub const VERSION: u16 = 1;
+
+    pub fn size(&self) -> u32 {
+        self.bytes.len() as u32 + self.bss_size
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer::default();
+        out.bytes(Library::MAGIC);
+        out.short(Library::VERSION);
+        out.long(self.bss_size);
+        out.long(self.bytes.len() as u32);
+        out.bytes(&self.bytes);
+
+        out.long(self.exports.len() as u32);
+        for (name, offset) in self.exports.iter() {
+            out.string(name);
+            out.long(*offset);
+        }
+
+        out.long(self.fixups.len() as u32);
+        for fixup in self.fixups.iter() {
+            out.long(fixup.offset);
+            out.byte(fixup.size);
+            out.byte(fixup.pc_relative as u8);
+            match &fixup.import {
+                None => out.byte(0),
+                Some(name) => {
+                    out.byte(1);
+                    out.string(name);
+                }
+            }
+            out.long(fixup.addend as u32);
+        }
+        out.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Library> {
+        let mut input = Reader { bytes, pos: 0 };
+        if input.take(4)? != Library::MAGIC {
+            return Err(invalid("not a bit32 shared library"));
+        }
+        let version = input.short()?;
+        if version != Library::VERSION {
+            return Err(invalid(&format!("unsupported library version {}", version)));
+        }
+
+        let bss_size = input.long()?;
+        let len = input.long()? as usize;
+        let mut library = Library {
+            bytes: input.take(len)?.to_vec(),
+            bss_size,
+            ..Library::default()
+        };
+        for _ in 0..input.long()? {
+            library.exports.push((input.string()?, input.long()?));
+        }
+        for _ in 0..input.long()? {
+            let offset = input.long()?;
+            let size = input.byte()?;
+            let pc_relative = input.byte()? != 0;
+            let import = match input.byte()? {
+                0 => None,
+                _ => Some(input.string()?),
+            };
+            let fixup = Fixup {
+                offset,
+                size,
+                pc_relative,
+                import,
+                addend: input.long()? as i32,
+            };
+            if !matches!(size, 1 | 2 | 4) {
+                return Err(invalid(&format!("fixup of {} bytes", size)));
+            }
+            if offset as usize + size as usize > library.bytes.len() {
+                return Err(invalid("fixup outside the library"));
+            }
+            library.fixups.push(fixup);
+        }
+        Ok(library)
+    }
+
+    pub fn write_to_file<T: AsRef<Path> + ?Sized>(&self, path: &T) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn read_from_file<T: AsRef<Path> + ?Sized>(path: &T) -> io::Result<Library> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        Library::from_bytes(&buffer)
+    }
+}
+
+/// A library the loader has put in memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loaded {
+    pub path: String,
+    pub base: u32,
+    pub size: u32,
+}
+
+/// Maps shared libraries into guest memory one after another and keeps the
+/// table imports are resolved through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loader {
+    /// where the next library goes.
+    pub next: u32,
+    pub libraries: Vec<Loaded>,
+    /// exports of every loaded library. when two define the same symbol the
+    /// first one loaded wins.
+    pub symbols: HashMap<String, u32>,
+}
+
+impl Default for Loader {
+    fn default() -> Loader {
+        Loader {
+            next: Loader::DEFAULT_BASE,
+            libraries: Vec::new(),
+            symbols: HashMap::new(),
+        }
+    }
+}
+
+impl Loader {
+    /// above the vga buffer, for raw programs. images move it past their bss.
+    pub const DEFAULT_BASE: u32 = 0x10_0000;
+    pub const ALIGN: u32 = 16;
+
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Loads `library` at the next free address below `limit`, patches its fixups
+    /// and adds its exports to the table. Returns where it went.
+    pub fn load(
+        &mut self,
+        memory: &mut Memory,
+        limit: u32,
+        path: &str,
+        library: &Library,
+    ) -> io::Result<u32> {
+        let base = self.next.next_multiple_of(Loader::ALIGN);
+        let end = base as u64 + library.size() as u64;
+        if end > limit as u64 || end > memory.bus.ram.len() as u64 {
+            return Err(invalid("library does not fit in memory"));
+        }
+
+        // resolve everything before touching memory, so a failed load leaves no trace.
+        let exports = library
+            .exports
+            .iter()
+            .map(|(name, offset)| (name.as_str(), base.wrapping_add(*offset)))
+            .collect::<HashMap<&str, u32>>();
+        let mut patches = Vec::new();
+        for fixup in library.fixups.iter() {
+            if !matches!(fixup.size, 1 | 2 | 4) {
+                return Err(invalid(&format!("fixup of {} bytes", fixup.size)));
+            }
+            let field = base.wrapping_add(fixup.offset);
+            let current = library.bytes[fixup.offset as usize..][..fixup.size as usize]
+                .iter()
+                .rev()
+                .fold(0i64, |value, byte| value << 8 | *byte as i64);
+            let value = match &fixup.import {
+                None if fixup.pc_relative => current - base as i64,
+                None => current + base as i64,
+                Some(name) => {
+                    let Some(address) = self.symbol(name) else {
+                        return Err(invalid(&format!("undefined symbol `{}`", name)));
+                    };
+                    let value = address as i64 + fixup.addend as i64;
+                    match fixup.pc_relative {
+                        true => value - field as i64,
+                        false => value,
+                    }
+                }
+            };
+            patches.push((field as usize, fixup.size as usize, value));
+        }
+
+        let start = base as usize;
+        memory.bus.ram.write(start, &library.bytes)?;
+        let bss = start + library.bytes.len();
+        memory.bus.ram.fill(bss, library.bss_size as usize, 0)?;
+        for (field, size, value) in patches {
+            memory.bus.ram.write(field, &value.to_le_bytes()[..size])?;
+        }
+
+        for (name, address) in exports {
+            self.symbols.entry(name.to_string()).or_insert(address);
+        }
+        self.libraries.push(Loaded {
+            path: path.to_string(),
+            base,
+            size: library.size(),
+        });
+        self.next = end as u32;
+        Ok(base)
+    }
+
+    /// Loads the library at `path` unless it already is, and returns its address.
+    pub fn load_file(&mut self, memory: &mut Memory, limit: u32, path: &str) -> io::Result<u32> {
+        if let Some(loaded) = self.libraries.iter().find(|loaded| loaded.path == path) {
+            return Ok(loaded.base);
+        }
+        let library = Library::read_from_file(path)?;
+        self.load(memory, limit, path, &library)
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::archive::Archive;
+use crate::library::{Fixup, Library};
+use crate::object::{Object, SectionKind, Target};
+use crate::opcodes::Opcode;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    Undefined {
+        symbol: String,
+        object: String,
+    },
+    Duplicate {
+        symbol: String,
+        first: String,
+        second: String,
+    },
+    Overflow {
+        object: String,
+        section: SectionKind,
+        offset: u32,
+        value: i64,
+        size: u8,
+    },
+}
+
+impl LinkError {
+    /// The object the error is reported against.
+    pub fn object(&self) -> &str {
+        match self {
+            LinkError::Undefined { object, .. } => object,
+            LinkError::Duplicate { second, .. } => second,
+            LinkError::Overflow { object, .. } => object,
+        }
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::Undefined { symbol, .. } => write!(f, "undefined symbol `{}`", symbol),
+            LinkError::Duplicate { symbol, first, .. } => {
+                write!(
+                    f,
+                    "duplicate symbol `{}`, first defined in {}",
+                    symbol, first
+                )
+            }
+            LinkError::Overflow {
+                section,
+                offset,
+                value,
+                size,
+                ..
+            } => write!(
+                f,
+                "relocated value {} at {}+0x{:X} does not fit in {} byte(s)",
+                value,
+                section.name(),
+                offset,
+                size
+            ),
+        }
+    }
+}
+
+/// An object file and the name used for it in diagnostics.
+pub struct Input {
+    pub name: String,
+    pub object: Object,
+}
+
+/// Where one object's section ended up in the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub object: usize,
+    pub section: SectionKind,
+    pub address: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkedSymbol {
+    pub name: String,
+    pub object: usize,
+    pub section: SectionKind,
+    pub address: u32,
+    pub global: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linked {
+    pub base: u32,
+    /// every `.text` followed by every `.data`, to be loaded at `base`.
+    pub bytes: Vec<u8>,
+    /// zero filled memory directly after `bytes`.
+    pub bss_size: u32,
+    pub placements: Vec<Placement>,
+    pub symbols: Vec<LinkedSymbol>,
+}
+
+impl Linked {
+    pub fn placement(&self, object: usize, section: SectionKind) -> &Placement {
+        self.placements
+            .iter()
+            .find(|p| p.object == object && p.section == section)
+            .unwrap()
+    }
+
+    /// Bytes from `symbol` up to the next symbol in its section, or the section's end.
+    pub fn symbol_size(&self, symbol: &LinkedSymbol) -> u32 {
+        let placement = self.placement(symbol.object, symbol.section);
+        let end = self
+            .symbols
+            .iter()
+            .filter(|next| {
+                next.object == symbol.object
+                    && next.section == symbol.section
+                    && next.address > symbol.address
+            })
+            .map(|next| next.address)
+            .min()
+            .unwrap_or(placement.address + placement.size);
+        end - symbol.address
+    }
+
+    pub fn global(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.global && symbol.name == name)
+            .map(|symbol| symbol.address)
+    }
+}
+
+/// Adds the archive members that define symbols `inputs` use but don't define,
+/// then whatever those members need in turn. Archives are searched in order and
+/// members nothing asks for are left out.
+pub fn add_archive_members(inputs: &mut Vec<Input>, archives: &[(String, Archive)]) {
+    let mut added = HashSet::new();
+    loop {
+        let defined = inputs
+            .iter()
+            .flat_map(|input| input.object.symbols.iter())
+            .filter(|symbol| symbol.global && symbol.section.is_some())
+            .map(|symbol| symbol.name.as_str())
+            .collect::<HashSet<&str>>();
+        let mut wanted = Vec::new();
+        for symbol in inputs.iter().flat_map(|input| input.object.symbols.iter()) {
+            if symbol.section.is_some() || defined.contains(symbol.name.as_str()) {
+                continue;
+            }
+            let found = archives
+                .iter()
+                .enumerate()
+                .find_map(|(i, (_, archive))| archive.find(&symbol.name).map(|member| (i, member)));
+            if let Some(found) = found {
+                if added.insert(found) {
+                    wanted.push(found);
+                }
+            }
+        }
+        if wanted.is_empty() {
+            return;
+        }
+        for (i, member) in wanted {
+            let (name, archive) = &archives[i];
+            let member = &archive.members[member];
+            inputs.push(Input {
+                name: format!("{}({})", name, member.name),
+                object: member.object.clone(),
+            });
+        }
+    }
+}
+
+/// Lays out every section of `inputs` starting at `base`, resolves symbols
+/// across objects and applies relocations.
+pub fn link(inputs: &[Input], base: u32) -> Result<Linked, Vec<LinkError>> {
+    link_at(inputs, base, None)
+}
+
+/// Links `inputs` at 0 into a shared library. Symbols no input defines become
+/// imports, and every field holding an address gets a fixup so the loader can
+/// move the library anywhere.
+pub fn link_shared(inputs: &[Input]) -> Result<Library, Vec<LinkError>> {
+    let mut fixups = Vec::new();
+    let linked = link_at(inputs, 0, Some(&mut fixups))?;
+    Ok(Library {
+        exports: linked
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.global)
+            .map(|symbol| (symbol.name.clone(), symbol.address))
+            .collect(),
+        bytes: linked.bytes,
+        bss_size: linked.bss_size,
+        fixups,
+    })
+}
+
+/// `link`, collecting fixups instead of reporting undefined symbols when
+/// `fixups` is set.
+fn link_at(
+    inputs: &[Input],
+    base: u32,
+    mut fixups: Option<&mut Vec<Fixup>>,
+) -> Result<Linked, Vec<LinkError>> {
+    let mut errors = Vec::new();
+    let mut linked = Linked {
+        base,
+        bytes: Vec::new(),
+        bss_size: 0,
+        placements: Vec::new(),
+        symbols: Vec::new(),
+    };
+
+    let mut address = base;
+    for kind in SectionKind::ALL {
+        for (i, input) in inputs.iter().enumerate() {
+            let section = input.object.section(kind);
+            let padding = address.wrapping_neg() % section.align.max(1);
+            address = address.wrapping_add(padding);
+            match kind {
+                SectionKind::Bss => linked.bss_size += padding,
+                SectionKind::Text => linked
+                    .bytes
+                    .extend(std::iter::repeat_n(Opcode::Nop as u8, padding as usize)),
+                SectionKind::Data => linked
+                    .bytes
+                    .extend(std::iter::repeat_n(0, padding as usize)),
+            }
+            linked.placements.push(Placement {
+                object: i,
+                section: kind,
+                address,
+                size: section.size,
+            });
+            if kind == SectionKind::Bss {
+                linked.bss_size += section.size;
+            } else {
+                linked.bytes.extend_from_slice(&section.bytes);
+            }
+            address = address.wrapping_add(section.size);
+        }
+    }
+
+    let mut globals: HashMap<&str, (usize, u32)> = HashMap::new();
+    for (i, input) in inputs.iter().enumerate() {
+        for symbol in input.object.symbols.iter() {
+            let Some(section) = symbol.section else {
+                continue;
+            };
+            let address = linked
+                .placement(i, section)
+                .address
+                .wrapping_add(symbol.value);
+            if symbol.global {
+                if let Some((first, _)) = globals.get(symbol.name.as_str()) {
+                    errors.push(LinkError::Duplicate {
+                        symbol: symbol.name.clone(),
+                        first: inputs[*first].name.clone(),
+                        second: input.name.clone(),
+                    });
+                    continue;
+                }
+                globals.insert(&symbol.name, (i, address));
+            }
+            linked.symbols.push(LinkedSymbol {
+                name: symbol.name.clone(),
+                object: i,
+                section,
+                address,
+                global: symbol.global,
+            });
+        }
+    }
+
+    for (i, input) in inputs.iter().enumerate() {
+        for relocation in input.object.relocations.iter() {
+            let field = linked
+                .placement(i, relocation.section)
+                .address
+                .wrapping_add(relocation.offset);
+            let fixup = |import| Fixup {
+                offset: field - base,
+                size: relocation.size,
+                pc_relative: relocation.pc_relative,
+                import,
+                addend: relocation.addend,
+            };
+            let target = match &relocation.target {
+                Target::Absolute => 0,
+                Target::Section(kind) => linked.placement(i, *kind).address,
+                Target::Symbol(name) => match globals.get(name.as_str()) {
+                    Some((_, address)) => *address,
+                    None if fixups.is_some() => {
+                        fixups.as_mut().unwrap().push(fixup(Some(name.clone())));
+                        continue;
+                    }
+                    None => {
+                        let error = LinkError::Undefined {
+                            symbol: name.clone(),
+                            object: input.name.clone(),
+                        };
+                        if !errors.contains(&error) {
+                            errors.push(error);
+                        }
+                        continue;
+                    }
+                },
+            };
+            // a field moves with the library when exactly one of it and its target does.
+            let absolute = relocation.target == Target::Absolute;
+            if let Some(fixups) = fixups.as_mut() {
+                if absolute == relocation.pc_relative {
+                    fixups.push(fixup(None));
+                }
+            }
+
+            let mut value = target as i64 + relocation.addend as i64;
+            if relocation.pc_relative {
+                value -= field as i64;
+            }
+
+            let bits = relocation.size as u32 * 8;
+            let fits = bits >= 32 || (value >= -(1i64 << (bits - 1)) && value < (1i64 << bits));
+            if !fits {
+                errors.push(LinkError::Overflow {
+                    object: input.name.clone(),
+                    section: relocation.section,
+                    offset: relocation.offset,
+                    value,
+                    size: relocation.size,
+                });
+                continue;
+            }
+
+            let start = (field - base) as usize;
+            let size = relocation.size as usize;
+            linked.bytes[start..start + size].copy_from_slice(&value.to_le_bytes()[..size]);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(linked)
+    } else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt::Write;
+
+use crate::assembler::SourceLine;
+use crate::image::Image;
+use crate::linker::{Input, Linked};
+use crate::object::{Object, SectionKind};
+
+/// A source line and where its bytes went, recorded by the assembler.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingEntry {
+    pub line: SourceLine,
+    pub section: SectionKind,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// The address and contents of each section, in `SectionKind` order.
+/// `.bss` has no contents.
+pub type Layout<'a> = [(u32, &'a [u8]); 3];
+
+/// Sections of an unlinked object, each starting at 0.
+pub fn object_layout(object: &Object) -> Layout<'_> {
+    SectionKind::ALL.map(|kind| (0, object.section(kind).bytes.as_slice()))
+}
+
+/// Sections of `object` where the linker placed them, with relocations applied.
+pub fn linked_layout(linked: &Linked, object: usize) -> Layout<'_> {
+    SectionKind::ALL.map(|kind| {
+        let placement = linked.placement(object, kind);
+        if kind == SectionKind::Bss {
+            return (placement.address, &[][..]);
+        }
+        let start = (placement.address - linked.base) as usize;
+        (
+            placement.address,
+            &linked.bytes[start..start + placement.size as usize],
+        )
+    })
+}
+
+const BYTES_PER_ROW: usize = 8;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Source lines side by side with their addresses and encoded bytes.
+pub fn listing(entries: &[ListingEntry], layout: Layout) -> String {
+    let mut out = String::new();
+    let mut file = None;
+    for entry in entries.iter() {
+        // includes and macros from other files get a header where they start.
+        if file != Some(&entry.line.file) {
+            writeln!(out, "; {}", entry.line.file).unwrap();
+            file = Some(&entry.line.file);
+        }
+        let (base, bytes) = layout[entry.section.index()];
+        let address = base.wrapping_add(entry.offset);
+        let start = entry.offset as usize;
+        let contents = bytes.get(start..start + entry.size as usize).unwrap_or(&[]);
+        let mut rows = contents.chunks(BYTES_PER_ROW);
+
+        let first = rows.next().unwrap_or(&[]);
+        writeln!(
+            out,
+            "{:08X}  {:<width$}  {:>5}  {}",
+            address,
+            hex(first),
+            entry.line.line,
+            entry.line.text,
+            width = BYTES_PER_ROW * 3 - 1
+        )
+        .unwrap();
+        for (i, row) in rows.enumerate() {
+            let address = address.wrapping_add(((i + 1) * BYTES_PER_ROW) as u32);
+            writeln!(out, "{:08X}  {}", address, hex(row)).unwrap();
+        }
+    }
+    out
+}
+
+/// The memory layout of a link: segments, every section of every object and every
+/// symbol with its size. `image` is `None` for flat binaries.
+pub fn map(inputs: &[Input], linked: &Linked, image: Option<&Image>) -> String {
+    let mut out = String::new();
+    let bss_address = linked.base.wrapping_add(linked.bytes.len() as u32);
+
+    writeln!(out, "segments").unwrap();
+    writeln!(out, "  {:<10}  {:<10}  kind", "address", "size").unwrap();
+    match image {
+        Some(image) => {
+            for segment in image.segments.iter() {
+                let len = segment.bytes.len();
+                writeln!(out, "  0x{:08X}  0x{:08X}  load", segment.address, len).unwrap();
+            }
+            writeln!(
+                out,
+                "  0x{:08X}  0x{:08X}  bss",
+                image.bss_address, image.bss_size
+            )
+            .unwrap();
+            match image.stack_top {
+                0 => writeln!(
+                    out,
+                    "  {:<10}  0x{:08X}  stack, at the end of memory",
+                    "-", image.stack_size
+                ),
+                top => writeln!(
+                    out,
+                    "  0x{:08X}  0x{:08X}  stack",
+                    top.wrapping_sub(image.stack_size),
+                    image.stack_size
+                ),
+            }
+            .unwrap();
+            writeln!(out, "  entry 0x{:08X}", image.entry).unwrap();
+        }
+        None => {
+            let len = linked.bytes.len();
+            writeln!(out, "  0x{:08X}  0x{:08X}  flat binary", linked.base, len).unwrap();
+            writeln!(
+                out,
+                "  0x{:08X}  0x{:08X}  bss",
+                bss_address, linked.bss_size
+            )
+            .unwrap();
+        }
+    }
+
+    writeln!(out, "\nsections").unwrap();
+    writeln!(
+        out,
+        "  {:<10}  {:<10}  {:<7} object",
+        "address", "size", "section"
+    )
+    .unwrap();
+    for placement in linked.placements.iter().filter(|p| p.size > 0) {
+        writeln!(
+            out,
+            "  0x{:08X}  0x{:08X}  {:<7} {}",
+            placement.address,
+            placement.size,
+            placement.section.name(),
+            inputs[placement.object].name
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\nsymbols").unwrap();
+    writeln!(
+        out,
+        "  {:<10}  {:<10}  {:<7} {:<6} name",
+        "address", "size", "section", "scope"
+    )
+    .unwrap();
+    let mut symbols = linked.symbols.iter().collect::<Vec<_>>();
+    symbols.sort_by_key(|symbol| (symbol.address, !symbol.global));
+    for symbol in symbols {
+        writeln!(
+            out,
+            "  0x{:08X}  0x{:08X}  {:<7} {:<6} {} ({})",
+            symbol.address,
+            linked.symbol_size(symbol),
+            symbol.section.name(),
+            if symbol.global { "global" } else { "local" },
+            symbol.name,
+            inputs[symbol.object].name
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::assembler::{tokenize, Assembler, SourceLine, Spanned, Token};
+use crate::cpu::{Cpu, NUM_REGISTERS};
+use crate::json::Json;
+use crate::object::invalid;
+use crate::opcodes::{Opcode, OperandKind, Width};
+use crate::preprocessor::Preprocessor;
+
+/// An identifier in a document. Lines and characters count from 0, like LSP positions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Occurrence {
+    pub name: String,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    /// where a label, `%macro` or `%define` is defined rather than used.
+    pub definition: bool,
+}
+
+/// Every identifier in `text`.
+pub fn occurrences(text: &str) -> Vec<Occurrence> {
+    let mut out = Vec::new();
+    for line in SourceLine::split("", text) {
+        let Ok(tokens) = tokenize(&line) else {
+            continue;
+        };
+        let directive = tokens
+            .first()
+            .is_some_and(|first| first.token == Token::Symbol("%"));
+        // labels lead the line, each followed by a colon.
+        let mut labels = 0;
+        while let [Spanned {
+            token: Token::Ident(_),
+            ..
+        }, Spanned {
+            token: Token::Symbol(":"),
+            ..
+        }, ..] = &tokens[labels * 2..]
+        {
+            labels += 1;
+        }
+
+        for (i, spanned) in tokens.iter().enumerate() {
+            let Token::Ident(name) = &spanned.token else {
+                continue;
+            };
+            let definition = match directive {
+                // `%macro name` and `%define name`, skipping the directive itself.
+                true if i == 1 => continue,
+                true => {
+                    i == 2
+                        && matches!(&tokens[1].token, Token::Ident(word) if word == "macro" || word == "define")
+                }
+                false => i < labels * 2,
+            };
+            out.push(Occurrence {
+                name: name.clone(),
+                line: line.line - 1,
+                start: spanned.column - 1,
+                end: spanned.column - 1 + name.chars().count(),
+                definition,
+            });
+        }
+    }
+    out
+}
+
+/// The file a `file://` uri points at.
+fn uri_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < path.len() {
+        let escaped = path
+            .get(i + 1..i + 3)
+            .filter(|_| path.as_bytes()[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(path.as_bytes()[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+fn range(line: usize, start: usize, end: usize) -> Json {
+    let position =
+        |character: usize| Json::object([("line", line.into()), ("character", character.into())]);
+    Json::object([("start", position(start)), ("end", position(end))])
+}
+
+fn location(uri: &str, occurrence: &Occurrence) -> Json {
+    Json::object([
+        ("uri", uri.into()),
+        (
+            "range",
+            range(occurrence.line, occurrence.start, occurrence.end),
+        ),
+    ])
+}
+
+fn kind_name(kind: OperandKind) -> &'static str {
+    match kind {
+        OperandKind::Imm => "imm",
+        OperandKind::Reg => "reg",
+        OperandKind::Abs => "abs",
+        OperandKind::Mem => "mem",
+        OperandKind::Indirect => "ind",
+    }
+}
+
+/// One line of hover text for an encoding of a mnemonic.
+fn describe(opcode: Opcode) -> String {
+    let suffix = match opcode.width() {
+        Some(Width::Byte) => ".b",
+        Some(Width::Short) => ".s",
+        Some(Width::Long) => ".l",
+        None => "",
+    };
+    let (first, second) = opcode.operand_kinds();
+    let (first_size, second_size) = opcode.operand_sizes();
+    let operands = [(first, first_size), (second, second_size)]
+        .into_iter()
+        .filter_map(|(kind, size)| kind.map(|kind| format!("{} ({})", kind_name(kind), size)))
+        .collect::<Vec<String>>();
+    format!(
+        "- `0x{:02X}` `{}{} {}`: {} bytes, {:?}",
+        opcode as u8,
+        opcode.mnemonic(),
+        suffix,
+        operands.join(", "),
+        1 + first_size + second_size,
+        opcode
+    )
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+/// What the server can do, sent in reply to `initialize`.
+fn capabilities() -> Json {
+    Json::object([(
+        "capabilities",
+        Json::object([
+            // the client sends the whole document on every change.
+            ("textDocumentSync", 1usize.into()),
+            ("definitionProvider", true.into()),
+            ("referencesProvider", true.into()),
+            ("hoverProvider", true.into()),
+            ("completionProvider", Json::object([])),
+        ]),
+    )])
+}
+
+/// Language server state: the open documents, by uri.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+}
+
+impl Server {
+    /// Handles one message, returning the replies and notifications to send back.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").as_str().unwrap_or("");
+        let params = message.get("params");
+        let uri = params
+            .get("textDocument")
+            .get("uri")
+            .as_str()
+            .unwrap_or("")
+            .to_string();
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str();
+                self.documents
+                    .insert(uri.clone(), text.unwrap_or("").to_string());
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didChange" => {
+                if let Json::Array(changes) = params.get("contentChanges") {
+                    if let Some(text) = changes.last().and_then(|c| c.get("text").as_str()) {
+                        self.documents.insert(uri.clone(), text.to_string());
+                    }
+                }
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                let params = Json::object([("uri", uri.into()), ("diagnostics", vec![].into())]);
+                return vec![notification("textDocument/publishDiagnostics", params)];
+            }
+            _ => {}
+        }
+
+        let id = message.get("id");
+        // nothing to say to any other notification.
+        if *id == Json::Null {
+            return Vec::new();
+        }
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => Json::Null,
+            "textDocument/definition" => self.definition(&uri, params),
+            "textDocument/references" => self.references(&uri, params),
+            "textDocument/hover" => self.hover(&uri, params),
+            "textDocument/completion" => completion(),
+            _ => {
+                let error = Json::object([
+                    ("code", (-32601i64).into()),
+                    ("message", format!("unknown method `{}`", method).into()),
+                ]);
+                return vec![Json::object([
+                    ("jsonrpc", "2.0".into()),
+                    ("id", id.clone()),
+                    ("error", error),
+                ])];
+            }
+        };
+        vec![Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id.clone()),
+            ("result", result),
+        ])]
+    }
+
+    /// Assembles the document and reports the first error, if any.
+    fn diagnostics(&self, uri: &str) -> Json {
+        let text = self.documents.get(uri).map_or("", |text| text.as_str());
+        let path = uri_path(uri);
+        let lines = SourceLine::split(&path, text);
+        let result = Preprocessor::new()
+            .process(&lines)
+            .and_then(|lines| Assembler::new().assemble(&lines));
+
+        let mut diagnostics = Vec::new();
+        if let Err(err) = result {
+            // errors in included files go on the first line.
+            let (line, column, message) = match err.file == path {
+                true => (err.line - 1, err.column.saturating_sub(1), err.message),
+                false => (0, 0, err.to_string()),
+            };
+            let end = text
+                .lines()
+                .nth(line)
+                .map_or(0, |text| text.chars().count());
+            diagnostics.push(Json::object([
+                ("range", range(line, column, end.max(column + 1))),
+                ("severity", 1usize.into()),
+                ("source", "bit32".into()),
+                ("message", message.into()),
+            ]));
+        }
+        let params = Json::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]);
+        notification("textDocument/publishDiagnostics", params)
+    }
+
+    /// The occurrences in the document and the one under the cursor in `params`.
+    fn lookup(&self, uri: &str, params: &Json) -> (Vec<Occurrence>, Option<Occurrence>) {
+        let text = self.documents.get(uri).map_or("", |text| text.as_str());
+        let occurrences = occurrences(text);
+        let position = params.get("position");
+        let line = position.get("line").as_u64().unwrap_or(0) as usize;
+        let character = position.get("character").as_u64().unwrap_or(0) as usize;
+        let under = occurrences
+            .iter()
+            .find(|o| o.line == line && o.start <= character && character <= o.end)
+            .cloned();
+        (occurrences, under)
+    }
+
+    fn definition(&self, uri: &str, params: &Json) -> Json {
+        let (occurrences, under) = self.lookup(uri, params);
+        let Some(under) = under else {
+            return Json::Null;
+        };
+        occurrences
+            .iter()
+            .find(|o| o.definition && o.name == under.name)
+            .map_or(Json::Null, |definition| location(uri, definition))
+    }
+
+    fn references(&self, uri: &str, params: &Json) -> Json {
+        let (occurrences, under) = self.lookup(uri, params);
+        let Some(under) = under else {
+            return Json::Null;
+        };
+        // only labels and macros have references, not mnemonics or registers.
+        let same = |o: &&Occurrence| o.name == under.name;
+        if !occurrences.iter().filter(same).any(|o| o.definition) {
+            return Json::Null;
+        }
+        let declaration = params
+            .get("context")
+            .get("includeDeclaration")
+            .as_bool()
+            .unwrap_or(true);
+        let locations = occurrences
+            .iter()
+            .filter(same)
+            .filter(|o| declaration || !o.definition)
+            .map(|o| location(uri, o))
+            .collect::<Vec<Json>>();
+        locations.into()
+    }
+
+    fn hover(&self, uri: &str, params: &Json) -> Json {
+        let (occurrences, under) = self.lookup(uri, params);
+        let Some(under) = under else {
+            return Json::Null;
+        };
+        let name = under.name.to_ascii_lowercase();
+        let (mnemonic, _) = name.rsplit_once('.').unwrap_or((&name, ""));
+
+        let encodings = Opcode::all()
+            .filter(|opcode| opcode.mnemonic() == mnemonic)
+            .map(describe)
+            .collect::<Vec<String>>();
+        let text = if !encodings.is_empty() {
+            format!("**{}**\n\n{}", mnemonic, encodings.join("\n"))
+        } else if let Some(index) = Cpu::reg_str_to_index(&name) {
+            format!("register `{}`, index {}", name, index)
+        } else if let Some(definition) = occurrences
+            .iter()
+            .find(|o| o.definition && o.name == under.name)
+        {
+            let text = self.documents[uri].lines().nth(definition.line);
+            format!(
+                "```\n{}\n```\nline {}",
+                text.unwrap_or("").trim(),
+                definition.line + 1
+            )
+        } else {
+            return Json::Null;
+        };
+        Json::object([
+            (
+                "contents",
+                Json::object([("kind", "markdown".into()), ("value", text.into())]),
+            ),
+            ("range", range(under.line, under.start, under.end)),
+        ])
+    }
+}
+
+/// Every mnemonic and register name.
+fn completion() -> Json {
+    let mut mnemonics = Opcode::all()
+        .map(|opcode| opcode.mnemonic())
+        .collect::<Vec<&str>>();
+    mnemonics.sort();
+    mnemonics.dedup();
+
+    // completion item kinds 14 and 6 are keywords and variables.
+    let item =
+        |label: &str, kind: usize| Json::object([("label", label.into()), ("kind", kind.into())]);
+    let mut items = mnemonics
+        .into_iter()
+        .map(|mnemonic| item(mnemonic, 14))
+        .collect::<Vec<Json>>();
+    items.extend((0..NUM_REGISTERS).map(|i| item(Cpu::reg_index_to_str(&i), 6)));
+    items.into()
+}
+
+/// Reads one `Content-Length` framed message, or `None` at the end of input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(invalid("message without a Content-Length header"));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|_| invalid("message is not utf-8"))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Speaks JSON-RPC over `input` and `output` until the client sends `exit`.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = Server::default();
+    while let Some(body) = read_message(&mut input)? {
+        let message = match Json::parse(&body) {
+            Ok(message) => message,
+            Err(err) => {
+                let error = Json::object([("code", (-32700i64).into()), ("message", err.into())]);
+                let reply = Json::object([
+                    ("jsonrpc", "2.0".into()),
+                    ("id", Json::Null),
+                    ("error", error),
+                ]);
+                write_message(&mut output, &reply)?;
+                continue;
+            }
+        };
+        if message.get("method").as_str() == Some("exit") {
+            break;
+        }
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+    }
+    Ok(())
+}
@@ -3,6 +3,7 @@ use hardware::{Config, Hardware};
 use std::cell::RefCell;
 use std::env::{self};
 use std::io::stdout;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::time::Instant;
 
@@ -10,21 +11,423 @@ use crossterm::terminal::LeaveAlternateScreen;
 use crossterm::{cursor, execute};
 use debug::Debugger;
 
+pub mod abi;
+pub mod archive;
+pub mod assembler;
+pub mod bus;
+pub mod compiler;
 pub mod cpu;
 pub mod debug;
+pub mod debuginfo;
+pub mod disassembler;
+pub mod exception;
 pub mod functions;
 pub mod gpu;
 pub mod handlers;
 pub mod hardware;
+pub mod image;
+pub mod json;
+pub mod library;
+pub mod linker;
+pub mod listing;
+pub mod lsp;
+pub mod mmu;
+pub mod object;
 pub mod opcodes;
+pub mod pic;
+pub mod preprocessor;
+pub mod ram;
 pub mod test;
 
+/// Flags that are followed by a value.
+const VALUE_FLAGS: [&str; 8] = [
+    "-o",
+    "-l",
+    "--map",
+    "--entry",
+    "--stack",
+    "--stack-size",
+    "--idt",
+    "--memory",
+];
+
+/// Arguments that are neither flags nor the value of one.
+fn positional(args: &[String]) -> Vec<&String> {
+    args.iter()
+        .enumerate()
+        .filter(|(i, arg)| {
+            !arg.starts_with('-') && (*i == 0 || !VALUE_FLAGS.contains(&args[i - 1].as_str()))
+        })
+        .map(|(_, arg)| arg)
+        .collect()
+}
+
+fn option<'a>(args: &'a [String], flag: &str) -> Option<&'a String> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    args.get(i + 1)
+}
+
+fn number_option(args: &[String], flag: &str) -> Option<u32> {
+    let value = option(args, flag)?;
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse::<u32>(),
+    };
+    match parsed {
+        Ok(value) => Some(value),
+        Err(_) => {
+            eprintln!("invalid value `{}` for {}", value, flag);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn link_failed(errors: Vec<linker::LinkError>) -> ! {
+    for err in errors {
+        eprintln!("{}: error: {}", err.object(), err);
+    }
+    std::process::exit(1);
+}
+
+/// Links `inputs` and writes them to `output` as an executable image, or as a
+/// flat binary with `--raw`. `--map <file>` also writes the memory layout.
+fn write_executable(inputs: &[linker::Input], args: &[String], output: &Path) -> linker::Linked {
+    let linked = linker::link(inputs, 0).unwrap_or_else(|errors| link_failed(errors));
+    debuginfo::DebugInfo::from_link(inputs, &linked)
+        .write_to_file(&debuginfo::DebugInfo::path_for(output))
+        .unwrap();
+    let write_map = |image: Option<&image::Image>| {
+        if let Some(path) = option(args, "--map") {
+            std::fs::write(path, listing::map(inputs, &linked, image)).unwrap();
+        }
+    };
+    if args.iter().any(|arg| arg == "--raw") {
+        std::fs::write(output, &linked.bytes).unwrap();
+        write_map(None);
+        return linked;
+    }
+
+    let entry = match option(args, "--entry") {
+        Some(symbol) => linked.global(symbol).unwrap_or_else(|| {
+            eprintln!("error: entry symbol `{}` is not defined", symbol);
+            std::process::exit(1);
+        }),
+        None => linked.global("start").unwrap_or(linked.base),
+    };
+    let mut image = image::Image::from_linked(&linked, entry);
+    image.stack_top = number_option(args, "--stack").unwrap_or(image.stack_top);
+    image.stack_size = number_option(args, "--stack-size").unwrap_or(image.stack_size);
+    image.idt_base = number_option(args, "--idt").unwrap_or(image.idt_base);
+    image.write_to_file(output).unwrap();
+    write_map(Some(&image));
+    linked
+}
+
+fn asm(args: &[String]) {
+    let Some(input) = positional(args).first().copied() else {
+        eprintln!("usage: bit32 asm <input> [-c | -E | --raw] [-o <output>] [-l <listing>] [--map <map>]");
+        std::process::exit(1);
+    };
+    // `-E` prints the source after macro expansion and stops.
+    if args.iter().any(|arg| arg == "-E") {
+        match preprocessor::preprocess_file(input) {
+            Ok(lines) => print!("{}", preprocessor::expanded_source(&lines)),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    // `-c` stops after assembling and writes a relocatable object instead.
+    let object_only = args.iter().any(|arg| arg == "-c");
+    let output = match option(args, "-o") {
+        Some(output) => PathBuf::from(output),
+        None if object_only => Path::new(input).with_extension("o"),
+        None => Path::new(input).with_extension("bin"),
+    };
+
+    let mut assembler = assembler::Assembler::new();
+    let result = preprocessor::preprocess_file(input).and_then(|lines| assembler.assemble(&lines));
+    let object = match result {
+        Ok(object) => object,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    // `-l` writes a listing, with section offsets for objects and addresses otherwise.
+    let write_listing = |layout: listing::Layout| {
+        if let Some(path) = option(args, "-l") {
+            std::fs::write(path, listing::listing(&assembler.listing, layout)).unwrap();
+        }
+    };
+    if object_only {
+        write_listing(listing::object_layout(&object));
+        object.write_to_file(&output).unwrap();
+        return;
+    }
+    let inputs = [linker::Input {
+        name: input.clone(),
+        object,
+    }];
+    let linked = write_executable(&inputs, args, &output);
+    write_listing(listing::linked_layout(&linked, 0));
+}
+
+fn cc(args: &[String]) {
+    let Some(input) = positional(args).first().copied() else {
+        eprintln!("usage: bit32 cc <input> [-S | -c | --raw] [-o <output>] [--map <map>]");
+        std::process::exit(1);
+    };
+    let lines = match compiler::compile_file(input) {
+        Ok(lines) => lines,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    // `-S` stops after compiling and writes the assembly instead.
+    if args.iter().any(|arg| arg == "-S") {
+        let output = match option(args, "-o") {
+            Some(output) => PathBuf::from(output),
+            None => Path::new(input).with_extension("asm"),
+        };
+        std::fs::write(output, preprocessor::expanded_source(&lines)).unwrap();
+        return;
+    }
+    let object_only = args.iter().any(|arg| arg == "-c");
+    let output = match option(args, "-o") {
+        Some(output) => PathBuf::from(output),
+        None if object_only => Path::new(input).with_extension("o"),
+        None => Path::new(input).with_extension("bin"),
+    };
+
+    let object = match assembler::Assembler::new().assemble(&lines) {
+        Ok(object) => object,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    if object_only {
+        object.write_to_file(&output).unwrap();
+        return;
+    }
+    let input = linker::Input {
+        name: input.clone(),
+        object,
+    };
+    write_executable(&[input], args, &output);
+}
+
+fn link(args: &[String]) {
+    let output = match option(args, "-o") {
+        Some(output) => PathBuf::from(output),
+        None => PathBuf::from("a.bin"),
+    };
+    let files = positional(args);
+    if files.is_empty() {
+        eprintln!("usage: bit32 link <objects and archives...> [-o <output>] [--raw | --shared] [--map <map>] [--entry <symbol>] [--stack <address>] [--stack-size <size>] [--idt <address>]");
+        std::process::exit(1);
+    }
+
+    let mut inputs = Vec::new();
+    let mut archives = Vec::new();
+    for file in files {
+        let bytes = std::fs::read(file);
+        let result = bytes.and_then(|bytes| {
+            if archive::Archive::is_archive(&bytes) {
+                archives.push((file.clone(), archive::Archive::from_bytes(&bytes)?));
+                return Ok(());
+            }
+            inputs.push(linker::Input {
+                name: file.clone(),
+                object: object::Object::from_bytes(&bytes)?,
+            });
+            Ok(())
+        });
+        if let Err(err) = result {
+            eprintln!("{}: {}", file, err);
+            std::process::exit(1);
+        }
+    }
+    linker::add_archive_members(&mut inputs, &archives);
+    // `--shared` writes a library for `syscall 4` to load at runtime instead.
+    if args.iter().any(|arg| arg == "--shared") {
+        let library = linker::link_shared(&inputs).unwrap_or_else(|errors| link_failed(errors));
+        library.write_to_file(&output).unwrap();
+        return;
+    }
+    write_executable(&inputs, args, &output);
+}
+
+fn ar(args: &[String]) {
+    let usage = || -> ! {
+        eprintln!("usage: bit32 ar create <archive> <objects...>");
+        eprintln!("       bit32 ar list <archive>");
+        eprintln!("       bit32 ar extract <archive> [members...]");
+        std::process::exit(1);
+    };
+    let (Some(command), Some(path)) = (args.first(), args.get(1)) else {
+        usage();
+    };
+    let fail = |file: &str, err: std::io::Error| -> ! {
+        eprintln!("{}: {}", file, err);
+        std::process::exit(1);
+    };
+
+    match command.as_str() {
+        "create" => {
+            let mut members = Vec::new();
+            for file in args[2..].iter() {
+                let object = object::Object::read_from_file(file).unwrap_or_else(|err| fail(file, err));
+                let name = Path::new(file).file_name().unwrap().to_string_lossy().to_string();
+                members.push(archive::Member { name, object });
+            }
+            archive::Archive::new(members)
+                .write_to_file(path)
+                .unwrap_or_else(|err| fail(path, err));
+        }
+        "list" => {
+            let archive = archive::Archive::read_from_file(path).unwrap_or_else(|err| fail(path, err));
+            for (i, member) in archive.members.iter().enumerate() {
+                println!("{}", member.name);
+                for (symbol, _) in archive.index.iter().filter(|(_, m)| *m as usize == i) {
+                    println!("    {}", symbol);
+                }
+            }
+        }
+        "extract" => {
+            let archive = archive::Archive::read_from_file(path).unwrap_or_else(|err| fail(path, err));
+            let wanted = &args[2..];
+            for name in wanted {
+                if !archive.members.iter().any(|member| member.name == *name) {
+                    eprintln!("{}: no member named {}", path, name);
+                    std::process::exit(1);
+                }
+            }
+            for member in archive.members.iter() {
+                if wanted.is_empty() || wanted.contains(&member.name) {
+                    member
+                        .object
+                        .write_to_file(&member.name)
+                        .unwrap_or_else(|err| fail(&member.name, err));
+                }
+            }
+        }
+        _ => usage(),
+    }
+}
+
+fn disasm(args: &[String]) {
+    let Some(input) = args.first() else {
+        eprintln!("usage: bit32 disasm <file>");
+        std::process::exit(1);
+    };
+    let program = std::fs::read(input).unwrap_or_else(|err| {
+        eprintln!("{}: {}", input, err);
+        std::process::exit(1);
+    });
+
+    // name addresses when the debug info sidecar is around.
+    let info = debuginfo::DebugInfo::read_from_file(&debuginfo::DebugInfo::path_for(input)).ok();
+    let print = |bytes: &[u8], base: u32| {
+        for instruction in disassembler::disassemble(bytes, base) {
+            let text = match &info {
+                Some(info) => instruction.symbolic_text(|address| info.symbolize(address)),
+                None => instruction.text(),
+            };
+            if let Some(label) = info.as_ref().and_then(|info| {
+                info.symbols
+                    .iter()
+                    .find(|symbol| symbol.address == instruction.address)
+            }) {
+                println!("{}:", label.name);
+            }
+            println!("{}", instruction.row(&text));
+        }
+    };
+
+    let Ok(image) = image::Image::from_bytes(&program) else {
+        print(&program, 0);
+        return;
+    };
+    println!("entry 0x{:08X}", image.entry);
+    for segment in image.segments.iter() {
+        println!("\nsegment 0x{:08X}:", segment.address);
+        print(&segment.bytes, segment.address);
+    }
+}
+
 fn main() {
     let args = env::args().collect::<Vec<String>>();
     let file = args[1].clone();
 
+    if file == "asm" {
+        asm(&args[2..]);
+        return;
+    }
+    if file == "ar" {
+        ar(&args[2..]);
+        return;
+    }
+    if file == "cc" {
+        cc(&args[2..]);
+        return;
+    }
+    if file == "disasm" {
+        disasm(&args[2..]);
+        return;
+    }
+    if file == "link" {
+        link(&args[2..]);
+        return;
+    }
+    // a language server for editors, speaking json-rpc over stdio.
+    if file == "lsp" {
+        if let Err(err) = lsp::serve(std::io::stdin().lock(), stdout().lock()) {
+            eprintln!("lsp: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // executables are images unless `raw` asks for a flat binary loaded at 0.
+    let raw = args.contains(&String::from("raw"));
+    // `abi` checks that every call preserves the registers the calling convention says it must.
+    let check_abi = args.contains(&String::from("abi"));
+    // `--memory <bytes>` sizes guest memory, which is only allocated as it's used.
+    let memory = number_option(&args, "--memory")
+        .map_or(cpu::Memory::DEFAULT_SIZE, |size| size as usize);
+    let load = |cpu: &mut Cpu| {
+        if check_abi {
+            cpu.abi_checker = Some(abi::AbiChecker::default());
+        }
+        let result = if raw {
+            cpu.load_program_from_file(&file)
+        } else {
+            cpu.load_image_from_file(&file)
+        };
+        if let Err(err) = result {
+            eprintln!("{}: {}", file, err);
+            std::process::exit(1);
+        }
+    };
+    let report = |cpu: &Cpu| {
+        if let Some(fault) = &cpu.fault_report {
+            eprintln!("{}", fault);
+        }
+        let Some(checker) = &cpu.abi_checker else {
+            return;
+        };
+        for violation in checker.violations.iter() {
+            eprintln!("abi violation: {}", violation);
+        }
+    };
+
     if args.contains(&String::from("debug")) {
-        let mut debugger = Debugger { file: file.clone() };
+        let mut debugger = Debugger::new(&file, raw, memory);
         std::panic::set_hook(Box::new(|info| {
             execute!(stdout(), LeaveAlternateScreen).unwrap();
             execute!(stdout(), cursor::Show).unwrap();
@@ -32,26 +435,34 @@ fn main() {
         }));
         debugger.run(&file);
     } else if args.contains(&String::from("graphical")) {
-        let cpu = Rc::new(RefCell::new(Cpu::new()));
+        let cpu = Rc::new(RefCell::new(Cpu::with_memory(memory)));
         let gpu = Rc::new(RefCell::new(gpu::GPU::new()));
         let cfg = Config {
             cpu: cpu.clone(),
             id: 0,
+            interrupts: cpu.borrow().interrupts.clone(),
         };
         gpu.clone().borrow_mut().init(cfg);
         let cpu = cpu.clone();
         cpu.borrow_mut().hardware.push(gpu.clone());
-        cpu.borrow_mut().load_program_from_file(&file).unwrap();
+        // the text buffer is the gpu's own vram.
+        let vram = bus::Region::Device(gpu.clone());
+        cpu.borrow_mut()
+            .memory
+            .bus
+            .map(Cpu::VGA_BUFFER_ADDRESS, gpu::GPU::MAPPED_SIZE, vram)
+            .expect("nothing else is mapped yet");
+        load(&mut cpu.borrow_mut());
         let start = Instant::now();
-        let mut cycles = 0;
         while !cpu.borrow_mut().has_flag(Cpu::HALT_FLAG) {
             cpu.borrow_mut().cycle();
-            cycles += 1;
         }
+        report(&cpu.borrow());
 
+        // instructions cost different numbers of cycles, see `Cpu::costs`.
         let elapsed = start.elapsed();
         let seconds = elapsed.as_secs_f64();
-        let clock_speed_hz = cycles as f64 / seconds;
+        let clock_speed_hz = cpu.borrow().cycles as f64 / seconds;
         if clock_speed_hz >= 1_000_000.0 {
             println!(
             "Average CPU clock speed: {:.2} Mhz",
@@ -69,19 +480,16 @@ fn main() {
             );
         }
     } else {
-        let mut cpu = Cpu::new();
-        cpu.load_program_from_file(&file).unwrap();
+        let mut cpu = Cpu::with_memory(memory);
+        load(&mut cpu);
 
         let start = Instant::now();
-        let mut cycles = 0;
-        while !cpu.has_flag(Cpu::HALT_FLAG) {
-            cpu.cycle();
-            cycles += 1;
-        }
+        cpu.run();
+        report(&cpu);
 
         let elapsed = start.elapsed();
         let seconds = elapsed.as_secs_f64();
-        let clock_speed_hz = cycles as f64 / seconds;
+        let clock_speed_hz = cpu.cycles as f64 / seconds;
         println!(
             "Average CPU clock speed: {:.2} Mhz",
             clock_speed_hz / 1_000_000.0
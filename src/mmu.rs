@@ -0,0 +1,165 @@
+use crate::ram::Ram;
+
+/// Translates virtual addresses through page tables the guest keeps in memory.
+///
+/// Paging is on while `base`, the `ptb` register, isn't 0. Like x86 without
+/// the extensions there are two levels of 4 KiB tables with 1024 four byte
+/// entries each: the top 10 bits of an address pick a directory entry, the
+/// next 10 an entry in the table it points at, and the low 12 are the offset
+/// into the page. An entry holds the address of the next table or of the page
+/// in its top 20 bits and the `PRESENT`, `WRITABLE`, `USER` and `EXECUTABLE`
+/// bits below, and an access needs the bit in both levels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mmu {
+    /// physical address of the page directory, 0 when paging is off.
+    pub base: u32,
+    /// only pages with `USER` can be reached, set while the cpu runs user code.
+    pub user: bool,
+    tlb: [Option<TlbEntry>; Mmu::TLB_SIZE],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TlbEntry {
+    page: u32,
+    frame: u32,
+    /// the permission bits of both levels, and-ed.
+    flags: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// Why a translation failed, reported through the page fault vector with
+/// `address` in the `pfa` register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFault {
+    pub address: u32,
+    /// `PRESENT` when the page was there but didn't allow the access, plus
+    /// `FAULT_WRITE` or `FAULT_EXECUTE` for the kind of access and `FAULT_USER`
+    /// when user code made it.
+    pub code: u32,
+}
+
+impl Default for Mmu {
+    fn default() -> Mmu {
+        Mmu {
+            base: 0,
+            user: false,
+            tlb: [None; Mmu::TLB_SIZE],
+        }
+    }
+}
+
+impl Mmu {
+    pub const PAGE_SIZE: u32 = 4096;
+    pub const TLB_SIZE: usize = 64;
+
+    pub const PRESENT: u32 = 1 << 0;
+    pub const WRITABLE: u32 = 1 << 1;
+    pub const USER: u32 = 1 << 2;
+    pub const EXECUTABLE: u32 = 1 << 3;
+
+    pub const FAULT_WRITE: u32 = 1 << 1;
+    pub const FAULT_USER: u32 = 1 << 2;
+    pub const FAULT_EXECUTE: u32 = 1 << 3;
+
+    pub fn enabled(&self) -> bool {
+        self.base != 0
+    }
+
+    /// Switches to the page directory at `base`, forgetting every cached
+    /// translation.
+    pub fn set_base(&mut self, base: u32) {
+        self.base = base;
+        self.flush();
+    }
+
+    pub fn flush(&mut self) {
+        self.tlb = [None; Mmu::TLB_SIZE];
+    }
+
+    /// Forgets the cached translation for the page holding `address`, for after
+    /// the guest changes its entry.
+    pub fn invalidate(&mut self, address: u32) {
+        let page = address / Mmu::PAGE_SIZE;
+        let slot = &mut self.tlb[page as usize % Mmu::TLB_SIZE];
+        if slot.is_some_and(|entry| entry.page == page) {
+            *slot = None;
+        }
+    }
+
+    /// The physical address for `address`, walking the tables in `memory` when
+    /// the tlb doesn't know the page.
+    pub fn translate(
+        &mut self,
+        memory: &Ram,
+        address: u32,
+        access: Access,
+    ) -> Result<u32, PageFault> {
+        let page = address / Mmu::PAGE_SIZE;
+        let slot = page as usize % Mmu::TLB_SIZE;
+        let entry = match self.tlb[slot] {
+            Some(entry) if entry.page == page => entry,
+            _ => {
+                let entry = self.walk(memory, address, access)?;
+                self.tlb[slot] = Some(entry);
+                entry
+            }
+        };
+
+        let (mut needed, kind) = match access {
+            Access::Read => (0, 0),
+            Access::Write => (Mmu::WRITABLE, Mmu::FAULT_WRITE),
+            Access::Execute => (Mmu::EXECUTABLE, Mmu::FAULT_EXECUTE),
+        };
+        if self.user {
+            needed |= Mmu::USER;
+        }
+        if entry.flags & needed != needed {
+            return Err(PageFault {
+                address,
+                code: Mmu::PRESENT | kind | self.user_code(),
+            });
+        }
+        Ok(entry.frame | (address % Mmu::PAGE_SIZE))
+    }
+
+    fn user_code(&self) -> u32 {
+        if self.user {
+            Mmu::FAULT_USER
+        } else {
+            0
+        }
+    }
+
+    fn walk(&self, memory: &Ram, address: u32, access: Access) -> Result<TlbEntry, PageFault> {
+        let not_present = PageFault {
+            address,
+            code: self.user_code()
+                | match access {
+                    Access::Read => 0,
+                    Access::Write => Mmu::FAULT_WRITE,
+                    Access::Execute => Mmu::FAULT_EXECUTE,
+                },
+        };
+        // tables are always in ram, and read as not present past its end.
+        let entry = |at: u32| memory.long(at as usize);
+
+        let directory = entry(self.base.wrapping_add((address >> 22) * 4))
+            .filter(|directory| directory & Mmu::PRESENT != 0)
+            .ok_or(not_present)?;
+        let table = directory & !(Mmu::PAGE_SIZE - 1);
+        let page = entry(table.wrapping_add((address >> 12 & 0x3FF) * 4))
+            .filter(|page| page & Mmu::PRESENT != 0)
+            .ok_or(not_present)?;
+        Ok(TlbEntry {
+            page: address / Mmu::PAGE_SIZE,
+            frame: page & !(Mmu::PAGE_SIZE - 1),
+            flags: directory & page & (Mmu::PAGE_SIZE - 1),
+        })
+    }
+}
@@ -0,0 +1,328 @@
+use std::io::{self, Read};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SectionKind {
+    #[default]
+    Text,
+    Data,
+    Bss,
+}
+
+impl SectionKind {
+    pub const ALL: [SectionKind; 3] = [SectionKind::Text, SectionKind::Data, SectionKind::Bss];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SectionKind::Text => ".text",
+            SectionKind::Data => ".data",
+            SectionKind::Bss => ".bss",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SectionKind> {
+        SectionKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Section {
+    /// contents, always empty for `.bss`.
+    pub bytes: Vec<u8>,
+    pub size: u32,
+    /// the linker places the section at a multiple of this. 0 and 1 mean anywhere.
+    pub align: u32,
+}
+
+/// What a relocation or expression is relative to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+    Absolute,
+    /// the start of a section in the same object.
+    Section(SectionKind),
+    /// a symbol defined in some other object.
+    Symbol(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// `None` for symbols declared `extern`.
+    pub section: Option<SectionKind>,
+    /// offset from the start of `section`.
+    pub value: u32,
+    pub global: bool,
+}
+
+/// A field of `size` bytes at `offset` in `section` that holds the address of
+/// `target + addend`, or with `pc_relative` that address minus the field's own address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub section: SectionKind,
+    pub offset: u32,
+    pub size: u8,
+    pub pc_relative: bool,
+    pub target: Target,
+    pub addend: i32,
+}
+
+/// The source line an instruction at `offset` in `section` was assembled from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineEntry {
+    pub section: SectionKind,
+    pub offset: u32,
+    pub file: String,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Object {
+    pub sections: [Section; 3],
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+    pub lines: Vec<LineEntry>,
+}
+
+impl Object {
+    pub const MAGIC: &'static [u8; 4] = b"B32O";
+    pub const VERSION: u16 = 3;
+
+    pub fn section(&self, kind: SectionKind) -> &Section {
+        &self.sections[kind.index()]
+    }
+
+    pub fn section_mut(&mut self, kind: SectionKind) -> &mut Section {
+        &mut self.sections[kind.index()]
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer::default();
+        out.bytes(Object::MAGIC);
+        out.short(Object::VERSION);
+
+        for section in self.sections.iter() {
+            out.long(section.size);
+            out.long(section.align);
+            out.long(section.bytes.len() as u32);
+            out.bytes(&section.bytes);
+        }
+
+        out.long(self.symbols.len() as u32);
+        for symbol in self.symbols.iter() {
+            out.string(&symbol.name);
+            out.byte(match symbol.section {
+                None => 0,
+                Some(kind) => kind.index() as u8 + 1,
+            });
+            out.long(symbol.value);
+            out.byte(symbol.global as u8);
+        }
+
+        out.long(self.relocations.len() as u32);
+        for relocation in self.relocations.iter() {
+            out.byte(relocation.section.index() as u8);
+            out.long(relocation.offset);
+            out.byte(relocation.size);
+            out.byte(relocation.pc_relative as u8);
+            out.target(&relocation.target);
+            out.long(relocation.addend as u32);
+        }
+
+        out.long(self.lines.len() as u32);
+        for entry in self.lines.iter() {
+            out.byte(entry.section.index() as u8);
+            out.long(entry.offset);
+            out.string(&entry.file);
+            out.long(entry.line);
+        }
+        out.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Object> {
+        let mut input = Reader { bytes, pos: 0 };
+        if input.take(4)? != Object::MAGIC {
+            return Err(invalid("not a bit32 object file"));
+        }
+        let version = input.short()?;
+        if version != Object::VERSION {
+            return Err(invalid(&format!("unsupported object version {}", version)));
+        }
+
+        let mut object = Object::default();
+        for (kind, section) in SectionKind::ALL.into_iter().zip(object.sections.iter_mut()) {
+            section.size = input.long()?;
+            section.align = input.long()?;
+            let len = input.long()? as usize;
+            section.bytes = input.take(len)?.to_vec();
+            // the linker lays sections out by size but copies their bytes.
+            let expected = match kind {
+                SectionKind::Bss => 0,
+                _ => section.size as usize,
+            };
+            if len != expected {
+                return Err(invalid(&format!(
+                    "{} holds {} bytes but is {} long",
+                    kind.name(),
+                    len,
+                    section.size
+                )));
+            }
+        }
+
+        for _ in 0..input.long()? {
+            let name = input.string()?;
+            let section = match input.byte()? {
+                0 => None,
+                n => Some(section_kind(n - 1)?),
+            };
+            let value = input.long()?;
+            let global = input.byte()? != 0;
+            object.symbols.push(Symbol {
+                name,
+                section,
+                value,
+                global,
+            });
+        }
+
+        for _ in 0..input.long()? {
+            let section = section_kind(input.byte()?)?;
+            let offset = input.long()?;
+            let size = input.byte()?;
+            let pc_relative = input.byte()? != 0;
+            let target = input.target()?;
+            let addend = input.long()? as i32;
+            // the linker patches the field in place, so it has to be there.
+            let end = offset as u64 + size as u64;
+            let fits = end <= object.section(section).bytes.len() as u64;
+            if section == SectionKind::Bss || !matches!(size, 1 | 2 | 4) || !fits {
+                return Err(invalid(&format!(
+                    "relocation of {} bytes at {}+0x{:X} is outside its section",
+                    size,
+                    section.name(),
+                    offset
+                )));
+            }
+            object.relocations.push(Relocation {
+                section,
+                offset,
+                size,
+                pc_relative,
+                target,
+                addend,
+            });
+        }
+
+        for _ in 0..input.long()? {
+            object.lines.push(LineEntry {
+                section: section_kind(input.byte()?)?,
+                offset: input.long()?,
+                file: input.string()?,
+                line: input.long()?,
+            });
+        }
+        Ok(object)
+    }
+
+    pub fn write_to_file<T: AsRef<Path> + ?Sized>(&self, path: &T) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn read_from_file<T: AsRef<Path> + ?Sized>(path: &T) -> io::Result<Object> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        Object::from_bytes(&buffer)
+    }
+}
+
+pub fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn section_kind(index: u8) -> io::Result<SectionKind> {
+    SectionKind::ALL
+        .get(index as usize)
+        .copied()
+        .ok_or_else(|| invalid(&format!("invalid section index {}", index)))
+}
+
+/// Little endian serialization helpers shared by the toolchain's file formats.
+#[derive(Default)]
+pub struct Writer(pub Vec<u8>);
+
+impl Writer {
+    pub fn byte(&mut self, value: u8) {
+        self.0.push(value);
+    }
+    pub fn short(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn long(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+    pub fn string(&mut self, value: &str) {
+        self.short(value.len() as u16);
+        self.bytes(value.as_bytes());
+    }
+    fn target(&mut self, target: &Target) {
+        match target {
+            Target::Absolute => self.byte(0),
+            Target::Section(kind) => self.byte(kind.index() as u8 + 1),
+            Target::Symbol(name) => {
+                self.byte(4);
+                self.string(name);
+            }
+        }
+    }
+}
+
+pub struct Reader<'a> {
+    pub bytes: &'a [u8],
+    pub pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.pos + len > self.bytes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated file",
+            ));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+    pub fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn short(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+    pub fn long(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    pub fn string(&mut self) -> io::Result<String> {
+        let len = self.short()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("invalid utf8 in string"))
+    }
+    fn target(&mut self) -> io::Result<Target> {
+        match self.byte()? {
+            0 => Ok(Target::Absolute),
+            4 => Ok(Target::Symbol(self.string()?)),
+            n => Ok(Target::Section(section_kind(n - 1)?)),
+        }
+    }
+}
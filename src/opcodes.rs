@@ -1,5 +1,5 @@
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
     Hlt,
 
@@ -327,18 +327,102 @@ pub enum Opcode {
     Return,
     Syscall,
     ClearCarry,
+
+    // stack frames, see `abi`
+    Enter,
+    Leave,
+
+    // jumps on a single flag, set by the alu
+    JumpZero,
+    JumpNotZero,
+    JumpSign,
+    JumpNotSign,
+    JumpCarry,
+    JumpNotCarry,
+    JumpOverflow,
+    JumpNotOverflow,
+
+    // compare two operands, only setting flags. the left operand is always a
+    // register, there's no room left in the opcode byte for memory on the left.
+    CompareRegImmByte,
+    CompareRegImmShort,
+    CompareRegImmLong,
+
+    CompareRegRegByte,
+    CompareRegRegShort,
+    CompareRegRegLong,
+
+    CompareRegAbsByte,
+    CompareRegAbsShort,
+    CompareRegAbsLong,
+
+    CompareRegIndirectByte,
+    CompareRegIndirectShort,
+    CompareRegIndirectLong,
+
+    // jumps on the flags of a two operand compare
+    JumpAbove,
+    JumpAboveEqual,
+    JumpBelow,
+    JumpBelowEqual,
+    JumpGreaterThan,
+    JumpGreaterThanEqual,
+    JumpLessThan,
+    JumpLessThanEqual,
+
+    DisableInterrupts,
+    EnableInterrupts,
+
+    // paging, see `mmu`
+    InvalidatePage,
+
+    // enters the supervisor, see `Cpu::USER_FLAG`
+    Trap,
+
+    // the cycle counter, see `Cpu::cycles`
+    ReadCycles,
     
     // * This must ALWAYS! be the last opcode.
     Nop,
 }
 
+/// How an instruction operand is encoded and interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    /// a literal value, or an address for jumps and calls.
+    Imm,
+    /// a register index.
+    Reg,
+    /// an absolute memory address.
+    Abs,
+    /// a memory address relative to the end of the instruction.
+    Mem,
+    /// a register index holding a memory address.
+    Indirect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Short,
+    Long,
+}
+
+impl Width {
+    pub fn size(&self) -> usize {
+        match self {
+            Width::Byte => 1,
+            Width::Short => 2,
+            Width::Long => 4,
+        }
+    }
+}
+
 impl From<u8> for Opcode {
     fn from(value: u8) -> Self {
-        if value > Opcode::Nop as u8 {
-            panic!("Invalid opcode: {}", value);
-        }
-        unsafe {
-            return std::mem::transmute::<u8, Opcode>(value);
+        match Opcode::from_byte(value) {
+            Some(opcode) => opcode,
+            None => panic!("Invalid opcode: {}", value),
         }
     }
 }
@@ -364,16 +448,25 @@ impl Opcode {
             | Opcode::MoveRegIndirectByte
             | Opcode::MoveRegIndirectShort
             | Opcode::MoveRegIndirectLong
+            | Opcode::ReadCycles
             | Opcode::ReadByte
             | Opcode::ReadShort
             | Opcode::ReadLong
             | Opcode::WriteByteReg
             | Opcode::WriteShortReg
             | Opcode::WriteLongReg
+            | Opcode::CompareRegImmByte
+            | Opcode::CompareRegRegByte
+            | Opcode::CompareRegRegShort
+            | Opcode::CompareRegRegLong
+            | Opcode::CompareRegIndirectByte
+            | Opcode::CompareRegIndirectShort
+            | Opcode::CompareRegIndirectLong
             | Opcode::WriteByteImm => (1, 1),
             // second arg short
             Opcode::MoveImmRegShort
             | Opcode::MoveImmIndirectShort
+            | Opcode::CompareRegImmShort
             | Opcode::WriteShortImm => (1, 2),
             // second arg long
             Opcode::MoveImmRegLong
@@ -390,6 +483,10 @@ impl Opcode {
             | Opcode::MoveMemIndirectByte
             | Opcode::MoveMemIndirectShort
             | Opcode::MoveMemIndirectLong
+            | Opcode::CompareRegImmLong
+            | Opcode::CompareRegAbsByte
+            | Opcode::CompareRegAbsShort
+            | Opcode::CompareRegAbsLong
             | Opcode::WriteLongImm => (1, 4),
 
             // first arg long
@@ -524,8 +621,22 @@ impl Opcode {
             | Opcode::ArithShiftRightByteImm
             | Opcode::RotateLeftByteImm
             | Opcode::RotateRightByteImm
+            // shift amounts are always a single byte
+            | Opcode::LogShiftLeftShortImm
+            | Opcode::LogShiftLeftLongImm
+            | Opcode::LogShiftRightShortImm
+            | Opcode::LogShiftRightLongImm
+            | Opcode::ArithShiftLeftShortImm
+            | Opcode::ArithShiftLeftLongImm
+            | Opcode::ArithShiftRightShortImm
+            | Opcode::ArithShiftRightLongImm
+            | Opcode::RotateLeftShortImm
+            | Opcode::RotateLeftLongImm
+            | Opcode::RotateRightShortImm
+            | Opcode::RotateRightLongImm
             // other
             | Opcode::Interrupt
+            | Opcode::InvalidatePage
             | Opcode::Syscall => (1,0),
 
             // short imm
@@ -541,13 +652,7 @@ impl Opcode {
             | Opcode::SignedMulShortImm
             | Opcode::SignedDivShortImm
             | Opcode::OrShortImm
-            | Opcode::XorShortImm
-            | Opcode::LogShiftLeftShortImm
-            | Opcode::LogShiftRightShortImm
-            | Opcode::ArithShiftLeftShortImm
-            | Opcode::ArithShiftRightShortImm
-            | Opcode::RotateLeftShortImm
-            | Opcode::RotateRightShortImm => (2,0),
+            | Opcode::XorShortImm => (2,0),
 
             // long imm
             Opcode::AddLongImm
@@ -563,12 +668,6 @@ impl Opcode {
             | Opcode::SignedDivLongImm
             | Opcode::OrLongImm
             | Opcode::XorLongImm
-            | Opcode::LogShiftLeftLongImm
-            | Opcode::LogShiftRightLongImm
-            | Opcode::ArithShiftLeftLongImm
-            | Opcode::ArithShiftRightLongImm
-            | Opcode::RotateLeftLongImm
-            | Opcode::RotateRightLongImm
             // jumps
             | Opcode::JumpEqual
             | Opcode::JumpNotEqual
@@ -581,15 +680,874 @@ impl Opcode {
             | Opcode::JumpSignedGreaterEqual
             | Opcode::JumpSignedLess
             | Opcode::JumpSignedLessEqual
+            | Opcode::JumpZero
+            | Opcode::JumpNotZero
+            | Opcode::JumpSign
+            | Opcode::JumpNotSign
+            | Opcode::JumpCarry
+            | Opcode::JumpNotCarry
+            | Opcode::JumpOverflow
+            | Opcode::JumpNotOverflow
+            | Opcode::JumpAbove
+            | Opcode::JumpAboveEqual
+            | Opcode::JumpBelow
+            | Opcode::JumpBelowEqual
+            | Opcode::JumpGreaterThan
+            | Opcode::JumpGreaterThanEqual
+            | Opcode::JumpLessThan
+            | Opcode::JumpLessThanEqual
             // call
-            | Opcode::Call => (4, 0),
+            | Opcode::Call
+            | Opcode::Enter => (4, 0),
 
             // nullary ops
             Opcode::InterruptReturn
             | Opcode::Return
             | Opcode::Hlt
             | Opcode::ClearCarry
+            | Opcode::DisableInterrupts
+            | Opcode::EnableInterrupts
+            | Opcode::Trap
+            | Opcode::Leave
             | Opcode::Nop => (0,0),
         }
     }
-}
\ No newline at end of file
+
+    /// Like `Opcode::from`, but returns `None` for bytes past the last opcode.
+    pub fn from_byte(value: u8) -> Option<Opcode> {
+        if value > Opcode::Nop as u8 {
+            return None;
+        }
+        unsafe { Some(std::mem::transmute::<u8, Opcode>(value)) }
+    }
+
+    /// Every valid opcode, in encoding order.
+    pub fn all() -> impl Iterator<Item = Opcode> {
+        (0..=Opcode::Nop as u8).map(Opcode::from)
+    }
+
+    /// The assembly mnemonic, shared by every width and operand variant.
+    pub fn mnemonic(&self) -> &'static str {
+        match *self {
+            Opcode::Hlt => "hlt",
+            Opcode::MoveImmRegByte
+            | Opcode::MoveImmRegShort
+            | Opcode::MoveImmRegLong
+            | Opcode::MoveRegRegByte
+            | Opcode::MoveRegRegShort
+            | Opcode::MoveRegRegLong
+            | Opcode::MoveAbsRegByte
+            | Opcode::MoveAbsRegShort
+            | Opcode::MoveAbsRegLong
+            | Opcode::MoveMemRegByte
+            | Opcode::MoveMemRegShort
+            | Opcode::MoveMemRegLong
+            | Opcode::MoveIndirectRegByte
+            | Opcode::MoveIndirectRegShort
+            | Opcode::MoveIndirectRegLong
+            | Opcode::MoveImmAbsByte
+            | Opcode::MoveImmAbsShort
+            | Opcode::MoveImmAbsLong
+            | Opcode::MoveRegAbsByte
+            | Opcode::MoveRegAbsShort
+            | Opcode::MoveRegAbsLong
+            | Opcode::MoveAbsAbsByte
+            | Opcode::MoveAbsAbsShort
+            | Opcode::MoveAbsAbsLong
+            | Opcode::MoveMemAbsByte
+            | Opcode::MoveMemAbsShort
+            | Opcode::MoveMemAbsLong
+            | Opcode::MoveIndirectAbsByte
+            | Opcode::MoveIndirectAbsShort
+            | Opcode::MoveIndirectAbsLong
+            | Opcode::MoveImmMemByte
+            | Opcode::MoveImmMemShort
+            | Opcode::MoveImmMemLong
+            | Opcode::MoveRegMemByte
+            | Opcode::MoveRegMemShort
+            | Opcode::MoveRegMemLong
+            | Opcode::MoveAbsMemByte
+            | Opcode::MoveAbsMemShort
+            | Opcode::MoveAbsMemLong
+            | Opcode::MoveMemMemByte
+            | Opcode::MoveMemMemShort
+            | Opcode::MoveMemMemLong
+            | Opcode::MoveIndirectMemByte
+            | Opcode::MoveIndirectMemShort
+            | Opcode::MoveIndirectMemLong
+            | Opcode::MoveImmIndirectByte
+            | Opcode::MoveImmIndirectShort
+            | Opcode::MoveImmIndirectLong
+            | Opcode::MoveRegIndirectByte
+            | Opcode::MoveRegIndirectShort
+            | Opcode::MoveRegIndirectLong
+            | Opcode::MoveAbsIndirectByte
+            | Opcode::MoveAbsIndirectShort
+            | Opcode::MoveAbsIndirectLong
+            | Opcode::MoveMemIndirectByte
+            | Opcode::MoveMemIndirectShort
+            | Opcode::MoveMemIndirectLong
+            | Opcode::MoveIndirectIndirectByte
+            | Opcode::MoveIndirectIndirectShort
+            | Opcode::MoveIndirectIndirectLong => "mov",
+            Opcode::AddByteImm
+            | Opcode::AddShortImm
+            | Opcode::AddLongImm
+            | Opcode::AddByteReg
+            | Opcode::AddShortReg
+            | Opcode::AddLongReg => "add",
+            Opcode::AddCarryByteImm
+            | Opcode::AddCarryShortImm
+            | Opcode::AddCarryLongImm
+            | Opcode::AddCarryByteReg
+            | Opcode::AddCarryShortReg
+            | Opcode::AddCarryLongReg => "adc",
+            Opcode::SubByteImm
+            | Opcode::SubShortImm
+            | Opcode::SubLongImm
+            | Opcode::SubByteReg
+            | Opcode::SubShortReg
+            | Opcode::SubLongReg => "sub",
+            Opcode::SubBorrowByteImm
+            | Opcode::SubBorrowShortImm
+            | Opcode::SubBorrowLongImm
+            | Opcode::SubBorrowByteReg
+            | Opcode::SubBorrowShortReg
+            | Opcode::SubBorrowLongReg => "sbb",
+            Opcode::MulByteImm
+            | Opcode::MulShortImm
+            | Opcode::MulLongImm
+            | Opcode::MulByteReg
+            | Opcode::MulShortReg
+            | Opcode::MulLongReg => "mul",
+            Opcode::DivByteImm
+            | Opcode::DivShortImm
+            | Opcode::DivLongImm
+            | Opcode::DivByteReg
+            | Opcode::DivShortReg
+            | Opcode::DivLongReg => "div",
+            Opcode::SignedMulByteImm
+            | Opcode::SignedMulShortImm
+            | Opcode::SignedMulLongImm
+            | Opcode::SignedMulByteReg
+            | Opcode::SignedMulShortReg
+            | Opcode::SignedMulLongReg => "imul",
+            Opcode::SignedDivByteImm
+            | Opcode::SignedDivShortImm
+            | Opcode::SignedDivLongImm
+            | Opcode::SignedDivByteReg
+            | Opcode::SignedDivShortReg
+            | Opcode::SignedDivLongReg => "idiv",
+            Opcode::AndByteImm
+            | Opcode::AndShortImm
+            | Opcode::AndLongImm
+            | Opcode::AndByteReg
+            | Opcode::AndShortReg
+            | Opcode::AndLongReg => "and",
+            Opcode::OrByteImm
+            | Opcode::OrShortImm
+            | Opcode::OrLongImm
+            | Opcode::OrByteReg
+            | Opcode::OrShortReg
+            | Opcode::OrLongReg => "or",
+            Opcode::XorByteImm
+            | Opcode::XorShortImm
+            | Opcode::XorLongImm
+            | Opcode::XorByteReg
+            | Opcode::XorShortReg
+            | Opcode::XorLongReg => "xor",
+            Opcode::PushByteImm
+            | Opcode::PushShortImm
+            | Opcode::PushLongImm
+            | Opcode::PushByteReg
+            | Opcode::PushShortReg
+            | Opcode::PushLongReg => "push",
+            Opcode::CompareByteImm
+            | Opcode::CompareShortImm
+            | Opcode::CompareLongImm
+            | Opcode::CompareByteReg
+            | Opcode::CompareShortReg
+            | Opcode::CompareLongReg
+            | Opcode::CompareRegImmByte
+            | Opcode::CompareRegImmShort
+            | Opcode::CompareRegImmLong
+            | Opcode::CompareRegRegByte
+            | Opcode::CompareRegRegShort
+            | Opcode::CompareRegRegLong
+            | Opcode::CompareRegAbsByte
+            | Opcode::CompareRegAbsShort
+            | Opcode::CompareRegAbsLong
+            | Opcode::CompareRegIndirectByte
+            | Opcode::CompareRegIndirectShort
+            | Opcode::CompareRegIndirectLong => "cmp",
+            Opcode::LogShiftLeftByteImm
+            | Opcode::LogShiftLeftShortImm
+            | Opcode::LogShiftLeftLongImm
+            | Opcode::LogShiftLeftByteReg
+            | Opcode::LogShiftLeftShortReg
+            | Opcode::LogShiftLeftLongReg => "shl",
+            Opcode::LogShiftRightByteImm
+            | Opcode::LogShiftRightShortImm
+            | Opcode::LogShiftRightLongImm
+            | Opcode::LogShiftRightByteReg
+            | Opcode::LogShiftRightShortReg
+            | Opcode::LogShiftRightLongReg => "shr",
+            Opcode::ArithShiftLeftByteImm
+            | Opcode::ArithShiftLeftShortImm
+            | Opcode::ArithShiftLeftLongImm
+            | Opcode::ArithShiftLeftByteReg
+            | Opcode::ArithShiftLeftShortReg
+            | Opcode::ArithShiftLeftLongReg => "sal",
+            Opcode::ArithShiftRightByteImm
+            | Opcode::ArithShiftRightShortImm
+            | Opcode::ArithShiftRightLongImm
+            | Opcode::ArithShiftRightByteReg
+            | Opcode::ArithShiftRightShortReg
+            | Opcode::ArithShiftRightLongReg => "sar",
+            Opcode::RotateLeftByteImm
+            | Opcode::RotateLeftShortImm
+            | Opcode::RotateLeftLongImm
+            | Opcode::RotateLeftByteReg
+            | Opcode::RotateLeftShortReg
+            | Opcode::RotateLeftLongReg => "rol",
+            Opcode::RotateRightByteImm
+            | Opcode::RotateRightShortImm
+            | Opcode::RotateRightLongImm
+            | Opcode::RotateRightByteReg
+            | Opcode::RotateRightShortReg
+            | Opcode::RotateRightLongReg => "ror",
+            Opcode::ReadByte
+            | Opcode::ReadShort
+            | Opcode::ReadLong => "in",
+            Opcode::WriteByteImm
+            | Opcode::WriteShortImm
+            | Opcode::WriteLongImm
+            | Opcode::WriteByteReg
+            | Opcode::WriteShortReg
+            | Opcode::WriteLongReg => "out",
+            Opcode::PopByte
+            | Opcode::PopShort
+            | Opcode::PopLong => "pop",
+            Opcode::NegateByte
+            | Opcode::NegateShort
+            | Opcode::NegateLong => "neg",
+            Opcode::NotByte
+            | Opcode::NotShort
+            | Opcode::NotLong => "not",
+            Opcode::IncrementByte
+            | Opcode::IncrementShort
+            | Opcode::IncrementLong => "inc",
+            Opcode::DecrementByte
+            | Opcode::DecrementShort
+            | Opcode::DecrementLong => "dec",
+            Opcode::JumpEqual => "je",
+            Opcode::JumpNotEqual => "jne",
+            Opcode::JumpGreater => "jg",
+            Opcode::JumpGreaterEqual => "jge",
+            Opcode::JumpLess => "jl",
+            Opcode::JumpLessEqual => "jle",
+            Opcode::JumpSignedGreater => "jsg",
+            Opcode::JumpSignedGreaterEqual => "jsge",
+            Opcode::JumpSignedLess => "jsl",
+            Opcode::JumpSignedLessEqual => "jsle",
+            Opcode::JumpImm
+            | Opcode::JumpReg => "jmp",
+            Opcode::Interrupt => "int",
+            Opcode::InterruptReturn => "iret",
+            Opcode::Call => "call",
+            Opcode::Return => "ret",
+            Opcode::Syscall => "syscall",
+            Opcode::ClearCarry => "clc",
+            Opcode::DisableInterrupts => "cli",
+            Opcode::EnableInterrupts => "sti",
+            Opcode::InvalidatePage => "invlpg",
+            Opcode::Trap => "trap",
+            Opcode::ReadCycles => "rdcyc",
+            Opcode::Enter => "enter",
+            Opcode::Leave => "leave",
+            Opcode::JumpZero => "jz",
+            Opcode::JumpNotZero => "jnz",
+            Opcode::JumpSign => "js",
+            Opcode::JumpNotSign => "jns",
+            Opcode::JumpCarry => "jc",
+            Opcode::JumpNotCarry => "jnc",
+            Opcode::JumpOverflow => "jo",
+            Opcode::JumpNotOverflow => "jno",
+            Opcode::JumpAbove => "ja",
+            Opcode::JumpAboveEqual => "jae",
+            Opcode::JumpBelow => "jb",
+            Opcode::JumpBelowEqual => "jbe",
+            Opcode::JumpGreaterThan => "jgt",
+            Opcode::JumpGreaterThanEqual => "jgte",
+            Opcode::JumpLessThan => "jlt",
+            Opcode::JumpLessThanEqual => "jlte",
+            Opcode::Nop => "nop",
+        }
+    }
+
+    /// The `.b`, `.s` or `.l` suffix selecting this variant, if any.
+    pub fn width(&self) -> Option<Width> {
+        match *self {
+            Opcode::Hlt
+            | Opcode::JumpEqual
+            | Opcode::JumpNotEqual
+            | Opcode::JumpGreater
+            | Opcode::JumpGreaterEqual
+            | Opcode::JumpLess
+            | Opcode::JumpLessEqual
+            | Opcode::JumpSignedGreater
+            | Opcode::JumpSignedGreaterEqual
+            | Opcode::JumpSignedLess
+            | Opcode::JumpSignedLessEqual
+            | Opcode::JumpImm
+            | Opcode::JumpReg
+            | Opcode::Interrupt
+            | Opcode::InterruptReturn
+            | Opcode::Call
+            | Opcode::Return
+            | Opcode::Syscall
+            | Opcode::ClearCarry
+            | Opcode::DisableInterrupts
+            | Opcode::EnableInterrupts
+            | Opcode::InvalidatePage
+            | Opcode::Trap
+            | Opcode::ReadCycles
+            | Opcode::Enter
+            | Opcode::Leave
+            | Opcode::JumpZero
+            | Opcode::JumpNotZero
+            | Opcode::JumpSign
+            | Opcode::JumpNotSign
+            | Opcode::JumpCarry
+            | Opcode::JumpNotCarry
+            | Opcode::JumpOverflow
+            | Opcode::JumpNotOverflow
+            | Opcode::JumpAbove
+            | Opcode::JumpAboveEqual
+            | Opcode::JumpBelow
+            | Opcode::JumpBelowEqual
+            | Opcode::JumpGreaterThan
+            | Opcode::JumpGreaterThanEqual
+            | Opcode::JumpLessThan
+            | Opcode::JumpLessThanEqual
+            | Opcode::Nop => None,
+            Opcode::MoveImmRegByte
+            | Opcode::MoveRegRegByte
+            | Opcode::MoveAbsRegByte
+            | Opcode::MoveMemRegByte
+            | Opcode::MoveIndirectRegByte
+            | Opcode::MoveImmAbsByte
+            | Opcode::MoveRegAbsByte
+            | Opcode::MoveAbsAbsByte
+            | Opcode::MoveMemAbsByte
+            | Opcode::MoveIndirectAbsByte
+            | Opcode::MoveImmMemByte
+            | Opcode::MoveRegMemByte
+            | Opcode::MoveAbsMemByte
+            | Opcode::MoveMemMemByte
+            | Opcode::MoveIndirectMemByte
+            | Opcode::MoveImmIndirectByte
+            | Opcode::MoveRegIndirectByte
+            | Opcode::MoveAbsIndirectByte
+            | Opcode::MoveMemIndirectByte
+            | Opcode::MoveIndirectIndirectByte
+            | Opcode::AddByteImm
+            | Opcode::AddByteReg
+            | Opcode::AddCarryByteImm
+            | Opcode::AddCarryByteReg
+            | Opcode::SubByteImm
+            | Opcode::SubByteReg
+            | Opcode::SubBorrowByteImm
+            | Opcode::SubBorrowByteReg
+            | Opcode::MulByteImm
+            | Opcode::MulByteReg
+            | Opcode::DivByteImm
+            | Opcode::DivByteReg
+            | Opcode::SignedMulByteImm
+            | Opcode::SignedMulByteReg
+            | Opcode::SignedDivByteImm
+            | Opcode::SignedDivByteReg
+            | Opcode::AndByteImm
+            | Opcode::AndByteReg
+            | Opcode::OrByteImm
+            | Opcode::OrByteReg
+            | Opcode::XorByteImm
+            | Opcode::XorByteReg
+            | Opcode::PushByteImm
+            | Opcode::PushByteReg
+            | Opcode::CompareByteImm
+            | Opcode::CompareByteReg
+            | Opcode::LogShiftLeftByteImm
+            | Opcode::LogShiftLeftByteReg
+            | Opcode::LogShiftRightByteImm
+            | Opcode::LogShiftRightByteReg
+            | Opcode::ArithShiftLeftByteImm
+            | Opcode::ArithShiftLeftByteReg
+            | Opcode::ArithShiftRightByteImm
+            | Opcode::ArithShiftRightByteReg
+            | Opcode::RotateLeftByteImm
+            | Opcode::RotateLeftByteReg
+            | Opcode::RotateRightByteImm
+            | Opcode::RotateRightByteReg
+            | Opcode::ReadByte
+            | Opcode::WriteByteImm
+            | Opcode::WriteByteReg
+            | Opcode::PopByte
+            | Opcode::NegateByte
+            | Opcode::NotByte
+            | Opcode::IncrementByte
+            | Opcode::CompareRegImmByte
+            | Opcode::CompareRegRegByte
+            | Opcode::CompareRegAbsByte
+            | Opcode::CompareRegIndirectByte
+            | Opcode::DecrementByte => Some(Width::Byte),
+            Opcode::MoveImmRegShort
+            | Opcode::MoveRegRegShort
+            | Opcode::MoveAbsRegShort
+            | Opcode::MoveMemRegShort
+            | Opcode::MoveIndirectRegShort
+            | Opcode::MoveImmAbsShort
+            | Opcode::MoveRegAbsShort
+            | Opcode::MoveAbsAbsShort
+            | Opcode::MoveMemAbsShort
+            | Opcode::MoveIndirectAbsShort
+            | Opcode::MoveImmMemShort
+            | Opcode::MoveRegMemShort
+            | Opcode::MoveAbsMemShort
+            | Opcode::MoveMemMemShort
+            | Opcode::MoveIndirectMemShort
+            | Opcode::MoveImmIndirectShort
+            | Opcode::MoveRegIndirectShort
+            | Opcode::MoveAbsIndirectShort
+            | Opcode::MoveMemIndirectShort
+            | Opcode::MoveIndirectIndirectShort
+            | Opcode::AddShortImm
+            | Opcode::AddShortReg
+            | Opcode::AddCarryShortImm
+            | Opcode::AddCarryShortReg
+            | Opcode::SubShortImm
+            | Opcode::SubShortReg
+            | Opcode::SubBorrowShortImm
+            | Opcode::SubBorrowShortReg
+            | Opcode::MulShortImm
+            | Opcode::MulShortReg
+            | Opcode::DivShortImm
+            | Opcode::DivShortReg
+            | Opcode::SignedMulShortImm
+            | Opcode::SignedMulShortReg
+            | Opcode::SignedDivShortImm
+            | Opcode::SignedDivShortReg
+            | Opcode::AndShortImm
+            | Opcode::AndShortReg
+            | Opcode::OrShortImm
+            | Opcode::OrShortReg
+            | Opcode::XorShortImm
+            | Opcode::XorShortReg
+            | Opcode::PushShortImm
+            | Opcode::PushShortReg
+            | Opcode::CompareShortImm
+            | Opcode::CompareShortReg
+            | Opcode::LogShiftLeftShortImm
+            | Opcode::LogShiftLeftShortReg
+            | Opcode::LogShiftRightShortImm
+            | Opcode::LogShiftRightShortReg
+            | Opcode::ArithShiftLeftShortImm
+            | Opcode::ArithShiftLeftShortReg
+            | Opcode::ArithShiftRightShortImm
+            | Opcode::ArithShiftRightShortReg
+            | Opcode::RotateLeftShortImm
+            | Opcode::RotateLeftShortReg
+            | Opcode::RotateRightShortImm
+            | Opcode::RotateRightShortReg
+            | Opcode::ReadShort
+            | Opcode::WriteShortImm
+            | Opcode::WriteShortReg
+            | Opcode::PopShort
+            | Opcode::NegateShort
+            | Opcode::NotShort
+            | Opcode::IncrementShort
+            | Opcode::CompareRegImmShort
+            | Opcode::CompareRegRegShort
+            | Opcode::CompareRegAbsShort
+            | Opcode::CompareRegIndirectShort
+            | Opcode::DecrementShort => Some(Width::Short),
+            Opcode::MoveImmRegLong
+            | Opcode::MoveRegRegLong
+            | Opcode::MoveAbsRegLong
+            | Opcode::MoveMemRegLong
+            | Opcode::MoveIndirectRegLong
+            | Opcode::MoveImmAbsLong
+            | Opcode::MoveRegAbsLong
+            | Opcode::MoveAbsAbsLong
+            | Opcode::MoveMemAbsLong
+            | Opcode::MoveIndirectAbsLong
+            | Opcode::MoveImmMemLong
+            | Opcode::MoveRegMemLong
+            | Opcode::MoveAbsMemLong
+            | Opcode::MoveMemMemLong
+            | Opcode::MoveIndirectMemLong
+            | Opcode::MoveImmIndirectLong
+            | Opcode::MoveRegIndirectLong
+            | Opcode::MoveAbsIndirectLong
+            | Opcode::MoveMemIndirectLong
+            | Opcode::MoveIndirectIndirectLong
+            | Opcode::AddLongImm
+            | Opcode::AddLongReg
+            | Opcode::AddCarryLongImm
+            | Opcode::AddCarryLongReg
+            | Opcode::SubLongImm
+            | Opcode::SubLongReg
+            | Opcode::SubBorrowLongImm
+            | Opcode::SubBorrowLongReg
+            | Opcode::MulLongImm
+            | Opcode::MulLongReg
+            | Opcode::DivLongImm
+            | Opcode::DivLongReg
+            | Opcode::SignedMulLongImm
+            | Opcode::SignedMulLongReg
+            | Opcode::SignedDivLongImm
+            | Opcode::SignedDivLongReg
+            | Opcode::AndLongImm
+            | Opcode::AndLongReg
+            | Opcode::OrLongImm
+            | Opcode::OrLongReg
+            | Opcode::XorLongImm
+            | Opcode::XorLongReg
+            | Opcode::PushLongImm
+            | Opcode::PushLongReg
+            | Opcode::CompareLongImm
+            | Opcode::CompareLongReg
+            | Opcode::LogShiftLeftLongImm
+            | Opcode::LogShiftLeftLongReg
+            | Opcode::LogShiftRightLongImm
+            | Opcode::LogShiftRightLongReg
+            | Opcode::ArithShiftLeftLongImm
+            | Opcode::ArithShiftLeftLongReg
+            | Opcode::ArithShiftRightLongImm
+            | Opcode::ArithShiftRightLongReg
+            | Opcode::RotateLeftLongImm
+            | Opcode::RotateLeftLongReg
+            | Opcode::RotateRightLongImm
+            | Opcode::RotateRightLongReg
+            | Opcode::ReadLong
+            | Opcode::WriteLongImm
+            | Opcode::WriteLongReg
+            | Opcode::PopLong
+            | Opcode::NegateLong
+            | Opcode::NotLong
+            | Opcode::IncrementLong
+            | Opcode::CompareRegImmLong
+            | Opcode::CompareRegRegLong
+            | Opcode::CompareRegAbsLong
+            | Opcode::CompareRegIndirectLong
+            | Opcode::DecrementLong => Some(Width::Long),
+        }
+    }
+
+    /// Operand kinds, in the same order as `operand_sizes`.
+    pub fn operand_kinds(&self) -> (Option<OperandKind>, Option<OperandKind>) {
+        match *self {
+            Opcode::Hlt
+            | Opcode::InterruptReturn
+            | Opcode::Return
+            | Opcode::ClearCarry
+            | Opcode::DisableInterrupts
+            | Opcode::EnableInterrupts
+            | Opcode::Trap
+            | Opcode::Leave
+            | Opcode::Nop => (None, None),
+            Opcode::MoveImmRegByte
+            | Opcode::MoveImmRegShort
+            | Opcode::MoveImmRegLong => (Some(OperandKind::Reg), Some(OperandKind::Imm)),
+            Opcode::MoveRegRegByte
+            | Opcode::MoveRegRegShort
+            | Opcode::MoveRegRegLong
+            | Opcode::ReadCycles => (Some(OperandKind::Reg), Some(OperandKind::Reg)),
+            Opcode::MoveAbsRegByte
+            | Opcode::MoveAbsRegShort
+            | Opcode::MoveAbsRegLong => (Some(OperandKind::Reg), Some(OperandKind::Abs)),
+            Opcode::MoveMemRegByte
+            | Opcode::MoveMemRegShort
+            | Opcode::MoveMemRegLong => (Some(OperandKind::Reg), Some(OperandKind::Mem)),
+            Opcode::MoveIndirectRegByte
+            | Opcode::MoveIndirectRegShort
+            | Opcode::MoveIndirectRegLong => (Some(OperandKind::Reg), Some(OperandKind::Indirect)),
+            Opcode::MoveImmAbsByte
+            | Opcode::MoveImmAbsShort
+            | Opcode::MoveImmAbsLong => (Some(OperandKind::Abs), Some(OperandKind::Imm)),
+            Opcode::MoveRegAbsByte
+            | Opcode::MoveRegAbsShort
+            | Opcode::MoveRegAbsLong => (Some(OperandKind::Abs), Some(OperandKind::Reg)),
+            Opcode::MoveAbsAbsByte
+            | Opcode::MoveAbsAbsShort
+            | Opcode::MoveAbsAbsLong => (Some(OperandKind::Abs), Some(OperandKind::Abs)),
+            Opcode::MoveMemAbsByte
+            | Opcode::MoveMemAbsShort
+            | Opcode::MoveMemAbsLong => (Some(OperandKind::Abs), Some(OperandKind::Mem)),
+            Opcode::MoveIndirectAbsByte
+            | Opcode::MoveIndirectAbsShort
+            | Opcode::MoveIndirectAbsLong => (Some(OperandKind::Abs), Some(OperandKind::Indirect)),
+            Opcode::MoveImmMemByte
+            | Opcode::MoveImmMemShort
+            | Opcode::MoveImmMemLong => (Some(OperandKind::Mem), Some(OperandKind::Imm)),
+            Opcode::MoveRegMemByte
+            | Opcode::MoveRegMemShort
+            | Opcode::MoveRegMemLong => (Some(OperandKind::Mem), Some(OperandKind::Reg)),
+            Opcode::MoveAbsMemByte
+            | Opcode::MoveAbsMemShort
+            | Opcode::MoveAbsMemLong => (Some(OperandKind::Mem), Some(OperandKind::Abs)),
+            Opcode::MoveMemMemByte
+            | Opcode::MoveMemMemShort
+            | Opcode::MoveMemMemLong => (Some(OperandKind::Mem), Some(OperandKind::Mem)),
+            Opcode::MoveIndirectMemByte
+            | Opcode::MoveIndirectMemShort
+            | Opcode::MoveIndirectMemLong => (Some(OperandKind::Mem), Some(OperandKind::Indirect)),
+            Opcode::MoveImmIndirectByte
+            | Opcode::MoveImmIndirectShort
+            | Opcode::MoveImmIndirectLong => (Some(OperandKind::Indirect), Some(OperandKind::Imm)),
+            Opcode::MoveRegIndirectByte
+            | Opcode::MoveRegIndirectShort
+            | Opcode::MoveRegIndirectLong => (Some(OperandKind::Indirect), Some(OperandKind::Reg)),
+            Opcode::MoveAbsIndirectByte
+            | Opcode::MoveAbsIndirectShort
+            | Opcode::MoveAbsIndirectLong => (Some(OperandKind::Indirect), Some(OperandKind::Abs)),
+            Opcode::MoveMemIndirectByte
+            | Opcode::MoveMemIndirectShort
+            | Opcode::MoveMemIndirectLong => (Some(OperandKind::Indirect), Some(OperandKind::Mem)),
+            Opcode::MoveIndirectIndirectByte
+            | Opcode::MoveIndirectIndirectShort
+            | Opcode::MoveIndirectIndirectLong => (Some(OperandKind::Indirect), Some(OperandKind::Indirect)),
+            Opcode::AddByteImm
+            | Opcode::AddShortImm
+            | Opcode::AddLongImm
+            | Opcode::AddCarryByteImm
+            | Opcode::AddCarryShortImm
+            | Opcode::AddCarryLongImm
+            | Opcode::SubByteImm
+            | Opcode::SubShortImm
+            | Opcode::SubLongImm
+            | Opcode::SubBorrowByteImm
+            | Opcode::SubBorrowShortImm
+            | Opcode::SubBorrowLongImm
+            | Opcode::MulByteImm
+            | Opcode::MulShortImm
+            | Opcode::MulLongImm
+            | Opcode::DivByteImm
+            | Opcode::DivShortImm
+            | Opcode::DivLongImm
+            | Opcode::SignedMulByteImm
+            | Opcode::SignedMulShortImm
+            | Opcode::SignedMulLongImm
+            | Opcode::SignedDivByteImm
+            | Opcode::SignedDivShortImm
+            | Opcode::SignedDivLongImm
+            | Opcode::AndByteImm
+            | Opcode::AndShortImm
+            | Opcode::AndLongImm
+            | Opcode::OrByteImm
+            | Opcode::OrShortImm
+            | Opcode::OrLongImm
+            | Opcode::XorByteImm
+            | Opcode::XorShortImm
+            | Opcode::XorLongImm
+            | Opcode::PushByteImm
+            | Opcode::PushShortImm
+            | Opcode::PushLongImm
+            | Opcode::CompareByteImm
+            | Opcode::CompareShortImm
+            | Opcode::CompareLongImm
+            | Opcode::LogShiftLeftByteImm
+            | Opcode::LogShiftLeftShortImm
+            | Opcode::LogShiftLeftLongImm
+            | Opcode::LogShiftRightByteImm
+            | Opcode::LogShiftRightShortImm
+            | Opcode::LogShiftRightLongImm
+            | Opcode::ArithShiftLeftByteImm
+            | Opcode::ArithShiftLeftShortImm
+            | Opcode::ArithShiftLeftLongImm
+            | Opcode::ArithShiftRightByteImm
+            | Opcode::ArithShiftRightShortImm
+            | Opcode::ArithShiftRightLongImm
+            | Opcode::RotateLeftByteImm
+            | Opcode::RotateLeftShortImm
+            | Opcode::RotateLeftLongImm
+            | Opcode::RotateRightByteImm
+            | Opcode::RotateRightShortImm
+            | Opcode::RotateRightLongImm
+            | Opcode::JumpEqual
+            | Opcode::JumpNotEqual
+            | Opcode::JumpGreater
+            | Opcode::JumpGreaterEqual
+            | Opcode::JumpLess
+            | Opcode::JumpLessEqual
+            | Opcode::JumpSignedGreater
+            | Opcode::JumpSignedGreaterEqual
+            | Opcode::JumpSignedLess
+            | Opcode::JumpSignedLessEqual
+            | Opcode::JumpZero
+            | Opcode::JumpNotZero
+            | Opcode::JumpSign
+            | Opcode::JumpNotSign
+            | Opcode::JumpCarry
+            | Opcode::JumpNotCarry
+            | Opcode::JumpOverflow
+            | Opcode::JumpNotOverflow
+            | Opcode::JumpAbove
+            | Opcode::JumpAboveEqual
+            | Opcode::JumpBelow
+            | Opcode::JumpBelowEqual
+            | Opcode::JumpGreaterThan
+            | Opcode::JumpGreaterThanEqual
+            | Opcode::JumpLessThan
+            | Opcode::JumpLessThanEqual
+            | Opcode::JumpImm
+            | Opcode::Interrupt
+            | Opcode::Call
+            | Opcode::Enter
+            | Opcode::Syscall => (Some(OperandKind::Imm), None),
+            Opcode::AddByteReg
+            | Opcode::AddShortReg
+            | Opcode::AddLongReg
+            | Opcode::AddCarryByteReg
+            | Opcode::AddCarryShortReg
+            | Opcode::AddCarryLongReg
+            | Opcode::SubByteReg
+            | Opcode::SubShortReg
+            | Opcode::SubLongReg
+            | Opcode::SubBorrowByteReg
+            | Opcode::SubBorrowShortReg
+            | Opcode::SubBorrowLongReg
+            | Opcode::MulByteReg
+            | Opcode::MulShortReg
+            | Opcode::MulLongReg
+            | Opcode::DivByteReg
+            | Opcode::DivShortReg
+            | Opcode::DivLongReg
+            | Opcode::SignedMulByteReg
+            | Opcode::SignedMulShortReg
+            | Opcode::SignedMulLongReg
+            | Opcode::SignedDivByteReg
+            | Opcode::SignedDivShortReg
+            | Opcode::SignedDivLongReg
+            | Opcode::AndByteReg
+            | Opcode::AndShortReg
+            | Opcode::AndLongReg
+            | Opcode::OrByteReg
+            | Opcode::OrShortReg
+            | Opcode::OrLongReg
+            | Opcode::XorByteReg
+            | Opcode::XorShortReg
+            | Opcode::XorLongReg
+            | Opcode::PushByteReg
+            | Opcode::PushShortReg
+            | Opcode::PushLongReg
+            | Opcode::CompareByteReg
+            | Opcode::CompareShortReg
+            | Opcode::CompareLongReg
+            | Opcode::LogShiftLeftByteReg
+            | Opcode::LogShiftLeftShortReg
+            | Opcode::LogShiftLeftLongReg
+            | Opcode::LogShiftRightByteReg
+            | Opcode::LogShiftRightShortReg
+            | Opcode::LogShiftRightLongReg
+            | Opcode::ArithShiftLeftByteReg
+            | Opcode::ArithShiftLeftShortReg
+            | Opcode::ArithShiftLeftLongReg
+            | Opcode::ArithShiftRightByteReg
+            | Opcode::ArithShiftRightShortReg
+            | Opcode::ArithShiftRightLongReg
+            | Opcode::RotateLeftByteReg
+            | Opcode::RotateLeftShortReg
+            | Opcode::RotateLeftLongReg
+            | Opcode::RotateRightByteReg
+            | Opcode::RotateRightShortReg
+            | Opcode::RotateRightLongReg
+            | Opcode::PopByte
+            | Opcode::PopShort
+            | Opcode::PopLong
+            | Opcode::NegateByte
+            | Opcode::NegateShort
+            | Opcode::NegateLong
+            | Opcode::NotByte
+            | Opcode::NotShort
+            | Opcode::NotLong
+            | Opcode::IncrementByte
+            | Opcode::IncrementShort
+            | Opcode::IncrementLong
+            | Opcode::DecrementByte
+            | Opcode::DecrementShort
+            | Opcode::DecrementLong
+            | Opcode::JumpReg
+            | Opcode::InvalidatePage => (Some(OperandKind::Reg), None),
+            Opcode::ReadByte
+            | Opcode::ReadShort
+            | Opcode::ReadLong
+            | Opcode::WriteByteReg
+            | Opcode::WriteShortReg
+            | Opcode::WriteLongReg => (Some(OperandKind::Imm), Some(OperandKind::Reg)),
+            Opcode::WriteByteImm
+            | Opcode::WriteShortImm
+            | Opcode::WriteLongImm => (Some(OperandKind::Imm), Some(OperandKind::Imm)),
+            Opcode::CompareRegImmByte
+            | Opcode::CompareRegImmShort
+            | Opcode::CompareRegImmLong => (Some(OperandKind::Reg), Some(OperandKind::Imm)),
+            Opcode::CompareRegRegByte
+            | Opcode::CompareRegRegShort
+            | Opcode::CompareRegRegLong => (Some(OperandKind::Reg), Some(OperandKind::Reg)),
+            Opcode::CompareRegAbsByte
+            | Opcode::CompareRegAbsShort
+            | Opcode::CompareRegAbsLong => (Some(OperandKind::Reg), Some(OperandKind::Abs)),
+            Opcode::CompareRegIndirectByte
+            | Opcode::CompareRegIndirectShort
+            | Opcode::CompareRegIndirectLong => (Some(OperandKind::Reg), Some(OperandKind::Indirect)),
+        }
+    }
+
+    /// Cycles this takes unless `Cpu::costs` says otherwise: one, plus two for
+    /// every memory operand, with the stack, multiplication, division, port i/o
+    /// and handlers costing more.
+    pub fn cost(&self) -> u32 {
+        let (first, second) = self.operand_kinds();
+        let memory = [first, second]
+            .into_iter()
+            .filter(|kind| {
+                matches!(
+                    kind,
+                    Some(OperandKind::Abs | OperandKind::Mem | OperandKind::Indirect)
+                )
+            })
+            .count() as u32;
+        let base = match self.mnemonic() {
+            "push" | "pop" | "call" | "ret" | "enter" | "leave" => 3,
+            "mul" | "imul" => 4,
+            "in" | "out" => 10,
+            "int" | "iret" | "trap" | "syscall" => 10,
+            "div" | "idiv" => 20,
+            _ => 1,
+        };
+        base + 2 * memory
+    }
+
+    /// Whether only the supervisor may run this: port i/o, the idt, halting,
+    /// interrupts and paging.
+    pub fn privileged(&self) -> bool {
+        matches!(
+            *self,
+            Opcode::Hlt
+                | Opcode::ReadByte
+                | Opcode::ReadShort
+                | Opcode::ReadLong
+                | Opcode::WriteByteImm
+                | Opcode::WriteShortImm
+                | Opcode::WriteLongImm
+                | Opcode::WriteByteReg
+                | Opcode::WriteShortReg
+                | Opcode::WriteLongReg
+                | Opcode::Interrupt
+                | Opcode::InterruptReturn
+                | Opcode::DisableInterrupts
+                | Opcode::EnableInterrupts
+                | Opcode::InvalidatePage
+        )
+    }
+}
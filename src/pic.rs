@@ -0,0 +1,101 @@
+/// Interrupt controller in front of the idt. Every vector is a line: `int n`
+/// raises line n, and the pic decides when its handler runs.
+///
+/// A line is delivered when it isn't masked and its priority beats every
+/// interrupt in service that hasn't been acknowledged yet, so a higher priority
+/// interrupt can nest inside a lower one. Anything else waits in the pending
+/// queue until an `iret` lets it through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pic {
+    pub masked: [bool; Pic::LINES],
+    /// higher runs first and nests inside lower. every line starts at 0.
+    pub priorities: [u8; Pic::LINES],
+    /// raised lines waiting for delivery, oldest first. a line is only queued once.
+    pub pending: Vec<u8>,
+    /// lines whose handlers are running, innermost last, and whether each has
+    /// been acknowledged with an end of interrupt.
+    pub in_service: Vec<(u8, bool)>,
+}
+
+impl Default for Pic {
+    fn default() -> Pic {
+        Pic {
+            masked: [false; Pic::LINES],
+            priorities: [0; Pic::LINES],
+            pending: Vec::new(),
+            in_service: Vec::new(),
+        }
+    }
+}
+
+impl Pic {
+    pub const LINES: usize = 256;
+
+    pub fn raise(&mut self, line: u8) {
+        if !self.pending.contains(&line) {
+            self.pending.push(line);
+        }
+    }
+
+    pub fn set_masked(&mut self, line: u8, masked: bool) {
+        self.masked[line as usize] = masked;
+    }
+
+    pub fn set_priority(&mut self, line: u8, priority: u8) {
+        self.priorities[line as usize] = priority;
+    }
+
+    /// The priority a line has to beat to be delivered now, if anything is
+    /// blocking.
+    fn threshold(&self) -> Option<u8> {
+        self.in_service
+            .iter()
+            .filter(|(_, acknowledged)| !acknowledged)
+            .map(|(line, _)| self.priorities[*line as usize])
+            .max()
+    }
+
+    /// Takes the line to deliver next and marks it in service: the highest
+    /// priority one that can be delivered, oldest first among equals.
+    pub fn take(&mut self) -> Option<u8> {
+        let threshold = self.threshold();
+        let mut best: Option<(usize, u8)> = None;
+        for (i, line) in self.pending.iter().enumerate() {
+            let priority = self.priorities[*line as usize];
+            let blocked = threshold.is_some_and(|threshold| priority <= threshold);
+            if self.masked[*line as usize] || blocked {
+                continue;
+            }
+            if best.is_none_or(|(_, best)| priority > best) {
+                best = Some((i, priority));
+            }
+        }
+        let line = self.pending.remove(best?.0);
+        self.in_service.push((line, false));
+        Some(line)
+    }
+
+    /// Puts `line` in service without queueing it, for `trap`. It's already
+    /// acknowledged, so it holds nothing back.
+    pub fn enter(&mut self, line: u8) {
+        self.in_service.push((line, true));
+    }
+
+    /// Acknowledges the innermost interrupt, letting interrupts of any
+    /// priority nest inside it before its `iret`.
+    pub fn end_of_interrupt(&mut self) {
+        let current = self
+            .in_service
+            .iter_mut()
+            .rev()
+            .find(|(_, acknowledged)| !acknowledged);
+        if let Some((_, acknowledged)) = current {
+            *acknowledged = true;
+        }
+    }
+
+    /// The innermost handler returned.
+    pub fn finish(&mut self) {
+        self.in_service.pop();
+    }
+}
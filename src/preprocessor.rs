@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::assembler::{self, AsmError, SourceLine};
+
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub params: usize,
+    pub body: Vec<SourceLine>,
+}
+
+/// One level of `%if` nesting.
+struct Condition {
+    /// lines are currently being kept.
+    active: bool,
+    /// some branch of this `%if` has already been kept.
+    taken: bool,
+    parent_active: bool,
+    seen_else: bool,
+}
+
+/// Expands `%define` constants, `%macro` invocations, `%include` and
+/// conditional assembly, producing plain lines for the assembler.
+#[derive(Default)]
+pub struct Preprocessor {
+    pub defines: HashMap<String, String>,
+    pub macros: HashMap<String, Macro>,
+    expansions: usize,
+    depth: usize,
+}
+
+/// `text` without its `;` comment.
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == ';' => return &text[..i],
+            None => {}
+        }
+    }
+    text
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Splits macro arguments on commas that aren't inside brackets, parens or quotes.
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '[' | '(') => depth += 1,
+            (None, ']' | ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                args.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !args.is_empty() {
+        args.push(current.trim().to_string());
+    }
+    args
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces every identifier that names a `%define` with its value.
+    fn substitute(&self, text: &str) -> String {
+        if self.defines.is_empty() {
+            return text.to_string();
+        }
+        let chars = text.chars().collect::<Vec<char>>();
+        let mut out = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c == '\'' || c == '"' {
+                // copy quoted text untouched.
+                out.push(c);
+                i += 1;
+                while i < chars.len() && chars[i] != c {
+                    if chars[i] == '\\' && i + 1 < chars.len() {
+                        out.push(chars[i]);
+                        i += 1;
+                    }
+                    out.push(chars[i]);
+                    i += 1;
+                }
+                if i < chars.len() {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+                continue;
+            }
+            if is_ident_start(c) && (i == 0 || !is_ident_char(chars[i - 1])) {
+                let start = i;
+                while i < chars.len() && is_ident_char(chars[i]) {
+                    i += 1;
+                }
+                let ident = chars[start..i].iter().collect::<String>();
+                match self.defines.get(&ident) {
+                    Some(value) => out.push_str(value),
+                    None => out.push_str(&ident),
+                }
+                continue;
+            }
+            out.push(c);
+            i += 1;
+        }
+        out
+    }
+
+    /// Evaluates the expression after a directive, keeping columns lined up with the source.
+    fn condition(
+        &self,
+        line: &SourceLine,
+        directive_len: usize,
+        rest: &str,
+    ) -> Result<bool, AsmError> {
+        let text = format!("{}{}", " ".repeat(directive_len), self.substitute(rest));
+        let expr_line = SourceLine {
+            file: line.file.clone(),
+            line: line.line,
+            text,
+        };
+        Ok(assembler::eval_constant(&expr_line)? != 0)
+    }
+
+    fn name<'a>(
+        &self,
+        line: &SourceLine,
+        directive: &str,
+        rest: &'a str,
+    ) -> Result<&'a str, AsmError> {
+        let name = rest.split_whitespace().next().unwrap_or("");
+        if name.is_empty() || !name.chars().all(is_ident_char) || !name.starts_with(is_ident_start)
+        {
+            return Err(AsmError::new(
+                line,
+                1,
+                format!("`{}` expects a name", directive),
+            ));
+        }
+        Ok(name)
+    }
+
+    /// Preprocesses `lines`, which may define macros and constants used by later calls.
+    pub fn process(&mut self, lines: &[SourceLine]) -> Result<Vec<SourceLine>, AsmError> {
+        let Some(first) = lines.first() else {
+            return Ok(Vec::new());
+        };
+        if self.depth > MAX_DEPTH {
+            return Err(AsmError::new(
+                first,
+                1,
+                "macros or includes nested too deeply",
+            ));
+        }
+        self.depth += 1;
+        let result = self.process_lines(lines);
+        self.depth -= 1;
+        result
+    }
+
+    fn process_lines(&mut self, lines: &[SourceLine]) -> Result<Vec<SourceLine>, AsmError> {
+        let mut output = Vec::new();
+        let mut conditions: Vec<Condition> = Vec::new();
+        let mut i = 0;
+
+        while i < lines.len() {
+            let line = &lines[i];
+            i += 1;
+            let code = strip_comment(&line.text);
+            let trimmed = code.trim_start();
+            let active = conditions.last().is_none_or(|c| c.active);
+
+            if let Some(directive) = trimmed.strip_prefix('%') {
+                let word = directive
+                    .split(|c: char| c.is_whitespace())
+                    .next()
+                    .unwrap_or("");
+                let rest = &directive[word.len()..];
+                // column padding for expressions, so errors point into the original line.
+                let offset = code.len() - rest.len();
+
+                match word {
+                    "if" | "ifdef" | "ifndef" => {
+                        let holds = active
+                            && match word {
+                                "if" => self.condition(line, offset, rest)?,
+                                "ifdef" => self.defines.contains_key(self.name(line, word, rest)?),
+                                _ => !self.defines.contains_key(self.name(line, word, rest)?),
+                            };
+                        conditions.push(Condition {
+                            active: holds,
+                            taken: holds,
+                            parent_active: active,
+                            seen_else: false,
+                        });
+                        continue;
+                    }
+                    "elif" | "else" | "endif" => {
+                        let Some(condition) = conditions.last_mut() else {
+                            return Err(AsmError::new(
+                                line,
+                                1,
+                                format!("`%{}` without `%if`", word),
+                            ));
+                        };
+                        if word == "endif" {
+                            conditions.pop();
+                            continue;
+                        }
+                        if condition.seen_else {
+                            return Err(AsmError::new(
+                                line,
+                                1,
+                                format!("`%{}` after `%else`", word),
+                            ));
+                        }
+                        let holds = condition.parent_active
+                            && !condition.taken
+                            && (word == "else" || self.condition(line, offset, rest)?);
+                        condition.seen_else = word == "else";
+                        condition.active = holds;
+                        condition.taken |= holds;
+                        continue;
+                    }
+                    _ if !active => continue,
+                    "define" => {
+                        let name = self.name(line, word, rest)?.to_string();
+                        let value = rest.trim_start()[name.len()..].trim();
+                        let value = self.substitute(value);
+                        self.defines.insert(name, value);
+                        continue;
+                    }
+                    "undef" => {
+                        let name = self.name(line, word, rest)?.to_string();
+                        self.defines.remove(&name);
+                        continue;
+                    }
+                    "include" => {
+                        let path = rest.trim();
+                        let Some(path) = path
+                            .strip_prefix('"')
+                            .and_then(|path| path.strip_suffix('"'))
+                        else {
+                            return Err(AsmError::new(
+                                line,
+                                offset + 1,
+                                "expected a quoted file name",
+                            ));
+                        };
+                        let relative = Path::new(&line.file)
+                            .parent()
+                            .map(|dir| dir.join(path))
+                            .filter(|path| path.exists());
+                        let included = match relative {
+                            Some(path) => assembler::read_source(&path),
+                            None => assembler::read_source(path),
+                        }
+                        .map_err(|err| {
+                            AsmError::new(
+                                line,
+                                offset + 1,
+                                format!("cannot include `{}`: {}", path, err.message),
+                            )
+                        })?;
+                        output.extend(self.process(&included)?);
+                        continue;
+                    }
+                    "macro" => {
+                        let mut parts = rest.split_whitespace();
+                        let name = self.name(line, word, rest)?.to_string();
+                        parts.next();
+                        let params = match parts.next() {
+                            None => 0,
+                            Some(count) => count.parse::<usize>().map_err(|_| {
+                                AsmError::new(
+                                    line,
+                                    1,
+                                    format!("invalid parameter count `{}`", count),
+                                )
+                            })?,
+                        };
+
+                        let mut body = Vec::new();
+                        loop {
+                            let Some(body_line) = lines.get(i) else {
+                                return Err(AsmError::new(
+                                    line,
+                                    1,
+                                    format!("macro `{}` is missing `%endmacro`", name),
+                                ));
+                            };
+                            i += 1;
+                            let text = strip_comment(&body_line.text).trim();
+                            if text.starts_with("%endmacro") {
+                                break;
+                            }
+                            if text.starts_with("%macro") {
+                                return Err(AsmError::new(
+                                    body_line,
+                                    1,
+                                    "macros cannot be defined inside other macros",
+                                ));
+                            }
+                            body.push(body_line.clone());
+                        }
+                        self.macros
+                            .insert(name.clone(), Macro { name, params, body });
+                        continue;
+                    }
+                    "endmacro" => {
+                        return Err(AsmError::new(line, 1, "`%endmacro` without `%macro`"));
+                    }
+                    _ => {
+                        return Err(AsmError::new(
+                            line,
+                            1,
+                            format!("unknown directive `%{}`", word),
+                        ))
+                    }
+                }
+            }
+
+            if !active {
+                continue;
+            }
+            let text = self.substitute(code);
+            match self.invocation(line, &text)? {
+                Some(expanded) => output.extend(expanded),
+                None => output.push(SourceLine {
+                    file: line.file.clone(),
+                    line: line.line,
+                    text,
+                }),
+            }
+        }
+
+        if !conditions.is_empty() {
+            let last = lines.last().unwrap();
+            return Err(AsmError::new(last, 1, "`%if` is missing `%endif`"));
+        }
+        Ok(output)
+    }
+
+    /// Expands `text` if it calls a macro, after any labels.
+    fn invocation(
+        &mut self,
+        line: &SourceLine,
+        text: &str,
+    ) -> Result<Option<Vec<SourceLine>>, AsmError> {
+        // skip leading `label:`s.
+        let mut rest = text.trim_start();
+        let mut labels = String::new();
+        while let Some(colon) = rest.find(':') {
+            let label = &rest[..colon];
+            if label.is_empty() || !label.chars().all(is_ident_char) {
+                break;
+            }
+            labels.push_str(&rest[..=colon]);
+            labels.push(' ');
+            rest = rest[colon + 1..].trim_start();
+        }
+
+        let name_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let Some(mac) = self.macros.get(&rest[..name_len]) else {
+            return Ok(None);
+        };
+        let args = split_args(&rest[name_len..]);
+        if args.len() != mac.params {
+            return Err(AsmError::new(
+                line,
+                text.len() - rest.len() + 1,
+                format!(
+                    "macro `{}` takes {} argument(s), found {}",
+                    mac.name,
+                    mac.params,
+                    args.len()
+                ),
+            ));
+        }
+
+        self.expansions += 1;
+        let prefix = format!("{}.{}.", mac.name, self.expansions);
+        let mut body = Vec::new();
+        if !labels.is_empty() {
+            body.push(SourceLine {
+                file: line.file.clone(),
+                line: line.line,
+                text: labels,
+            });
+        }
+        for body_line in mac.body.iter() {
+            // `%%name` is local to this expansion, `%N` is the N-th argument.
+            let mut text = body_line.text.replace("%%", &prefix);
+            for (n, arg) in args.iter().enumerate().rev() {
+                text = text.replace(&format!("%{}", n + 1), arg);
+            }
+            // expanded lines are reported at the call site.
+            body.push(SourceLine {
+                file: line.file.clone(),
+                line: line.line,
+                text,
+            });
+        }
+        self.process(&body).map(Some)
+    }
+}
+
+/// Reads and preprocesses `path`.
+pub fn preprocess_file<T: AsRef<Path> + ?Sized>(path: &T) -> Result<Vec<SourceLine>, AsmError> {
+    Preprocessor::new().process(&assembler::read_source(path)?)
+}
+
+/// Renders preprocessed lines back into source text.
+pub fn expanded_source(lines: &[SourceLine]) -> String {
+    lines
+        .iter()
+        .map(|line| format!("{}\n", line.text))
+        .collect()
+}
@@ -0,0 +1,133 @@
+use std::{fmt, io};
+
+/// Guest physical memory, allocated a page at a time on the first write that
+/// isn't 0, so memory nothing touched costs nothing. Unwritten bytes read as 0.
+#[derive(Debug, Clone)]
+pub struct Ram {
+    len: usize,
+    pages: Vec<Option<Box<[u8; Ram::PAGE_SIZE]>>>,
+}
+
+/// An access that runs past the end of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRange {
+    pub address: usize,
+    pub len: usize,
+}
+
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes at 0x{:08X} don't fit in memory",
+            self.len, self.address
+        )
+    }
+}
+
+impl std::error::Error for OutOfRange {}
+
+impl From<OutOfRange> for io::Error {
+    fn from(err: OutOfRange) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+impl Ram {
+    pub const PAGE_SIZE: usize = 4096;
+
+    pub fn new(len: usize) -> Ram {
+        Ram {
+            len,
+            pages: vec![None; len.div_ceil(Ram::PAGE_SIZE)],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How many bytes are actually allocated.
+    pub fn allocated(&self) -> usize {
+        self.pages.iter().flatten().count() * Ram::PAGE_SIZE
+    }
+
+    fn check(&self, address: usize, len: usize) -> Result<(), OutOfRange> {
+        match address.checked_add(len) {
+            Some(end) if end <= self.len => Ok(()),
+            _ => Err(OutOfRange { address, len }),
+        }
+    }
+
+    #[inline(always)]
+    pub fn get(&self, address: usize) -> Option<u8> {
+        if address >= self.len {
+            return None;
+        }
+        match &self.pages[address / Ram::PAGE_SIZE] {
+            Some(page) => Some(page[address % Ram::PAGE_SIZE]),
+            None => Some(0),
+        }
+    }
+
+    #[inline(always)]
+    pub fn set(&mut self, address: usize, value: u8) -> Result<(), OutOfRange> {
+        self.check(address, 1)?;
+        match &mut self.pages[address / Ram::PAGE_SIZE] {
+            Some(page) => page[address % Ram::PAGE_SIZE] = value,
+            // a page of zeroes is what's there already.
+            None if value == 0 => {}
+            None => self.page(address)[address % Ram::PAGE_SIZE] = value,
+        }
+        Ok(())
+    }
+
+    /// The page holding `address`, allocating it if it isn't yet.
+    fn page(&mut self, address: usize) -> &mut [u8; Ram::PAGE_SIZE] {
+        self.pages[address / Ram::PAGE_SIZE].get_or_insert_with(|| Box::new([0; Ram::PAGE_SIZE]))
+    }
+
+    /// A little endian long, for reading page tables.
+    pub fn long(&self, address: usize) -> Option<u32> {
+        let mut bytes = [0; 4];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.get(address.checked_add(i)?)?;
+        }
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read(&self, address: usize, len: usize) -> Result<Vec<u8>, OutOfRange> {
+        self.check(address, len)?;
+        Ok((address..address + len)
+            .map(|address| self.get(address).unwrap_or(0))
+            .collect())
+    }
+
+    /// Copies `bytes` in at `address`, or nothing if they don't all fit.
+    pub fn write(&mut self, address: usize, bytes: &[u8]) -> Result<(), OutOfRange> {
+        self.check(address, bytes.len())?;
+        let mut address = address;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let offset = address % Ram::PAGE_SIZE;
+            let chunk = bytes.len().min(Ram::PAGE_SIZE - offset);
+            self.page(address)[offset..offset + chunk].copy_from_slice(&bytes[..chunk]);
+            address += chunk;
+            bytes = &bytes[chunk..];
+        }
+        Ok(())
+    }
+
+    /// Sets `len` bytes at `address` to `value`, or nothing if they don't all fit.
+    pub fn fill(&mut self, address: usize, len: usize, value: u8) -> Result<(), OutOfRange> {
+        self.check(address, len)?;
+        for address in address..address + len {
+            self.set(address, value)?;
+        }
+        Ok(())
+    }
+}
@@ -1,2154 +1,3791 @@
-mod le_bytes_junk {
-    pub trait ToLeTrait {
-        #[allow(dead_code)]
-        fn to_le_bytes(self) -> Vec<u8>;
-    }
-    impl ToLeTrait for u8 {
-        fn to_le_bytes(self) -> Vec<u8> {
-            self.to_le_bytes().to_vec()
-        }
-    }
+#[cfg(test)]
+mod tests {
+    /// Runs an assembly snippet on a fresh cpu and checks the registers, flags,
+    /// memory and output it leaves behind.
+    mod harness {
+        use std::fmt::Write;
+
+        use crate::{
+            assembler::assemble,
+            cpu::{Cpu, NUM_REGISTERS},
+        };
 
-    impl ToLeTrait for u16 {
-        fn to_le_bytes(self) -> Vec<u8> {
-            self.to_le_bytes().to_vec()
+        const FLAGS: [(&str, u32); 8] = [
+            ("halt", Cpu::HALT_FLAG),
+            ("interrupt", Cpu::INTERRUPT_FLAG),
+            ("carry", Cpu::CARRY_FLAG),
+            ("zero", Cpu::ZERO_FLAG),
+            ("sign", Cpu::SIGN_FLAG),
+            ("overflow", Cpu::OVERFLOW_FLAG),
+            ("interrupt_enable", Cpu::INTERRUPT_ENABLE_FLAG),
+            ("user", Cpu::USER_FLAG),
+        ];
+
+        /// a snippet still running after this many instructions is assumed stuck.
+        const MAX_CYCLES: usize = 100_000;
+
+        #[derive(Default)]
+        pub struct Case {
+            source: String,
+            registers: Vec<(usize, u32)>,
+            flags: Vec<(&'static str, bool)>,
+            memory: Vec<(usize, Vec<u8>)>,
+            expected_registers: Vec<(usize, u32)>,
+            expected_flags: Vec<(&'static str, bool)>,
+            expected_memory: Vec<(usize, Vec<u8>)>,
+            expected_output: Option<String>,
         }
-    }
-    impl ToLeTrait for u32 {
-        fn to_le_bytes(self) -> Vec<u8> {
-            self.to_le_bytes().to_vec()
+
+        /// A case running `source`, which halts when it runs off its end.
+        pub fn case(source: &str) -> Case {
+            Case {
+                source: source.to_string(),
+                ..Case::default()
+            }
         }
-    }
-    pub trait OurFrom<T> {
-        #[allow(dead_code)]
-        fn our_from(value: T) -> Self;
-    }
-    impl OurFrom<u32> for u16 {
-        fn our_from(value: u32) -> Self {
-            value as u16
+
+        fn register(name: &str) -> usize {
+            Cpu::reg_str_to_index(name).unwrap_or_else(|| panic!("no register `{}`", name))
         }
-    }
-    impl OurFrom<u32> for u32 {
-        fn our_from(value: u32) -> Self {
-            value as u32
+
+        fn flag(name: &str) -> u32 {
+            match FLAGS.iter().find(|(flag, _)| *flag == name) {
+                Some((_, bit)) => *bit,
+                None => panic!("no flag `{}`", name),
+            }
         }
-    }
-    impl OurFrom<u32> for u8 {
-        fn our_from(value: u32) -> Self {
-            value as u8
+
+        fn hex(bytes: &[u8]) -> String {
+            let bytes = bytes.iter().map(|byte| format!("{:02X}", byte));
+            format!("[{}]", bytes.collect::<Vec<String>>().join(" "))
         }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    mod stack {
-        use crate::{
-            cpu::{Cpu, SP},
-            opcodes::Opcode,
-        };
+        impl Case {
+            /// Sets a register before the snippet runs.
+            pub fn reg(mut self, name: &str, value: u32) -> Case {
+                self.registers.push((register(name), value));
+                self
+            }
 
-        fn create_cpu() -> Cpu {
-            let mut cpu = Cpu::new();
-            cpu.registers[SP] = 50;
-            return cpu;
+            pub fn flag(mut self, name: &'static str, set: bool) -> Case {
+                flag(name);
+                self.flags.push((name, set));
+                self
+            }
+
+            /// Writes `bytes` to memory at `address`, after the snippet is loaded at 0.
+            pub fn mem(mut self, address: usize, bytes: &[u8]) -> Case {
+                self.memory.push((address, bytes.to_vec()));
+                self
+            }
+
+            pub fn expect_reg(mut self, name: &str, value: u32) -> Case {
+                self.expected_registers.push((register(name), value));
+                self
+            }
+
+            pub fn expect_flag(mut self, name: &'static str, set: bool) -> Case {
+                flag(name);
+                self.expected_flags.push((name, set));
+                self
+            }
+
+            pub fn expect_mem(mut self, address: usize, bytes: &[u8]) -> Case {
+                self.expected_memory.push((address, bytes.to_vec()));
+                self
+            }
+
+            /// What the snippet prints through syscalls, one line per print.
+            pub fn expect_output(mut self, output: &str) -> Case {
+                self.expected_output = Some(output.to_string());
+                self
+            }
+
+            /// Assembles and runs the snippet, panicking with every difference from
+            /// the expected state. Returns the cpu for anything else worth checking.
+            pub fn run(self) -> Cpu {
+                let program = assemble("case.asm", &self.source)
+                    .unwrap_or_else(|err| panic!("{}\n{}", self.source, err));
+                let mut cpu = Cpu::new();
+                cpu.output = Some(String::new());
+                cpu.load_program(&program).unwrap();
+                for (register, value) in self.registers.iter() {
+                    cpu.registers[*register] = *value;
+                }
+                for (name, set) in self.flags.iter() {
+                    cpu.set_flag(flag(name), *set);
+                }
+                for (address, bytes) in self.memory.iter() {
+                    cpu.memory.bus.ram.write(*address, bytes).unwrap();
+                }
+
+                let mut differences = Vec::new();
+                let mut cycles = 0;
+                while !cpu.has_flag(Cpu::HALT_FLAG) {
+                    if cycles == MAX_CYCLES {
+                        differences.push(format!("still running after {} cycles", cycles));
+                        break;
+                    }
+                    cpu.cycle();
+                    cycles += 1;
+                }
+
+                for (register, expected) in self.expected_registers.iter() {
+                    let found = cpu.registers[*register];
+                    if found != *expected {
+                        differences.push(format!(
+                            "{}: expected 0x{:08X} ({}), found 0x{:08X} ({})",
+                            Cpu::reg_index_to_str(register),
+                            expected,
+                            expected,
+                            found,
+                            found
+                        ));
+                    }
+                }
+                for (name, expected) in self.expected_flags.iter() {
+                    let found = cpu.has_flag(flag(name));
+                    if found != *expected {
+                        let state = |set: bool| if set { "set" } else { "clear" };
+                        differences.push(format!(
+                            "{} flag: expected {}, found {}",
+                            name,
+                            state(*expected),
+                            state(found)
+                        ));
+                    }
+                }
+                for (address, expected) in self.expected_memory.iter() {
+                    let found = cpu.memory.bus.ram.read(*address, expected.len()).unwrap();
+                    if found != *expected {
+                        differences.push(format!(
+                            "memory at 0x{:08X}: expected {}, found {}",
+                            address,
+                            hex(expected),
+                            hex(&found)
+                        ));
+                    }
+                }
+                if let Some(expected) = self.expected_output.as_ref() {
+                    let found = cpu.output.as_deref().unwrap_or_default();
+                    if found != expected {
+                        differences.push(format!(
+                            "output: expected {:?}, found {:?}",
+                            expected, found
+                        ));
+                    }
+                }
+
+                if !differences.is_empty() {
+                    let mut report = String::new();
+                    for line in self.source.lines() {
+                        writeln!(report, "    {}", line).unwrap();
+                    }
+                    for difference in differences {
+                        writeln!(report, "  {}", difference).unwrap();
+                    }
+                    for register in 0..NUM_REGISTERS {
+                        let name = Cpu::reg_index_to_str(&register);
+                        let value = cpu.registers[register];
+                        let end = if register % 6 == 5 { "\n" } else { "  " };
+                        write!(report, "{:>5} 0x{:08X}{}", name, value, end).unwrap();
+                    }
+                    panic!("case failed:\n{}", report);
+                }
+                cpu
+            }
         }
+    }
+    mod stack {
+        use super::harness::case;
 
         #[test]
         fn push_byte_reg() {
-            let mut cpu = create_cpu();
-            let expected_sp = cpu.sp() - 1;
-
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::PushByteReg as u8, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.memory.byte(expected_sp), 100);
+            case("push.b rax")
+                .reg("sp", 50)
+                .reg("rax", 100)
+                .expect_mem(49, &[100])
+                .expect_reg("sp", 49)
+                .run();
         }
         #[test]
         fn push_byte_imm() {
-            let mut cpu = create_cpu();
-            let expected_sp = cpu.sp() - 1;
-
-            cpu.load_program(&[Opcode::PushByteImm as u8, 100]);
-            cpu.run();
-
-            assert_eq!(cpu.memory.byte(expected_sp), 100);
+            case("push.b 100")
+                .reg("sp", 50)
+                .expect_mem(49, &[100])
+                .run();
         }
 
         #[test]
         fn push_short_reg() {
-            let mut cpu = create_cpu();
-            let expected_sp = cpu.sp() - 2;
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::PushShortReg as u8, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.memory.short(expected_sp), 100);
+            case("push.s rax")
+                .reg("sp", 50)
+                .reg("rax", 100)
+                .expect_mem(48, &100u16.to_le_bytes())
+                .expect_reg("sp", 48)
+                .run();
         }
 
         #[test]
         fn push_short_imm() {
-            let mut cpu = create_cpu();
-            let expected_sp = cpu.sp() - 2;
-            cpu.load_program(&[Opcode::PushShortImm as u8, 100, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.memory.short(expected_sp), 100);
+            case("push.s 100")
+                .reg("sp", 50)
+                .expect_mem(48, &100u16.to_le_bytes())
+                .run();
         }
 
         #[test]
         fn push_long_reg() {
-            let mut cpu = create_cpu();
-            let expected_sp = cpu.sp() - 4;
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::PushLongReg as u8, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.memory.long(expected_sp), 100);
+            case("push.l rax")
+                .reg("sp", 50)
+                .reg("rax", 100)
+                .expect_mem(46, &100u32.to_le_bytes())
+                .expect_reg("sp", 46)
+                .run();
         }
 
         #[test]
         fn push_long_imm() {
-            let mut cpu = create_cpu();
-            let expected_sp = cpu.sp() - 4;
-            cpu.load_program(&[Opcode::PushLongImm as u8, 100, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.memory.long(expected_sp), 100);
+            case("push.l 100")
+                .reg("sp", 50)
+                .expect_mem(46, &100u32.to_le_bytes())
+                .run();
         }
 
         #[test]
         fn pop_byte_reg() {
-            let mut cpu = create_cpu();
-            let expected_sp = cpu.sp() + 1;
-            cpu.memory.set_byte(cpu.sp(), 100);
-
-            cpu.load_program(&[Opcode::PopByte as u8, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 100);
-            assert_eq!(cpu.sp(), expected_sp);
+            case("pop.b rax")
+                .reg("sp", 50)
+                .mem(50, &[100])
+                .expect_reg("rax", 100)
+                .expect_reg("sp", 51)
+                .run();
         }
 
         #[test]
         fn pop_short_reg() {
-            let mut cpu = create_cpu();
-            let expected_sp = cpu.sp() + 2;
-            cpu.memory.set_short(cpu.sp(), 100);
-
-            cpu.load_program(&[Opcode::PopShort as u8, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 100);
-            assert_eq!(cpu.sp(), expected_sp);
+            case("pop.s rax")
+                .reg("sp", 50)
+                .mem(50, &100u16.to_le_bytes())
+                .expect_reg("rax", 100)
+                .expect_reg("sp", 52)
+                .run();
         }
 
         #[test]
         fn pop_long_reg() {
-            let mut cpu = create_cpu();
-            let expected_sp = cpu.sp() + 4;
-            cpu.memory.set_long(cpu.sp(), 100);
-
-            cpu.load_program(&[Opcode::PopLong as u8, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 100);
-            assert_eq!(cpu.sp(), expected_sp);
+            case("pop.l rax")
+                .reg("sp", 50)
+                .mem(50, &100u32.to_le_bytes())
+                .expect_reg("rax", 100)
+                .expect_reg("sp", 54)
+                .run();
         }
     }
     mod general {
-        use crate::cpu::Cpu;
+        use super::harness::case;
 
         #[test]
         fn hlt() {
-            let mut cpu = Cpu::new();
-            cpu.load_program(&[0]);
-            cpu.run();
+            case("hlt")
+                .expect_reg("ip", 1)
+                .expect_flag("halt", true)
+                .run();
+        }
+
+        #[test]
+        fn prints_through_syscalls() {
+            case("mov rax, text\nsyscall 2\nmov rax, 1\nmov rbx, 42\nsyscall 3\nhlt\ntext: asciz \"hi\"")
+                .expect_output("hi\n42\n")
+                .run();
+        }
+
+        #[test]
+        fn prints_invalid_utf8_lossily() {
+            case("mov rax, text\nsyscall 2\nhlt\ntext: db 'a', 0xFF, 0")
+                .expect_output("a\u{FFFD}\n")
+                .run();
+        }
 
-            assert_eq!(cpu.ip(), 1);
-            assert!(cpu.has_flag(Cpu::HALT_FLAG));
+        #[test]
+        #[should_panic(expected = "rax: expected 0x00000002 (2), found 0x00000001 (1)")]
+        fn reports_differences() {
+            case("mov rax, 1").expect_reg("rax", 2).run();
         }
     }
     mod add {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
+
         #[test]
         fn add_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-
-            cpu.load_program(&[Opcode::AddByteReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 150);
+            case("add.b rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 150)
+                .run();
         }
 
         #[test]
         fn add_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-
-            cpu.load_program(&[Opcode::AddShortReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 150);
+            case("add.s rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 150)
+                .run();
         }
 
         #[test]
         fn add_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-
-            cpu.load_program(&[Opcode::AddLongReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 150);
+            case("add.l rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 150)
+                .run();
         }
         #[test]
         fn add_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::AddByteImm as u8, 100]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 200);
+            case("add.b 100")
+                .reg("rax", 100)
+                .expect_reg("rax", 200)
+                .run();
         }
         #[test]
         fn add_byte_imm_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 156;
-            cpu.load_program(&[Opcode::AddByteImm as u8, 100]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0);
+            case("add.b 100").reg("rax", 156).expect_reg("rax", 0).run();
         }
         #[test]
         fn add_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::AddShortImm as u8, 100, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 200);
+            case("add.s 100")
+                .reg("rax", 100)
+                .expect_reg("rax", 200)
+                .run();
         }
         #[test]
         fn add_short_imm_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 65535;
-            cpu.load_program(&[Opcode::AddShortImm as u8, 100, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 99);
+            case("add.s 100")
+                .reg("rax", 65535)
+                .expect_reg("rax", 99)
+                .run();
         }
         #[test]
         fn add_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::AddLongImm as u8, 100, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 200);
+            case("add.l 100")
+                .reg("rax", 100)
+                .expect_reg("rax", 200)
+                .run();
         }
         #[test]
         fn add_long_imm_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 4_294_967_295;
-            cpu.load_program(&[Opcode::AddLongImm as u8, 100, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 99);
+            case("add.l 100")
+                .reg("rax", 4_294_967_295)
+                .expect_reg("rax", 99)
+                .run();
         }
         #[test]
         fn add_carry_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-
-            cpu.load_program(&[Opcode::AddByteReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 150);
+            case("adc.b rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 150)
+                .run();
         }
 
         #[test]
         fn add_carry_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-
-            cpu.load_program(&[Opcode::AddShortReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 150);
+            case("adc.s rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 150)
+                .run();
         }
 
         #[test]
         fn add_carry_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-
-            cpu.load_program(&[Opcode::AddCarryLongReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 150);
+            case("adc.l rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 150)
+                .run();
         }
         #[test]
         fn add_carry_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::AddCarryByteImm as u8, 100]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 200);
+            case("adc.b 100")
+                .reg("rax", 100)
+                .expect_reg("rax", 200)
+                .run();
         }
         #[test]
         fn add_carry_byte_imm_carry_set() {
-            let mut cpu = Cpu::new();
-            cpu.set_flag(Cpu::CARRY_FLAG, true);
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::AddCarryByteImm as u8, 100]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 201);
+            case("adc.b 100")
+                .flag("carry", true)
+                .reg("rax", 100)
+                .expect_reg("rax", 201)
+                .run();
         }
         #[test]
         fn add_carry_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::AddCarryShortImm as u8, 100, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 200);
+            case("adc.s 100")
+                .reg("rax", 100)
+                .expect_reg("rax", 200)
+                .run();
         }
         #[test]
         fn add_carry_short_imm_carry_set() {
-            let mut cpu = Cpu::new();
-            cpu.set_flag(Cpu::CARRY_FLAG, true);
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::AddCarryShortImm as u8, 100, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 201);
+            case("adc.s 100")
+                .flag("carry", true)
+                .reg("rax", 100)
+                .expect_reg("rax", 201)
+                .run();
         }
         #[test]
         fn add_carry_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0F;
-            cpu.load_program(&[Opcode::AddCarryLongImm as u8, 0xF0, 0xFF, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0xFFFF);
+            case("adc.l 0xFFF0")
+                .reg("rax", 0x0F)
+                .expect_reg("rax", 0xFFFF)
+                .run();
         }
         #[test]
         fn add_carry_long_imm_carry_set() {
-            let mut cpu = Cpu::new();
-            cpu.set_flag(Cpu::CARRY_FLAG, true);
-            cpu.registers[0] = 0x0F;
-            cpu.load_program(&[Opcode::AddCarryLongImm as u8, 0xF0, 0xFF, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0x10000);
+            case("adc.l 0xFFF0")
+                .flag("carry", true)
+                .reg("rax", 0x0F)
+                .expect_reg("rax", 0x10000)
+                .run();
         }
     }
     mod sub {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
+
         #[test]
         fn sub_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::SubByteImm as u8, 100]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0);
+            case("sub.b 100").reg("rax", 100).expect_reg("rax", 0).run();
         }
         #[test]
         fn sub_byte_imm_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0;
-            cpu.load_program(&[Opcode::SubByteImm as u8, 100]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 156);
+            case("sub.b 100").reg("rax", 0).expect_reg("rax", 156).run();
         }
         #[test]
         fn sub_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::SubShortImm as u8, 100, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0);
+            case("sub.s 100").reg("rax", 100).expect_reg("rax", 0).run();
         }
         #[test]
         fn sub_short_imm_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0;
-            cpu.load_program(&[Opcode::SubShortImm as u8, 100, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 65536 - 100);
+            case("sub.s 100")
+                .reg("rax", 0)
+                .expect_reg("rax", 65536 - 100)
+                .run();
         }
         #[test]
         fn sub_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::SubLongImm as u8, 100, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0);
+            case("sub.l 100").reg("rax", 100).expect_reg("rax", 0).run();
         }
         #[test]
         fn sub_long_imm_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0;
-            cpu.load_program(&[Opcode::SubLongImm as u8, 100, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 4_294_967_295 - 99);
+            case("sub.l 100")
+                .reg("rax", 0)
+                .expect_reg("rax", 4_294_967_295 - 99)
+                .run();
         }
         #[test]
         fn sub_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-            cpu.load_program(&[Opcode::SubByteReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 50);
+            case("sub.b rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 50)
+                .run();
         }
         #[test]
         fn sub_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-            cpu.load_program(&[Opcode::SubShortReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 50);
+            case("sub.s rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 50)
+                .run();
         }
         #[test]
         fn sub_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-            cpu.load_program(&[Opcode::SubLongReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 50);
+            case("sub.l rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 50)
+                .run();
         }
         #[test]
         fn sub_borrow_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::SubBorrowByteImm as u8, 100]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0);
+            case("sbb.b 100").reg("rax", 100).expect_reg("rax", 0).run();
         }
         #[test]
         fn sub_borrow_byte_imm_carry_set() {
-            let mut cpu = Cpu::new();
-            cpu.set_flag(Cpu::CARRY_FLAG, true);
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::SubBorrowByteImm as u8, 49]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 50);
+            case("sbb.b 49")
+                .flag("carry", true)
+                .reg("rax", 100)
+                .expect_reg("rax", 50)
+                .run();
         }
         #[test]
         fn sub_borrow_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::SubBorrowShortImm as u8, 100, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0);
+            case("sbb.s 100").reg("rax", 100).expect_reg("rax", 0).run();
         }
         #[test]
         fn sub_borrow_short_imm_carry_set() {
-            let mut cpu = Cpu::new();
-            cpu.set_flag(Cpu::CARRY_FLAG, true);
-            cpu.registers[0] = 0xFFFF;
-            cpu.load_program(&[Opcode::SubBorrowShortImm as u8, 0xFF, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0xFEFF);
+            case("sbb.s 0xFF")
+                .flag("carry", true)
+                .reg("rax", 0xFFFF)
+                .expect_reg("rax", 0xFEFF)
+                .run();
         }
         #[test]
         fn sub_borrow_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::SubBorrowLongImm as u8, 100, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0);
+            case("sbb.l 100").reg("rax", 100).expect_reg("rax", 0).run();
         }
         #[test]
         fn sub_borrow_long_imm_carry_set() {
-            let mut cpu = Cpu::new();
-            cpu.set_flag(Cpu::CARRY_FLAG, true);
-            cpu.registers[0] = 0xFFFFFFFF;
-            cpu.load_program(&[Opcode::SubBorrowLongImm as u8, 0xFF, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 0xFFFFFEFF);
+            case("sbb.l 0xFF")
+                .flag("carry", true)
+                .reg("rax", 0xFFFFFFFF)
+                .expect_reg("rax", 0xFFFFFEFF)
+                .run();
         }
         #[test]
         fn sub_borrow_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-            cpu.load_program(&[Opcode::SubBorrowByteReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 50);
+            case("sbb.b rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 50)
+                .run();
         }
         #[test]
         fn sub_borrow_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-            cpu.load_program(&[Opcode::SubBorrowShortReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 50);
+            case("sbb.s rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 50)
+                .run();
         }
         #[test]
         fn sub_borrow_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 50;
-            cpu.load_program(&[Opcode::SubBorrowLongReg as u8, 1]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 50);
+            case("sbb.l rbx")
+                .reg("rax", 100)
+                .reg("rbx", 50)
+                .expect_reg("rax", 50)
+                .run();
         }
     }
     mod mul {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
+
         #[test]
         fn mul_byte() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 2;
-            cpu.load_program(&[Opcode::MulByteImm as u8, 10]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 20);
+            case("mul.b 10").reg("rax", 2).expect_reg("rax", 20).run();
         }
         #[test]
         fn mul_byte_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::MulByteImm as u8, 100]);
-            cpu.run();
-
-            let result = 100u8.wrapping_mul(100u8);
-            assert_eq!((cpu.registers[0] & 0xFF) as u8, result);
+            case("mul.b 100")
+                .reg("rax", 100)
+                .expect_reg("rax", 100u8.wrapping_mul(100) as u32)
+                .run();
         }
         #[test]
         fn mul_short() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 2;
-            cpu.load_program(&[Opcode::MulShortImm as u8, 10, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 20);
+            case("mul.s 10").reg("rax", 2).expect_reg("rax", 20).run();
         }
         #[test]
         fn mul_short_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 70_000;
-            cpu.load_program(&[Opcode::MulShortImm as u8, 100, 0]);
-            cpu.run();
-
-            let result = 4_464u16.wrapping_mul(100u16);
-            assert_eq!((cpu.registers[0] & 0xFFFF) as u16, result);
+            // 70,000 as a short is 4,464
+            case("mul.s 100")
+                .reg("rax", 70_000)
+                .expect_reg("rax", 4_464u16.wrapping_mul(100) as u32)
+                .run();
         }
         #[test]
         fn mul_long() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 2;
-            cpu.load_program(&[Opcode::MulLongImm as u8, 10, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 20);
+            case("mul.l 10").reg("rax", 2).expect_reg("rax", 20).run();
         }
         #[test]
         fn mul_long_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 2_000_000_000;
-            cpu.load_program(&[Opcode::MulLongImm as u8, 100, 0, 0, 0]);
-            cpu.run();
-
-            let result = 2_000_000_000u32.wrapping_mul(100);
-            assert_eq!(cpu.registers[0], result);
+            case("mul.l 100")
+                .reg("rax", 2_000_000_000)
+                .expect_reg("rax", 2_000_000_000u32.wrapping_mul(100))
+                .run();
         }
         #[test]
         fn singed_mul_byte() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = -2_i32 as u32;
-            cpu.load_program(&[Opcode::SignedMulByteImm as u8, 10]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0] as i32, -20);
+            case("imul.b 10")
+                .reg("rax", -2_i32 as u32)
+                .expect_reg("rax", -20_i32 as u32)
+                .run();
         }
         #[test]
         fn singed_mul_short() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = -2_i32 as u32;
-            cpu.load_program(&[Opcode::SignedMulShortImm as u8, 0, 10]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0] as i32, -5120);
+            case("imul.s 0x0A00")
+                .reg("rax", -2_i32 as u32)
+                .expect_reg("rax", -5120_i32 as u32)
+                .run();
         }
         #[test]
         fn singed_mul_long() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = -2_i32 as u32;
-            cpu.load_program(&[Opcode::SignedMulLongImm as u8, 0, 0, 0, 10]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0] as i32, -335544320);
+            case("imul.l 0x0A000000")
+                .reg("rax", -2_i32 as u32)
+                .expect_reg("rax", -335544320_i32 as u32)
+                .run();
         }
     }
     mod div {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
+
         #[test]
         fn div_byte() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.load_program(&[Opcode::DivByteImm as u8, 2]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 5);
-            assert_eq!(cpu.registers[1], 0);
+            case("div.b 2")
+                .reg("rax", 10)
+                .expect_reg("rax", 5)
+                .expect_reg("rbx", 0)
+                .run();
         }
         #[test]
         fn div_byte_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 1000;
-            cpu.load_program(&[Opcode::DivByteImm as u8, 2]);
-            cpu.run();
             // 1000 as a byte wraps to 232
-            let result = 232u8.wrapping_div(2);
-            assert_eq!((cpu.registers[0] & 0xFF) as u8, result);
-            assert_eq!((cpu.registers[1] & 0xFF) as u8, 0);
+            case("div.b 2")
+                .reg("rax", 1000)
+                .expect_reg("rax", 232 / 2)
+                .expect_reg("rbx", 0)
+                .run();
         }
         #[test]
         fn div_byte_remainder() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 9;
-            cpu.load_program(&[Opcode::DivByteImm as u8, 2]);
-            cpu.run();
-            assert_eq!((cpu.registers[0] & 0xFF) as u8, 4);
-            assert_eq!((cpu.registers[1] & 0xFF) as u8, 1);
+            case("div.b 2")
+                .reg("rax", 9)
+                .expect_reg("rax", 4)
+                .expect_reg("rbx", 1)
+                .run();
         }
 
         #[test]
         fn div_short() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.load_program(&[Opcode::DivShortImm as u8, 2, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 5);
-            assert_eq!(cpu.registers[1], 0);
+            case("div.s 2")
+                .reg("rax", 10)
+                .expect_reg("rax", 5)
+                .expect_reg("rbx", 0)
+                .run();
         }
         #[test]
         fn div_short_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 70_000; // Value larger than u16::MAX
-            cpu.load_program(&[Opcode::DivShortImm as u8, 2, 0]);
-            cpu.run();
             // 70,000 as a u16 wraps to 4,464
-            let result = 4_464u16 / 2;
-            assert_eq!((cpu.registers[0] & 0xFFFF) as u16, result);
-            assert_eq!(cpu.registers[1], 0);
+            case("div.s 2")
+                .reg("rax", 70_000)
+                .expect_reg("rax", 4_464 / 2)
+                .expect_reg("rbx", 0)
+                .run();
         }
         #[test]
         fn div_short_remainder() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 9;
-            cpu.load_program(&[Opcode::DivShortImm as u8, 2, 0]);
-            cpu.run();
-            assert_eq!(cpu.registers[0], 4);
-            assert_eq!(cpu.registers[1], 1);
+            case("div.s 2")
+                .reg("rax", 9)
+                .expect_reg("rax", 4)
+                .expect_reg("rbx", 1)
+                .run();
         }
         #[test]
         fn div_long() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.load_program(&[Opcode::DivLongImm as u8, 2, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 5);
-            assert_eq!(cpu.registers[1], 0);
+            case("div.l 2")
+                .reg("rax", 10)
+                .expect_reg("rax", 5)
+                .expect_reg("rbx", 0)
+                .run();
         }
         #[test]
         fn div_long_wrap() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 2_000_000_000;
-            cpu.load_program(&[Opcode::DivLongImm as u8, 2, 0, 0, 0]);
-            cpu.run();
-
-            let result = 1_000_000_000u32;
-            assert_eq!(cpu.registers[0], result);
-            assert_eq!(cpu.registers[1], 0);
+            case("div.l 2")
+                .reg("rax", 2_000_000_000)
+                .expect_reg("rax", 1_000_000_000)
+                .expect_reg("rbx", 0)
+                .run();
         }
 
         #[test]
         fn div_long_remainder() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 9;
-            cpu.load_program(&[Opcode::DivLongImm as u8, 2, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0], 4);
-            assert_eq!(cpu.registers[1], 1);
+            case("div.l 2")
+                .reg("rax", 9)
+                .expect_reg("rax", 4)
+                .expect_reg("rbx", 1)
+                .run();
         }
 
         #[test]
         fn singed_div_byte() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = -10_i32 as u32;
-            cpu.load_program(&[Opcode::SignedDivByteImm as u8, 2]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0] as i32, -5);
-            assert_eq!(cpu.registers[1], 0);
+            case("idiv.b 2")
+                .reg("rax", -10_i32 as u32)
+                .expect_reg("rax", -5_i32 as u32)
+                .expect_reg("rbx", 0)
+                .run();
         }
         #[test]
         fn singed_div_short() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = -10_i32 as u32;
-            cpu.load_program(&[Opcode::SignedDivShortImm as u8, 2, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0] as i32, -5);
-            assert_eq!(cpu.registers[1], 0);
+            case("idiv.s 2")
+                .reg("rax", -10_i32 as u32)
+                .expect_reg("rax", -5_i32 as u32)
+                .expect_reg("rbx", 0)
+                .run();
         }
         #[test]
         fn singed_div_long() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = -10_i32 as u32;
-            cpu.load_program(&[Opcode::SignedDivLongImm as u8, 2, 0, 0, 0]);
-            cpu.run();
-
-            assert_eq!(cpu.registers[0] as i32, -5);
-            assert_eq!(cpu.registers[1], 0);
+            case("idiv.l 2")
+                .reg("rax", -10_i32 as u32)
+                .expect_reg("rax", -5_i32 as u32)
+                .expect_reg("rbx", 0)
+                .run();
         }
     }
     mod jump {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::{case, Case};
+
+        /// `jump` to a label that sets rcx, comparing rax against rbx.
+        fn branch(jump: &str, rax: u32, rbx: u32) -> Case {
+            case(&format!("{} taken\nhlt\ntaken: mov.l rcx, 1", jump))
+                .reg("rax", rax)
+                .reg("rbx", rbx)
+        }
 
         #[test]
         fn jump() {
-            let mut cpu = Cpu::new();
-            let mut program = vec![];
-            program.push(Opcode::JumpImm as u8);
-            program.extend_from_slice(&10_u32.to_le_bytes());
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], 10);
+            case("jmp taken\nhlt\ntaken: mov.l rcx, 1")
+                .expect_reg("rcx", 1)
+                .run();
         }
 
         #[test]
         fn jump_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            let mut program = vec![];
-            program.push(Opcode::JumpReg as u8);
-            program.push(0);
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], 10);
+            case("mov.l rax, taken\njmp rax\nhlt\ntaken: mov.l rcx, 1")
+                .expect_reg("rcx", 1)
+                .run();
         }
 
         #[test]
         fn jne() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 11;
-            let mut program = vec![];
-            program.push(Opcode::JumpNotEqual as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], jmp_addr);
+            branch("jne", 10, 11).expect_reg("rcx", 1).run();
         }
 
         #[test]
         fn jne_negative() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 10;
-            let mut program = vec![];
-            program.push(Opcode::JumpNotEqual as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            let no_jmp_addr = program.len() as u32;
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], no_jmp_addr);
+            branch("jne", 10, 10).expect_reg("rcx", 0).run();
         }
 
         #[test]
         fn je() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 10;
-            let mut program = vec![];
-            program.push(Opcode::JumpEqual as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], jmp_addr);
+            branch("je", 10, 10).expect_reg("rcx", 1).run();
         }
 
         #[test]
         fn je_negative() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 11;
-            let mut program = vec![];
-            program.push(Opcode::JumpEqual as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            let no_jmp_addr = program.len() as u32;
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], no_jmp_addr);
+            branch("je", 10, 11).expect_reg("rcx", 0).run();
         }
 
         #[test]
         fn jl_when_less() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 11;
-            let mut program = vec![];
-            program.push(Opcode::JumpLess as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], jmp_addr);
+            branch("jl", 10, 11).expect_reg("rcx", 1).run();
         }
         #[test]
         fn jl_when_eq() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 10;
-            let mut program = vec![];
-            program.push(Opcode::JumpLess as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            let no_jmp_addr = program.len() as u32;
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], no_jmp_addr);
+            branch("jl", 10, 10).expect_reg("rcx", 0).run();
         }
         #[test]
         fn jl_when_gr() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 9;
-            let mut program = vec![];
-            program.push(Opcode::JumpLess as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            let no_jmp_addr = program.len() as u32;
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], no_jmp_addr);
+            branch("jl", 10, 9).expect_reg("rcx", 0).run();
         }
 
         #[test]
         fn jg_when_less() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 11;
-            let mut program = vec![];
-            program.push(Opcode::JumpGreater as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            let no_jmp_addr = program.len() as u32;
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], no_jmp_addr);
+            branch("jg", 10, 11).expect_reg("rcx", 0).run();
         }
         #[test]
         fn jg_when_eq() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 10;
-            let mut program = vec![];
-            program.push(Opcode::JumpGreater as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            let no_jmp_addr = program.len() as u32;
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], no_jmp_addr);
+            branch("jg", 10, 10).expect_reg("rcx", 0).run();
         }
         #[test]
         fn jg_when_gr() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 9;
-            let mut program = vec![];
-            program.push(Opcode::JumpGreater as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], jmp_addr);
+            branch("jg", 10, 9).expect_reg("rcx", 1).run();
         }
 
         #[test]
         fn jle_when_less() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 11;
-            let mut program = vec![];
-            program.push(Opcode::JumpLessEqual as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], jmp_addr);
+            branch("jle", 10, 11).expect_reg("rcx", 1).run();
         }
         #[test]
         fn jle_when_eq() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 10;
-            let mut program = vec![];
-            program.push(Opcode::JumpLessEqual as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            let _no_jmp_addr = program.len() as u32;
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], jmp_addr);
+            branch("jle", 10, 10).expect_reg("rcx", 1).run();
         }
         #[test]
         fn jle_when_gr() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 9;
-            let mut program = vec![];
-            program.push(Opcode::JumpLessEqual as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            let no_jmp_addr = program.len() as u32;
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], no_jmp_addr);
+            branch("jle", 10, 9).expect_reg("rcx", 0).run();
         }
 
         #[test]
         fn jge_when_less() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 11;
-            let mut program = vec![];
-            program.push(Opcode::JumpGreaterEqual as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            let no_jmp_addr = program.len() as u32;
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], no_jmp_addr);
+            branch("jge", 10, 11).expect_reg("rcx", 0).run();
         }
         #[test]
         fn jge_when_eq() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 10;
-            let mut program = vec![];
-            program.push(Opcode::JumpGreaterEqual as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            let _no_jmp_addr = program.len() as u32;
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], jmp_addr);
+            branch("jge", 10, 10).expect_reg("rcx", 1).run();
         }
         #[test]
         fn jge_when_gr() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 10;
-            cpu.registers[1] = 9;
-            let mut program = vec![];
-            program.push(Opcode::JumpGreaterEqual as u8);
-            let jmp_addr = 10 as u32;
-            program.extend_from_slice(&jmp_addr.to_le_bytes());
-            cpu.load_program(program.as_slice());
-            cpu.cycle();
-            assert_eq!(cpu.registers[crate::cpu::IP], jmp_addr);
+            branch("jge", 10, 9).expect_reg("rcx", 1).run();
         }
     }
     mod compare {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
+
         #[test]
         fn compare_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.load_program(&[Opcode::CompareByteImm as u8, 100]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 1);
+            case("cmp.b 100").reg("rax", 100).expect_reg("rax", 1).run();
         }
         #[test]
         fn compare_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 511;
-            cpu.load_program(&[Opcode::CompareShortImm as u8, 255, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 1);
+            case("cmp.s 511").reg("rax", 511).expect_reg("rax", 1).run();
         }
         #[test]
         fn compare_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFF_FF_FF_FF;
-            cpu.load_program(&[Opcode::CompareLongImm as u8, 0xFF, 0xFF, 0xFF, 0xFF]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 1);
+            case("cmp.l 0xFFFFFFFF")
+                .reg("rax", 0xFF_FF_FF_FF)
+                .expect_reg("rax", 1)
+                .run();
         }
         #[test]
         fn compare_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 100;
-            cpu.load_program(&[Opcode::CompareByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 1);
+            case("cmp.b rbx")
+                .reg("rax", 100)
+                .reg("rbx", 100)
+                .expect_reg("rax", 1)
+                .run();
         }
         #[test]
         fn compare_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 511;
-            cpu.registers[1] = 511;
-            cpu.load_program(&[Opcode::CompareShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 1);
+            case("cmp.s rbx")
+                .reg("rax", 511)
+                .reg("rbx", 511)
+                .expect_reg("rax", 1)
+                .run();
         }
         #[test]
         fn compare_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFF_FF_FF_FF;
-            cpu.registers[1] = 0xFF_FF_FF_FF;
-            cpu.load_program(&[Opcode::CompareLongReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 1);
+            case("cmp.l rbx")
+                .reg("rax", 0xFF_FF_FF_FF)
+                .reg("rbx", 0xFF_FF_FF_FF)
+                .expect_reg("rax", 1)
+                .run();
         }
         #[test]
         fn compare_byte_imm_neg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0;
-            cpu.load_program(&[Opcode::CompareByteImm as u8, 100]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0);
+            case("cmp.b 100").reg("rax", 0).expect_reg("rax", 0).run();
         }
         #[test]
         fn compare_short_imm_neg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0;
-            cpu.load_program(&[Opcode::CompareShortImm as u8, 255, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0);
+            case("cmp.s 511").reg("rax", 0).expect_reg("rax", 0).run();
         }
         #[test]
         fn compare_long_imm_neg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0;
-            cpu.load_program(&[Opcode::CompareLongImm as u8, 0xFF, 0xFF, 0xFF, 0xFF]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0);
+            case("cmp.l 0xFFFFFFFF")
+                .reg("rax", 0)
+                .expect_reg("rax", 0)
+                .run();
         }
         #[test]
         fn compare_byte_reg_neg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 100;
-            cpu.registers[1] = 0;
-            cpu.load_program(&[Opcode::CompareByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0);
+            case("cmp.b rbx")
+                .reg("rax", 100)
+                .reg("rbx", 0)
+                .expect_reg("rax", 0)
+                .run();
         }
         #[test]
         fn compare_short_reg_neg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 511;
-            cpu.registers[1] = 0;
-            cpu.load_program(&[Opcode::CompareShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0);
+            case("cmp.s rbx")
+                .reg("rax", 511)
+                .reg("rbx", 0)
+                .expect_reg("rax", 0)
+                .run();
         }
         #[test]
         fn compare_long_reg_neg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFF_FF_FF_FF;
-            cpu.registers[1] = 0;
-            cpu.load_program(&[Opcode::CompareLongReg as u8, 0]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 1);
+            case("cmp.l rax")
+                .reg("rax", 0xFF_FF_FF_FF)
+                .reg("rbx", 0)
+                .expect_reg("rax", 1)
+                .run();
         }
     }
     mod and {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
 
         #[test]
         fn and_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFF;
-            cpu.load_program(&[Opcode::AndByteImm as u8, 0xCC]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xCC);
+            case("and.b 0xCC")
+                .reg("rax", 0xFF)
+                .expect_reg("rax", 0xCC)
+                .run();
         }
 
         #[test]
         fn and_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFFFFFFFF;
-            cpu.load_program(&[Opcode::AndLongImm as u8, 0xCC, 0xCC, 0xCC, 0xCC]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xCCCCCCCC);
+            case("and.l 0xCCCCCCCC")
+                .reg("rax", 0xFFFFFFFF)
+                .expect_reg("rax", 0xCCCCCCCC)
+                .run();
         }
 
         #[test]
         fn and_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFFFF;
-            cpu.load_program(&[Opcode::AndShortImm as u8, 0xCC, 0xCC]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xCCCC);
+            case("and.s 0xCCCC")
+                .reg("rax", 0xFFFF)
+                .expect_reg("rax", 0xCCCC)
+                .run();
         }
 
         #[test]
         fn and_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFF;
-            cpu.registers[1] = 0xCC;
-            cpu.load_program(&[Opcode::AndByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xCC);
+            case("and.b rbx")
+                .reg("rax", 0xFF)
+                .reg("rbx", 0xCC)
+                .expect_reg("rax", 0xCC)
+                .run();
         }
 
         #[test]
         fn and_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFFFFFFFF;
-            cpu.registers[1] = 0xCCCCCCCC;
-            cpu.load_program(&[Opcode::AndLongReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xCCCCCCCC);
+            case("and.l rbx")
+                .reg("rax", 0xFFFFFFFF)
+                .reg("rbx", 0xCCCCCCCC)
+                .expect_reg("rax", 0xCCCCCCCC)
+                .run();
         }
 
         #[test]
         fn and_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFFFF;
-            cpu.registers[1] = 0xCCCC;
-            cpu.load_program(&[Opcode::AndShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xCCCC);
+            case("and.s rbx")
+                .reg("rax", 0xFFFF)
+                .reg("rbx", 0xCCCC)
+                .expect_reg("rax", 0xCCCC)
+                .run();
         }
     }
     mod or {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
 
         #[test]
         fn or_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFA;
-            cpu.registers[1] = 0x55;
-            cpu.load_program(&[Opcode::OrByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xFF);
+            case("or.b rbx")
+                .reg("rax", 0xFA)
+                .reg("rbx", 0x55)
+                .expect_reg("rax", 0xFF)
+                .run();
         }
 
         #[test]
         fn or_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFAFA;
-            cpu.registers[1] = 0x5555;
-            cpu.load_program(&[Opcode::OrShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xFFFF);
+            case("or.s rbx")
+                .reg("rax", 0xFAFA)
+                .reg("rbx", 0x5555)
+                .expect_reg("rax", 0xFFFF)
+                .run();
         }
 
         #[test]
         fn or_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFAFAFAFA;
-            cpu.registers[1] = 0x55555555;
-            cpu.load_program(&[Opcode::OrLongReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xFFFFFFFF);
+            case("or.l rbx")
+                .reg("rax", 0xFAFAFAFA)
+                .reg("rbx", 0x55555555)
+                .expect_reg("rax", 0xFFFFFFFF)
+                .run();
         }
 
         #[test]
         fn or_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFA;
-            cpu.load_program(&[Opcode::OrByteImm as u8, 0x55]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xFF);
+            case("or.b 0x55")
+                .reg("rax", 0xFA)
+                .expect_reg("rax", 0xFF)
+                .run();
         }
 
         #[test]
         fn or_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFAFA;
-            cpu.load_program(&[Opcode::OrShortImm as u8, 0x55, 0x55]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xFFFF);
+            case("or.s 0x5555")
+                .reg("rax", 0xFAFA)
+                .expect_reg("rax", 0xFFFF)
+                .run();
         }
 
         #[test]
         fn or_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFAFAFAFA;
-            cpu.load_program(&[Opcode::OrLongImm as u8, 0x55, 0x55, 0x55, 0x55]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xFFFFFFFF);
+            case("or.l 0x55555555")
+                .reg("rax", 0xFAFAFAFA)
+                .expect_reg("rax", 0xFFFFFFFF)
+                .run();
         }
     }
     mod xor {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
 
         #[test]
         fn xor_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFA;
-            cpu.registers[1] = 0x55;
-            cpu.load_program(&[Opcode::XorByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xAF);
+            case("xor.b rbx")
+                .reg("rax", 0xFA)
+                .reg("rbx", 0x55)
+                .expect_reg("rax", 0xAF)
+                .run();
         }
 
         #[test]
         fn xor_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFAFA;
-            cpu.registers[1] = 0x5555;
-            cpu.load_program(&[Opcode::XorShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xAFAF);
+            case("xor.s rbx")
+                .reg("rax", 0xFAFA)
+                .reg("rbx", 0x5555)
+                .expect_reg("rax", 0xAFAF)
+                .run();
         }
 
         #[test]
         fn xor_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFAFAFAFA;
-            cpu.registers[1] = 0x55555555;
-            cpu.load_program(&[Opcode::XorLongReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xAFAFAFAF);
+            case("xor.l rbx")
+                .reg("rax", 0xFAFAFAFA)
+                .reg("rbx", 0x55555555)
+                .expect_reg("rax", 0xAFAFAFAF)
+                .run();
         }
 
         #[test]
         fn xor_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFA;
-            cpu.load_program(&[Opcode::XorByteImm as u8, 0x55]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xAF);
+            case("xor.b 0x55")
+                .reg("rax", 0xFA)
+                .expect_reg("rax", 0xAF)
+                .run();
         }
 
         #[test]
         fn xor_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFAFA;
-            cpu.load_program(&[Opcode::XorShortImm as u8, 0x55, 0x55]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xAFAF);
+            case("xor.s 0x5555")
+                .reg("rax", 0xFAFA)
+                .expect_reg("rax", 0xAFAF)
+                .run();
         }
 
         #[test]
         fn xor_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0xFAFAFAFA;
-            cpu.load_program(&[Opcode::XorLongImm as u8, 0x55, 0x55, 0x55, 0x55]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xAFAFAFAF);
+            case("xor.l 0x55555555")
+                .reg("rax", 0xFAFAFAFA)
+                .expect_reg("rax", 0xAFAFAFAF)
+                .run();
         }
     }
     mod logical_shift {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
+
+        #[test]
+        fn counts_past_the_width() {
+            // the last bit shifted out is the carry, none once the count is past it.
+            let cases = [
+                ("shl.b 8", 0x81, 0, true),
+                ("shl.b 9", 0xFF, 0, false),
+                ("shl.s 16", 0x8001, 0, true),
+                ("shl.s 17", 0xFFFF, 0, false),
+                ("shl.l 32", 0x0000_0001, 0, true),
+                ("shl.l 33", 0xFFFF_FFFF, 0, false),
+                ("shr.b 8", 0x80, 0, true),
+                ("shr.b 9", 0xFF, 0, false),
+                ("shr.s 16", 0x8000, 0, true),
+                ("shr.s 17", 0xFFFF, 0, false),
+                ("shr.l 32", 0x8000_0000, 0, true),
+                ("shr.l 33", 0xFFFF_FFFF, 0, false),
+            ];
+            for (source, rax, result, carry) in cases {
+                case(source)
+                    .reg("rax", rax)
+                    .expect_reg("rax", result)
+                    .expect_flag("carry", carry)
+                    .run();
+            }
+            case("mov.l rbx, 40\nshl.l rbx")
+                .reg("rax", 0xFFFF_FFFF)
+                .expect_reg("rax", 0)
+                .expect_flag("carry", false)
+                .run();
+            case("mov.l rbx, 40\nshr.l rbx")
+                .reg("rax", 0xFFFF_FFFF)
+                .expect_reg("rax", 0)
+                .expect_flag("carry", false)
+                .run();
+        }
 
         #[test]
         fn left_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0F;
-            cpu.load_program(&[Opcode::LogShiftLeftByteImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1E);
+            case("shl.b 1")
+                .reg("rax", 0x0F)
+                .expect_reg("rax", 0x1E)
+                .run();
         }
 
         #[test]
         fn left_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0FFF;
-            cpu.load_program(&[Opcode::LogShiftLeftShortImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1FFE);
+            case("shl.s 1")
+                .reg("rax", 0x0FFF)
+                .expect_reg("rax", 0x1FFE)
+                .run();
         }
 
         #[test]
         fn left_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0FFFFFFF;
-            cpu.load_program(&[Opcode::LogShiftLeftLongImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1FFFFFFE);
+            case("shl.l 1")
+                .reg("rax", 0x0FFFFFFF)
+                .expect_reg("rax", 0x1FFFFFFE)
+                .run();
         }
 
         #[test]
         fn left_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0F;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::LogShiftLeftByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1E);
+            case("shl.b rbx")
+                .reg("rax", 0x0F)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x1E)
+                .run();
         }
 
         #[test]
         fn left_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0FFF;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::LogShiftLeftShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1FFE);
+            case("shl.s rbx")
+                .reg("rax", 0x0FFF)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x1FFE)
+                .run();
         }
 
         #[test]
         fn left_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0FFFFFFF;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::LogShiftLeftLongReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1FFFFFFE);
+            case("shl.l rbx")
+                .reg("rax", 0x0FFFFFFF)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x1FFFFFFE)
+                .run();
         }
 
         #[test]
         fn right_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1E;
-            cpu.load_program(&[Opcode::LogShiftRightByteImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0F);
+            case("shr.b 1")
+                .reg("rax", 0x1E)
+                .expect_reg("rax", 0x0F)
+                .run();
         }
 
         #[test]
         fn right_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1FFE;
-            cpu.load_program(&[Opcode::LogShiftRightShortImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0FFF);
+            case("shr.s 1")
+                .reg("rax", 0x1FFE)
+                .expect_reg("rax", 0x0FFF)
+                .run();
         }
 
         #[test]
         fn right_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1FFFFFFE;
-            cpu.load_program(&[Opcode::LogShiftRightLongImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0FFFFFFF);
+            case("shr.l 1")
+                .reg("rax", 0x1FFFFFFE)
+                .expect_reg("rax", 0x0FFFFFFF)
+                .run();
+        }
+
+        #[test]
+        fn right_long_imm_keeps_sign() {
+            case("sar.l 4")
+                .reg("rax", 0xFFFFFF00)
+                .expect_reg("rax", 0xFFFFFFF0)
+                .run();
         }
 
         #[test]
         fn right_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1E;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::LogShiftRightByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0F);
+            case("shr.b rbx")
+                .reg("rax", 0x1E)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x0F)
+                .run();
         }
 
         #[test]
         fn right_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1FFE;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::LogShiftRightShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0FFF);
+            case("shr.s rbx")
+                .reg("rax", 0x1FFE)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x0FFF)
+                .run();
         }
 
         #[test]
         fn right_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1FFFFFFE;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::LogShiftRightLongReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0FFFFFFF);
+            case("shr.l rbx")
+                .reg("rax", 0x1FFFFFFE)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x0FFFFFFF)
+                .run();
         }
     }
     mod arith_shift {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
+
+        #[test]
+        fn counts_past_the_width() {
+            // right shifts fill with the sign, which is also the carry.
+            let cases = [
+                ("sal.b 9", 0xFF, 0, false),
+                ("sal.s 17", 0xFFFF, 0, false),
+                ("sal.l 33", 0xFFFF_FFFF, 0, false),
+                ("sar.b 8", 0x80, u32::MAX, true),
+                ("sar.b 9", 0x40, 0, false),
+                ("sar.s 16", 0x8000, u32::MAX, true),
+                ("sar.s 17", 0x4000, 0, false),
+                ("sar.l 32", 0x8000_0000, u32::MAX, true),
+                ("sar.l 33", 0x4000_0000, 0, false),
+            ];
+            for (source, rax, result, carry) in cases {
+                case(source)
+                    .reg("rax", rax)
+                    .expect_reg("rax", result)
+                    .expect_flag("carry", carry)
+                    .run();
+            }
+            case("mov.l rbx, 40\nsar.l rbx")
+                .reg("rax", 0x8000_0000)
+                .expect_reg("rax", u32::MAX)
+                .expect_flag("carry", true)
+                .run();
+        }
 
         #[test]
         fn left_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0F;
-            cpu.load_program(&[Opcode::ArithShiftLeftByteImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1E);
+            case("sal.b 1")
+                .reg("rax", 0x0F)
+                .expect_reg("rax", 0x1E)
+                .run();
         }
 
         #[test]
         fn left_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0FFF;
-            cpu.load_program(&[Opcode::ArithShiftLeftShortImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1FFE);
+            case("sal.s 1")
+                .reg("rax", 0x0FFF)
+                .expect_reg("rax", 0x1FFE)
+                .run();
         }
 
         #[test]
         fn left_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0FFFFFFF;
-            cpu.load_program(&[Opcode::ArithShiftLeftLongImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1FFFFFFE);
+            case("sal.l 1")
+                .reg("rax", 0x0FFFFFFF)
+                .expect_reg("rax", 0x1FFFFFFE)
+                .run();
         }
 
         #[test]
         fn left_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0F;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::ArithShiftLeftByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1E);
+            case("sal.b rbx")
+                .reg("rax", 0x0F)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x1E)
+                .run();
         }
 
         #[test]
         fn left_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0FFF;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::ArithShiftLeftShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1FFE);
+            case("sal.s rbx")
+                .reg("rax", 0x0FFF)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x1FFE)
+                .run();
         }
 
         #[test]
         fn left_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x0FFFFFFF;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::ArithShiftLeftLongReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x1FFFFFFE);
+            case("sal.l rbx")
+                .reg("rax", 0x0FFFFFFF)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x1FFFFFFE)
+                .run();
         }
 
         #[test]
         fn right_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1E;
-            cpu.load_program(&[Opcode::ArithShiftRightByteImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0F);
+            case("sar.b 1")
+                .reg("rax", 0x1E)
+                .expect_reg("rax", 0x0F)
+                .run();
         }
 
         #[test]
         fn right_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1FFE;
-            cpu.load_program(&[Opcode::ArithShiftRightShortImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0FFF);
+            case("sar.s 1")
+                .reg("rax", 0x1FFE)
+                .expect_reg("rax", 0x0FFF)
+                .run();
         }
 
         #[test]
         fn right_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1FFFFFFE;
-            cpu.load_program(&[Opcode::ArithShiftRightLongImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0FFFFFFF);
+            case("sar.l 1")
+                .reg("rax", 0x1FFFFFFE)
+                .expect_reg("rax", 0x0FFFFFFF)
+                .run();
         }
 
         #[test]
         fn right_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1E;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::ArithShiftRightByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0F);
+            case("sar.b rbx")
+                .reg("rax", 0x1E)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x0F)
+                .run();
         }
 
         #[test]
         fn right_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1FFE;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::ArithShiftRightShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0FFF);
+            case("sar.s rbx")
+                .reg("rax", 0x1FFE)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x0FFF)
+                .run();
         }
 
         #[test]
         fn right_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x1FFFFFFE;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::ArithShiftRightLongReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0FFFFFFF);
+            case("sar.l rbx")
+                .reg("rax", 0x1FFFFFFE)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x0FFFFFFF)
+                .run();
         }
     }
     mod rotate {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
 
         #[test]
         fn left_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x85;
-            cpu.load_program(&[Opcode::RotateLeftByteImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0B);
+            case("rol.b 1")
+                .reg("rax", 0x85)
+                .expect_reg("rax", 0x0B)
+                .run();
         }
 
         #[test]
         fn left_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x8001;
-            cpu.load_program(&[Opcode::RotateLeftShortImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0003);
+            case("rol.s 1")
+                .reg("rax", 0x8001)
+                .expect_reg("rax", 0x0003)
+                .run();
         }
 
         #[test]
         fn left_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x80000001;
-            cpu.load_program(&[Opcode::RotateLeftLongImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x00000003);
+            case("rol.l 1")
+                .reg("rax", 0x80000001)
+                .expect_reg("rax", 0x00000003)
+                .run();
         }
 
         #[test]
         fn left_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x85;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::RotateLeftByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0B);
+            case("rol.b rbx")
+                .reg("rax", 0x85)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x0B)
+                .run();
         }
 
         #[test]
         fn left_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x8001;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::RotateLeftShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x0003);
+            case("rol.s rbx")
+                .reg("rax", 0x8001)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x0003)
+                .run();
         }
 
         #[test]
         fn left_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x80000001;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::RotateLeftLongReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0x00000003);
+            case("rol.l rbx")
+                .reg("rax", 0x80000001)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0x00000003)
+                .run();
         }
 
         #[test]
         fn right_byte_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x85;
-            cpu.load_program(&[Opcode::RotateRightByteImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xC2);
+            case("ror.b 1")
+                .reg("rax", 0x85)
+                .expect_reg("rax", 0xC2)
+                .run();
         }
 
         #[test]
         fn right_short_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x8001;
-            cpu.load_program(&[Opcode::RotateRightShortImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xC000);
+            case("ror.s 1")
+                .reg("rax", 0x8001)
+                .expect_reg("rax", 0xC000)
+                .run();
         }
 
         #[test]
         fn right_long_imm() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x80000001;
-            cpu.load_program(&[Opcode::RotateRightLongImm as u8, 0x01]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xC0000000);
+            case("ror.l 1")
+                .reg("rax", 0x80000001)
+                .expect_reg("rax", 0xC0000000)
+                .run();
         }
 
         #[test]
         fn right_byte_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x85;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::RotateRightByteReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xC2);
+            case("ror.b rbx")
+                .reg("rax", 0x85)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0xC2)
+                .run();
         }
 
         #[test]
         fn right_short_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x8001;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::RotateRightShortReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xC000);
+            case("ror.s rbx")
+                .reg("rax", 0x8001)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0xC000)
+                .run();
         }
 
         #[test]
         fn right_long_reg() {
-            let mut cpu = Cpu::new();
-            cpu.registers[0] = 0x80000001;
-            cpu.registers[1] = 0x01;
-            cpu.load_program(&[Opcode::RotateRightLongReg as u8, 1]);
-            cpu.cycle();
-            assert_eq!(cpu.registers[0], 0xC0000000);
+            case("ror.l rbx")
+                .reg("rax", 0x80000001)
+                .reg("rbx", 0x01)
+                .expect_reg("rax", 0xC0000000)
+                .run();
+        }
+    }
+    mod flags {
+        use super::harness::{case, Case};
+
+        fn flags(case: Case, zero: bool, sign: bool, carry: bool, overflow: bool) {
+            case.expect_flag("zero", zero)
+                .expect_flag("sign", sign)
+                .expect_flag("carry", carry)
+                .expect_flag("overflow", overflow)
+                .run();
+        }
+
+        #[test]
+        fn add_per_width() {
+            flags(case("add.b 1").reg("rax", 0x7F), false, true, false, true);
+            flags(case("add.b 1").reg("rax", 0xFF), true, false, true, false);
+            flags(case("add.s 1").reg("rax", 0x7FFF), false, true, false, true);
+            flags(case("add.s 1").reg("rax", 0xFFFF), true, false, true, false);
+            flags(
+                case("add.l 1").reg("rax", 0x7FFF_FFFF),
+                false,
+                true,
+                false,
+                true,
+            );
+            flags(
+                case("add.l 0x80000000").reg("rax", 0x8000_0000),
+                true,
+                false,
+                true,
+                true,
+            );
+            // only the low byte counts for a byte add.
+            flags(
+                case("add.b 1").reg("rax", 0x100),
+                false,
+                false,
+                false,
+                false,
+            );
+        }
+
+        #[test]
+        fn sub_per_width() {
+            flags(case("sub.b 1").reg("rax", 0), false, true, true, false);
+            flags(case("sub.b 1").reg("rax", 0x80), false, false, false, true);
+            flags(case("sub.s 5").reg("rax", 5), true, false, false, false);
+            flags(
+                case("sub.l 1").reg("rax", 0x8000_0000),
+                false,
+                false,
+                false,
+                true,
+            );
+            flags(
+                case("sub.l rbx").reg("rax", 1).reg("rbx", 0xFFFF_FFFF),
+                false,
+                false,
+                true,
+                false,
+            );
+        }
+
+        #[test]
+        fn add_carry_uses_carry_in() {
+            flags(
+                case("adc.b 0").reg("rax", 0xFF).flag("carry", true),
+                true,
+                false,
+                true,
+                false,
+            );
+        }
+
+        #[test]
+        fn mul_sets_carry_when_the_product_is_truncated() {
+            flags(case("mul.b 2").reg("rax", 0x80), true, false, true, true);
+            flags(case("mul.s 2").reg("rax", 0x80), false, false, false, false);
+            flags(case("imul.b 2").reg("rax", 0x40), false, true, true, true);
+            flags(case("imul.b 2").reg("rax", 0xC0), false, true, false, false);
+        }
+
+        #[test]
+        fn logic_clears_carry_and_overflow() {
+            flags(
+                case("and.b 0x0F").reg("rax", 0xF0).flag("carry", true),
+                true,
+                false,
+                false,
+                false,
+            );
+            flags(case("or.s 0x8000").reg("rax", 1), false, true, false, false);
+            flags(
+                case("xor.l rax").reg("rax", 1234),
+                true,
+                false,
+                false,
+                false,
+            );
+            flags(
+                case("not.b rax").reg("rax", 0xFF),
+                true,
+                false,
+                false,
+                false,
+            );
+        }
+
+        #[test]
+        fn shifts_carry_the_last_bit_out() {
+            flags(case("shl.b 1").reg("rax", 0x81), false, false, true, true);
+            flags(
+                case("shr.s 1").reg("rax", 0x0003),
+                false,
+                false,
+                true,
+                false,
+            );
+            flags(case("sar.b 1").reg("rax", 0x81), false, true, true, false);
+            flags(
+                case("shl.l 0").reg("rax", 0x8000_0000),
+                false,
+                true,
+                false,
+                false,
+            );
+            flags(case("rol.b 1").reg("rax", 0x80), false, false, true, true);
+            flags(case("ror.b 1").reg("rax", 0x01), false, true, true, true);
+        }
+
+        #[test]
+        fn neg_inc_and_dec() {
+            flags(case("neg.b rbx").reg("rbx", 0x80), false, true, true, true);
+            flags(case("neg.l rbx"), true, false, false, false);
+            // inc and dec keep the carry they found.
+            flags(
+                case("inc.b rbx").reg("rbx", 0x7F).flag("carry", true),
+                false,
+                true,
+                true,
+                true,
+            );
+            flags(case("dec.s rbx").reg("rbx", 1), true, false, false, false);
+        }
+
+        #[test]
+        fn jumps_test_one_flag() {
+            let jumps = [
+                ("jz", "jnz", "zero"),
+                ("js", "jns", "sign"),
+                ("jc", "jnc", "carry"),
+                ("jo", "jno", "overflow"),
+            ];
+            for (jump, jump_not, flag) in jumps {
+                for set in [false, true] {
+                    let source = |jump: &str| format!("{} taken\nhlt\ntaken:\nmov.l rbx, 1", jump);
+                    case(&source(jump))
+                        .flag(flag, set)
+                        .expect_reg("rbx", set as u32)
+                        .run();
+                    case(&source(jump_not))
+                        .flag(flag, set)
+                        .expect_reg("rbx", !set as u32)
+                        .run();
+                }
+            }
+        }
+
+        #[test]
+        fn compare_only_sets_flags() {
+            let compare = case("cmp.l rcx, rdx").reg("rcx", 3).reg("rdx", 5);
+            flags(
+                compare.expect_reg("rcx", 3).expect_reg("rdx", 5),
+                false,
+                true,
+                true,
+                false,
+            );
+            flags(
+                case("cmp.b rcx, 0x80").reg("rcx", 0x7F),
+                false,
+                true,
+                true,
+                true,
+            );
+            flags(
+                case("cmp.s rcx, [0x200]")
+                    .reg("rcx", 0x1234)
+                    .mem(0x200, &0x1234u16.to_le_bytes()),
+                true,
+                false,
+                false,
+                false,
+            );
+            flags(
+                case("cmp.l rcx, [rdx]")
+                    .reg("rcx", 1)
+                    .reg("rdx", 0x200)
+                    .mem(0x200, &2u32.to_le_bytes()),
+                false,
+                true,
+                true,
+                false,
+            );
+        }
+
+        #[test]
+        fn signed_and_unsigned_jumps() {
+            // (lhs, rhs, jumps taken after `cmp lhs, rhs`)
+            let cases = [
+                (1, 2, ["jb", "jbe", "jlt", "jlte"]),
+                (2, 1, ["ja", "jae", "jgt", "jgte"]),
+                (-1i32 as u32, 1, ["ja", "jae", "jlt", "jlte"]),
+                (1, -1i32 as u32, ["jb", "jbe", "jgt", "jgte"]),
+            ];
+            let all = ["ja", "jae", "jb", "jbe", "jgt", "jgte", "jlt", "jlte"];
+            for (lhs, rhs, taken) in cases {
+                for jump in all {
+                    let source =
+                        format!("cmp.l rcx, rdx\n{} taken\nhlt\ntaken:\nmov.l rbx, 1", jump);
+                    case(&source)
+                        .reg("rcx", lhs)
+                        .reg("rdx", rhs)
+                        .expect_reg("rbx", taken.contains(&jump) as u32)
+                        .run();
+                }
+            }
+            for jump in ["jae", "jbe", "jgte", "jlte"] {
+                let source = format!("cmp.b rcx, 7\n{} taken\nhlt\ntaken:\nmov.l rbx, 1", jump);
+                case(&source).reg("rcx", 7).expect_reg("rbx", 1).run();
+            }
+        }
+
+        #[test]
+        fn counts_down_to_zero() {
+            case("mov.l rcx, 5\nloop:\nadd.l 2\ndec.l rcx\njnz loop")
+                .expect_reg("rax", 10)
+                .expect_flag("zero", true)
+                .run();
         }
     }
     mod control_flow {
-        use crate::{cpu::Cpu, opcodes::Opcode};
+        use super::harness::case;
 
         #[test]
         fn call() {
-            let mut cpu = Cpu::new();
-            cpu.memory.set_byte(100, Opcode::Hlt as u8);
-            cpu.load_program(&[Opcode::Call as u8, 100]);
-            cpu.run();
-            assert_eq!(cpu.ip(), 101);
+            case("call routine\nhlt\nroutine: mov.l rcx, 1")
+                .expect_reg("rcx", 1)
+                .run();
         }
         #[test]
         fn _return() {
-            let mut cpu = Cpu::new();
-            // 1 cycle in routine
-            cpu.memory.set_byte(100, Opcode::Return as u8);
-
-            // call: 1 cycle
-            // long addr: 4 cycles
-            // ret instruction: 1 cycle
-            // halt on return: 1 cycle
-            cpu.load_program(&[Opcode::Call as u8, 100, 0, 0, 0, 0]);
-            cpu.run();
-            assert_eq!(cpu.ip(), 6)
+            case("call routine\nmov.l rbx, 2\nhlt\nroutine: mov.l rcx, 1\nret")
+                .expect_reg("rbx", 2)
+                .expect_reg("rcx", 1)
+                .run();
         }
     }
     mod int {
+        use super::harness::case;
+
+        #[test]
+        fn interrupt() {
+            // an idt at 0x100, with the handler for int 0.
+            case("mov.l idt, 0x100\nmov.l [0x100], handler\nint 0\nmov.l rbx, 1\nhlt\nhandler: mov.b rax, 10\niret")
+                .expect_reg("rax", 10)
+                .expect_reg("rbx", 1)
+                .expect_flag("interrupt", false)
+                .run();
+        }
+    }
+    mod pic {
+        use std::{cell::RefCell, rc::Rc};
+
+        use super::harness::case;
         use crate::{
-            cpu::{Cpu, FLAGS, IDT},
-            opcodes::Opcode,
+            assembler::assemble,
+            cpu::{Cpu, IDT},
+            hardware::{Config, Hardware, Interrupts},
+            pic::Pic,
         };
 
+        // handlers for lines 1 and 2 in an idt at 0x300, each printing its line.
+        // `one` runs `inner` between printing `1` and `1 done`.
+        fn program(setup: &str, inner: &str, main: &str) -> String {
+            format!(
+                "mov.l idt, 0x300\nmov.l [0x304], one\nmov.l [0x308], two\n\
+                 {setup}\n{main}\nmov.l rax, end\nsyscall 2\nhlt\n\
+                 one:\nmov.l rax, s1\nsyscall 2\n{inner}\nmov.l rax, s1done\nsyscall 2\niret\n\
+                 two:\nmov.l rax, s2\nsyscall 2\niret\n\
+                 s1: asciz \"1\"\ns1done: asciz \"1 done\"\ns2: asciz \"2\"\nend: asciz \"end\""
+            )
+        }
+
         #[test]
-        fn interrupt() {
-            // TODO: we need to improve this test. when I try to make it more comprehensive
-            // it just totally fails. its probably me just writing it out wrong, but still.
-            let mut cpu = Cpu::new();
+        fn queues_while_busy() {
+            case(&program("", "int 2", "int 1"))
+                .expect_output("1\n1 done\n2\nend\n")
+                .expect_flag("interrupt", false)
+                .run();
+        }
 
-            // address of isr_table.
-            cpu.registers[IDT] = 1;
+        #[test]
+        fn higher_priority_nests() {
+            let setup = "mov.l rax, 2\nmov.l rbx, 5\nsyscall 7";
+            case(&program(setup, "int 2", "int 1"))
+                .expect_output("1\n2\n1 done\nend\n")
+                .run();
+        }
 
-            cpu.load_program(&[
-                Opcode::Interrupt as u8, // 0  ; int 0
-                Opcode::MoveImmRegByte as u8,
-                0,
-                10,
-                Opcode::InterruptReturn as u8, // 1  ; iret
-            ]);
+        #[test]
+        fn end_of_interrupt_lets_anything_nest() {
+            case(&program("", "syscall 8\nint 2", "int 1"))
+                .expect_output("1\n2\n1 done\nend\n")
+                .run();
+        }
 
-            cpu.run();
-            assert_eq!(cpu.ip(), 3);
-            assert_eq!((cpu.registers[FLAGS] & Cpu::INTERRUPT_FLAG as u32), 0);
+        #[test]
+        fn masked_lines_wait() {
+            let setup =
+                "mov.l rax, 2\nmov.l rbx, 1\nsyscall 6\nint 2\nmov.l rax, s1done\nsyscall 2";
+            // unmasking is enough, it arrives before the next instruction.
+            let main = "mov.l rax, 2\nmov.l rbx, 0\nsyscall 6";
+            case(&program(setup, "", main))
+                .expect_output("1 done\n2\nend\n")
+                .run();
         }
-    }
-    mod mov {
-        const SRC_VAL: u32 = 0xCAFEC0DE;
-        const DST_REG: usize = 0;
-        const SRC_REG: usize = 1;
-        const DST_ADR: usize = 10;
-        const SRC_ADR: usize = 20;
-        use std::marker::PhantomData;
 
-        use crate::{
-            cpu::Cpu,
-            opcodes::Opcode,
-            test::le_bytes_junk::{OurFrom, ToLeTrait},
-        };
-        struct TestBuilder<T> {
-            cpu: Cpu,
-            op: u8,
-            src: Vec<u8>,
-            dst: Vec<u8>,
-            _marker: PhantomData<T>,
-        }
-        impl<T> TestBuilder<T>
-        where
-            T: Copy + std::fmt::Debug + OurFrom<u32> + ToLeTrait + Eq + Into<u32>,
-        {
-            fn new(op: Opcode) -> TestBuilder<T> {
-                TestBuilder {
-                    cpu: Cpu::new(),
-                    op: op as u8,
-                    src: vec![],
-                    dst: vec![],
-                    _marker: PhantomData,
-                }
-            }
-            fn run(&mut self) -> &mut Self {
-                let mut progam = vec![self.op];
-                progam.extend_from_slice(&self.dst);
-                progam.extend_from_slice(&self.src);
-                self.cpu.load_program(&progam);
-                self.cpu.cycle();
-                self
-            }
-            fn assert_reg_eq(&mut self) {
-                let dst_val = T::our_from(self.cpu.registers[DST_REG]);
-                assert_eq!(dst_val, T::our_from(SRC_VAL));
-            }
-            fn assert_adr_eq(&mut self) {
-                let dst_val = T::our_from(self.cpu.memory.long(DST_ADR));
-                assert_eq!(dst_val, T::our_from(SRC_VAL));
-            }
-            fn set_dst_reg(&mut self) -> &mut Self {
-                self.dst.clear();
-                self.dst.push(DST_REG as u8);
-                self
-            }
-            fn set_dst_abs(&mut self) -> &mut Self {
-                self.dst.clear();
-                self.dst.extend_from_slice(&(DST_ADR as u32).to_le_bytes());
-                self
-            }
-            fn set_dst_mem(&mut self, rel_to: usize) -> &mut Self {
-                self.dst.clear();
-                self.dst
-                    .extend_from_slice(&((DST_ADR - rel_to) as u32).to_le_bytes());
-                self
-            }
-            fn set_dst_ind(&mut self) -> &mut Self {
-                self.dst.clear();
-                self.dst.push(DST_REG as u8);
-                self.cpu.registers[DST_REG] = DST_ADR as u32;
-                self
-            }
-            fn set_src_imm(&mut self) -> &mut Self {
-                let val = T::our_from(SRC_VAL);
-                self.src.clear();
-                self.src.extend_from_slice(&val.to_le_bytes());
-                self
-            }
-            fn set_src_reg(&mut self) -> &mut Self {
-                self.src.clear();
-                self.src.push(SRC_REG as u8);
-                self.cpu.registers[SRC_REG] = T::our_from(SRC_VAL).into();
-                self
+        #[test]
+        fn cli_holds_interrupts_until_sti() {
+            let main = "cli\nint 1\nmov.l rax, s2\nsyscall 2\nsti";
+            case(&program("", "", main))
+                .expect_output("2\n1\n1 done\nend\n")
+                .expect_flag("interrupt_enable", true)
+                .run();
+            case("cli").expect_flag("interrupt_enable", false).run();
+        }
+
+        #[test]
+        fn waits_for_an_idt() {
+            let source = "int 1\nmov.l rax, s2\nsyscall 2\nmov.l [0x304], one\nmov.l idt, 0x300\n\
+                          mov.l rax, end\nsyscall 2\nhlt\n\
+                          one:\nmov.l rax, s1\nsyscall 2\niret\n\
+                          s1: asciz \"1\"\ns2: asciz \"2\"\nend: asciz \"end\"";
+            case(source)
+                .expect_output("2\n1\nend\n")
+                .expect_flag("interrupt", false)
+                .run();
+        }
+
+        #[test]
+        fn iret_restores_flags() {
+            let source = "mov.l idt, 0x300\nmov.l [0x304], isr\n\
+                          mov.l rax, 0xFFFFFFFF\nadd.l 1\nint 1\nhlt\n\
+                          isr:\nmov.l rax, 1\nadd.l 1\niret";
+            case(source)
+                .expect_reg("rax", 2)
+                .expect_flag("carry", true)
+                .expect_flag("zero", true)
+                .expect_flag("interrupt", false)
+                .run();
+        }
+
+        /// Raises the line written to it.
+        struct Doorbell(Option<Interrupts>);
+
+        impl Hardware for Doorbell {
+            fn init(&mut self, config: Config) {
+                self.0 = Some(config.interrupts);
             }
-            fn set_src_abs(&mut self) -> &mut Self {
-                self.src.clear();
-                self.src.extend_from_slice(&(SRC_ADR as u32).to_le_bytes());
-                let val_bytes = T::our_from(SRC_VAL).to_le_bytes();
-                self.cpu
-                    .memory
-                    .buffer
-                    .splice(SRC_ADR..SRC_ADR + val_bytes.len(), val_bytes);
-                self
+            fn deinit(&mut self) {}
+            fn read(&self) -> u8 {
+                0
             }
-            fn set_src_mem(&mut self, rel_to: usize) -> &mut Self {
-                self.src.clear();
-                self.src
-                    .extend_from_slice(&((SRC_ADR - rel_to) as u32).to_le_bytes());
-                let val_bytes = T::our_from(SRC_VAL).to_le_bytes();
-                self.cpu
-                    .memory
-                    .buffer
-                    .splice(SRC_ADR..SRC_ADR + val_bytes.len(), val_bytes);
-                self
+            fn write(&mut self, line: u8) {
+                self.0.as_ref().unwrap().raise(line);
             }
-            fn set_src_ind(&mut self) -> &mut Self {
-                self.src.clear();
-                self.src.push(SRC_REG as u8);
-                self.cpu.registers[SRC_REG] = SRC_ADR as u32;
-                let val_bytes = T::our_from(SRC_VAL).to_le_bytes();
-                self.cpu
-                    .memory
-                    .buffer
-                    .splice(SRC_ADR..SRC_ADR + val_bytes.len(), val_bytes);
-                self
+        }
+
+        #[test]
+        fn devices_interrupt_between_instructions() {
+            let source = program("", "", "out.b 0, 1\nmov.l rax, s2\nsyscall 2");
+            let program = assemble("test.asm", &source).unwrap();
+            let cpu = Rc::new(RefCell::new(Cpu::new()));
+            cpu.borrow_mut().output = Some(String::new());
+            cpu.borrow_mut().load_program(&program).unwrap();
+            let doorbell = Rc::new(RefCell::new(Doorbell(None)));
+            doorbell.borrow_mut().init(Config {
+                cpu: cpu.clone(),
+                id: 0,
+                interrupts: cpu.borrow().interrupts.clone(),
+            });
+            cpu.borrow_mut().hardware.push(doorbell);
+            cpu.borrow_mut().run();
+            let output = cpu.borrow().output.clone();
+            assert_eq!(output.as_deref(), Some("1\n1 done\n2\nend\n"));
+        }
+
+        #[test]
+        fn interrupts_from_other_threads() {
+            let mut cpu = Cpu::new();
+            let interrupts = cpu.interrupts.clone();
+            std::thread::spawn(move || interrupts.raise(4))
+                .join()
+                .unwrap();
+            assert!(cpu.pic.pending.is_empty());
+            cpu.registers[IDT] = 0x300;
+            cpu.memory.set_long(0x300 + 4 * 4, 0x40);
+            cpu.cycle();
+            assert_eq!(cpu.pic.in_service, [(4, false)]);
+            // the handler's first instruction ran in the same cycle.
+            assert_eq!(cpu.ip(), 0x41);
+        }
+
+        #[test]
+        fn picks_highest_priority_then_oldest() {
+            let mut pic = Pic::default();
+            pic.set_priority(7, 3);
+            pic.raise(4);
+            pic.raise(9);
+            pic.raise(7);
+            pic.raise(4);
+            assert_eq!(pic.pending, [4, 9, 7]);
+            assert_eq!(pic.take(), Some(7));
+            // 7 is in service, so nothing at priority 0 gets through.
+            assert_eq!(pic.take(), None);
+            pic.finish();
+            assert_eq!(pic.take(), Some(4));
+            pic.end_of_interrupt();
+            assert_eq!(pic.take(), Some(9));
+            assert_eq!(pic.in_service, [(4, true), (9, false)]);
+        }
+    }
+    mod ram {
+        use crate::assembler::assemble;
+        use crate::cpu::Cpu;
+        use crate::ram::{OutOfRange, Ram};
+
+        #[test]
+        fn pages_are_allocated_on_first_write() {
+            let mut ram = Ram::new(3 * Ram::PAGE_SIZE + 10);
+            assert_eq!(ram.allocated(), 0);
+            assert_eq!(ram.get(0x2000), Some(0));
+            ram.set(0x2000, 0).unwrap();
+            ram.fill(0x100, 0x2000, 0).unwrap();
+            assert_eq!(ram.allocated(), 0);
+
+            ram.write(0xFFE, &[1, 2, 3, 4]).unwrap();
+            assert_eq!(ram.allocated(), 2 * Ram::PAGE_SIZE);
+            assert_eq!(ram.read(0xFFD, 6), Ok(vec![0, 1, 2, 3, 4, 0]));
+            assert_eq!(ram.long(0xFFE), Some(0x04030201));
+        }
+
+        #[test]
+        fn out_of_range() {
+            let mut ram = Ram::new(Ram::PAGE_SIZE + 10);
+            let len = ram.len();
+            assert_eq!(ram.get(len - 1), Some(0));
+            assert_eq!(ram.get(len), None);
+            assert_eq!(ram.long(len - 2), None);
+            assert_eq!(
+                ram.set(len, 1),
+                Err(OutOfRange {
+                    address: len,
+                    len: 1
+                })
+            );
+            // nothing is written unless all of it fits.
+            assert!(ram.write(len - 2, &[1, 2, 3]).is_err());
+            assert_eq!(ram.get(len - 2), Some(0));
+            assert!(ram.read(usize::MAX, 2).is_err());
+        }
+
+        #[test]
+        fn a_new_cpu_costs_almost_nothing() {
+            let cpu = Cpu::new();
+            assert_eq!(cpu.memory.bus.ram.len(), 100 * 1024 * 1024);
+            assert!(cpu.memory.bus.ram.allocated() <= 2 * Ram::PAGE_SIZE);
+        }
+
+        #[test]
+        fn small_memory() {
+            let program = assemble("case.asm", "mov.l [0x1000], 1\nhlt").unwrap();
+            let mut cpu = Cpu::with_memory(0x1000);
+            assert!(cpu.load_program(&[0; 0x1001]).is_err());
+            cpu.load_program(&program).unwrap();
+            cpu.run();
+            assert_eq!(
+                cpu.fault_report.as_deref(),
+                Some("unhandled memory fault (code 0x1000) at 0x00000000")
+            );
+        }
+    }
+    mod bus {
+        use std::{cell::RefCell, rc::Rc};
+
+        use crate::assembler::assemble;
+        use crate::bus::{BusError, Overlap, Region};
+        use crate::cpu::Cpu;
+        use crate::hardware::{Config, Hardware};
+
+        fn run(cpu: &mut Cpu, source: &str) {
+            let program = assemble("case.asm", source).unwrap();
+            cpu.load_program(&program).unwrap();
+            cpu.run();
+        }
+
+        /// Eight bytes of registers that remember every write.
+        #[derive(Default)]
+        struct Registers {
+            bytes: [u8; 8],
+            writes: Vec<(usize, u8)>,
+        }
+
+        impl Hardware for Registers {
+            fn init(&mut self, _: Config) {}
+            fn deinit(&mut self) {}
+            fn read(&self) -> u8 {
+                0
+            }
+            fn write(&mut self, _: u8) {}
+            fn read_mapped(&mut self, offset: usize) -> u8 {
+                self.bytes[offset]
+            }
+            fn write_mapped(&mut self, offset: usize, value: u8) {
+                self.bytes[offset] = value;
+                self.writes.push((offset, value));
+            }
+        }
+
+        #[test]
+        fn rom_reads_but_faults_on_writes() {
+            let mut cpu = Cpu::new();
+            let rom = Region::Rom(vec![0x78, 0x56, 0x34, 0x12]);
+            cpu.memory.bus.map(0x8000, 8, rom).unwrap();
+            run(
+                &mut cpu,
+                "mov.l rbx, [0x8000]\nmov.l rcx, [0x8004]\nmov.b [0x8001], 1",
+            );
+            assert_eq!(cpu.registers[1], 0x12345678);
+            assert_eq!(cpu.registers[2], 0);
+            assert_eq!(cpu.memory.bus.get(0x8001), Ok(0x56));
+            assert_eq!(
+                cpu.memory.bus.set(0x8001, 1),
+                Err(BusError::ReadOnly(0x8001))
+            );
+            assert_eq!(
+                cpu.fault_report.as_deref(),
+                Some("unhandled memory fault (code 0x8001) at 0x0000000C")
+            );
+        }
+
+        #[test]
+        fn devices_see_offsets_into_their_range() {
+            // mapped past the end of ram, where nothing was before.
+            let mut cpu = Cpu::with_memory(0x1000);
+            let device = Rc::new(RefCell::new(Registers::default()));
+            let region = Region::Device(device.clone());
+            cpu.memory.bus.map(0x10000, 8, region).unwrap();
+            run(
+                &mut cpu,
+                "mov.s [0x10002], 0xBEEF\nmov.b rbx, [0x10003]\nhlt",
+            );
+            assert_eq!(cpu.fault_report, None);
+            assert_eq!(cpu.registers[1], 0xBE);
+            assert_eq!(device.borrow().writes, [(2, 0xEF), (3, 0xBE)]);
+            assert_eq!(
+                cpu.memory.bus.get(0x10008),
+                Err(BusError::Unmapped(0x10008))
+            );
+        }
+
+        #[test]
+        fn mappings_cant_overlap() {
+            let mut cpu = Cpu::new();
+            let bus = &mut cpu.memory.bus;
+            bus.map(0x100, 0x10, Region::Rom(Vec::new())).unwrap();
+            let overlap = bus.map(0x10F, 2, Region::Rom(Vec::new()));
+            assert_eq!(
+                overlap,
+                Err(Overlap {
+                    start: 0x10F,
+                    len: 2
+                })
+            );
+            assert!(bus.map(0xFF, 1, Region::Rom(Vec::new())).is_ok());
+            assert!(bus.map(0x110, 0, Region::Rom(Vec::new())).is_err());
+            assert!(bus.map(usize::MAX, 2, Region::Rom(Vec::new())).is_err());
+        }
+    }
+    mod cycles {
+        use super::harness::case;
+        use crate::assembler::assemble;
+        use crate::cpu::Cpu;
+        use crate::opcodes::Opcode;
+
+        #[test]
+        fn default_costs() {
+            assert_eq!(Opcode::MoveRegRegLong.cost(), 1);
+            assert_eq!(Opcode::MoveRegAbsLong.cost(), 3);
+            assert_eq!(Opcode::MoveIndirectIndirectLong.cost(), 5);
+            assert!(Opcode::DivLongReg.cost() > Opcode::MulLongReg.cost());
+            assert!(Opcode::MulLongReg.cost() > Opcode::AddLongReg.cost());
+        }
+
+        #[test]
+        fn instructions_add_their_cost() {
+            let cpu = case("mov.l rax, 1\nmov.l rbx, [0x100]\ndiv.l rax")
+                .expect_reg("rbx", 0)
+                .run();
+            let expected = Opcode::MoveImmRegLong.cost()
+                + Opcode::MoveRegAbsLong.cost()
+                + Opcode::DivLongReg.cost()
+                + Opcode::Hlt.cost();
+            assert_eq!(cpu.cycles, expected as u64);
+        }
+
+        #[test]
+        fn rdcyc_reads_both_halves() {
+            let program = assemble("case.asm", "nop\nrdcyc rdx, rcx\nhlt").unwrap();
+            let mut cpu = Cpu::new();
+            cpu.load_program(&program).unwrap();
+            cpu.costs[Opcode::Nop as usize] = 0xFFFF_FFF0;
+            cpu.cycles = 0x1_0000_0000;
+            cpu.run();
+            // the count includes the rdcyc itself.
+            let read = 0x1_FFFF_FFF0 + Opcode::ReadCycles.cost() as u64;
+            assert_eq!(cpu.registers[3], (read >> 32) as u32);
+            assert_eq!(cpu.registers[2], read as u32);
+        }
+    }
+    mod exception {
+        use super::harness::case;
+        use crate::opcodes::Opcode;
+
+        // installs `handler` for `vector` in an idt at 0x300.
+        fn with_handler(vector: u32, handler: &str, body: &str) -> String {
+            format!(
+                "mov.l idt, 0x300\nmov.l [{}], {}\n{}",
+                0x300 + vector * 4,
+                handler,
+                body
+            )
+        }
+
+        const REPORT: &str = "report:\npop.l rcx\npop.l rdx\nhlt";
+
+        #[test]
+        fn divide_by_zero_runs_the_handler() {
+            // the two moves take 15 bytes, so `div` is at 21.
+            let source = with_handler(0, "report", "mov.l rbx, 0\ndiv.l rbx\nhlt\n");
+            case(&(source + REPORT))
+                .reg("rax", 10)
+                .expect_reg("rax", 10)
+                .expect_reg("rcx", 0)
+                .expect_reg("rdx", 21)
+                .expect_flag("interrupt", true)
+                .run();
+        }
+
+        #[test]
+        fn handler_can_skip_the_instruction() {
+            let body = "db 0xFF\nmov.l rbx, 7\nhlt\nskip:\npop.l rcx\npop.l rax\nadd.l 1\npush.l rax\niret";
+            case(&with_handler(6, "skip", body))
+                .expect_reg("rcx", 0xFF)
+                .expect_reg("rbx", 7)
+                .expect_flag("interrupt", false)
+                .run();
+        }
+
+        #[test]
+        fn bad_register() {
+            let source = with_handler(13, "report", "jmp 0x400\n");
+            let cpu = case(&(source + REPORT))
+                .mem(0x400, &[Opcode::IncrementLong as u8, 40])
+                .expect_reg("rcx", 40)
+                .expect_reg("rdx", 0x400)
+                .run();
+            assert_eq!(cpu.fault_report, None);
+        }
+
+        #[test]
+        fn bad_syscalls() {
+            let source = with_handler(6, "report", "syscall 200\nhlt\n");
+            case(&(source + REPORT)).expect_reg("rcx", 200).run();
+            let source = with_handler(13, "report", "mov.l rax, 40\nsyscall 3\nhlt\n");
+            case(&(source + REPORT))
+                .expect_reg("rcx", 40)
+                .expect_output("")
+                .run();
+        }
+
+        #[test]
+        fn memory_fault_without_a_handler_halts() {
+            // the faulting `mov` is undone.
+            let cpu = case("mov.l rbx, 1\nmov.l rbx, [0xFFFFFFF0]")
+                .expect_reg("rbx", 1)
+                .expect_flag("halt", true)
+                .run();
+            assert_eq!(
+                cpu.fault_report.as_deref(),
+                Some("unhandled memory fault (code 0xFFFFFFF0) at 0x00000006")
+            );
+        }
+
+        #[test]
+        fn fetch_at_the_top_of_memory_faults() {
+            let cpu = case("jmp 0xFFFFFFFF").expect_flag("halt", true).run();
+            let report = cpu.fault_report.unwrap();
+            assert!(report.starts_with("unhandled memory fault"), "{}", report);
+        }
+
+        #[test]
+        fn stack_underflow_faults() {
+            let cpu = case("mov.l sp, 2\npush.l rbx")
+                .expect_flag("halt", true)
+                .run();
+            let report = cpu.fault_report.unwrap();
+            assert!(report.starts_with("unhandled memory fault"), "{}", report);
+        }
+
+        #[test]
+        fn signed_divide_overflow() {
+            let cpu = case("idiv.l rbx")
+                .reg("rax", 0x8000_0000)
+                .reg("rbx", u32::MAX)
+                .expect_reg("rax", 0x8000_0000)
+                .run();
+            let report = cpu.fault_report.unwrap();
+            assert!(report.starts_with("unhandled divide error"), "{}", report);
+        }
+
+        #[test]
+        fn fault_in_a_handler_is_a_double_fault() {
+            let source = with_handler(0, "again", "div.l rbx\nhlt\nagain:\ndiv.l rbx");
+            let cpu = case(&source).run();
+            assert_eq!(
+                cpu.fault_report.as_deref(),
+                Some(
+                    "double fault: divide error (code 0x0) at 0x00000012 \
+                     while handling divide error (code 0x0) at 0x0000000F"
+                )
+            );
+        }
+    }
+    mod mmu {
+        use super::harness::{case, Case};
+        use crate::mmu::{Access, Mmu, PageFault};
+        use crate::ram::Ram;
+
+        const RWX: u32 = Mmu::PRESENT | Mmu::WRITABLE | Mmu::EXECUTABLE;
+
+        // identity maps the first page, which holds the program and an idt at
+        // 0x300, and maps 0x400000.. to a data page at 0x5000, a read only page
+        // at 0x6000 and a stack page at 0x7000.
+        fn paged(source: &str) -> Case {
+            let entry = |address: u32, flags: u32| (address | flags).to_le_bytes();
+            case(&format!(
+                "mov.l idt, 0x300\nmov.l [0x338], report\nmov.l ptb, 0x10000\n{}\n\
+                 hlt\nreport:\npop.l rcx\npop.l rdx\nhlt",
+                source
+            ))
+            .reg("sp", 0x403000)
+            .mem(0x10000, &entry(0x11000, RWX))
+            .mem(0x10004, &entry(0x12000, RWX))
+            .mem(0x11000, &entry(0, RWX))
+            .mem(0x12000, &entry(0x5000, Mmu::PRESENT | Mmu::WRITABLE))
+            .mem(0x12004, &entry(0x6000, Mmu::PRESENT))
+            .mem(0x12008, &entry(0x7000, Mmu::PRESENT | Mmu::WRITABLE))
+        }
+
+        #[test]
+        fn translates_reads_and_writes() {
+            paged("mov.l [0x400010], 0x1234\nmov.l rbx, [0x400010]")
+                .expect_reg("rbx", 0x1234)
+                .expect_mem(0x5010, &0x1234u32.to_le_bytes())
+                .run();
+        }
+
+        #[test]
+        fn write_to_a_read_only_page() {
+            let cpu = paged("mov.l [0x401004], 1")
+                .expect_reg("rcx", Mmu::PRESENT | Mmu::FAULT_WRITE)
+                .expect_reg("pfa", 0x401004)
+                .expect_mem(0x6004, &[0, 0, 0, 0])
+                .run();
+            assert_eq!(cpu.fault_report, None);
+        }
+
+        #[test]
+        fn write_straddling_a_read_only_page() {
+            paged("mov.l [0x400FFE], 0x11223344")
+                .expect_reg("rcx", Mmu::PRESENT | Mmu::FAULT_WRITE)
+                .expect_reg("pfa", 0x401000)
+                .expect_mem(0x5FFE, &[0, 0])
+                .run();
+        }
+
+        #[test]
+        fn executing_data() {
+            paged("jmp 0x400000")
+                .expect_reg("rcx", Mmu::PRESENT | Mmu::FAULT_EXECUTE)
+                .expect_reg("rdx", 0x400000)
+                .expect_reg("pfa", 0x400000)
+                .run();
+        }
+
+        #[test]
+        fn unmapped_page() {
+            paged("mov.l rbx, [0x800000]")
+                .expect_reg("rcx", 0)
+                .expect_reg("pfa", 0x800000)
+                .run();
+        }
+
+        #[test]
+        fn tlb_keeps_translations_until_invalidated() {
+            let mut memory = Ram::new(0x10000);
+            let mut set = |at: usize, value: u32| {
+                memory.write(at, &value.to_le_bytes()).unwrap();
+            };
+            set(0x1000, 0x2000 | RWX);
+            set(0x2004, 0x8000 | Mmu::PRESENT);
+            let mut mmu = Mmu::default();
+            mmu.set_base(0x1000);
+            assert_eq!(mmu.translate(&memory, 0x1234, Access::Read), Ok(0x8234));
+            assert_eq!(
+                mmu.translate(&memory, 0x1234, Access::Write),
+                Err(PageFault {
+                    address: 0x1234,
+                    code: Mmu::PRESENT | Mmu::FAULT_WRITE
+                })
+            );
+
+            memory.write(0x2004, &(0x9000 | RWX).to_le_bytes()).unwrap();
+            assert_eq!(mmu.translate(&memory, 0x1234, Access::Read), Ok(0x8234));
+            mmu.invalidate(0x1FFF);
+            assert_eq!(mmu.translate(&memory, 0x1234, Access::Write), Ok(0x9234));
+
+            assert_eq!(
+                mmu.translate(&memory, 0x40_0000, Access::Execute),
+                Err(PageFault {
+                    address: 0x40_0000,
+                    code: Mmu::FAULT_EXECUTE
+                })
+            );
+        }
+
+        #[test]
+        fn invlpg_drops_a_stale_translation() {
+            // 0x403000 maps the page table, so the snippet can make the read
+            // only page writable. the first read caches its old flags.
+            let writable = 0x6000 | Mmu::PRESENT | Mmu::WRITABLE;
+            let source = format!(
+                "mov.l rbx, [0x401000]\nmov.l [0x403004], {}\nmov.l rbx, 0x401000\n\
+                 invlpg rbx\nmov.l [0x401000], 7",
+                writable
+            );
+            paged(&source)
+                .mem(
+                    0x1200C,
+                    &(0x12000 | Mmu::PRESENT | Mmu::WRITABLE).to_le_bytes(),
+                )
+                .expect_mem(0x6000, &[7, 0, 0, 0])
+                .expect_reg("pfa", 0)
+                .run();
+        }
+    }
+    mod privilege {
+        use super::harness::case;
+        use crate::cpu::Cpu;
+        use crate::mmu::{Access, Mmu, PageFault};
+        use crate::opcodes::Opcode;
+        use crate::ram::Ram;
+
+        // drops to `user` through `iret` with the user stack at 0x1800 and the
+        // supervisor one at 0x2000. protection faults and traps are handled by
+        // `report` and `service`, which puts 42 in rax.
+        fn in_user_mode(user: &str) -> String {
+            format!(
+                "mov.l idt, 0x300\nmov.l [0x330], report\nmov.l [0x500], service\n\
+                 mov.l ksp, 0x2000\npush.l 0x1800\npush.l {}\npush.l user\niret\n\
+                 user:\n{}\n\
+                 service:\nmov.l rax, 42\niret\n\
+                 report:\npop.l rcx\npop.l rdx\npop.l rbx\npop.l rex\nhlt",
+                Cpu::USER_FLAG | Cpu::INTERRUPT_ENABLE_FLAG,
+                user
+            )
+        }
+
+        #[test]
+        fn privileged_instruction_faults_onto_the_supervisor_stack() {
+            case(&in_user_mode("mov.l r9, 5\nhlt"))
+                .expect_reg("r9", 5)
+                .expect_reg("rcx", Opcode::Hlt as u32)
+                .expect_reg("rbx", Cpu::USER_FLAG | Cpu::INTERRUPT_ENABLE_FLAG)
+                .expect_reg("rex", 0x1800)
+                .expect_reg("sp", 0x2000)
+                .expect_flag("user", false)
+                .run();
+        }
+
+        #[test]
+        fn control_registers_are_off_limits() {
+            case(&in_user_mode("mov.l idt, 0"))
+                .expect_reg("rcx", Opcode::MoveImmRegLong as u32)
+                .expect_reg("idt", 0x300)
+                .run();
+            case(&in_user_mode("push.l flags"))
+                .expect_reg("rcx", Opcode::PushLongReg as u32)
+                .run();
+        }
+
+        #[test]
+        fn page_faults_are_restartable() {
+            // user code calls with its stack on an unmapped page. the page fault
+            // handler maps it through the page tables, identity mapped at
+            // 0x12000, and returns to run the `call` again.
+            let source = format!(
+                "mov.l idt, 0x300\nmov.l [0x330], report\nmov.l [0x338], pager\n\
+                 mov.l ksp, 0x2000\nmov.l ptb, 0x10000\n\
+                 push.l 0x402000\npush.l {}\npush.l user\niret\n\
+                 user:\ncall routine\nmov.l r13, 1\nmov.l r9, sp\nhlt\n\
+                 routine:\npush.l 0x1234\npop.l r12\nret\n\
+                 pager:\npop.l rcx\ninc.l r10\nmov.l rbx, pfa\nmov.l [0x12004], {}\n\
+                 invlpg rbx\niret\n\
+                 report:\nhlt",
+                Cpu::USER_FLAG | Cpu::INTERRUPT_ENABLE_FLAG,
+                0x8000 | Mmu::PRESENT | Mmu::WRITABLE | Mmu::USER
+            );
+            let entry = |address: u32, flags: u32| (address | flags).to_le_bytes();
+            let all = Mmu::PRESENT | Mmu::WRITABLE | Mmu::EXECUTABLE | Mmu::USER;
+            let supervisor = Mmu::PRESENT | Mmu::WRITABLE;
+            case(&source)
+                .reg("sp", 0x2000)
+                .mem(0x10000, &entry(0x11000, all))
+                .mem(0x10004, &entry(0x12000, all))
+                .mem(0x11000, &entry(0, all))
+                .mem(0x11004, &entry(0x1000, supervisor))
+                .mem(0x11048, &entry(0x12000, supervisor))
+                .expect_reg("rcx", Mmu::FAULT_WRITE | Mmu::FAULT_USER)
+                .expect_reg("pfa", 0x401FFC)
+                .expect_reg("r10", 1)
+                .expect_reg("r12", 0x1234)
+                .expect_reg("r13", 1)
+                .expect_reg("r9", 0x402000)
+                .expect_mem(0x8FF8, &0x1234u32.to_le_bytes())
+                .run();
+        }
+
+        #[test]
+        fn user_code_cant_touch_the_pic() {
+            case(&in_user_mode("syscall 6"))
+                .expect_reg("rcx", Opcode::Syscall as u32)
+                .run();
+        }
+
+        #[test]
+        fn user_code_cant_load_libraries() {
+            for syscall in ["syscall 4", "syscall 5"] {
+                case(&in_user_mode(syscall))
+                    .expect_reg("rcx", Opcode::Syscall as u32)
+                    .expect_reg("rex", 0x1800)
+                    .run();
+            }
+        }
+
+        #[test]
+        fn trap_calls_the_supervisor() {
+            // back in user mode after the trap, `hlt` faults again.
+            let cpu = case(&in_user_mode("push.l 7\ntrap\npop.l r9\nhlt"))
+                .expect_reg("rax", 42)
+                .expect_reg("r9", 7)
+                .expect_reg("rcx", Opcode::Hlt as u32)
+                .expect_reg("rex", 0x1800)
+                .run();
+            assert!(cpu.pic.in_service.is_empty());
+        }
+
+        #[test]
+        fn trap_without_a_handler_halts() {
+            let cpu = case("trap")
+                .reg("sp", 0x2000)
+                .expect_reg("sp", 0x2000)
+                .expect_flag("halt", true)
+                .run();
+            assert_eq!(
+                cpu.fault_report.as_deref(),
+                Some("unhandled trap at 0x00000000")
+            );
+            let cpu = case("mov.l idt, 0x300\ntrap").run();
+            assert_eq!(
+                cpu.fault_report.as_deref(),
+                Some("unhandled trap at 0x00000006")
+            );
+            assert!(cpu.pic.in_service.is_empty());
+        }
+
+        #[test]
+        fn user_pages() {
+            let mut memory = Ram::new(0x3000);
+            let flags = Mmu::PRESENT | Mmu::WRITABLE;
+            let mut set = |at: usize, value: u32| {
+                memory.write(at, &value.to_le_bytes()).unwrap();
+            };
+            set(0x1000, 0x2000 | flags | Mmu::USER);
+            set(0x2000, 0x5000 | flags);
+            set(0x2004, 0x6000 | flags | Mmu::USER);
+            let mut mmu = Mmu::default();
+            mmu.set_base(0x1000);
+            assert_eq!(mmu.translate(&memory, 0x10, Access::Read), Ok(0x5010));
+
+            mmu.user = true;
+            assert_eq!(mmu.translate(&memory, 0x1010, Access::Write), Ok(0x6010));
+            assert_eq!(
+                mmu.translate(&memory, 0x10, Access::Read),
+                Err(PageFault {
+                    address: 0x10,
+                    code: Mmu::PRESENT | Mmu::FAULT_USER
+                })
+            );
+        }
+    }
+    mod mov {
+        use super::harness::case;
+
+        const VALUE: u32 = 0xCAFEC0DE;
+        const DST_ADR: usize = 0x100;
+        const SRC_ADR: usize = 0x200;
+        const WIDTHS: [(&str, usize); 3] = [("b", 1), ("s", 2), ("l", 4)];
+
+        /// `reg` and `ind` use `register`, `abs` and `mem` use `address`.
+        fn operand(kind: &str, register: &str, address: usize) -> String {
+            match kind {
+                "reg" => register.to_string(),
+                "abs" => format!("[0x{:X}]", address),
+                "mem" => format!("[rel 0x{:X}]", address),
+                "ind" => format!("[{}]", register),
+                _ => unreachable!(),
+            }
+        }
+
+        /// Moves `VALUE` from a `src` operand into a `dst` operand at every width.
+        /// rax is the destination register and rbx the source register.
+        fn mov(dst: &str, src: &str) {
+            for (suffix, len) in WIDTHS {
+                let value = VALUE & (u32::MAX >> (32 - len * 8));
+                let bytes = &VALUE.to_le_bytes()[..len];
+                let src_operand = match src {
+                    "imm" => format!("0x{:X}", value),
+                    _ => operand(src, "rbx", SRC_ADR),
+                };
+                let source = format!(
+                    "mov.{} {}, {}",
+                    suffix,
+                    operand(dst, "rax", DST_ADR),
+                    src_operand
+                );
+
+                let mut test = case(&source).mem(SRC_ADR, bytes);
+                test = match src {
+                    "reg" => test.reg("rbx", value),
+                    "ind" => test.reg("rbx", SRC_ADR as u32),
+                    _ => test,
+                };
+                test = match dst {
+                    "reg" => test.expect_reg("rax", value),
+                    "ind" => test.reg("rax", DST_ADR as u32).expect_mem(DST_ADR, bytes),
+                    _ => test.expect_mem(DST_ADR, bytes),
+                };
+                test.run();
             }
         }
+
         mod to_reg {
-            use super::TestBuilder;
-            use crate::opcodes::Opcode;
+            use super::mov;
 
             #[test]
-            fn from_imm_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveImmRegByte);
-                builder.set_dst_reg().set_src_imm().run().assert_reg_eq();
-            }
-            #[test]
-            fn from_imm_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveImmRegShort);
-                builder.set_dst_reg().set_src_imm().run().assert_reg_eq();
-            }
-            #[test]
-            fn from_imm_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveImmRegLong);
-                builder.set_dst_reg().set_src_imm().run().assert_reg_eq();
-            }
-            #[test]
-            fn from_reg_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveRegRegByte);
-                builder.set_dst_reg().set_src_reg().run().assert_reg_eq();
-            }
-            #[test]
-            fn from_reg_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveRegRegShort);
-                builder.set_dst_reg().set_src_reg().run().assert_reg_eq();
-            }
-            #[test]
-            fn from_reg_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveRegRegLong);
-                builder.set_dst_reg().set_src_reg().run().assert_reg_eq();
-            }
-            #[test]
-            fn from_abs_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveAbsRegByte);
-                builder.set_dst_reg().set_src_abs().run().assert_reg_eq();
-            }
-            #[test]
-            fn from_abs_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveAbsRegShort);
-                builder.set_dst_reg().set_src_abs().run().assert_reg_eq();
-            }
-            #[test]
-            fn from_abs_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveAbsRegLong);
-                builder.set_dst_reg().set_src_abs().run().assert_reg_eq();
-            }
-            #[test]
-            fn from_mem_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveMemRegByte);
-                builder.set_dst_reg().set_src_mem(6).run().assert_reg_eq();
-            }
-            #[test]
-            fn from_mem_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveMemRegShort);
-                builder.set_dst_reg().set_src_mem(6).run().assert_reg_eq();
+            fn from_imm() {
+                mov("reg", "imm");
             }
             #[test]
-            fn from_mem_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveMemRegLong);
-                builder.set_dst_reg().set_src_mem(6).run().assert_reg_eq();
+            fn from_reg() {
+                mov("reg", "reg");
             }
             #[test]
-            fn from_ind_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveIndirectRegByte);
-                builder.set_dst_reg().set_src_ind().run().assert_reg_eq();
+            fn from_abs() {
+                mov("reg", "abs");
             }
             #[test]
-            fn from_ind_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveIndirectRegShort);
-                builder.set_dst_reg().set_src_ind().run().assert_reg_eq();
+            fn from_mem() {
+                mov("reg", "mem");
             }
             #[test]
-            fn from_ind_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveIndirectRegLong);
-                builder.set_dst_reg().set_src_ind().run().assert_reg_eq();
+            fn from_ind() {
+                mov("reg", "ind");
             }
         }
         mod to_abs {
-            use super::TestBuilder;
-            use crate::opcodes::Opcode;
+            use super::mov;
 
             #[test]
-            fn from_imm_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveImmAbsByte);
-                builder.set_dst_abs().set_src_imm().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_imm_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveImmAbsShort);
-                builder.set_dst_abs().set_src_imm().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_imm_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveImmAbsLong);
-                builder.set_dst_abs().set_src_imm().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_reg_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveRegAbsByte);
-                builder.set_dst_abs().set_src_reg().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_reg_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveRegAbsShort);
-                builder.set_dst_abs().set_src_reg().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_reg_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveRegAbsLong);
-                builder.set_dst_abs().set_src_reg().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_abs_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveAbsAbsByte);
-                builder.set_dst_abs().set_src_abs().run().assert_adr_eq();
+            fn from_imm() {
+                mov("abs", "imm");
             }
             #[test]
-            fn from_abs_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveAbsAbsShort);
-                builder.set_dst_abs().set_src_abs().run().assert_adr_eq();
+            fn from_reg() {
+                mov("abs", "reg");
             }
             #[test]
-            fn from_abs_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveAbsAbsLong);
-                builder.set_dst_abs().set_src_abs().run().assert_adr_eq();
+            fn from_abs() {
+                mov("abs", "abs");
             }
             #[test]
-            fn from_mem_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveMemAbsByte);
-                builder.set_dst_abs().set_src_mem(9).run().assert_adr_eq();
+            fn from_mem() {
+                mov("abs", "mem");
             }
             #[test]
-            fn from_mem_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveMemAbsShort);
-                builder.set_dst_abs().set_src_mem(9).run().assert_adr_eq();
-            }
-            #[test]
-            fn from_mem_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveMemAbsLong);
-                builder.set_dst_abs().set_src_mem(9).run().assert_adr_eq();
-            }
-            #[test]
-            fn from_ind_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveIndirectAbsByte);
-                builder.set_dst_abs().set_src_ind().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_ind_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveIndirectAbsShort);
-                builder.set_dst_abs().set_src_ind().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_ind_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveIndirectAbsLong);
-                builder.set_dst_abs().set_src_ind().run().assert_adr_eq();
+            fn from_ind() {
+                mov("abs", "ind");
             }
         }
         mod to_mem {
-            use super::TestBuilder;
-            use crate::opcodes::Opcode;
+            use super::mov;
 
             #[test]
-            fn from_imm_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveImmMemByte);
-                builder.set_dst_mem(6).set_src_imm().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_imm_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveImmMemShort);
-                builder.set_dst_mem(7).set_src_imm().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_imm_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveImmMemLong);
-                builder.set_dst_mem(9).set_src_imm().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_reg_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveRegMemByte);
-                builder.set_dst_mem(6).set_src_reg().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_reg_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveRegMemShort);
-                builder.set_dst_mem(6).set_src_reg().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_reg_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveRegMemLong);
-                builder.set_dst_mem(6).set_src_reg().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_abs_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveAbsMemByte);
-                builder.set_dst_mem(9).set_src_abs().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_abs_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveAbsMemShort);
-                builder.set_dst_mem(9).set_src_abs().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_abs_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveAbsMemLong);
-                builder.set_dst_mem(9).set_src_abs().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_mem_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveMemMemByte);
-                builder.set_dst_mem(9).set_src_mem(9).run().assert_adr_eq();
-            }
-            #[test]
-            fn from_mem_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveMemMemShort);
-                builder.set_dst_mem(9).set_src_mem(9).run().assert_adr_eq();
+            fn from_imm() {
+                mov("mem", "imm");
             }
             #[test]
-            fn from_mem_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveMemMemLong);
-                builder.set_dst_mem(9).set_src_mem(9).run().assert_adr_eq();
+            fn from_reg() {
+                mov("mem", "reg");
             }
             #[test]
-            fn from_ind_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveIndirectMemByte);
-                builder.set_dst_mem(6).set_src_ind().run().assert_adr_eq();
+            fn from_abs() {
+                mov("mem", "abs");
             }
             #[test]
-            fn from_ind_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveIndirectMemShort);
-                builder.set_dst_mem(6).set_src_ind().run().assert_adr_eq();
+            fn from_mem() {
+                mov("mem", "mem");
             }
             #[test]
-            fn from_ind_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveIndirectMemLong);
-                builder.set_dst_mem(6).set_src_ind().run().assert_adr_eq();
+            fn from_ind() {
+                mov("mem", "ind");
             }
         }
         mod to_ind {
-            use super::TestBuilder;
-            use crate::opcodes::Opcode;
+            use super::mov;
 
             #[test]
-            fn from_imm_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveImmIndirectByte);
-                builder.set_dst_ind().set_src_imm().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_imm_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveImmIndirectShort);
-                builder.set_dst_ind().set_src_imm().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_imm_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveImmIndirectLong);
-                builder.set_dst_ind().set_src_imm().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_reg_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveRegIndirectByte);
-                builder.set_dst_ind().set_src_reg().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_reg_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveRegIndirectShort);
-                builder.set_dst_ind().set_src_reg().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_reg_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveRegIndirectLong);
-                builder.set_dst_ind().set_src_reg().run().assert_adr_eq();
+            fn from_imm() {
+                mov("ind", "imm");
             }
             #[test]
-            fn from_abs_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveAbsIndirectByte);
-                builder.set_dst_ind().set_src_abs().run().assert_adr_eq();
+            fn from_reg() {
+                mov("ind", "reg");
             }
             #[test]
-            fn from_abs_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveAbsIndirectShort);
-                builder.set_dst_ind().set_src_abs().run().assert_adr_eq();
+            fn from_abs() {
+                mov("ind", "abs");
             }
             #[test]
-            fn from_abs_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveAbsIndirectLong);
-                builder.set_dst_ind().set_src_abs().run().assert_adr_eq();
+            fn from_mem() {
+                mov("ind", "mem");
             }
             #[test]
-            fn from_mem_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveMemIndirectByte);
-                builder.set_dst_ind().set_src_mem(6).run().assert_adr_eq();
-            }
-            #[test]
-            fn from_mem_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveMemIndirectShort);
-                builder.set_dst_ind().set_src_mem(6).run().assert_adr_eq();
-            }
-            #[test]
-            fn from_mem_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveMemIndirectLong);
-                builder.set_dst_ind().set_src_mem(6).run().assert_adr_eq();
-            }
-            #[test]
-            fn from_ind_byte() {
-                let mut builder = TestBuilder::<u8>::new(Opcode::MoveIndirectIndirectByte);
-                builder.set_dst_ind().set_src_ind().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_ind_short() {
-                let mut builder = TestBuilder::<u16>::new(Opcode::MoveIndirectIndirectShort);
-                builder.set_dst_ind().set_src_ind().run().assert_adr_eq();
-            }
-            #[test]
-            fn from_ind_long() {
-                let mut builder = TestBuilder::<u32>::new(Opcode::MoveIndirectIndirectLong);
-                builder.set_dst_ind().set_src_ind().run().assert_adr_eq();
+            fn from_ind() {
+                mov("ind", "ind");
             }
         }
     }
     mod io {
         use crate::{
+            assembler::assemble,
             cpu::Cpu,
             gpu,
             hardware::{Config, Hardware},
-            opcodes::Opcode,
         };
         use std::{cell::RefCell, rc::Rc};
 
         #[test]
         fn gpu() {
             let cpu = Rc::new(RefCell::new(Cpu::new()));
-            cpu.borrow_mut().load_program(&[
-                Opcode::WriteByteImm as u8,
-                0,
-                1,
-                Opcode::WriteByteImm as u8,
-                0,
-                0,
-            ]);
+            let program = assemble("test.asm", "out.b 0, 1\nout.b 0, 0").unwrap();
+            cpu.borrow_mut().load_program(&program).unwrap();
             let gpu = Rc::new(RefCell::new(gpu::GPU::new()));
             cpu.borrow_mut().hardware.push(gpu.clone());
             let cfg = Config {
                 cpu: cpu.clone(),
                 id: 0,
+                interrupts: cpu.borrow().interrupts.clone(),
             };
             gpu.clone().borrow_mut().init(cfg);
             cpu.borrow_mut().run();
         }
     }
+    mod assembler {
+        use crate::{
+            assembler::{assemble, AsmError},
+            cpu::Cpu,
+            opcodes::Opcode,
+        };
+
+        #[test]
+        fn selects_move_variant_from_operands() {
+            let program = assemble(
+                "test.asm",
+                "mov.b rax, 1\nmov.s [0x100], rbx\nmov [rbx], [rel 0]\nmov rcx, [rdx]",
+            )
+            .unwrap();
+            assert_eq!(program[0], Opcode::MoveImmRegByte as u8);
+            assert_eq!(program[3], Opcode::MoveRegAbsShort as u8);
+            assert_eq!(program[9], Opcode::MoveMemIndirectLong as u8);
+            assert_eq!(program[15], Opcode::MoveIndirectRegLong as u8);
+        }
+
+        #[test]
+        fn forward_references() {
+            let program = assemble("test.asm", "jmp end\nnop\nend: hlt").unwrap();
+            assert_eq!(
+                program,
+                vec![
+                    Opcode::JumpImm as u8,
+                    6,
+                    0,
+                    0,
+                    0,
+                    Opcode::Nop as u8,
+                    Opcode::Hlt as u8
+                ]
+            );
+        }
+
+        #[test]
+        fn countdown() {
+            let source = "
+                mov rcx, 5
+                mov rdx, 0
+            loop:
+                mov rax, rdx
+                add 2
+                mov rdx, rax
+                dec rcx
+                mov rax, rcx
+                mov rbx, 0
+                jne loop
+                hlt
+            ";
+            let program = assemble("test.asm", source).unwrap();
+            let mut cpu = Cpu::new();
+            cpu.load_program(&program).unwrap();
+            cpu.run();
+            assert_eq!(cpu.registers[3], 10);
+            assert_eq!(cpu.registers[2], 0);
+        }
+
+        #[test]
+        fn error_location() {
+            let err = assemble("test.asm", "nop\n  mov.b rax, 300").unwrap_err();
+            assert_eq!(
+                err,
+                AsmError {
+                    file: "test.asm".to_string(),
+                    line: 2,
+                    column: 14,
+                    message: "value 300 does not fit in 1 byte(s)".to_string(),
+                }
+            );
+
+            let err = assemble("test.asm", "jmp nowhere").unwrap_err();
+            assert_eq!((err.line, err.column), (1, 5));
+        }
+
+        #[test]
+        fn rejects_bad_operands() {
+            assert!(assemble("test.asm", "push [rax]").is_err());
+            assert!(assemble("test.asm", "jmp.b 0").is_err());
+            assert!(assemble("test.asm", "a: a: nop").is_err());
+        }
+
+        #[test]
+        fn compare_needs_a_register_on_the_left() {
+            for source in ["cmp.l [0x200], 5", "cmp.b [rax], rbx", "cmp 1, rax"] {
+                let err = assemble("test.asm", source).unwrap_err();
+                assert_eq!(err.message, "the left operand of `cmp` must be a register");
+            }
+            let err = assemble("test.asm", "cmp.l [0x200], 5").unwrap_err();
+            assert_eq!((err.line, err.column), (1, 7));
+        }
+
+        #[test]
+        fn data_directives() {
+            let source = "db 1, 'a', \"hi\", -1\ndw 0x1234, end\ndd 0xDEADBEEF\nasciz \"ok\\n\"\nalign 4\nascii \"x\"\nresb 2\nend:";
+            let program = assemble("test.asm", source).unwrap();
+            assert_eq!(program.len(), 23);
+            assert_eq!(program[..5], [1, b'a', b'h', b'i', 0xFF]);
+            assert_eq!(
+                program[13..20],
+                [
+                    b'o',
+                    b'k',
+                    b'\n',
+                    0,
+                    Opcode::Nop as u8,
+                    Opcode::Nop as u8,
+                    Opcode::Nop as u8
+                ]
+            );
+
+            let mut cpu = Cpu::new();
+            cpu.load_program(&program).unwrap();
+            assert_eq!(cpu.memory.short(5), 0x1234);
+            assert_eq!(cpu.memory.short(7), 23);
+            assert_eq!(cpu.memory.long(9), 0xDEADBEEF);
+            assert_eq!(program[20..], [b'x', 0, 0]);
+
+            let error = |source: &str| assemble("test.asm", source).unwrap_err().message;
+            assert_eq!(error("align 3"), "alignment 3 is not a power of two");
+            assert_eq!(error("resb later\nlater:"), "undefined symbol `later`");
+            assert_eq!(
+                error("section .bss\nresd 4\ndw 1"),
+                "initialized data is not allowed in `.bss`"
+            );
+            assert_eq!(error("db 256"), "value 256 does not fit in 1 byte(s)");
+            assert_eq!(
+                error("section .bss\nresb 0xFFFFFFFF\nresb 0xFFFFFFFF"),
+                "section `.bss` exceeds 4 GiB"
+            );
+            assert_eq!(
+                error("section .bss\nresb 0xFFFFFFF0\nalign 32"),
+                "section `.bss` exceeds 4 GiB"
+            );
+            assert_eq!(
+                error("resd 0x7FFFFFFFFFFFFFFF"),
+                "cannot reserve 9223372036854775807 item(s)"
+            );
+        }
+
+        #[test]
+        fn incbin_relative_to_the_source() {
+            let dir = std::env::temp_dir().join("bit32_assembler_incbin");
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("table.bin"), [9, 8, 7]).unwrap();
+            let main = dir.join("main.asm");
+            std::fs::write(
+                &main,
+                "start: hlt\nincbin \"table.bin\"\nafter: db after - start",
+            )
+            .unwrap();
+
+            let object = crate::assembler::assemble_file(&main).unwrap();
+            assert_eq!(object.sections[0].bytes, vec![0, 9, 8, 7, 4]);
+        }
+    }
+    mod disassembler {
+        use crate::{
+            assembler::assemble,
+            disassembler::{decode, disassemble},
+            opcodes::Opcode,
+        };
+
+        #[test]
+        fn round_trip() {
+            let source = "mov.b [0x200], 7\nloop: dec.l rbx\npush.s 0x1234\nmov.l rax, [rel data]\njne loop\ndata: hlt";
+            let program = assemble("test.asm", source).unwrap();
+            let text = disassemble(&program, 0)
+                .iter()
+                .map(|instruction| instruction.text())
+                .collect::<Vec<String>>();
+            assert_eq!(
+                text,
+                vec![
+                    "mov.b [0x200], 7",
+                    "dec.l rbx",
+                    "push.s 0x1234",
+                    "mov.l rax, [rel 0x16]",
+                    "jne 0x6",
+                    "hlt",
+                ]
+            );
+            assert_eq!(assemble("test.asm", &text.join("\n")).unwrap(), program);
+        }
+
+        #[test]
+        fn invalid_bytes() {
+            let decoded = disassemble(&[0xFF, Opcode::Nop as u8, Opcode::JumpImm as u8, 1], 0);
+            assert_eq!(decoded.len(), 4);
+            assert!(!decoded[0].is_valid());
+            assert!(decoded[1].is_valid());
+            assert!(!decoded[2].is_valid());
+            assert_eq!(decoded[0].text(), "(bad) 0xFF");
+            assert_eq!(
+                decode(&[Opcode::PopByte as u8, 200], 0).text(),
+                "pop.b r?200"
+            );
+        }
+    }
+    mod linker {
+        use crate::{
+            assembler::{Assembler, SourceLine},
+            cpu::Cpu,
+            linker::{link, Input, LinkError},
+            object::{Object, SectionKind},
+            opcodes::Opcode,
+        };
+
+        fn object(name: &str, source: &str) -> Input {
+            Input {
+                name: name.to_string(),
+                object: Assembler::new()
+                    .assemble(&SourceLine::split(name, source))
+                    .unwrap(),
+            }
+        }
+
+        #[test]
+        fn resolves_across_objects() {
+            let main = object(
+                "main.asm",
+                "extern add_ten, value\nglobal start\nstart: mov rax, [value]\ncall add_ten\nmov [rel result], rax\nhlt\nsection .data\nresult: nop",
+            );
+            let lib = object(
+                "lib.asm",
+                "global add_ten, value\nadd_ten: add 10\nret\nsection .data\nvalue: nop",
+            );
+            assert_eq!(main.object.relocations.len(), 3);
+
+            let linked = link(&[main, lib], 0).unwrap();
+            assert_eq!(linked.global("start"), Some(0));
+            let value = linked.global("value").unwrap();
+            assert_eq!(value, linked.placement(1, SectionKind::Data).address);
+
+            let mut cpu = Cpu::new();
+            cpu.load_program(&linked.bytes).unwrap();
+            cpu.memory.bus.ram.set(value as usize, 5).unwrap();
+            while !cpu.has_flag(Cpu::HALT_FLAG) {
+                cpu.cycle();
+            }
+            let result = linked.placement(0, SectionKind::Data).address as usize;
+            assert_eq!(cpu.memory.bus.ram.get(result), Some(15));
+        }
+
+        #[test]
+        fn aligns_sections() {
+            let a = object("a.asm", "nop\nsection .data\ndb 1\nsection .bss\nresb 1");
+            let b = object(
+                "b.asm",
+                "nop\nsection .data\nalign 8\nvalue: dd 7\nsection .bss\nalign 16\nbuffer: resd 4",
+            );
+            let linked = link(&[a, b], 0).unwrap();
+            assert_eq!(linked.placement(1, SectionKind::Data).address, 8);
+            assert_eq!(linked.bytes[..2], [Opcode::Nop as u8, Opcode::Nop as u8]);
+            assert_eq!(linked.bytes[2..12], [1, 0, 0, 0, 0, 0, 7, 0, 0, 0]);
+            assert_eq!(linked.placement(1, SectionKind::Bss).address, 16);
+            assert_eq!(linked.bss_size, 20);
+        }
+
+        #[test]
+        fn reports_undefined_and_duplicate_symbols() {
+            let a = object(
+                "a.asm",
+                "extern missing\nglobal f\nf: call missing\njmp missing",
+            );
+            let b = object("b.asm", "global f\nf: ret");
+            let errors = link(&[a, b], 0).unwrap_err();
+            assert_eq!(
+                errors,
+                vec![
+                    LinkError::Duplicate {
+                        symbol: "f".to_string(),
+                        first: "a.asm".to_string(),
+                        second: "b.asm".to_string(),
+                    },
+                    LinkError::Undefined {
+                        symbol: "missing".to_string(),
+                        object: "a.asm".to_string(),
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn object_round_trip() {
+            let input = object(
+                "a.asm",
+                "extern f\nglobal g\ng: call f\nsection .data\nmov [g], 1",
+            );
+            let bytes = input.object.to_bytes();
+            assert_eq!(Object::from_bytes(&bytes).unwrap(), input.object);
+            assert!(Object::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        }
+
+        #[test]
+        fn rejects_relocations_outside_their_section() {
+            let input = object("a.asm", "extern f\ncall f\nsection .bss\nresb 8");
+            let mut past_the_end = input.object.clone();
+            past_the_end.relocations[0].offset = 3;
+            let mut in_bss = input.object.clone();
+            in_bss.relocations[0].section = SectionKind::Bss;
+            in_bss.relocations[0].offset = 0;
+            for bad in [past_the_end, in_bss] {
+                let err = Object::from_bytes(&bad.to_bytes()).unwrap_err();
+                assert!(err.to_string().contains("outside its section"), "{}", err);
+            }
+
+            let mut short = input.object.clone();
+            short.sections[0].bytes.pop();
+            assert!(Object::from_bytes(&short.to_bytes()).is_err());
+        }
+    }
+    mod image {
+        use crate::{
+            assembler::{Assembler, SourceLine},
+            cpu::{Cpu, BP, IDT, IP, SP},
+            image::{Image, Segment},
+            linker::{link, Input},
+        };
+
+        fn image(source: &str) -> Image {
+            let object = Assembler::new()
+                .assemble(&SourceLine::split("test.asm", source))
+                .unwrap();
+            let input = Input {
+                name: "test.asm".to_string(),
+                object,
+            };
+            let linked = link(&[input], 0).unwrap();
+            Image::from_linked(&linked, linked.global("start").unwrap())
+        }
+
+        #[test]
+        fn loads_and_runs() {
+            let mut image = image(
+                "global start\nhlt\nstart: mov rax, [rel value]\npush rax\nhlt\nsection .data\nvalue: nop",
+            );
+            assert_eq!(image.entry, 1);
+            assert_eq!(image.segments.len(), 2);
+            image.stack_top = 0x10000;
+            image.idt_base = 0x8000;
+            image = Image::from_bytes(&image.to_bytes()).unwrap();
+
+            let mut cpu = Cpu::new();
+            cpu.load_image(&image).unwrap();
+            assert_eq!(cpu.registers[IP], 1);
+            assert_eq!(cpu.registers[BP], 0x10000);
+            assert_eq!(cpu.registers[IDT], 0x8000);
+
+            let data = image.segments[1].address as usize;
+            cpu.memory.bus.ram.set(data, 42).unwrap();
+            while !cpu.has_flag(Cpu::HALT_FLAG) {
+                cpu.cycle();
+            }
+            assert_eq!(cpu.registers[SP], 0x10000 - 4);
+            assert_eq!(cpu.memory.long(0x10000 - 4), 42);
+        }
+
+        #[test]
+        fn rejects_invalid_images() {
+            let valid = image("global start\nstart: hlt");
+            let mut cpu = Cpu::new();
+            let size = cpu.memory.bus.ram.len() as u32;
+            assert!(cpu.load_image(&valid).is_ok());
+
+            let mut bad = valid.clone();
+            bad.entry = 100;
+            assert!(cpu.load_image(&bad).is_err());
+
+            let mut bad = valid.clone();
+            bad.segments.push(Segment {
+                address: size - 1,
+                bytes: vec![0, 0],
+            });
+            assert!(cpu.load_image(&bad).is_err());
+
+            let mut bad = valid.clone();
+            bad.segments.push(Segment {
+                address: 0,
+                bytes: vec![0],
+            });
+            assert!(cpu.load_image(&bad).is_err());
+
+            let mut bad = valid.clone();
+            bad.stack_top = 16;
+            bad.stack_size = 32;
+            assert!(cpu.load_image(&bad).is_err());
+
+            let mut bytes = valid.to_bytes();
+            bytes[4] = 99;
+            assert!(Image::from_bytes(&bytes).is_err());
+            assert!(Image::from_bytes(b"B32O").is_err());
+        }
+    }
+    mod preprocessor {
+        use crate::{
+            assembler::{assemble, SourceLine},
+            preprocessor::Preprocessor,
+        };
+
+        fn expand(source: &str) -> Vec<String> {
+            Preprocessor::new()
+                .process(&SourceLine::split("test.asm", source))
+                .unwrap()
+                .into_iter()
+                .map(|line| line.text.trim().to_string())
+                .filter(|text| !text.is_empty())
+                .collect()
+        }
+
+        #[test]
+        fn macros_with_local_labels() {
+            let source = "%macro countdown 2\nmov %1, %2\n%%again: dec %1\njne %%again ; loop\n%endmacro\ncountdown rcx, 3\ncountdown rdx, 5\nhlt";
+            assert_eq!(
+                expand(source),
+                vec![
+                    "mov rcx, 3",
+                    "countdown.1.again: dec rcx",
+                    "jne countdown.1.again",
+                    "mov rdx, 5",
+                    "countdown.2.again: dec rdx",
+                    "jne countdown.2.again",
+                    "hlt",
+                ]
+            );
+            assert!(assemble("test.asm", source).is_ok());
+        }
+
+        #[test]
+        fn defines_and_conditions() {
+            let source = "%define SIZE 4\n%define DOUBLE SIZE * 2\n%if DOUBLE - 8\nmov rax, DOUBLE\n%elif 1\nnop\n%else\nhlt\n%endif\n%ifndef SIZE\nhlt\n%endif\n%ifdef SIZE\nmov rbx, 'S'\n%endif";
+            assert_eq!(expand(source), vec!["nop", "mov rbx, 'S'"]);
+            assert_eq!(
+                expand("%define SIZE 4\n%if SIZE\nmov rax, SIZE * 2\n%endif"),
+                vec!["mov rax, 4 * 2"]
+            );
+        }
+
+        #[test]
+        fn includes_relative_to_the_including_file() {
+            let dir = std::env::temp_dir().join("bit32_preprocessor_include");
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(
+                dir.join("lib.inc"),
+                "%define ANSWER 42\n%macro halt 0\nhlt\n%endmacro",
+            )
+            .unwrap();
+            let main = dir.join("main.asm");
+            std::fs::write(&main, "%include \"lib.inc\"\nmov rax, ANSWER\nhalt").unwrap();
+
+            let lines = crate::preprocessor::preprocess_file(&main).unwrap();
+            let text = lines
+                .iter()
+                .map(|line| line.text.as_str())
+                .collect::<Vec<&str>>();
+            assert_eq!(text, vec!["mov rax, 42", "hlt"]);
+        }
+
+        #[test]
+        fn reports_errors() {
+            let error = |source: &str| {
+                Preprocessor::new()
+                    .process(&SourceLine::split("test.asm", source))
+                    .unwrap_err()
+            };
+            assert_eq!(
+                error("nop\n%if 1\nnop").message,
+                "`%if` is missing `%endif`"
+            );
+            assert_eq!(error("%macro m 1\nnop").line, 1);
+            let wrong_args = error("%macro m 1\nnop\n%endmacro\nm 1, 2");
+            assert_eq!(
+                (wrong_args.line, wrong_args.message.as_str()),
+                (4, "macro `m` takes 1 argument(s), found 2")
+            );
+            assert_eq!(
+                error("%macro m 0\nm\n%endmacro\nm").message,
+                "macros or includes nested too deeply"
+            );
+            assert_eq!(error("%bogus").message, "unknown directive `%bogus`");
+        }
+    }
+    mod debuginfo {
+        use crate::{
+            assembler::{Assembler, SourceLine},
+            debuginfo::DebugInfo,
+            disassembler::decode,
+            linker::{link, Input},
+        };
+
+        fn info(sources: &[(&str, &str)]) -> (DebugInfo, Vec<u8>) {
+            let inputs = sources
+                .iter()
+                .map(|(name, source)| Input {
+                    name: name.to_string(),
+                    object: Assembler::new()
+                        .assemble(&SourceLine::split(name, source))
+                        .unwrap(),
+                })
+                .collect::<Vec<Input>>();
+            let linked = link(&inputs, 0).unwrap();
+            (DebugInfo::from_link(&inputs, &linked), linked.bytes)
+        }
+
+        #[test]
+        fn maps_addresses_to_lines_and_functions() {
+            let (info, bytes) = info(&[
+                (
+                    "main.asm",
+                    "extern helper\nglobal main\nmain: call helper\n\nhlt",
+                ),
+                ("lib.asm", "global helper\nhelper: mov rax, 1\n.done: ret"),
+            ]);
+
+            let line = info.line(5).unwrap();
+            assert_eq!((line.file.as_str(), line.line), ("main.asm", 5));
+            let line = info.line(6).unwrap();
+            assert_eq!((line.file.as_str(), line.line), ("lib.asm", 2));
+            assert!(info.line(7).is_none());
+
+            assert_eq!(info.function(0).unwrap().name, "main");
+            assert_eq!(info.function(12).unwrap().name, "helper");
+            assert_eq!(info.symbolize(12).unwrap(), ".done");
+            assert_eq!(info.symbolize(8).unwrap(), "helper+0x2");
+            assert_eq!(info.symbolize(100), None);
+
+            let call = decode(&bytes, 0);
+            assert_eq!(
+                call.symbolic_text(|address| info.symbolize(address)),
+                "call helper"
+            );
+            assert_eq!(DebugInfo::from_bytes(&info.to_bytes()).unwrap(), info);
+        }
+    }
+
+    mod compiler {
+        use crate::{
+            abi::AbiChecker,
+            assembler::{link_flat, Assembler},
+            compiler::compile,
+            cpu::Cpu,
+        };
+
+        /// Compiles and runs `source`, returning what `main` returned.
+        fn run(source: &str) -> u32 {
+            let lines = compile("test.c", source).unwrap();
+            let object = Assembler::new().assemble(&lines).unwrap();
+            let program = link_flat("test.c", object).unwrap();
+            let mut cpu = Cpu::new();
+            cpu.load_program(&program).unwrap();
+            cpu.abi_checker = Some(AbiChecker::default());
+            for _ in 0..100_000 {
+                if cpu.has_flag(Cpu::HALT_FLAG) {
+                    assert_eq!(cpu.abi_checker.unwrap().violations, vec![]);
+                    return cpu.registers[0];
+                }
+                cpu.cycle();
+            }
+            panic!("program did not halt");
+        }
+
+        fn error(source: &str) -> String {
+            compile("test.c", source).unwrap_err().to_string()
+        }
+
+        #[test]
+        fn functions_loops_and_arrays() {
+            let source = "
+                int32 fact(int32 n) {
+                    if (n <= 1) return 1;
+                    return n * fact(n - 1);
+                }
+                int32 sum(int32* values, int32 len) {
+                    int32 total = 0;
+                    for (int32 i = 0; i < len; i = i + 1) total = total + values[i];
+                    return total;
+                }
+                int32 main() {
+                    int32 squares[4];
+                    int32 i = 0;
+                    while (1) {
+                        if (i == 4) break;
+                        squares[i] = i * i;
+                        i = i + 1;
+                    }
+                    return fact(5) + sum(squares, 4);
+                }";
+            assert_eq!(run(source), 134);
+        }
+
+        #[test]
+        fn structs_pointers_and_narrow_types() {
+            let source = "
+                struct Pair { int8 small; uint16 wide; int32* next; };
+                struct Pair global;
+                int32 target = -20;
+                int32 main() {
+                    struct Pair* p = &global;
+                    p->small = -3;
+                    global.wide = 65535;
+                    p->next = &target;
+                    *global.next = *p->next / 4;
+                    uint8* bytes = \"abc\";
+                    return p->small * 1000 + global.wide - target + bytes[2] + sizeof(struct Pair);
+                }";
+            assert_eq!(run(source), (-3000i32 + 65535 + 5 + 99 + 7) as u32);
+        }
+
+        #[test]
+        fn logical_operators_short_circuit() {
+            let source = "
+                int32 calls;
+                int32 touch(int32 value) { calls = calls + 1; return value; }
+                int32 main() {
+                    int32 result = (touch(0) && touch(1)) * 100 + (touch(2) || touch(3)) * 10;
+                    return result + calls + (-1 < 1) * 1000;
+                }";
+            assert_eq!(run(source), 1012);
+        }
+
+        #[test]
+        fn reports_errors() {
+            assert_eq!(
+                error("int32 main() {\n  return missing;\n}"),
+                "test.c:2:10: error: undefined variable `missing`"
+            );
+            assert_eq!(
+                error("void f(int32 a) {}\nint32 main() { f(); return 0; }"),
+                "test.c:2:16: error: `f` takes 1 argument(s), found 0"
+            );
+            assert_eq!(
+                error("int32 main() { break; }"),
+                "test.c:1:16: error: `break` or `continue` outside of a loop"
+            );
+            assert_eq!(
+                error("int32 main() { return 1 }"),
+                "test.c:1:25: error: expected `;`, found `}`"
+            );
+        }
+
+        #[test]
+        fn rejects_oversized_types() {
+            assert_eq!(
+                error("int32 a[2000000000];"),
+                "test.c:1:7: error: `int32[2000000000]` is too large"
+            );
+            assert_eq!(
+                error("int32 b[4294967297];"),
+                "test.c:1:9: error: array length is too large"
+            );
+            assert_eq!(
+                error("int32 n = sizeof(int8[65536][65536]);"),
+                "test.c:1:11: error: `int8[65536][65536]` is too large"
+            );
+            assert_eq!(
+                error("struct Big { int8 a[4294967295]; int8 b[1]; };"),
+                "test.c:1:34: error: struct `Big` is too large"
+            );
+            assert_eq!(
+                error("int32 main() { int8 a[2147483647]; int8 b[2]; return 0; }"),
+                "test.c:1:36: error: `b` doesn't fit in the stack frame"
+            );
+        }
+    }
+
+    mod abi {
+        use crate::{
+            abi::{AbiChecker, Violation},
+            assembler::assemble,
+            cpu::{Cpu, BP, SP},
+        };
+
+        fn run(source: &str) -> Cpu {
+            let mut cpu = Cpu::new();
+            cpu.load_program(&assemble("test.asm", source).unwrap())
+                .unwrap();
+            cpu.abi_checker = Some(AbiChecker::default());
+            cpu.run();
+            cpu
+        }
+
+        #[test]
+        fn enter_and_leave_build_a_frame() {
+            let cpu =
+                run("mov bp, 0\nmov sp, 1000\nenter 12\nmov rax, bp\nmov rbx, sp\nleave\nhlt");
+            assert_eq!((cpu.registers[0], cpu.registers[1]), (996, 984));
+            assert_eq!((cpu.registers[BP], cpu.registers[SP]), (0, 1000));
+        }
+
+        #[test]
+        fn frame_bigger_than_the_stack_faults() {
+            let cpu = run("mov sp, 100\nenter 0x1000\npush.l 1\nhlt");
+            assert_eq!(cpu.registers[SP], 96u32.wrapping_sub(0x1000));
+            let report = cpu.fault_report.unwrap();
+            assert!(report.starts_with("unhandled memory fault"), "{}", report);
+        }
+
+        #[test]
+        fn checker_reports_clobbered_registers() {
+            let cpu = run("call good\ncall bad\nhlt\n\
+                 good: enter 4\npush rex\nmov rex, 1\nmov rax, 2\npop rex\nleave\nret\n\
+                 bad: mov rex, 7\nret");
+            let checker = cpu.abi_checker.unwrap();
+            assert_eq!(
+                checker.violations,
+                vec![Violation {
+                    call: 5,
+                    target: 34,
+                    register: 4,
+                    expected: 0,
+                    found: 7,
+                }]
+            );
+            assert_eq!(
+                checker.violations[0].to_string(),
+                "call at 0x00000005 to 0x00000022 returned with rex = 0x00000007, expected 0x00000000"
+            );
+        }
+    }
+
+    mod listing {
+        use crate::{
+            assembler::{Assembler, SourceLine},
+            image::Image,
+            linker::{link, Input},
+            listing::{linked_layout, listing, map, object_layout},
+        };
+
+        #[test]
+        fn listing_and_map() {
+            let source = "global start\nstart: call show\nhlt\nshow: ret\nsection .data\ntext: db 1, 2, 3, 4, 5, 6, 7, 8, 9";
+            let mut assembler = Assembler::new();
+            let object = assembler
+                .assemble(&SourceLine::split("test.asm", source))
+                .unwrap();
+
+            let unlinked = listing(&assembler.listing, object_layout(&object));
+            assert!(
+                unlinked.contains("00000000  D5 00 00 00 00               2  start: call show\n")
+            );
+
+            let inputs = [Input {
+                name: "test.asm".to_string(),
+                object,
+            }];
+            let linked = link(&inputs, 0x100).unwrap();
+            let text = listing(&assembler.listing, linked_layout(&linked, 0));
+            let lines = text.lines().collect::<Vec<&str>>();
+            assert_eq!(lines[0], "; test.asm");
+            assert_eq!(
+                lines[2],
+                "00000100  D5 06 01 00 00               2  start: call show"
+            );
+            assert_eq!(
+                lines[6],
+                "00000107  01 02 03 04 05 06 07 08      6  text: db 1, 2, 3, 4, 5, 6, 7, 8, 9"
+            );
+            assert_eq!(lines[7], "0000010F  09");
+
+            let image = Image::from_linked(&linked, 0x100);
+            let map = map(&inputs, &linked, Some(&image));
+            assert!(map.contains("  0x00000107  0x00000009  load\n"));
+            assert!(map.contains("  0x00000100  0x00000006  .text   global start (test.asm)\n"));
+            assert!(map.contains("  0x00000106  0x00000001  .text   local  show (test.asm)\n"));
+            assert!(map.contains("  entry 0x00000100\n"));
+        }
+    }
+
+    mod archive {
+        use crate::{
+            archive::{Archive, Member},
+            assembler::{Assembler, SourceLine},
+            linker::{add_archive_members, Input},
+        };
+
+        fn member(name: &str, source: &str) -> Member {
+            Member {
+                name: name.to_string(),
+                object: Assembler::new()
+                    .assemble(&SourceLine::split(name, source))
+                    .unwrap(),
+            }
+        }
+
+        fn library() -> Archive {
+            Archive::new(vec![
+                member("square.o", "global square\nsquare: mul rax\nret"),
+                member(
+                    "cube.o",
+                    "extern square\nglobal cube\ncube: call square\nret",
+                ),
+                member(
+                    "unused.o",
+                    "global unused, square\nunused: ret\nsquare: ret",
+                ),
+            ])
+        }
+
+        #[test]
+        fn round_trip_and_index() {
+            let archive = library();
+            assert_eq!(archive.find("square"), Some(0));
+            assert_eq!(archive.find("cube"), Some(1));
+            assert_eq!(archive.find("unused"), Some(2));
+            assert_eq!(archive.find("start"), None);
+
+            let bytes = archive.to_bytes();
+            assert!(Archive::is_archive(&bytes));
+            assert_eq!(Archive::from_bytes(&bytes).unwrap(), archive);
+            assert!(Archive::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        }
+
+        #[test]
+        fn rejects_member_names_that_are_paths() {
+            for name in ["../../x", "/abs/path", "dir\\x.o", ".."] {
+                let bytes = Archive::new(vec![member(name, "ret")]).to_bytes();
+                let err = Archive::from_bytes(&bytes).unwrap_err();
+                assert!(err.to_string().contains("is not a file name"), "{}", err);
+            }
+        }
+
+        #[test]
+        fn pulls_in_only_needed_members() {
+            let main = Assembler::new()
+                .assemble(&SourceLine::split(
+                    "main.asm",
+                    "extern cube\nglobal start\nstart: call cube\nhlt",
+                ))
+                .unwrap();
+            let mut inputs = vec![Input {
+                name: "main.asm".to_string(),
+                object: main,
+            }];
+            add_archive_members(&mut inputs, &[("lib.a".to_string(), library())]);
+            let names = inputs
+                .iter()
+                .map(|input| input.name.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(names, ["main.asm", "lib.a(cube.o)", "lib.a(square.o)"]);
+            assert!(crate::linker::link(&inputs, 0).is_ok());
+        }
+    }
+
+    mod library {
+        use crate::{
+            assembler::{assemble, Assembler, SourceLine},
+            cpu::Cpu,
+            library::{Library, Loader},
+            linker::{link_shared, Input},
+        };
+
+        fn library(name: &str, source: &str) -> Library {
+            let object = Assembler::new()
+                .assemble(&SourceLine::split(name, source))
+                .unwrap();
+            let input = Input {
+                name: name.to_string(),
+                object,
+            };
+            link_shared(&[input]).unwrap()
+        }
+
+        fn math() -> Library {
+            library(
+                "math.asm",
+                "global square, last\nsquare: mul rax\nmov [last], rax\nret\nsection .data\nlast: dd 0",
+            )
+        }
+
+        fn quad() -> Library {
+            library(
+                "quad.asm",
+                "extern square\nglobal fourth\nfourth: call square\ncall square\nret",
+            )
+        }
+
+        #[test]
+        fn imports_resolve_through_the_table() {
+            let quad = quad();
+            assert_eq!(quad.fixups.len(), 2);
+            assert!(quad
+                .fixups
+                .iter()
+                .all(|f| f.import.as_deref() == Some("square")));
+            assert_eq!(Library::from_bytes(&quad.to_bytes()).unwrap(), quad);
+
+            let mut cpu = Cpu::new();
+            let limit = cpu.registers[crate::cpu::SP];
+            let mut loader = Loader::default();
+            let err = loader.load(&mut cpu.memory, limit, "quad", &quad);
+            assert!(err.unwrap_err().to_string().contains("`square`"));
+            assert_eq!(loader, Loader::default());
+
+            let base = loader
+                .load(&mut cpu.memory, limit, "math", &math())
+                .unwrap();
+            assert_eq!(base, Loader::DEFAULT_BASE);
+            let next = loader.load(&mut cpu.memory, limit, "quad", &quad).unwrap();
+            assert_eq!(next % Loader::ALIGN, 0);
+            assert!(next > base);
+            assert_eq!(loader.symbol("fourth"), Some(next));
+        }
+
+        #[test]
+        fn rejects_fixups_wider_than_a_long() {
+            let mut math = math();
+            math.fixups[0].size = 8;
+            let err = Library::from_bytes(&math.to_bytes()).unwrap_err();
+            assert_eq!(err.to_string(), "fixup of 8 bytes");
+
+            let mut cpu = Cpu::new();
+            let limit = cpu.registers[crate::cpu::SP];
+            let err = Loader::default().load(&mut cpu.memory, limit, "math", &math);
+            assert_eq!(err.unwrap_err().to_string(), "fixup of 8 bytes");
+        }
+
+        #[test]
+        fn guests_load_libraries_with_a_syscall() {
+            let dir = std::env::temp_dir().join("bit32_library");
+            std::fs::create_dir_all(&dir).unwrap();
+            let math_path = dir.join("math.b32l");
+            let quad_path = dir.join("quad.b32l");
+            math().write_to_file(&math_path).unwrap();
+            quad().write_to_file(&quad_path).unwrap();
+
+            let source = format!(
+                "mov rax, math\nsyscall 4\nmov rax, quad\nsyscall 4\nmov rax, fourth\nsyscall 5\nmov rcx, rax\nmov rax, 3\npush.l done\njmp rcx\ndone: hlt\nmath: asciz \"{}\"\nquad: asciz \"{}\"\nfourth: asciz \"fourth\"",
+                math_path.display(),
+                quad_path.display()
+            );
+            let mut cpu = Cpu::new();
+            cpu.load_program(&assemble("test.asm", &source).unwrap())
+                .unwrap();
+            cpu.run();
+
+            assert_eq!(cpu.registers[0], 81);
+            assert_eq!(cpu.loader.libraries.len(), 2);
+            let last = cpu.loader.symbol("last").unwrap();
+            assert_eq!(cpu.memory.long(last as usize), 81);
+        }
+
+        #[test]
+        fn names_that_arent_utf8_load_nothing() {
+            let source = "mov rax, bad\nsyscall 4\nmov rbx, rax\nmov rax, bad\nsyscall 5\nhlt\nbad: db 0xFF, 0xFE, 0";
+            let mut cpu = Cpu::new();
+            cpu.load_program(&assemble("test.asm", source).unwrap())
+                .unwrap();
+            cpu.run();
+
+            assert_eq!(cpu.registers[0], 0);
+            assert_eq!(cpu.registers[1], 0);
+            assert!(cpu.loader.libraries.is_empty());
+        }
+    }
+    mod json {
+        use crate::json::Json;
+
+        #[test]
+        fn parse_and_print() {
+            let text = r#" {"a": [1, -2.5, true, null], "b": "q\"\n\u00e9\ud83d\ude00", "c": {}} "#;
+            let value = Json::parse(text).unwrap();
+            assert_eq!(
+                value.get("a"),
+                &Json::Array(vec![
+                    Json::Number(1.0),
+                    Json::Number(-2.5),
+                    Json::Bool(true),
+                    Json::Null,
+                ])
+            );
+            assert_eq!(value.get("b").as_str(), Some("q\"\n\u{e9}\u{1F600}"));
+            assert_eq!(value.get("missing"), &Json::Null);
+            assert_eq!(Json::parse(&value.to_string()).unwrap(), value);
+            assert_eq!(
+                value.to_string(),
+                "{\"a\":[1,-2.5,true,null],\"b\":\"q\\\"\\n\u{e9}\u{1F600}\",\"c\":{}}"
+            );
+            assert!(Json::parse("[1,").is_err());
+            assert!(Json::parse("{} x").is_err());
+        }
+    }
+    mod lsp {
+        use crate::{
+            json::Json,
+            lsp::{serve, Server},
+        };
+
+        const URI: &str = "file:///tmp/bit32_lsp/main.asm";
+        const SOURCE: &str =
+            "%macro twice 1\nadd %1\nadd %1\n%endmacro\nstart: mov rax, 1\ntwice 2\njmp start\nhlt";
+
+        fn request(method: &str, line: usize, character: usize) -> Json {
+            let position = Json::object([("line", line.into()), ("character", character.into())]);
+            let params = Json::object([
+                ("textDocument", Json::object([("uri", URI.into())])),
+                ("position", position),
+            ]);
+            Json::object([
+                ("id", 7usize.into()),
+                ("method", method.into()),
+                ("params", params),
+            ])
+        }
+
+        fn open(server: &mut Server, text: &str) -> Json {
+            let document = Json::object([("uri", URI.into()), ("text", text.into())]);
+            let params = Json::object([("textDocument", document)]);
+            let message = Json::object([
+                ("method", "textDocument/didOpen".into()),
+                ("params", params),
+            ]);
+            let mut replies = server.handle(&message);
+            replies.remove(0).get("params").get("diagnostics").clone()
+        }
+
+        fn result(server: &mut Server, method: &str, line: usize, character: usize) -> Json {
+            server.handle(&request(method, line, character))[0]
+                .get("result")
+                .clone()
+        }
+
+        fn lines(locations: &Json) -> Vec<u64> {
+            let Json::Array(locations) = locations else {
+                panic!("expected locations, found {}", locations);
+            };
+            let line = |l: &Json| l.get("range").get("start").get("line").as_u64().unwrap();
+            locations.iter().map(line).collect()
+        }
+
+        #[test]
+        fn navigation_hover_and_completion() {
+            let mut server = Server::default();
+            assert_eq!(open(&mut server, SOURCE), Json::Array(vec![]));
+
+            // `start` in `jmp start` and `twice` where it's invoked.
+            assert_eq!(
+                lines(&Json::Array(vec![result(
+                    &mut server,
+                    "textDocument/definition",
+                    6,
+                    5
+                )])),
+                [4]
+            );
+            assert_eq!(
+                lines(&Json::Array(vec![result(
+                    &mut server,
+                    "textDocument/definition",
+                    5,
+                    1
+                )])),
+                [0]
+            );
+            assert_eq!(
+                lines(&result(&mut server, "textDocument/references", 4, 2)),
+                [4, 6]
+            );
+            assert_eq!(
+                result(&mut server, "textDocument/references", 4, 8),
+                Json::Null
+            );
+
+            let hover = result(&mut server, "textDocument/hover", 4, 8);
+            let text = hover.get("contents").get("value").as_str().unwrap();
+            assert!(text.starts_with("**mov**"));
+            assert!(text.contains("`mov.l reg (1), imm (4)`: 6 bytes, MoveImmRegLong"));
+            let hover = result(&mut server, "textDocument/hover", 4, 12);
+            assert_eq!(
+                hover.get("contents").get("value").as_str(),
+                Some("register `rax`, index 0")
+            );
+
+            let Json::Array(items) = result(&mut server, "textDocument/completion", 0, 0) else {
+                panic!("expected completion items");
+            };
+            let labels = items
+                .iter()
+                .map(|item| item.get("label").as_str().unwrap())
+                .collect::<Vec<_>>();
+            assert!(labels.contains(&"enter") && labels.contains(&"r15"));
+            assert_eq!(labels.iter().filter(|label| **label == "mov").count(), 1);
+        }
+
+        #[test]
+        fn reports_assembler_errors() {
+            let mut server = Server::default();
+            let diagnostics = open(&mut server, "start: mov rax, 1\n  frob rax");
+            let Json::Array(diagnostics) = diagnostics else {
+                panic!("expected diagnostics");
+            };
+            assert_eq!(diagnostics.len(), 1);
+            let start = diagnostics[0].get("range").get("start");
+            assert_eq!(start.get("line").as_u64(), Some(1));
+            assert_eq!(start.get("character").as_u64(), Some(2));
+        }
+
+        #[test]
+        fn speaks_json_rpc_over_a_stream() {
+            let messages = [
+                r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+                r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+                r#"{"jsonrpc":"2.0","id":2,"method":"frobnicate"}"#,
+                r#"{"jsonrpc":"2.0","method":"exit"}"#,
+            ];
+            let input = messages
+                .iter()
+                .map(|body| format!("Content-Length: {}\r\n\r\n{}", body.len(), body))
+                .collect::<String>();
+            let mut output = Vec::new();
+            serve(input.as_bytes(), &mut output).unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+            let replies = output
+                .split("Content-Length: ")
+                .skip(1)
+                .map(|message| Json::parse(message.split_once("\r\n\r\n").unwrap().1).unwrap())
+                .collect::<Vec<Json>>();
+            assert_eq!(replies.len(), 2);
+            assert_eq!(
+                replies[0]
+                    .get("result")
+                    .get("capabilities")
+                    .get("hoverProvider"),
+                &Json::Bool(true)
+            );
+            assert_eq!(replies[1].get("error").get("code"), &Json::Number(-32601.0));
+            assert_eq!(replies[1].get("id").as_u64(), Some(2));
+        }
+    }
 }